@@ -26,7 +26,7 @@ use std::time::Duration;
 // Synchronous anonymous datagrams:
 //    cargo run --example anonymous --no-default-features --features sync
 
-#[cfg(all(feature = "async", not(feature = "sync")))]
+#[cfg(feature = "async")]
 #[tokio::main]
 async fn main() {
     use yosemite::{style::Anonymous, Session};
@@ -57,7 +57,7 @@ async fn main() {
 
     let mut client = Session::<Anonymous>::new(Default::default()).await.unwrap();
 
-    for message in vec!["hello, world", "testing 123", "goodbye, world"] {
+    for message in ["hello, world", "testing 123", "goodbye, world"] {
         client.send_to(message.as_bytes(), &destination).await.unwrap();
         tokio::time::sleep(Duration::from_secs(1)).await;
     }
@@ -93,7 +93,7 @@ fn main() {
 
     let mut client = Session::<Anonymous>::new(Default::default()).unwrap();
 
-    for message in vec!["hello, world", "testing 123", "goodbye, world"] {
+    for message in ["hello, world", "testing 123", "goodbye, world"] {
         client.send_to(message.as_bytes(), &destination).unwrap();
         std::thread::sleep(Duration::from_secs(1));
     }
@@ -24,7 +24,7 @@ use tracing_subscriber::prelude::*;
 // Synchronous client-server:
 //    cargo run --example client_server --no-default-features --features sync
 
-#[cfg(all(feature = "async", not(feature = "sync")))]
+#[cfg(feature = "async")]
 #[tokio::main]
 async fn main() {
     use futures::{AsyncReadExt, AsyncWriteExt};
@@ -43,7 +43,7 @@ async fn main() {
             let mut buffer = vec![0u8; 14];
 
             stream.read_exact(&mut buffer).await.unwrap();
-            stream.write_all(&mut buffer).await.unwrap();
+            stream.write_all(&buffer).await.unwrap();
 
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
@@ -80,7 +80,7 @@ fn main() {
             let mut buffer = vec![0u8; 14];
 
             stream.read_exact(&mut buffer).unwrap();
-            stream.write_all(&mut buffer).unwrap();
+            stream.write_all(&buffer).unwrap();
 
             std::thread::sleep(std::time::Duration::from_secs(1));
         }
@@ -24,7 +24,7 @@ use tracing_subscriber::prelude::*;
 // Synchronous eepget:
 //    cargo run --example eepget --no-default-features --features sync -- <host>
 
-#[cfg(all(feature = "async", not(feature = "sync")))]
+#[cfg(feature = "async")]
 #[tokio::main]
 async fn main() {
     use futures::{AsyncReadExt, AsyncWriteExt};
@@ -24,7 +24,7 @@ use tracing_subscriber::prelude::*;
 // Synchronous client-server:
 //    cargo run --example forwarded --no-default-features --features sync
 
-#[cfg(all(feature = "async", not(feature = "sync")))]
+#[cfg(feature = "async")]
 #[tokio::main]
 async fn main() {
     use futures::{AsyncReadExt, AsyncWriteExt};
@@ -56,7 +56,7 @@ async fn main() {
         while let Ok((mut stream, _)) = listener.accept().await {
             let mut buffer = vec![0u8; 14];
             stream.read_exact(&mut buffer).await.unwrap();
-            stream.write_all(&mut buffer).await.unwrap();
+            stream.write_all(&buffer).await.unwrap();
 
             tokio::time::sleep(std::time::Duration::from_secs(1)).await;
         }
@@ -107,7 +107,7 @@ fn main() {
         while let Ok((mut stream, _)) = listener.accept() {
             let mut buffer = vec![0u8; 14];
             stream.read_exact(&mut buffer).unwrap();
-            stream.write_all(&mut buffer).unwrap();
+            stream.write_all(&buffer).unwrap();
 
             tracing::info!("read = {:?}", std::str::from_utf8(&buffer));
 
@@ -24,7 +24,7 @@ use tracing_subscriber::prelude::*;
 // Synchronous destination generation:
 //    cargo run --example generate_destination --no-default-features --features sync
 
-#[cfg(all(feature = "async", not(feature = "sync")))]
+#[cfg(feature = "async")]
 #[tokio::main]
 async fn main() {
     use yosemite::{style::Stream, DestinationKind, RouterApi, Session, SessionOptions};
@@ -24,7 +24,7 @@ use tracing_subscriber::prelude::*;
 // Synchronous host lookup:
 //    cargo run --example host_lookup --no-default-features --features sync -- <host>
 
-#[cfg(all(feature = "async", not(feature = "sync")))]
+#[cfg(feature = "async")]
 #[tokio::main]
 async fn main() {
     use yosemite::RouterApi;
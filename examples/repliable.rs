@@ -26,7 +26,7 @@ use std::time::Duration;
 // Synchronous repliable datagrams:
 //    cargo run --example repliable --no-default-features --features sync
 
-#[cfg(all(feature = "async", not(feature = "sync")))]
+#[cfg(feature = "async")]
 #[tokio::main]
 async fn main() {
     use yosemite::{style::Repliable, Session};
@@ -54,7 +54,7 @@ async fn main() {
     let mut client = Session::<Repliable>::new(Default::default()).await.unwrap();
     let mut buffer = [0u8; 1024];
 
-    for message in vec!["hello, world", "testing 123", "goodbye, world"] {
+    for message in ["hello, world", "testing 123", "goodbye, world"] {
         // send message to server
         client.send_to(message.as_bytes(), &destination).await.unwrap();
 
@@ -98,7 +98,7 @@ fn main() {
     let mut client = Session::<Repliable>::new(Default::default()).unwrap();
     let mut buffer = [0u8; 1024];
 
-    for message in vec!["hello, world", "testing 123", "goodbye, world"] {
+    for message in ["hello, world", "testing 123", "goodbye, world"] {
         // send message to server
         client.send_to(message.as_bytes(), &destination).unwrap();
 
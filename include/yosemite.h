@@ -0,0 +1,92 @@
+/*
+ * C API of yosemite, available with the `ffi` feature.
+ *
+ * All calls block the calling thread. Failing calls return NULL or a negative value and the error
+ * can be read with `yosemite_last_error()` on the same thread. See the documentation of the `ffi`
+ * module for details.
+ */
+
+#ifndef YOSEMITE_H
+#define YOSEMITE_H
+
+#include <stddef.h>
+#include <stdint.h>
+#include <sys/types.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Session of virtual streams. */
+typedef struct YosemiteSession YosemiteSession;
+
+/* Virtual stream. */
+typedef struct YosemiteStream YosemiteStream;
+
+/* Session of repliable datagrams. */
+typedef struct YosemiteDatagramSession YosemiteDatagramSession;
+
+/* Session options. Zero ports and NULL strings use the defaults. */
+typedef struct YosemiteOptions {
+    /* TCP port of the SAMv3 bridge. */
+    uint16_t samv3_tcp_port;
+
+    /* UDP port of the SAMv3 bridge. */
+    uint16_t samv3_udp_port;
+
+    /* Nickname of the session. */
+    const char *nickname;
+
+    /* Private key of a persistent destination. If NULL, the destination is transient. */
+    const char *private_key;
+} YosemiteOptions;
+
+/*
+ * Callback invoked from a background thread with inbound virtual streams. The callback owns the
+ * stream and must free it. The stream is NULL if accepting failed and no more streams are
+ * accepted.
+ */
+typedef void (*YosemiteAcceptCallback)(void *user_data, YosemiteStream *stream);
+
+const char *yosemite_last_error(void);
+YosemiteOptions yosemite_options_default(void);
+
+YosemiteSession *yosemite_session_new(const YosemiteOptions *options);
+const char *yosemite_session_destination(const YosemiteSession *session);
+const char *yosemite_session_public_destination(const YosemiteSession *session);
+YosemiteStream *yosemite_session_connect(YosemiteSession *session, const char *destination);
+YosemiteStream *yosemite_session_accept(YosemiteSession *session);
+int yosemite_session_set_accept_callback(
+    YosemiteSession *session,
+    YosemiteAcceptCallback callback,
+    void *user_data
+);
+void yosemite_session_free(YosemiteSession *session);
+
+const char *yosemite_stream_remote_destination(const YosemiteStream *stream);
+ssize_t yosemite_stream_read(YosemiteStream *stream, uint8_t *buf, size_t len);
+int yosemite_stream_write(YosemiteStream *stream, const uint8_t *buf, size_t len);
+void yosemite_stream_free(YosemiteStream *stream);
+
+YosemiteDatagramSession *yosemite_datagram_session_new(const YosemiteOptions *options);
+const char *yosemite_datagram_session_destination(const YosemiteDatagramSession *session);
+const char *yosemite_datagram_session_public_destination(const YosemiteDatagramSession *session);
+int yosemite_datagram_session_send(
+    YosemiteDatagramSession *session,
+    const uint8_t *buf,
+    size_t len,
+    const char *destination
+);
+ssize_t yosemite_datagram_session_recv(
+    YosemiteDatagramSession *session,
+    uint8_t *buf,
+    size_t len,
+    const char **sender
+);
+void yosemite_datagram_session_free(YosemiteDatagramSession *session);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* YOSEMITE_H */
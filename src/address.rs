@@ -0,0 +1,406 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! I2P destination addresses.
+
+use crate::options::{LeaseSetAuth, LeaseSetType, SessionOptions, SignatureType};
+
+use sha2::{Digest, Sha256};
+
+/// Alphabet of I2P's base64 encoding.
+const BASE64_ALPHABET: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-~";
+
+/// Alphabet of base32 encoding.
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Length of a destination without its certificate.
+///
+/// 256-byte public key, 128-byte signing public key and the 3-byte certificate header.
+const DESTINATION_BASE_LEN: usize = 387;
+
+/// Key certificate type.
+const CERTIFICATE_TYPE_KEY: u8 = 5;
+
+/// Length of the header of offline keys: 4-byte expiration and 2-byte transient signature type.
+const OFFLINE_HEADER_LEN: usize = 6;
+
+/// Flag of a blinded address whose lease set requires a secret.
+const BLINDED_FLAG_SECRET: u8 = 0x02;
+
+/// Flag of a blinded address whose lease set requires client authorization.
+const BLINDED_FLAG_CLIENT_AUTH: u8 = 0x04;
+
+/// Compute the `.b32.i2p` address of `destination`.
+///
+/// `destination` is a base64-encoded destination, such as one returned by
+/// [`RouterApi::lookup_name()`](crate::RouterApi::lookup_name), or the base64 of a destination
+/// followed by its private keys, such as one returned by
+/// [`Session::destination()`](crate::Session::destination) or
+/// [`RouterApi::generate_destination()`](crate::RouterApi::generate_destination).
+///
+/// Returns `None` if `destination` is not a valid destination.
+///
+/// ```
+/// # let destination = "";
+/// if let Some(address) = yosemite::b32_address(destination) {
+///     println!("reachable at {address}");
+/// }
+/// ```
+pub fn b32_address(destination: &str) -> Option<String> {
+    let destination = decode_destination(destination)?;
+
+    Some(format!(
+        "{}.b32.i2p",
+        encode_base32(&Sha256::digest(&destination))
+    ))
+}
+
+/// Compute the blinded `.b32.i2p` address of `destination` when it's hosted with `options`.
+///
+/// Sessions using [`LeaseSetType::Encrypted`] publish their lease set under a blinded
+/// destination, and clients can only look it up with the blinded address. The address encodes
+/// whether a lookup secret and client authorization are required, so it depends on the
+/// [`SessionOptions::lease_set_type`] and [`SessionOptions::lease_set_auth`] of the session.
+///
+/// `destination` is accepted in the same formats as in [`b32_address()`].
+///
+/// Returns `None` if `options` doesn't use an encrypted lease set, if `destination` is not a valid
+/// destination or if its signature type cannot be blinded. Only Ed25519 and RedDSA destinations can
+/// be blinded.
+///
+/// ```
+/// use yosemite::{LeaseSetType, SessionOptions, SignatureType};
+///
+/// # let destination = "";
+/// let options = SessionOptions {
+///     lease_set_type: LeaseSetType::Encrypted {
+///         secret: None,
+///         blinded_signature_type: SignatureType::RedDsaSha512Ed25519,
+///     },
+///     ..Default::default()
+/// };
+///
+/// if let Some(address) = yosemite::blinded_b32_address(destination, &options) {
+///     println!("reachable at {address}");
+/// }
+/// ```
+pub fn blinded_b32_address(destination: &str, options: &SessionOptions) -> Option<String> {
+    let LeaseSetType::Encrypted {
+        secret,
+        blinded_signature_type,
+    } = &options.lease_set_type
+    else {
+        return None;
+    };
+
+    let destination = decode_destination(destination)?;
+    if destination.len() < DESTINATION_BASE_LEN + 4
+        || destination[DESTINATION_BASE_LEN - 3] != CERTIFICATE_TYPE_KEY
+    {
+        return None;
+    }
+
+    let signature_type = SignatureType::from_code(u16::from_be_bytes([
+        destination[DESTINATION_BASE_LEN],
+        destination[DESTINATION_BASE_LEN + 1],
+    ]))?;
+    if !signature_type.is_blindable() || !blinded_signature_type.is_blindable() {
+        return None;
+    }
+
+    let mut flags = 0u8;
+    if secret.is_some() {
+        flags |= BLINDED_FLAG_SECRET;
+    }
+    if let Some(LeaseSetAuth::DhClients(_) | LeaseSetAuth::PskClients(_)) = &options.lease_set_auth
+    {
+        flags |= BLINDED_FLAG_CLIENT_AUTH;
+    }
+
+    // 32-byte signing public key is at the end of the 128-byte signing key field
+    let public_key = &destination[DESTINATION_BASE_LEN - 35..DESTINATION_BASE_LEN - 3];
+    let checksum = crc32fast::hash(public_key);
+
+    let mut address = Vec::with_capacity(3 + public_key.len());
+    address.extend_from_slice(&[
+        flags ^ checksum as u8,
+        signature_type.code() as u8 ^ (checksum >> 8) as u8,
+        blinded_signature_type.code() as u8 ^ (checksum >> 16) as u8,
+    ]);
+    address.extend_from_slice(public_key);
+
+    Some(format!("{}.b32.i2p", encode_base32(&address)))
+}
+
+/// Split `destination` of the form `host:port` into the host and the port.
+///
+/// `:` is not part of I2P's base64 alphabet, so base64 destinations are never split. If there's no
+/// port or the port is not a valid port number, `destination` is returned as-is.
+#[cfg(any(feature = "async", feature = "sync"))]
+pub(crate) fn split_port(destination: &str) -> (&str, Option<u16>) {
+    match destination.rsplit_once(':') {
+        Some((host, port)) if !host.is_empty() => match port.parse() {
+            Ok(port) => (host, Some(port)),
+            Err(_) => (destination, None),
+        },
+        _ => (destination, None),
+    }
+}
+
+/// Get the base64 of `destination` without the private keys that follow it.
+///
+/// `destination` is accepted in the same formats as in [`b32_address()`]. Returns `None` if
+/// `destination` is not a valid destination.
+pub(crate) fn public_destination(destination: &str) -> Option<String> {
+    decode_destination(destination).map(|destination| encode_base64(&destination))
+}
+
+/// Decode `destination` and strip any private keys that follow it.
+fn decode_destination(destination: &str) -> Option<Vec<u8>> {
+    let mut bytes = decode_base64(destination.trim())?;
+    let certificate_len = u16::from_be_bytes([
+        *bytes.get(DESTINATION_BASE_LEN - 2)?,
+        *bytes.get(DESTINATION_BASE_LEN - 1)?,
+    ]) as usize;
+
+    if bytes.len() < DESTINATION_BASE_LEN + certificate_len {
+        return None;
+    }
+    bytes.truncate(DESTINATION_BASE_LEN + certificate_len);
+
+    Some(bytes)
+}
+
+/// Check that `bytes` contain a destination followed by its private keys and encode them as I2P's
+/// base64, the form of the private key used in SAMv3.
+///
+/// This is the layout of `eepPriv.dat` files of Java I2P and of `.dat` key files of i2pd. If the
+/// signing private key is all zeros, the destination uses offline keys and the private keys are
+/// followed by the offline signature and the transient signing private key.
+///
+/// Returns the reason if `bytes` are not a valid private key.
+pub(crate) fn encode_private_key(bytes: &[u8]) -> Result<String, &'static str> {
+    let field = |offset: usize, len: usize| bytes.get(offset..offset + len).ok_or("truncated");
+
+    let certificate = field(DESTINATION_BASE_LEN - 3, 3)?;
+    let certificate_len = u16::from_be_bytes([certificate[1], certificate[2]]) as usize;
+
+    // destinations without a key certificate use DSA-SHA1 and ElGamal
+    let (signature_type, crypto_type) = match certificate[0] {
+        CERTIFICATE_TYPE_KEY => {
+            let payload = field(DESTINATION_BASE_LEN, 4)?;
+
+            (
+                u16::from_be_bytes([payload[0], payload[1]]),
+                u16::from_be_bytes([payload[2], payload[3]]),
+            )
+        }
+        _ => (0, 0),
+    };
+    let signature_type =
+        SignatureType::from_code(signature_type).ok_or("unsupported signature type")?;
+    let private_key_len = match crypto_type {
+        0 => 256,
+        1 | 4 => 32,
+        2 => 48,
+        3 => 66,
+        _ => return Err("unsupported encryption type"),
+    };
+
+    let offset = DESTINATION_BASE_LEN + certificate_len + private_key_len;
+    let signing_key = field(offset, signature_type.private_key_len())?;
+    let mut len = offset + signing_key.len();
+
+    if signing_key.iter().all(|byte| *byte == 0) {
+        let header = field(len, OFFLINE_HEADER_LEN)?;
+        let transient_type = SignatureType::from_code(u16::from_be_bytes([header[4], header[5]]))
+            .ok_or("unsupported transient signature type")?;
+
+        len += OFFLINE_HEADER_LEN
+            + transient_type.public_key_len()
+            + signature_type.signature_len()
+            + transient_type.private_key_len();
+        field(0, len)?;
+    }
+
+    if bytes.len() != len {
+        return Err("trailing data");
+    }
+
+    Ok(encode_base64(bytes))
+}
+
+/// Decode I2P's base64.
+pub(crate) fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut output = Vec::with_capacity(input.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0u32;
+
+    for byte in input {
+        let value = BASE64_ALPHABET.iter().position(|c| c == byte)? as u32;
+
+        buffer = (buffer << 6) | value;
+        bits += 6;
+
+        if bits >= 8 {
+            bits -= 8;
+            output.push((buffer >> bits) as u8);
+        }
+    }
+
+    Some(output)
+}
+
+/// Encode `input` as I2P's base64.
+pub(crate) fn encode_base64(input: &[u8]) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(3) * 4);
+
+    for chunk in input.chunks(3) {
+        let buffer = chunk.iter().enumerate().fold(0u32, |buffer, (i, byte)| {
+            buffer | (*byte as u32) << (16 - 8 * i)
+        });
+
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true =>
+                    output.push(BASE64_ALPHABET[((buffer >> (18 - 6 * i)) & 0x3f) as usize] as char),
+                false => output.push('='),
+            }
+        }
+    }
+
+    output
+}
+
+/// Encode `input` as lowercase base32 without padding.
+fn encode_base32(input: &[u8]) -> String {
+    let mut output = String::with_capacity(input.len().div_ceil(5) * 8);
+    let mut buffer = 0u32;
+    let mut bits = 0u32;
+
+    for byte in input {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+
+        while bits >= 5 {
+            bits -= 5;
+            output.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+
+    if bits > 0 {
+        output.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_encodings() {
+        assert_eq!(decode_base64("aGVsbG~~~v8=").unwrap(), b"hello\xff\xfe\xff");
+        assert_eq!(decode_base64("not base64!"), None);
+        assert_eq!(encode_base64(b"my secret"), "bXkgc2VjcmV0");
+        assert_eq!(encode_base64(b"\xfb\xff"), "-~8=");
+        assert_eq!(encode_base64(b"hello\xff\xfe\xff"), "aGVsbG~~~v8=");
+        assert_eq!(encode_base32(b"hello"), "nbswy3dp");
+        assert_eq!(encode_base32(b"hi"), "nbuq");
+    }
+
+    #[cfg(any(feature = "async", feature = "sync"))]
+    #[test]
+    fn split_ports() {
+        assert_eq!(split_port("service.i2p:8080"), ("service.i2p", Some(8080)));
+        assert_eq!(split_port("service.i2p"), ("service.i2p", None));
+        assert_eq!(split_port("service.i2p:http"), ("service.i2p:http", None));
+        assert_eq!(split_port("service.i2p:70000"), ("service.i2p:70000", None));
+        assert_eq!(split_port(":80"), (":80", None));
+        assert_eq!(
+            split_port("Hm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrk~AAAA"),
+            (
+                "Hm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrk~AAAA",
+                None
+            )
+        );
+    }
+
+    #[test]
+    fn b32_addresses() {
+        let destination = "Hm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8qKezDY9tzpuZg1GeEgZ3XFfnW0xyDVT6xXOunJCkwm6BQAEAAcAAA==";
+        let private_key = "Hm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8qKezDY9tzpuZg1GeEgZ3XFfnW0xyDVT6xXOunJCkwm6BQAEAAcAAMNzXkLePD4~I6GznxqM7VfS6vgXDT-tXljN6Q4aheFVwcOMZoklUKjlZtFxqe~jIBJRX6dp2LfYQPP7m7sp7kcJ8cpTnauhVsV6XH4x7eeHKPdLFGKKxwhb0N-x9Vu3-44L75nd~79rFvQBJe4-QkR7Iendzx7eMtLF7PEnniN9KJiDJCIwL-GRNcW-Vxo8WiRapRx0O3RlNqG8BLGbgCpsnM73Y3hyxSxYS3wpwWbPAKo9-FSnP96j75xl2hoalXvfOaqRiGyF0POKYbHGxlEWDuLPkQaXMQk7mzAvumgNyRIpugQO73mrmNVq64SeEEf21F9K5TKZo-Wv7HVVImpBavK0P4wcf~F2tSG0ovVP97b8zyiEc04eljzYDCt3tQ==";
+        let address = "kkm3xuyu5qehz4sir7bekuoibglcc6ewjanoqzszwfipcuou5eaq.b32.i2p";
+
+        assert_eq!(b32_address(destination).as_deref(), Some(address));
+        assert_eq!(b32_address(private_key).as_deref(), Some(address));
+        assert_eq!(
+            public_destination(private_key).as_deref(),
+            Some(destination)
+        );
+        assert_eq!(
+            public_destination(destination).as_deref(),
+            Some(destination)
+        );
+
+        // destination is truncated
+        assert_eq!(b32_address(&destination[..400]), None);
+        assert_eq!(b32_address("host.i2p"), None);
+    }
+
+    #[test]
+    fn blinded_b32_addresses() {
+        let destination = "Hm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8h5uuG3fuEHGHvEToJj2mOht-uSPN513S5qc8-qKHWXyHm64bd-4QcYe8ROgmPaY6G365I83nXdLmpzz6oodZfIebrht37hBxh7xE6CY9pjobfrkjzedd0uanPPqih1l8qKezDY9tzpuZg1GeEgZ3XFfnW0xyDVT6xXOunJCkwm6BQAEAAcAAA==";
+        let mut options = SessionOptions {
+            lease_set_type: LeaseSetType::Encrypted {
+                secret: None,
+                blinded_signature_type: SignatureType::RedDsaSha512Ed25519,
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(
+            blinded_b32_address(destination, &options).as_deref(),
+            Some("ggjlriu6zq3d3nz2nzta2rtyjam524k7tvwtdsbvkpvrltv2ojbjgcn2.b32.i2p")
+        );
+
+        options.lease_set_type = LeaseSetType::Encrypted {
+            secret: Some("secret".to_string()),
+            blinded_signature_type: SignatureType::RedDsaSha512Ed25519,
+        };
+        assert_eq!(
+            blinded_b32_address(destination, &options).as_deref(),
+            Some("gojlriu6zq3d3nz2nzta2rtyjam524k7tvwtdsbvkpvrltv2ojbjgcn2.b32.i2p")
+        );
+
+        options.lease_set_auth = Some(LeaseSetAuth::DhClients(vec![(
+            "alice".to_string(),
+            "key".to_string(),
+        )]));
+        assert_eq!(
+            blinded_b32_address(destination, &options).as_deref(),
+            Some("g6jlriu6zq3d3nz2nzta2rtyjam524k7tvwtdsbvkpvrltv2ojbjgcn2.b32.i2p")
+        );
+
+        // lease set is not encrypted
+        assert_eq!(blinded_b32_address(destination, &Default::default()), None);
+    }
+}
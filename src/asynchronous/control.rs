@@ -0,0 +1,253 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+#![cfg(feature = "async")]
+
+use crate::{
+    asynchronous::events::Events,
+    error::Error,
+    events::SessionEvent,
+    interceptor::SharedInterceptor,
+    options::{SessionOptions, DEFAULT_CONTROL_BUFFER_SIZE, DEFAULT_RESPONSE_BUFFER_SIZE},
+    proto::{
+        command::{Command, CommandWriter, Pong},
+        line::LineBuffer,
+        parser::Response,
+    },
+    status::SocketAddrs,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+
+use std::fmt;
+
+/// Maximum length of a response line.
+const MAX_RESPONSE_LEN: usize = 64 * 1024;
+
+/// Control socket to the SAMv3 server.
+///
+/// The socket is read through a single `BufReader` for its entire lifetime so that any bytes the
+/// router sent after a response line stay buffered and are not lost, and the response buffer is
+/// reused between reads.
+pub(crate) struct ControlSocket {
+    /// Buffered TCP stream.
+    stream: BufReader<TcpStream>,
+
+    /// Response buffer.
+    response: LineBuffer,
+
+    /// Session events, if the socket belongs to a session.
+    events: Option<Events>,
+
+    /// Interceptor for commands and responses, if configured.
+    interceptor: Option<SharedInterceptor>,
+
+    /// Should the wire trace be redacted.
+    #[cfg(feature = "wire-trace")]
+    redact_wire_trace: bool,
+}
+
+impl fmt::Debug for ControlSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ControlSocket")
+            .field("local_addr", &self.stream.get_ref().local_addr().ok())
+            .field("peer_addr", &self.stream.get_ref().peer_addr().ok())
+            .finish_non_exhaustive()
+    }
+}
+
+impl ControlSocket {
+    /// Connect to the SAMv3 server listening on `port` using default buffer sizes.
+    pub(crate) async fn connect(port: u16) -> crate::Result<Self> {
+        Self::connect_with_capacity(
+            port,
+            DEFAULT_CONTROL_BUFFER_SIZE,
+            DEFAULT_RESPONSE_BUFFER_SIZE,
+        )
+        .await
+    }
+
+    /// Connect to the SAMv3 server using the port and buffer sizes of `options`.
+    pub(crate) async fn from_options(options: &SessionOptions) -> crate::Result<Self> {
+        let mut socket = Self::connect_with_capacity(
+            options.samv3_tcp_port,
+            options.control_buffer_size,
+            options.response_buffer_size,
+        )
+        .await?;
+        socket.interceptor = options.interceptor.clone();
+
+        #[cfg(feature = "wire-trace")]
+        {
+            socket.redact_wire_trace = options.redact_wire_trace;
+        }
+
+        Ok(socket)
+    }
+
+    /// Connect to the SAMv3 server listening on `port`.
+    ///
+    /// The read buffer must be non-empty so it's at least one byte.
+    async fn connect_with_capacity(
+        port: u16,
+        read_capacity: usize,
+        response_capacity: usize,
+    ) -> crate::Result<Self> {
+        Ok(Self {
+            stream: BufReader::with_capacity(
+                read_capacity.max(1),
+                TcpStream::connect(format!("127.0.0.1:{port}")).await?,
+            ),
+            response: LineBuffer::new(response_capacity, MAX_RESPONSE_LEN),
+            events: None,
+            interceptor: None,
+            #[cfg(feature = "wire-trace")]
+            redact_wire_trace: true,
+        })
+    }
+
+    /// Emit messages the router sends outside of replies as session events.
+    pub(crate) fn with_events(mut self, events: Events) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Get local and peer address of the socket.
+    pub(crate) fn socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        Ok(SocketAddrs {
+            local: self.stream.get_ref().local_addr()?,
+            peer: self.stream.get_ref().peer_addr()?,
+        })
+    }
+
+    /// Pass `command` to the wire trace and the interceptor before it's sent.
+    fn observe_command(&self, command: &[u8]) {
+        #[cfg(feature = "wire-trace")]
+        crate::proto::trace::trace_command(command, self.redact_wire_trace);
+
+        if let Some(interceptor) = &self.interceptor {
+            interceptor.on_command(command);
+        }
+    }
+
+    /// Send `command` to the router.
+    pub(crate) async fn write_command(&mut self, command: &[u8]) -> crate::Result<()> {
+        self.observe_command(command);
+
+        self.stream.write_all(command).await.map_err(From::from)
+    }
+
+    /// Close the socket and wait until the router has closed its end of the connection.
+    ///
+    /// Anything the router sends before closing the connection is discarded. Errors are ignored
+    /// since they mean that the connection is already closed.
+    pub(crate) async fn close(&mut self) {
+        if self.stream.get_mut().shutdown().await.is_err() {
+            return;
+        }
+
+        while let Ok(input) = self.stream.fill_buf().await {
+            if input.is_empty() {
+                return;
+            }
+
+            let consumed = input.len();
+            self.stream.consume(consumed);
+        }
+    }
+
+    /// Read a single response line from the router.
+    ///
+    /// The line is returned without its terminator, which can be either `\n` or `\r\n`.
+    ///
+    /// Messages which the router may send at any time are not returned: `PING` is answered with
+    /// `PONG` and both are emitted as session events if the socket belongs to a session.
+    ///
+    /// Fails with `UnexpectedEof` if the router has closed the socket and with
+    /// [`Error::ResponseTooLong`] if the line is longer than [`MAX_RESPONSE_LEN`].
+    pub(crate) async fn read_response(&mut self) -> crate::Result<&str> {
+        loop {
+            self.read_line().await?;
+
+            #[cfg(feature = "wire-trace")]
+            crate::proto::trace::trace_response(
+                self.response.line().unwrap_or(""),
+                self.redact_wire_trace,
+            );
+
+            if let (Some(interceptor), Some(line)) = (&self.interceptor, self.response.line()) {
+                interceptor.on_response(line);
+            }
+
+            let event = match Response::parse_unsolicited(self.response.line().unwrap_or("")) {
+                Some(Response::Ping { data }) => SessionEvent::Ping {
+                    data: data.map(ToOwned::to_owned),
+                },
+                Some(Response::Pong { data }) => SessionEvent::Pong {
+                    data: data.map(ToOwned::to_owned),
+                },
+                _ => break,
+            };
+
+            if let SessionEvent::Ping { data } = &event {
+                let mut writer = CommandWriter::new();
+                self.write_command(
+                    Pong {
+                        data: data.as_deref(),
+                    }
+                    .write(&mut writer),
+                )
+                .await?;
+            }
+
+            if let Some(events) = &self.events {
+                events.emit(event);
+            }
+        }
+
+        self.response.line().ok_or(Error::Malformed)
+    }
+
+    /// Read a single line from the router into the response buffer.
+    async fn read_line(&mut self) -> crate::Result<()> {
+        self.response.clear();
+
+        while !self.response.is_complete() {
+            let input = self.stream.fill_buf().await?;
+
+            if input.is_empty() {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
+            }
+
+            let consumed = self.response.push(input)?;
+            self.stream.consume(consumed);
+        }
+
+        Ok(())
+    }
+
+    /// Consume `self` and return the underlying reader.
+    ///
+    /// Any bytes buffered after the last response line can be read from the returned reader.
+    pub(crate) fn into_inner(self) -> BufReader<TcpStream> {
+        self.stream
+    }
+}
@@ -0,0 +1,236 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Admission control of inbound streams and datagrams.
+
+use crate::tracing;
+
+use futures::future::BoxFuture;
+
+use std::{
+    fmt,
+    future::Future,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::gate";
+
+/// Decision of a gate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GateDecision {
+    /// Hand the stream or datagram to the application.
+    Allow,
+
+    /// Close the stream or drop the datagram.
+    Deny,
+}
+
+/// Ports of an inbound stream or datagram.
+///
+/// Ports are `0` if the sender didn't specify them or the router doesn't report them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Ports {
+    /// Port the stream or datagram was sent from.
+    pub from_port: u16,
+
+    /// Port the stream or datagram was sent to.
+    pub to_port: u16,
+}
+
+impl Ports {
+    /// Parse ports from the options following the destination in a stream or datagram header.
+    pub(crate) fn parse(options: &str) -> Self {
+        let port = |key: &str| {
+            options
+                .split(' ')
+                .find_map(|option| option.strip_prefix(key))
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(0u16)
+        };
+
+        Self {
+            from_port: port("FROM_PORT="),
+            to_port: port("TO_PORT="),
+        }
+    }
+
+    /// Parse ports from the `header` of a repliable datagram.
+    pub(crate) fn from_datagram_header(header: &[u8]) -> Self {
+        let header = std::str::from_utf8(header).unwrap_or_default().trim_end();
+
+        Self::parse(header.split_once(' ').map_or("", |(_, options)| options))
+    }
+}
+
+/// Gate callback.
+type GateFn = dyn Fn(String, Ports) -> BoxFuture<'static, GateDecision> + Send + Sync;
+
+/// Gate of a session.
+///
+/// Shared by the session and its background tasks so a gate set after the tasks have been
+/// started is used by them too.
+#[derive(Clone, Default)]
+pub(crate) struct Gate {
+    /// Gate callback, if set.
+    gate: Arc<Mutex<Option<Arc<GateFn>>>>,
+}
+
+impl fmt::Debug for Gate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Gate").finish_non_exhaustive()
+    }
+}
+
+impl Gate {
+    /// Set `gate`, replacing any previously set gate.
+    pub(crate) fn set<F, Fut>(&self, gate: F)
+    where
+        F: Fn(String, Ports) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = GateDecision> + Send + 'static,
+    {
+        let gate: Arc<GateFn> =
+            Arc::new(move |destination, ports| Box::pin(gate(destination, ports)));
+        *self.gate.lock().unwrap_or_else(PoisonError::into_inner) = Some(gate);
+    }
+
+    /// Should a stream or datagram from `destination` be admitted.
+    ///
+    /// Everything is admitted if no gate has been set.
+    pub(crate) async fn admits(&self, destination: &str, ports: Ports) -> bool {
+        let Some(gate) = self.gate.lock().unwrap_or_else(PoisonError::into_inner).clone() else {
+            return true;
+        };
+
+        match gate(destination.to_string(), ports).await {
+            GateDecision::Allow => true,
+            GateDecision::Deny => {
+                tracing::debug!(
+                    target: LOG_TARGET,
+                    ?ports,
+                    "denied by gate",
+                );
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        style,
+        testing::{stream_pair, with_new_nickname, MockSam},
+        Session,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn parse_ports() {
+        assert_eq!(
+            Ports::parse("FROM_PORT=1234 TO_PORT=80"),
+            Ports {
+                from_port: 1234,
+                to_port: 80
+            }
+        );
+        assert_eq!(Ports::parse(""), Ports::default());
+        assert_eq!(
+            Ports::from_datagram_header(b"destination TO_PORT=53\n").to_port,
+            53
+        );
+        assert_eq!(Ports::parse("TO_PORT=invalid").to_port, 0);
+    }
+
+    #[tokio::test]
+    async fn gate_replaced() {
+        let gate = Gate::default();
+        assert!(gate.admits("destination", Ports::default()).await);
+
+        gate.set(|_, ports: Ports| async move {
+            match ports.to_port {
+                22 => GateDecision::Deny,
+                _ => GateDecision::Allow,
+            }
+        });
+        let ssh = Ports {
+            from_port: 0,
+            to_port: 22,
+        };
+        assert!(!gate.admits("destination", ssh).await);
+        assert!(gate.clone().admits("destination", Ports::default()).await);
+
+        gate.set(|_, _| async { GateDecision::Allow });
+        assert!(gate.admits("destination", ssh).await);
+    }
+
+    #[tokio::test]
+    async fn gate() {
+        let sam = MockSam::new().unwrap();
+        let mut pair = stream_pair(sam.session_options()).await.unwrap();
+        let denied = pair.client.public_destination().to_string();
+        pair.server.set_gate(move |destination, _| {
+            let decision = match destination == denied {
+                true => crate::GateDecision::Deny,
+                false => crate::GateDecision::Allow,
+            };
+            async move { decision }
+        });
+
+        let mut allowed = Session::<style::Stream>::new(with_new_nickname(&sam.session_options()))
+            .await
+            .unwrap();
+        let destination = pair.server.public_destination().to_string();
+        let (stream, _, _) = futures::future::try_join3(
+            pair.server.accept(),
+            pair.client.connect(&destination),
+            async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                allowed.connect(&destination).await
+            },
+        )
+        .await
+        .unwrap();
+        assert_eq!(stream.remote_destination(), allowed.public_destination());
+        assert_eq!(pair.server.metrics_snapshot().streams_rejected, 1);
+
+        // datagrams are gated by their ports
+        let mut session = sam.session::<style::Repliable>().await.unwrap();
+        session.set_gate(|_, ports| async move {
+            match ports.to_port {
+                22 => crate::GateDecision::Deny,
+                _ => crate::GateDecision::Allow,
+            }
+        });
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        for (port, payload) in [(22, "denied"), (80, "allowed")] {
+            let datagram = format!(
+                "3.0 {} {} TO_PORT={port}\n{payload}",
+                pair.client.nickname(),
+                session.public_destination()
+            );
+            socket.send_to(datagram.as_bytes(), ("127.0.0.1", sam.udp_port())).unwrap();
+        }
+
+        let mut buffer = [0u8; 64];
+        let (nread, _) = session.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..nread], b"allowed");
+    }
+}
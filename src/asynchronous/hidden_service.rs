@@ -0,0 +1,264 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Hidden service hosting.
+
+use crate::{
+    address::b32_address,
+    asynchronous::{
+        router::RouterApi,
+        session::{style, Session},
+    },
+    error::Error,
+    options::{DestinationKind, SessionOptions},
+    tracing,
+};
+
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::hidden_service";
+
+/// ## Hidden service.
+///
+/// `HiddenService` bundles the steps of hosting a TCP service on I2P: loading or creating the
+/// keys of a persistent destination, creating a published session and forwarding inbound streams
+/// to the service. The service stays reachable for as long as the `HiddenService` is alive.
+///
+/// ```no_run
+/// use yosemite::HiddenService;
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let service = HiddenService::builder()
+///         .with_key_file("service.key")
+///         .with_forward_port(8080)
+///         .build()
+///         .await?;
+///
+///     println!("hosting at {}", service.b32_address());
+///     # Ok(())
+/// }
+/// ```
+pub struct HiddenService {
+    /// Session of the service.
+    session: Session<style::Stream>,
+
+    /// `.b32.i2p` address of the service.
+    b32_address: String,
+}
+
+impl HiddenService {
+    /// Create new [`HiddenServiceBuilder`].
+    pub fn builder() -> HiddenServiceBuilder {
+        HiddenServiceBuilder::default()
+    }
+
+    /// Get destination of the service.
+    pub fn destination(&self) -> &str {
+        self.session.destination()
+    }
+
+    /// Get `.b32.i2p` address of the service.
+    pub fn b32_address(&self) -> &str {
+        &self.b32_address
+    }
+
+    /// Get reference to the session of the service.
+    pub fn session(&self) -> &Session<style::Stream> {
+        &self.session
+    }
+}
+
+/// Builder for [`HiddenService`].
+#[derive(Default)]
+pub struct HiddenServiceBuilder {
+    /// Session options.
+    options: SessionOptions,
+
+    /// Path to the file holding the private key of the destination.
+    key_file: Option<PathBuf>,
+
+    /// Port of the TCP listener of the service.
+    forward_port: Option<u16>,
+}
+
+impl HiddenServiceBuilder {
+    /// Create the session from `options`.
+    ///
+    /// The session is always published and forwarded streams are always silent, i.e., the service
+    /// receives only the data of the stream. `destination` is overridden if a key file is set.
+    pub fn with_options(mut self, options: SessionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Load the private key of the destination from `path`.
+    ///
+    /// If the file doesn't exist, a new destination is generated and its private key is written to
+    /// `path`, so the service keeps its address across restarts. Without a key file, the
+    /// destination of [`HiddenServiceBuilder::with_options()`] is used.
+    pub fn with_key_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.key_file = Some(path.into());
+        self
+    }
+
+    /// Forward inbound streams to a TCP listener at `port`.
+    ///
+    /// Required.
+    pub fn with_forward_port(mut self, port: u16) -> Self {
+        self.forward_port = Some(port);
+        self
+    }
+
+    /// Create the session and start forwarding inbound streams.
+    pub async fn build(self) -> crate::Result<HiddenService> {
+        let Some(port) = self.forward_port else {
+            return Err(Error::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "forward port not set",
+            )));
+        };
+
+        let mut options = SessionOptions {
+            publish: true,
+            silent_forward: true,
+            ..self.options
+        };
+
+        if let Some(path) = &self.key_file {
+            options.destination = DestinationKind::Persistent {
+                private_key: load_or_generate_key(path, options.samv3_tcp_port).await?,
+            };
+        }
+
+        let mut session = Session::<style::Stream>::new(options).await?;
+        let b32_address = b32_address(session.destination()).ok_or(Error::Malformed)?;
+        session.forward(port).await?;
+
+        tracing::info!(
+            target: LOG_TARGET,
+            %b32_address,
+            ?port,
+            "hidden service started",
+        );
+
+        Ok(HiddenService {
+            session,
+            b32_address,
+        })
+    }
+}
+
+/// Read private key from `path` or, if the file doesn't exist, generate a new destination and
+/// store its private key to `path`.
+async fn load_or_generate_key(path: &Path, samv3_tcp_port: u16) -> crate::Result<String> {
+    match fs::read_to_string(path) {
+        Ok(private_key) => {
+            let private_key = private_key.trim();
+
+            if private_key.is_empty() {
+                return Err(Error::IoError(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "key file is empty",
+                )));
+            }
+
+            Ok(private_key.to_string())
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            let (_, private_key) = RouterApi::new(samv3_tcp_port).generate_destination().await?;
+            write_key_file(path, &private_key)?;
+
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?path,
+                "generated new destination for hidden service",
+            );
+
+            Ok(private_key)
+        }
+        Err(error) => Err(Error::IoError(error)),
+    }
+}
+
+/// Write `private_key` to a new file at `path`, readable only by the owner on Unix.
+fn write_key_file(path: &Path, private_key: &str) -> io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    io::Write::write_all(&mut options.open(path)?, private_key.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{style, testing::MockSam};
+    use futures::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn hidden_service() {
+        let sam = MockSam::new().unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let key_file =
+            std::env::temp_dir().join(format!("yosemite-{}.key", sam.session_options().nickname));
+
+        let service = crate::HiddenService::builder()
+            .with_options(sam.session_options())
+            .with_key_file(&key_file)
+            .with_forward_port(port)
+            .build()
+            .await
+            .unwrap();
+        assert!(service.b32_address().ends_with(".b32.i2p"));
+        assert_eq!(
+            std::fs::read_to_string(&key_file).unwrap(),
+            service.destination()
+        );
+
+        let mut client = sam.session::<style::Stream>().await.unwrap();
+        let mut stream = client.connect(service.session().public_destination()).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+
+        let (mut forwarded, _) = listener.accept().await.unwrap();
+        let mut buffer = [0u8; 5];
+        tokio::io::AsyncReadExt::read_exact(&mut forwarded, &mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        // the key is loaded from the key file when the service is restarted
+        let b32_address = service.b32_address().to_string();
+        drop(service);
+
+        let service = crate::HiddenService::builder()
+            .with_options(sam.session_options())
+            .with_key_file(&key_file)
+            .with_forward_port(port)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(service.b32_address(), b32_address);
+
+        std::fs::remove_file(key_file).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Management of multiple destinations.
+
+use crate::{
+    asynchronous::session::{style::SessionStyle, Session},
+    options::SessionOptions,
+    redaction::RedactedValue,
+    tracing,
+};
+
+use futures::lock::Mutex;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex as SyncMutex, PoisonError},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::manager";
+
+/// Session shared between the manager and its users.
+pub type SharedSession<S> = Arc<Mutex<Session<S>>>;
+
+/// Registered destination.
+struct Entry<S: SessionStyle> {
+    /// Options used to create the session.
+    options: SessionOptions,
+
+    /// Session of the destination, if it has been created.
+    ///
+    /// The lock is held while the session is created so concurrent lookups of the same name
+    /// create only one session.
+    session: Arc<Mutex<Option<SharedSession<S>>>>,
+}
+
+/// ## Destination manager.
+///
+/// `DestinationManager` owns the sessions of several destinations, each registered under a name
+/// with options of its own, e.g., one persistent destination per tenant of a gateway. Sessions are
+/// created lazily when a name is first looked up with [`DestinationManager::session()`] and shared
+/// with every caller that looks up the same name.
+///
+/// The manager can be shared between tasks, all methods take `&self`.
+///
+/// ```no_run
+/// use yosemite::{manager::DestinationManager, style::Stream, DestinationKind, SessionOptions};
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let manager = DestinationManager::<Stream>::new();
+///
+///     manager.register(
+///         "alice",
+///         SessionOptions {
+///             nickname: "alice".to_string(),
+///             destination: DestinationKind::Persistent {
+///                 private_key: std::fs::read_to_string("alice.key")?,
+///             },
+///             ..Default::default()
+///         },
+///     );
+///
+///     if let Some(session) = manager.session("alice").await? {
+///         let stream = session.lock().await.connect("host.i2p").await?;
+///     }
+///
+///     manager.shutdown();
+///     Ok(())
+/// }
+/// ```
+pub struct DestinationManager<S: SessionStyle> {
+    /// Registered destinations.
+    entries: SyncMutex<HashMap<String, Entry<S>>>,
+}
+
+impl<S: SessionStyle> Default for DestinationManager<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: SessionStyle> DestinationManager<S> {
+    /// Create new [`DestinationManager`].
+    pub fn new() -> Self {
+        Self {
+            entries: SyncMutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register destination `name` which is created from `options`.
+    ///
+    /// The session is not created until `name` is looked up. Each destination must have a
+    /// nickname of its own.
+    ///
+    /// If `name` was already registered, its previous options are returned and its session, if
+    /// any, is dropped by the manager.
+    pub fn register(
+        &self,
+        name: impl Into<String>,
+        options: SessionOptions,
+    ) -> Option<SessionOptions> {
+        let entry = Entry {
+            options,
+            session: Arc::new(Mutex::new(None)),
+        };
+
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(name.into(), entry)
+            .map(|entry| entry.options)
+    }
+
+    /// Get the session of destination `name`, creating it if it doesn't exist yet.
+    ///
+    /// Returns `Ok(None)` if `name` hasn't been registered. If the session cannot be created, the
+    /// error is returned and the session is created again on the next lookup.
+    pub async fn session(&self, name: &str) -> crate::Result<Option<SharedSession<S>>> {
+        let Some((options, slot)) = self
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .map(|entry| (entry.options.clone(), Arc::clone(&entry.session)))
+        else {
+            return Ok(None);
+        };
+
+        let mut slot = slot.lock().await;
+
+        if let Some(session) = &*slot {
+            return Ok(Some(Arc::clone(session)));
+        }
+
+        let session = Session::new(options).await?;
+        tracing::info!(
+            target: LOG_TARGET,
+            %name,
+            destination = %RedactedValue(session.destination()),
+            "destination created",
+        );
+
+        let session = Arc::new(Mutex::new(session));
+        *slot = Some(Arc::clone(&session));
+
+        Ok(Some(session))
+    }
+
+    /// Get names of the registered destinations.
+    pub fn names(&self) -> Vec<String> {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .keys()
+            .cloned()
+            .collect()
+    }
+
+    /// Has the session of destination `name` been created.
+    pub fn is_active(&self, name: &str) -> bool {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(name)
+            .and_then(|entry| entry.session.try_lock().map(|session| session.is_some()))
+            .unwrap_or(false)
+    }
+
+    /// Unregister destination `name`.
+    ///
+    /// The session, if any, is dropped by the manager and closed once all other handles to it
+    /// have been dropped. Returns the options of the destination if it was registered.
+    pub fn remove(&self, name: &str) -> Option<SessionOptions> {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(name)
+            .map(|entry| entry.options)
+    }
+
+    /// Unregister all destinations and drop their sessions.
+    ///
+    /// Each session is closed once all other handles to it have been dropped. Destinations can be
+    /// registered again after the shutdown.
+    pub fn shutdown(&self) {
+        let entries =
+            std::mem::take(&mut *self.entries.lock().unwrap_or_else(PoisonError::into_inner));
+
+        tracing::debug!(
+            target: LOG_TARGET,
+            num_destinations = entries.len(),
+            "shutting down destinations",
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        style,
+        testing::{with_new_nickname, MockSam},
+    };
+
+    #[tokio::test]
+    async fn destination_manager() {
+        let sam = MockSam::new().unwrap();
+        let manager = crate::manager::DestinationManager::<style::Stream>::new();
+
+        assert!(manager.register("alice", with_new_nickname(&sam.session_options())).is_none());
+        assert!(manager.register("bob", with_new_nickname(&sam.session_options())).is_none());
+        assert!(!manager.is_active("alice"));
+        assert!(manager.session("carol").await.unwrap().is_none());
+
+        let alice = manager.session("alice").await.unwrap().unwrap();
+        let bob = manager.session("bob").await.unwrap().unwrap();
+        assert!(manager.is_active("alice"));
+        assert!(Arc::ptr_eq(
+            &alice,
+            &manager.session("alice").await.unwrap().unwrap()
+        ));
+
+        assert_ne!(
+            alice.lock().await.destination(),
+            bob.lock().await.destination()
+        );
+
+        let mut names = manager.names();
+        names.sort();
+        assert_eq!(names, ["alice", "bob"]);
+
+        assert!(manager.remove("alice").is_some());
+        assert!(manager.session("alice").await.unwrap().is_none());
+
+        manager.shutdown();
+        assert!(manager.names().is_empty());
+        assert!(manager.session("bob").await.unwrap().is_none());
+    }
+}
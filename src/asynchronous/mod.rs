@@ -18,18 +18,20 @@
 
 #![cfg(feature = "async")]
 
-macro_rules! read_response {
-    ($stream:expr) => {{
-        use tokio::io::AsyncBufReadExt;
-
-        let mut reader = tokio::io::BufReader::new($stream);
-        let mut response = String::new();
-        reader.read_line(&mut response).await?;
-
-        (reader.into_inner(), response)
-    }};
-}
-
+mod control;
+mod events;
+pub(crate) mod gate;
+pub mod hidden_service;
+pub mod manager;
+#[cfg(feature = "yamux")]
+pub mod mux;
+pub mod proxy;
+pub mod rotation;
 pub mod router;
+#[cfg(feature = "router-control")]
+pub mod router_control;
 pub mod session;
 pub mod stream;
+pub mod tunnel;
+#[cfg(feature = "serde")]
+pub mod typed;
@@ -0,0 +1,299 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Stream multiplexing with yamux.
+
+use crate::{asynchronous::stream::Stream, error::Error, tracing};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    future::poll_fn,
+    AsyncRead, AsyncWrite, StreamExt,
+};
+use yamux::{Config, Connection, ConnectionError, Mode};
+
+use std::{
+    collections::VecDeque,
+    fmt, io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::mux";
+
+/// Number of inbound substreams queued for [`Multiplexer::accept()`] before further substreams are
+/// reset.
+const ACCEPT_BACKLOG: usize = 64;
+
+/// Request to open an outbound substream.
+type OpenRequest = oneshot::Sender<Result<yamux::Stream, ConnectionError>>;
+
+/// Convert yamux error into [`Error`].
+fn into_error(error: ConnectionError) -> Error {
+    match error {
+        ConnectionError::Io(error) => Error::IoError(error),
+        error => Error::IoError(io::Error::other(error)),
+    }
+}
+
+/// Error returned when the multiplexed connection has been closed.
+fn closed() -> Error {
+    Error::IoError(io::ErrorKind::BrokenPipe.into())
+}
+
+/// ## Stream multiplexer.
+///
+/// `Multiplexer` runs [yamux](https://github.com/hashicorp/yamux/blob/master/spec.md) over one
+/// virtual [`Stream`] so that any number of logical substreams can be opened to the remote
+/// destination without the tunnel round trips of opening a new virtual stream for each of them.
+///
+/// The side which opened the virtual stream creates the multiplexer with
+/// [`Multiplexer::client()`] and the side which accepted it with [`Multiplexer::server()`]. Both
+/// sides can open substreams with [`Multiplexer::open()`] and accept the substreams opened by the
+/// remote side with [`Multiplexer::accept()`]. The connection is driven by a background task and
+/// it's closed, along with all of its substreams, when the multiplexer is dropped.
+///
+/// The module is available with the `yamux` feature.
+///
+/// ```no_run
+/// use futures::AsyncWriteExt;
+/// use yosemite::{mux::Multiplexer, style::Stream, Session};
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let mut session = Session::<Stream>::new(Default::default()).await?;
+///     let multiplexer = Multiplexer::client(session.connect("host.i2p").await?);
+///
+///     let mut control = multiplexer.open().await?;
+///     let mut data = multiplexer.open().await?;
+///
+///     control.write_all(b"hello").await?;
+///     data.write_all(b"world").await?;
+///
+///     Ok(())
+/// }
+/// ```
+pub struct Multiplexer {
+    /// Remote destination.
+    remote_destination: String,
+
+    /// Requests to open outbound substreams.
+    requests: mpsc::UnboundedSender<OpenRequest>,
+
+    /// Inbound substreams.
+    inbound: mpsc::Receiver<yamux::Stream>,
+}
+
+impl fmt::Debug for Multiplexer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Multiplexer").finish_non_exhaustive()
+    }
+}
+
+impl Multiplexer {
+    /// Create new [`Multiplexer`] for an outbound virtual `stream`.
+    pub fn client(stream: Stream) -> Self {
+        Self::new(stream, Mode::Client)
+    }
+
+    /// Create new [`Multiplexer`] for an inbound virtual `stream`.
+    pub fn server(stream: Stream) -> Self {
+        Self::new(stream, Mode::Server)
+    }
+
+    /// Create new [`Multiplexer`] and start driving the connection.
+    fn new(stream: Stream, mode: Mode) -> Self {
+        let remote_destination = stream.remote_destination().to_string();
+        let connection = Connection::new(stream, Config::default(), mode);
+        let (requests_tx, requests) = mpsc::unbounded();
+        let (inbound_tx, inbound) = mpsc::channel(ACCEPT_BACKLOG);
+
+        tokio::spawn(drive(connection, requests, inbound_tx));
+
+        Self {
+            remote_destination,
+            requests: requests_tx,
+            inbound,
+        }
+    }
+
+    /// Get reference to the remote destination of the virtual stream.
+    pub fn remote_destination(&self) -> &str {
+        &self.remote_destination
+    }
+
+    /// Open substream to the remote destination.
+    ///
+    /// The remote destination is notified of the substream when data is first written to it.
+    pub async fn open(&self) -> crate::Result<Substream> {
+        let (tx, rx) = oneshot::channel();
+        self.requests.unbounded_send(tx).map_err(|_| closed())?;
+
+        match rx.await {
+            Ok(Ok(stream)) => Ok(Substream(stream)),
+            Ok(Err(error)) => Err(into_error(error)),
+            Err(_) => Err(closed()),
+        }
+    }
+
+    /// Accept substream opened by the remote destination.
+    ///
+    /// Fails once the connection has been closed.
+    pub async fn accept(&mut self) -> crate::Result<Substream> {
+        self.inbound.next().await.map(Substream).ok_or_else(closed)
+    }
+}
+
+/// Drive `connection` until it's closed or the multiplexer is dropped.
+async fn drive(
+    mut connection: Connection<Stream>,
+    mut requests: mpsc::UnboundedReceiver<OpenRequest>,
+    mut inbound: mpsc::Sender<yamux::Stream>,
+) {
+    let mut pending = VecDeque::<OpenRequest>::new();
+
+    let result = poll_fn(|cx| {
+        loop {
+            match requests.poll_next_unpin(cx) {
+                Poll::Ready(Some(request)) => pending.push_back(request),
+                // multiplexer has been dropped
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => break,
+            }
+        }
+
+        while !pending.is_empty() {
+            match connection.poll_new_outbound(cx) {
+                Poll::Ready(result) => {
+                    let failed = result.is_err();
+                    if let Some(request) = pending.pop_front() {
+                        let _ = request.send(result);
+                    }
+
+                    if failed {
+                        break;
+                    }
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        // polling inbound substreams drives the I/O of the connection
+        loop {
+            match connection.poll_next_inbound(cx) {
+                Poll::Ready(Some(Ok(stream))) =>
+                    if inbound.try_send(stream).is_err() {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            "accept backlog full, resetting inbound substream",
+                        );
+                    },
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Err(error)),
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    })
+    .await;
+
+    match result {
+        Ok(()) => {
+            let _ = poll_fn(|cx| connection.poll_close(cx)).await;
+        }
+        Err(error) => tracing::debug!(
+            target: LOG_TARGET,
+            ?error,
+            "multiplexed connection failed",
+        ),
+    }
+    inbound.close_channel();
+}
+
+/// Logical substream of a [`Multiplexer`].
+pub struct Substream(yamux::Stream);
+
+impl fmt::Debug for Substream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Substream").field("id", &self.0.id().val()).finish()
+    }
+}
+
+impl AsyncRead for Substream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Substream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{stream_pair, MockSam};
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[cfg(feature = "yamux")]
+    #[tokio::test]
+    async fn multiplexed_streams() {
+        use crate::mux::Multiplexer;
+
+        let sam = MockSam::new().unwrap();
+        let pair = stream_pair(sam.session_options()).await.unwrap();
+        let client = Multiplexer::client(pair.client_stream);
+        let mut server = Multiplexer::server(pair.server_stream);
+
+        let mut first = client.open().await.unwrap();
+        let mut second = client.open().await.unwrap();
+        first.write_all(b"first").await.unwrap();
+        second.write_all(b"second").await.unwrap();
+
+        // substreams are announced to the remote with their first frame
+        let mut buffer = [0u8; 6];
+        let mut inbound = server.accept().await.unwrap();
+        inbound.read_exact(&mut buffer[..5]).await.unwrap();
+        assert_eq!(&buffer[..5], b"first");
+
+        let mut inbound = server.accept().await.unwrap();
+        inbound.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"second");
+
+        // closing the client closes the connection
+        drop(client);
+        assert!(server.accept().await.is_err());
+    }
+}
@@ -0,0 +1,370 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! SOCKS5 proxy frontend.
+
+use crate::{
+    asynchronous::session::{style, Session, SessionHandle},
+    error::{Error, I2pError, ProtocolError},
+    redaction::RedactedValue,
+    tracing,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use std::{io, net::SocketAddr};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::proxy";
+
+/// SOCKS protocol version.
+const SOCKS_VERSION: u8 = 0x05;
+
+/// `NO AUTHENTICATION REQUIRED` method.
+const METHOD_NO_AUTHENTICATION: u8 = 0x00;
+
+/// `NO ACCEPTABLE METHODS` method.
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+
+/// `CONNECT` command.
+const COMMAND_CONNECT: u8 = 0x01;
+
+/// Domain name address type.
+const ADDRESS_TYPE_DOMAIN: u8 = 0x03;
+
+/// SOCKS5 reply code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reply {
+    /// Succeeded.
+    Succeeded = 0x00,
+
+    /// General SOCKS server failure.
+    GeneralFailure = 0x01,
+
+    /// Connection not allowed by ruleset.
+    NotAllowed = 0x02,
+
+    /// Host unreachable.
+    HostUnreachable = 0x04,
+
+    /// Command not supported.
+    CommandNotSupported = 0x07,
+
+    /// Address type not supported.
+    AddressTypeNotSupported = 0x08,
+}
+
+impl From<&Error> for Reply {
+    fn from(error: &Error) -> Self {
+        match error {
+            Error::I2p(error) | Error::Protocol(ProtocolError::Router(error)) => match error {
+                I2pError::CantReachPeer
+                | I2pError::PeerNotFound
+                | I2pError::InvalidKey
+                | I2pError::KeyNotFound
+                | I2pError::Timeout => Reply::HostUnreachable,
+                _ => Reply::GeneralFailure,
+            },
+            _ => Reply::GeneralFailure,
+        }
+    }
+}
+
+/// ## SOCKS5 proxy.
+///
+/// `Socks5Server` listens on a local TCP port and accepts SOCKS5 `CONNECT` requests for `.i2p`
+/// and `.b32.i2p` host names. Each request is connected to the requested destination over a
+/// shared [`Session`] and the client connection is bridged to the virtual stream, which gives any
+/// SOCKS-capable application access to I2P.
+///
+/// Only the `NO AUTHENTICATION REQUIRED` method and the domain name address type are supported.
+/// Host names are resolved by the router when the virtual stream is opened and the requested port
+/// is ignored. Clients are served concurrently, including opening their virtual streams.
+///
+/// ### Serve SOCKS5 clients on port 1080:
+///
+/// ```no_run
+/// use yosemite::{proxy::Socks5Server, style::Stream, Session};
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let session = Session::<Stream>::new(Default::default()).await?;
+///     let server = Socks5Server::new("127.0.0.1:1080", session).await?;
+///
+///     server.run().await
+/// }
+/// ```
+pub struct Socks5Server {
+    /// TCP listener for SOCKS5 clients.
+    listener: TcpListener,
+
+    /// Session used to open virtual streams.
+    ///
+    /// Streams are opened with `handle`, the session is kept alive for it.
+    _session: Session<style::Stream>,
+
+    /// Handle of the session, cloned for each client.
+    handle: SessionHandle<style::Stream>,
+}
+
+impl Socks5Server {
+    /// Create new [`Socks5Server`] listening on `address`.
+    ///
+    /// Virtual streams are opened over `session`.
+    pub async fn new(
+        address: impl ToSocketAddrs,
+        session: Session<style::Stream>,
+    ) -> crate::Result<Self> {
+        Ok(Self {
+            listener: TcpListener::bind(address).await?,
+            handle: session.handle(),
+            _session: session,
+        })
+    }
+
+    /// Get the local address the proxy is listening on.
+    pub fn local_addr(&self) -> crate::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept and serve SOCKS5 clients.
+    ///
+    /// Each client is served in a task of its own. Returns only if accepting a client fails.
+    pub async fn run(self) -> crate::Result<()> {
+        loop {
+            let (client, address) = self.listener.accept().await?;
+            let handle = self.handle.clone();
+
+            tracing::trace!(
+                target: LOG_TARGET,
+                ?address,
+                "accepted socks5 client",
+            );
+
+            tokio::spawn(async move {
+                if let Err(error) = serve_client(client, handle).await {
+                    tracing::debug!(
+                        target: LOG_TARGET,
+                        ?address,
+                        ?error,
+                        "socks5 client failed",
+                    );
+                }
+            });
+        }
+    }
+}
+
+/// Serve one SOCKS5 client.
+async fn serve_client(
+    mut client: TcpStream,
+    handle: SessionHandle<style::Stream>,
+) -> crate::Result<()> {
+    let Some(host) = read_request(&mut client).await? else {
+        return Ok(());
+    };
+
+    match handle.connect(&host).await {
+        Ok(stream) => {
+            tracing::debug!(
+                target: LOG_TARGET,
+                host = %RedactedValue(&host),
+                "socks5 connection established",
+            );
+
+            write_reply(&mut client, Reply::Succeeded).await?;
+            stream.bridge(client).await?;
+
+            Ok(())
+        }
+        Err(error) => {
+            write_reply(&mut client, Reply::from(&error)).await?;
+            Err(error)
+        }
+    }
+}
+
+/// Negotiate the authentication method and read the `CONNECT` request of the client.
+///
+/// Returns `Ok(None)` if the request cannot be served, in which case the client has been sent the
+/// reason.
+async fn read_request(client: &mut TcpStream) -> crate::Result<Option<String>> {
+    // method selection
+    let [version, num_methods] = read_array(client).await?;
+    if version != SOCKS_VERSION {
+        return Err(Error::Malformed);
+    }
+
+    let mut methods = vec![0u8; num_methods as usize];
+    client.read_exact(&mut methods).await?;
+
+    if !methods.contains(&METHOD_NO_AUTHENTICATION) {
+        client.write_all(&[SOCKS_VERSION, METHOD_NOT_ACCEPTABLE]).await?;
+        return Ok(None);
+    }
+    client.write_all(&[SOCKS_VERSION, METHOD_NO_AUTHENTICATION]).await?;
+
+    // request
+    let [version, command, _, address_type] = read_array(client).await?;
+    if version != SOCKS_VERSION {
+        return Err(Error::Malformed);
+    }
+
+    if command != COMMAND_CONNECT {
+        write_reply(client, Reply::CommandNotSupported).await?;
+        return Ok(None);
+    }
+
+    if address_type != ADDRESS_TYPE_DOMAIN {
+        write_reply(client, Reply::AddressTypeNotSupported).await?;
+        return Ok(None);
+    }
+
+    let [len] = read_array(client).await?;
+    let mut host = vec![0u8; len as usize];
+    client.read_exact(&mut host).await?;
+
+    // the port is read but i2p streams are connected to the destination regardless of it
+    let _port: [u8; 2] = read_array(client).await?;
+
+    match String::from_utf8(host) {
+        Ok(host) if is_i2p_host(&host) => Ok(Some(host)),
+        _ => {
+            write_reply(client, Reply::NotAllowed).await?;
+            Ok(None)
+        }
+    }
+}
+
+/// Is `host` a `.i2p` or `.b32.i2p` host name.
+fn is_i2p_host(host: &str) -> bool {
+    host.len() > ".i2p".len() && host.to_ascii_lowercase().ends_with(".i2p")
+}
+
+/// Read exactly `N` bytes from `client`.
+async fn read_array<const N: usize>(client: &mut TcpStream) -> io::Result<[u8; N]> {
+    let mut buffer = [0u8; N];
+    client.read_exact(&mut buffer).await?;
+
+    Ok(buffer)
+}
+
+/// Send reply to the request of the client.
+///
+/// The bound address is not meaningful for virtual streams and is always reported as `0.0.0.0:0`.
+async fn write_reply(client: &mut TcpStream, reply: Reply) -> io::Result<()> {
+    client
+        .write_all(&[SOCKS_VERSION, reply as u8, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{style, testing::MockSam};
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn i2p_hosts() {
+        assert!(is_i2p_host("host.i2p"));
+        assert!(is_i2p_host("HOST.I2P"));
+        assert!(is_i2p_host(
+            "ukeu3k5oycgaauneqgtnvselmt4yemvoilkln7jpvamvfx7dnkdq.b32.i2p"
+        ));
+        assert!(!is_i2p_host(".i2p"));
+        assert!(!is_i2p_host("example.com"));
+        assert!(!is_i2p_host("i2p"));
+    }
+
+    #[test]
+    fn error_replies() {
+        assert_eq!(
+            Reply::from(&Error::Protocol(ProtocolError::Router(
+                I2pError::CantReachPeer
+            ))),
+            Reply::HostUnreachable
+        );
+        assert_eq!(
+            Reply::from(&Error::I2p(I2pError::PeerNotFound)),
+            Reply::HostUnreachable
+        );
+        assert_eq!(Reply::from(&Error::Malformed), Reply::GeneralFailure);
+    }
+
+    #[tokio::test]
+    async fn socks5_proxy() {
+        use crate::proxy::Socks5Server;
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let sam = MockSam::new().unwrap();
+        let mut server = sam.session::<style::Stream>().await.unwrap();
+        let client = sam.session::<style::Stream>().await.unwrap();
+        sam.add_name("server.i2p", server.public_destination());
+
+        let proxy = Socks5Server::new("127.0.0.1:0", client).await.unwrap();
+        let address = proxy.local_addr().unwrap();
+        tokio::spawn(proxy.run());
+
+        let handle = tokio::spawn(async move {
+            let mut stream = server.accept().await.unwrap();
+            let mut buffer = [0u8; 5];
+
+            stream.read_exact(&mut buffer).await.unwrap();
+            stream.write_all(&buffer).await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        let mut socket = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut reply = [0u8; 2];
+        socket.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        socket.read_exact(&mut reply).await.unwrap();
+        assert_eq!(reply, [0x05, 0x00]);
+
+        let mut request = vec![0x05, 0x01, 0x00, 0x03, b"server.i2p".len() as u8];
+        request.extend_from_slice(b"server.i2p");
+        request.extend_from_slice(&80u16.to_be_bytes());
+        socket.write_all(&request).await.unwrap();
+
+        let mut reply = [0u8; 10];
+        socket.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply[..2], [0x05, 0x00]);
+
+        let mut buffer = [0u8; 5];
+        socket.write_all(b"hello").await.unwrap();
+        socket.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        handle.await.unwrap();
+
+        // non-i2p hosts are rejected
+        let mut socket = tokio::net::TcpStream::connect(address).await.unwrap();
+        socket.write_all(&[0x05, 0x01, 0x00]).await.unwrap();
+        socket.read_exact(&mut reply[..2]).await.unwrap();
+
+        let mut request = vec![0x05, 0x01, 0x00, 0x03, b"example.com".len() as u8];
+        request.extend_from_slice(b"example.com");
+        request.extend_from_slice(&80u16.to_be_bytes());
+        socket.write_all(&request).await.unwrap();
+
+        socket.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply[..2], [0x05, 0x02]);
+    }
+}
@@ -0,0 +1,335 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Key rotation of persistent destinations.
+
+use crate::{
+    asynchronous::{
+        router::RouterApi,
+        session::{style::SessionStyle, Session},
+    },
+    keystore::KeyStore,
+    options::{DestinationKind, SessionOptions},
+    redaction::RedactedValue,
+    tracing,
+};
+
+use tokio::time::Instant;
+
+use std::{sync::Arc, time::Duration};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::rotation";
+
+/// Default grace period during which the old and the new destination are both served.
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Callback invoked with the progress of a key rotation.
+type RotationHook = Arc<dyn Fn(&RotationEvent) + Send + Sync>;
+
+/// Progress of a key rotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RotationEvent {
+    /// The session of the new destination has been created and both destinations are served
+    /// until the grace period ends.
+    ///
+    /// Clients should be migrated to the new destination during the grace period.
+    Started {
+        /// Public destination of the old session.
+        old_destination: String,
+
+        /// Public destination of the new session.
+        new_destination: String,
+    },
+
+    /// The grace period has ended and the old session has been closed.
+    Completed {
+        /// Public destination of the old session.
+        old_destination: String,
+
+        /// Public destination of the new session.
+        new_destination: String,
+    },
+}
+
+/// ## Key rotation.
+///
+/// `KeyRotation` replaces the destination of a running service with a new one: a destination is
+/// generated and stored as the active key of the [`KeyStore`], a session is created for it and
+/// the old session is kept running for a grace period so clients can be migrated. Once the grace
+/// period ends, the old session is closed and its key is retired.
+///
+/// The application is notified of the progress with [`KeyRotation::with_hook()`].
+///
+/// ```no_run
+/// use yosemite::{rotation::KeyRotation, style::Stream, KeyStore, Session};
+/// use std::time::Duration;
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     # let session = Session::<Stream>::new(Default::default()).await?;
+///     let keystore = KeyStore::open("service.keys")?;
+///     let mut rotation = KeyRotation::new(keystore, Default::default())
+///         .with_grace_period(Duration::from_secs(3600))
+///         .with_hook(|event| println!("{event:?}"))
+///         .start(session)
+///         .await?;
+///
+///     // serve both `rotation.old_session()` and `rotation.new_session()`
+///
+///     let session = rotation.complete().await?;
+///     # Ok(())
+/// }
+/// ```
+pub struct KeyRotation {
+    /// Key store of the service.
+    keystore: KeyStore,
+
+    /// Options of the new session.
+    options: SessionOptions,
+
+    /// Grace period.
+    grace_period: Duration,
+
+    /// Rotation hook, if any.
+    hook: Option<RotationHook>,
+}
+
+impl KeyRotation {
+    /// Create new [`KeyRotation`].
+    ///
+    /// The session of the new destination is created from `options` and the new key is stored in
+    /// `keystore`. `destination` of `options` is overridden by the new destination.
+    pub fn new(keystore: KeyStore, options: SessionOptions) -> Self {
+        Self {
+            keystore,
+            options,
+            grace_period: DEFAULT_GRACE_PERIOD,
+            hook: None,
+        }
+    }
+
+    /// Serve the old destination for `grace_period` after the new session has been created.
+    ///
+    /// Defaults to 24 hours.
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    /// Call `hook` when the rotation starts and when it completes.
+    pub fn with_hook(mut self, hook: impl Fn(&RotationEvent) + Send + Sync + 'static) -> Self {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Start rotating the key of `session`.
+    ///
+    /// Generates a new destination, stores it as the active key and creates its session. The key
+    /// of the old session, if stored in the key store, is moved to
+    /// [`KeyState::Retiring`](crate::KeyState::Retiring).
+    pub async fn start<S: SessionStyle>(
+        mut self,
+        session: Session<S>,
+    ) -> crate::Result<Rotation<S>> {
+        let (_, private_key) = RouterApi::new(self.options.samv3_tcp_port)
+            .with_compatibility(self.options.compatibility)
+            .generate_destination_with_type(self.options.signature_type)
+            .await?;
+
+        let new_session = Session::<S>::new(SessionOptions {
+            destination: DestinationKind::Persistent {
+                private_key: private_key.clone(),
+            },
+            ..self.options
+        })
+        .await?;
+
+        let old_key = self.keystore.active().map(|key| key.private_key.clone());
+        self.keystore.activate(private_key);
+        self.keystore.save()?;
+
+        tracing::info!(
+            target: LOG_TARGET,
+            old_destination = %RedactedValue(session.destination()),
+            new_destination = %RedactedValue(new_session.destination()),
+            grace_period = ?self.grace_period,
+            "key rotation started",
+        );
+
+        let rotation = Rotation {
+            keystore: self.keystore,
+            old_key,
+            old_session: session,
+            new_session,
+            deadline: Instant::now() + self.grace_period,
+            hook: self.hook,
+        };
+        rotation.emit(RotationEvent::Started {
+            old_destination: rotation.old_session.public_destination().to_string(),
+            new_destination: rotation.new_session.public_destination().to_string(),
+        });
+
+        Ok(rotation)
+    }
+}
+
+/// Key rotation in progress, created with [`KeyRotation::start()`].
+///
+/// Both sessions are served until the rotation is completed with [`Rotation::complete()`].
+pub struct Rotation<S: SessionStyle> {
+    /// Key store of the service.
+    keystore: KeyStore,
+
+    /// Key of the old session, if it was stored in the key store.
+    old_key: Option<String>,
+
+    /// Session of the old destination.
+    old_session: Session<S>,
+
+    /// Session of the new destination.
+    new_session: Session<S>,
+
+    /// When does the grace period end.
+    deadline: Instant,
+
+    /// Rotation hook, if any.
+    hook: Option<RotationHook>,
+}
+
+impl<S: SessionStyle> Rotation<S> {
+    /// Get mutable reference to the session of the old destination.
+    pub fn old_session(&mut self) -> &mut Session<S> {
+        &mut self.old_session
+    }
+
+    /// Get mutable reference to the session of the new destination.
+    pub fn new_session(&mut self) -> &mut Session<S> {
+        &mut self.new_session
+    }
+
+    /// Get the time remaining in the grace period.
+    pub fn remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+
+    /// Wait for the grace period to end, close the old session and retire its key.
+    ///
+    /// Returns the session of the new destination.
+    pub async fn complete(mut self) -> crate::Result<Session<S>> {
+        tokio::time::sleep_until(self.deadline).await;
+
+        if let Some(old_key) = &self.old_key {
+            self.keystore.retire(old_key);
+            self.keystore.save()?;
+        }
+
+        let event = RotationEvent::Completed {
+            old_destination: self.old_session.public_destination().to_string(),
+            new_destination: self.new_session.public_destination().to_string(),
+        };
+        drop(self.old_session);
+
+        tracing::info!(
+            target: LOG_TARGET,
+            new_destination = %RedactedValue(self.new_session.destination()),
+            "key rotation completed",
+        );
+
+        if let Some(hook) = &self.hook {
+            hook(&event);
+        }
+
+        Ok(self.new_session)
+    }
+
+    /// Call the rotation hook with `event`, if there's a hook.
+    fn emit(&self, event: RotationEvent) {
+        if let Some(hook) = &self.hook {
+            hook(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        style,
+        testing::{with_new_nickname, MockSam},
+    };
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn key_rotation() {
+        use crate::{rotation::RotationEvent, KeyState, KeyStore};
+
+        let sam = MockSam::new().unwrap();
+        let path = std::env::temp_dir().join(format!(
+            "yosemite-rotation-{}-{}",
+            std::process::id(),
+            sam.tcp_port()
+        ));
+        let mut keystore = KeyStore::open(&path).unwrap();
+        keystore.activate("OLD_KEY");
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let session = sam.session::<style::Stream>().await.unwrap();
+        let old_destination = session.public_destination().to_string();
+
+        let mut rotation =
+            crate::rotation::KeyRotation::new(keystore, with_new_nickname(&sam.session_options()))
+                .with_grace_period(Duration::from_millis(50))
+                .with_hook({
+                    let events = Arc::clone(&events);
+                    move |event| events.lock().unwrap().push(event.clone())
+                })
+                .start(session)
+                .await
+                .unwrap();
+
+        let new_destination = rotation.new_session().public_destination().to_string();
+        assert_ne!(old_destination, new_destination);
+        assert_eq!(rotation.old_session().public_destination(), old_destination);
+        assert_eq!(
+            KeyStore::open(&path).unwrap().keys()[0].state,
+            KeyState::Retiring
+        );
+
+        let session = rotation.complete().await.unwrap();
+        assert_eq!(session.public_destination(), new_destination);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                RotationEvent::Started {
+                    old_destination: old_destination.clone(),
+                    new_destination: new_destination.clone(),
+                },
+                RotationEvent::Completed {
+                    old_destination,
+                    new_destination,
+                },
+            ]
+        );
+
+        let keystore = KeyStore::open(&path).unwrap();
+        assert_eq!(keystore.keys()[0].state, KeyState::Retired);
+        assert_eq!(keystore.active().unwrap().state, KeyState::Active);
+        std::fs::remove_file(path).unwrap();
+    }
+}
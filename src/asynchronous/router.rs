@@ -18,9 +18,27 @@
 
 #![cfg(feature = "async")]
 
-use crate::{options::SAMV3_TCP_PORT, proto::router::RouterApiController};
+use crate::{
+    asynchronous::control::ControlSocket,
+    error::{report_error, Error},
+    options::{LookupCredentials, RouterCompatibility, SignatureType, SAMV3_TCP_PORT},
+    proto::router::RouterApiController,
+};
 
-use tokio::{io::AsyncWriteExt, net::TcpStream};
+use std::{collections::HashMap, time::Duration};
+
+/// Kind of a naming lookup.
+#[derive(Clone, Copy)]
+enum Lookup<'a> {
+    /// Look up the destination.
+    Destination,
+
+    /// Look up an encrypted destination with lookup credentials.
+    Credentials(&'a LookupCredentials),
+
+    /// Look up the destination and its options.
+    Options,
+}
 
 /// ## Router API.
 ///
@@ -68,12 +86,20 @@ use tokio::{io::AsyncWriteExt, net::TcpStream};
 pub struct RouterApi {
     /// SAMv3 TCP port.
     port: u16,
+
+    /// Threshold after which a slow router response is reported.
+    slow_operation_threshold: Option<Duration>,
+
+    /// Router implementation whose deviations are accommodated.
+    compatibility: RouterCompatibility,
 }
 
 impl Default for RouterApi {
     fn default() -> Self {
         Self {
             port: SAMV3_TCP_PORT,
+            slow_operation_threshold: None,
+            compatibility: RouterCompatibility::Auto,
         }
     }
 }
@@ -83,61 +109,231 @@ impl RouterApi {
     ///
     /// `port` specifies the SAMv3 TCP port the router is listening on.
     pub fn new(port: u16) -> Self {
-        Self { port }
+        Self {
+            port,
+            slow_operation_threshold: None,
+            compatibility: RouterCompatibility::Auto,
+        }
+    }
+
+    /// Emit a warning if the router takes longer than `threshold` to respond to a command.
+    ///
+    /// The warning includes the state the controller was waiting in.
+    pub fn with_slow_operation_threshold(mut self, threshold: Duration) -> Self {
+        self.slow_operation_threshold = Some(threshold);
+        self
+    }
+
+    /// Accommodate the deviations of the router implementation selected by `compatibility`.
+    ///
+    /// Defaults to [`RouterCompatibility::Auto`].
+    pub fn with_compatibility(mut self, compatibility: RouterCompatibility) -> Self {
+        self.compatibility = compatibility;
+        self
     }
 }
 
 impl RouterApi {
     /// Attempt to look up the the destination associated with `name`.
     pub async fn lookup_name(&self, name: &str) -> crate::Result<String> {
-        let mut controller = RouterApiController::new();
-        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", self.port)).await?;
+        self.naming_lookup(name, Lookup::Destination)
+            .await
+            .map(|(destination, _)| destination)
+            .inspect_err(report_error)
+    }
+
+    /// Attempt to look up the destination associated with encrypted `name`.
+    ///
+    /// `credentials` are used by the router to look up the lease set of the destination if it's
+    /// hosted with [`LeaseSetType::Encrypted`](crate::LeaseSetType::Encrypted) and requires a
+    /// lookup secret or client authorization. If the router rejects the credentials,
+    /// [`Error::CredentialsRejected`](crate::Error::CredentialsRejected) is returned.
+    pub async fn lookup_name_with_credentials(
+        &self,
+        name: &str,
+        credentials: &LookupCredentials,
+    ) -> crate::Result<String> {
+        self.naming_lookup(name, Lookup::Credentials(credentials))
+            .await
+            .map(|(destination, _)| destination)
+            .map_err(Error::into_credentials_error)
+            .inspect_err(report_error)
+    }
+
+    /// Attempt to look up the destination associated with `name` and the options published for
+    /// it, such as service metadata.
+    ///
+    /// The lookup is made with `OPTIONS=true`, which requires SAMv3.2. If the router reports an
+    /// older version, [`Error::UnsupportedByRouter`](crate::Error::UnsupportedByRouter) is
+    /// returned.
+    pub async fn lookup_with_options(
+        &self,
+        name: &str,
+    ) -> crate::Result<(String, HashMap<String, String>)> {
+        self.naming_lookup(name, Lookup::Options).await.inspect_err(report_error)
+    }
+
+    /// Generate destination.
+    ///
+    /// The first element in the returned tuple is a base64-encoded destination which can used by
+    /// other destinations to connect to the generated destination. The second element in the tuple
+    /// is the private key of the destination which can be used to create the destination using
+    /// [`DestinationKind::Persistent`](crate::options::DestinationKind).
+    ///
+    /// The private key should be stored encrypted, e.g., with
+    /// [`KeyStore::open_with_passphrase()`](crate::KeyStore::open_with_passphrase).
+    pub async fn generate_destination(&self) -> crate::Result<(String, String)> {
+        self.dest_generate(None).await.inspect_err(report_error)
+    }
+
+    /// Generate destination with `signature_type`.
+    ///
+    /// See [`RouterApi::generate_destination()`] for the returned values.
+    pub async fn generate_destination_with_type(
+        &self,
+        signature_type: SignatureType,
+    ) -> crate::Result<(String, String)> {
+        self.dest_generate(Some(signature_type)).await.inspect_err(report_error)
+    }
+
+    /// Look up `name` over a new control connection.
+    async fn naming_lookup(
+        &self,
+        name: &str,
+        lookup: Lookup<'_>,
+    ) -> crate::Result<(String, HashMap<String, String>)> {
+        let mut controller = RouterApiController::new()
+            .with_slow_operation_threshold(self.slow_operation_threshold)
+            .with_compatibility(self.compatibility);
+        let mut stream = ControlSocket::connect(self.port).await?;
 
         // send handhake to router
         let command = controller.handshake_router_api()?;
-        stream.write_all(&command).await?;
+        stream.write_command(command).await?;
 
         // read handshake response
-        let (mut stream, response) = read_response!(stream);
-        controller.handle_response(&response)?;
+        let response = stream.read_response().await?;
+        controller.handle_response(response)?;
 
         // lookup hostname
-        let command = controller.lookup_name(name)?;
-        stream.write_all(&command).await?;
+        let command = match lookup {
+            Lookup::Destination => controller.lookup_name(name)?,
+            Lookup::Credentials(credentials) =>
+                controller.lookup_name_with_credentials(name, credentials)?,
+            Lookup::Options => controller.lookup_name_with_options(name)?,
+        };
+        stream.write_command(command).await?;
 
         // handle hostname lookup response
-        let (_session_stream, response) = read_response!(stream);
-        controller.handle_response(&response)?;
+        let response = stream.read_response().await?;
+        controller.handle_response(response)?;
+
+        let destination = match lookup {
+            Lookup::Options => controller.destination_with_options(),
+            _ => (controller.destination(), HashMap::new()),
+        };
+        quit(&mut controller, &mut stream).await;
 
-        Ok(controller.destination())
+        Ok(destination)
     }
 
-    /// Generate destination.
-    ///
-    /// The first element in the returned tuple is a base64-encoded destination which can used by
-    /// other destinations to connect to the generated destination. The second element in the tuple
-    /// is the private key of the destination which can be used to create the destination using
-    /// [`DestinationKind::Persistent`](crate::options::DestinationKind).
-    pub async fn generate_destination(&self) -> crate::Result<(String, String)> {
-        let mut controller = RouterApiController::new();
-        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", self.port)).await?;
+    /// Generate destination over a new control connection, with `signature_type` if any.
+    async fn dest_generate(
+        &self,
+        signature_type: Option<SignatureType>,
+    ) -> crate::Result<(String, String)> {
+        let mut controller = RouterApiController::new()
+            .with_slow_operation_threshold(self.slow_operation_threshold)
+            .with_compatibility(self.compatibility);
+        let mut stream = ControlSocket::connect(self.port).await?;
 
         // send handhake to router
         let command = controller.handshake_router_api()?;
-        stream.write_all(&command).await?;
+        stream.write_command(command).await?;
 
         // read handshake response
-        let (mut stream, response) = read_response!(stream);
-        controller.handle_response(&response)?;
+        let response = stream.read_response().await?;
+        controller.handle_response(response)?;
 
         // generate destination
-        let command = controller.generate_destination()?;
-        stream.write_all(&command).await?;
+        let command = match signature_type {
+            Some(signature_type) => controller.generate_destination_with_type(signature_type)?,
+            None => controller.generate_destination()?,
+        };
+        stream.write_command(command).await?;
 
         // read destination generation response
-        let (_session_stream, response) = read_response!(stream);
-        controller.handle_response(&response)?;
+        let response = stream.read_response().await?;
+        controller.handle_response(response)?;
+
+        let destination = controller.generated_destination();
+        quit(&mut controller, &mut stream).await;
+
+        Ok(destination)
+    }
+}
+
+/// Close the control connection with `QUIT`, if the router supports it.
+///
+/// Errors are ignored since the connection is closed regardless when it's dropped.
+async fn quit(controller: &mut RouterApiController, stream: &mut ControlSocket) {
+    let Ok(command) = controller.quit() else {
+        return;
+    };
+
+    if stream.write_command(command).await.is_err() {
+        return;
+    }
+
+    // the router may close the connection without acknowledging `QUIT`
+    if let Ok(response) = stream.read_response().await {
+        let _ = controller.handle_response(response);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockSam;
+
+    #[tokio::test]
+    async fn router_api() {
+        let sam = MockSam::new().unwrap();
+        let router = RouterApi::new(sam.tcp_port());
+
+        let (destination, _) = router.generate_destination().await.unwrap();
+        sam.add_name("host.i2p", destination.clone());
+
+        assert_eq!(router.lookup_name("host.i2p").await.unwrap(), destination);
+        assert!(router.lookup_name("unknown.i2p").await.is_err());
+
+        router
+            .generate_destination_with_type(SignatureType::RedDsaSha512Ed25519)
+            .await
+            .unwrap();
+        assert!(sam.commands().contains(&"DEST GENERATE SIGNATURE_TYPE=11".to_string()));
+
+        // connections are closed with `QUIT` once the command has succeeded
+        let commands = sam.commands();
+        assert_eq!(
+            commands.iter().filter(|command| *command == "QUIT").count(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_with_options() {
+        let sam = MockSam::new().unwrap();
+        let router = RouterApi::new(sam.tcp_port());
+
+        sam.respond_to(
+            "NAMING LOOKUP",
+            "NAMING REPLY RESULT=OK NAME=host.i2p VALUE=abcd OPTION:service=http",
+        );
+        let (destination, options) = router.lookup_with_options("host.i2p").await.unwrap();
 
-        Ok(controller.generated_destination())
+        assert_eq!(destination, "abcd");
+        assert_eq!(options.get("service").map(String::as_str), Some("http"));
+        assert!(sam.commands().contains(&"NAMING LOOKUP NAME=host.i2p OPTIONS=true".to_string()));
     }
 }
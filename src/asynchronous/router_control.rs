@@ -0,0 +1,521 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! I2PControl client.
+
+use crate::{
+    error::{report_error, Error},
+    json::{self, Value},
+    tracing,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, PoisonError,
+    },
+    time::Duration,
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::router_control";
+
+/// Default address of the I2PControl API, served by the `jsonrpc` webapp of the Java router.
+const DEFAULT_ADDRESS: ([u8; 4], u16) = ([127, 0, 0, 1], 7657);
+
+/// Default path of the I2PControl API.
+const DEFAULT_PATH: &str = "/jsonrpc/";
+
+/// Default timeout for I2PControl requests.
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Version of the I2PControl API.
+const API_VERSION: f64 = 1.0;
+
+/// Maximum size of an HTTP response.
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+/// Error codes returned by the router when the authentication token is missing or has expired.
+const TOKEN_ERRORS: [i64; 3] = [-32002, -32003, -32004];
+
+/// Bandwidth of the router, in bytes per second.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Bandwidth {
+    /// Inbound bandwidth averaged over one second.
+    pub inbound_1s: f64,
+
+    /// Inbound bandwidth averaged over 15 seconds.
+    pub inbound_15s: f64,
+
+    /// Outbound bandwidth averaged over one second.
+    pub outbound_1s: f64,
+
+    /// Outbound bandwidth averaged over 15 seconds.
+    pub outbound_15s: f64,
+}
+
+/// Status of the router, returned by [`RouterControl::router_status()`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RouterStatus {
+    /// Router version.
+    pub version: String,
+
+    /// Human-readable status of the router, e.g., `Running`.
+    pub status: String,
+
+    /// Time since the router was started.
+    pub uptime: Duration,
+
+    /// Network status code.
+    ///
+    /// `0` means the router is reachable, codes up to `7` are warnings such as being firewalled
+    /// and codes from `8` onwards are errors, such as a clock skew or having no active peers.
+    pub network_status: u32,
+
+    /// Bandwidth of the router.
+    pub bandwidth: Bandwidth,
+
+    /// Number of tunnels the router participates in.
+    pub participating_tunnels: u64,
+
+    /// Number of peers the router has been in contact with recently.
+    pub active_peers: u64,
+
+    /// Number of peers the router knows about.
+    pub known_peers: u64,
+}
+
+/// ## I2PControl client.
+///
+/// `RouterControl` queries the health of the router over the
+/// [I2PControl](https://geti2p.net/en/docs/api/i2pcontrol) JSON-RPC API, which answers questions
+/// SAMv3 cannot, such as how much bandwidth the router is using or how many tunnels it's
+/// participating in.
+///
+/// The client authenticates with the password of the API on the first request and again when the
+/// router reports that the authentication token has expired. Requests are sent over plain HTTP,
+/// by default to the `jsonrpc` webapp of the Java router at `127.0.0.1:7657/jsonrpc/`. Endpoints
+/// which are only served over HTTPS, such as the one of i2pd, must be reached through a
+/// TLS-terminating proxy.
+///
+/// The module is available with the `router-control` feature.
+///
+/// ```no_run
+/// use yosemite::router_control::RouterControl;
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let control = RouterControl::new("itoopie");
+///     let status = control.router_status().await?;
+///
+///     println!(
+///         "router {} up for {:?}, {} participating tunnels, {:.0} B/s out",
+///         status.version,
+///         status.uptime,
+///         status.participating_tunnels,
+///         status.bandwidth.outbound_15s,
+///     );
+///
+///     Ok(())
+/// }
+/// ```
+pub struct RouterControl {
+    /// Address of the I2PControl API.
+    address: SocketAddr,
+
+    /// HTTP path of the I2PControl API.
+    path: String,
+
+    /// Password of the I2PControl API.
+    password: String,
+
+    /// Request timeout.
+    timeout: Duration,
+
+    /// Authentication token, if authenticated.
+    token: Mutex<Option<String>>,
+
+    /// ID of the next request.
+    next_id: AtomicU64,
+}
+
+impl RouterControl {
+    /// Create new [`RouterControl`] which authenticates with `password`.
+    pub fn new(password: impl Into<String>) -> Self {
+        Self {
+            address: SocketAddr::from(DEFAULT_ADDRESS),
+            path: DEFAULT_PATH.to_string(),
+            password: password.into(),
+            timeout: DEFAULT_TIMEOUT,
+            token: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Send requests to the I2PControl API at `address`.
+    ///
+    /// Defaults to `127.0.0.1:7657`.
+    pub fn with_address(mut self, address: SocketAddr) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// Send requests to `path`.
+    ///
+    /// Defaults to `/jsonrpc/`.
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = path.into();
+        self
+    }
+
+    /// Fail requests which haven't been answered within `timeout`.
+    ///
+    /// Defaults to 10 seconds.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Authenticate with the I2PControl API.
+    ///
+    /// Requests authenticate automatically so calling this is only needed to check the password
+    /// before the first request.
+    pub async fn authenticate(&self) -> crate::Result<()> {
+        self.authenticate_inner().await.map(|_| ()).inspect_err(report_error)
+    }
+
+    /// Get the status of the router.
+    pub async fn router_status(&self) -> crate::Result<RouterStatus> {
+        let result = self
+            .router_info(&[
+                "i2p.router.version",
+                "i2p.router.status",
+                "i2p.router.uptime",
+                "i2p.router.net.status",
+                "i2p.router.net.bw.inbound.1s",
+                "i2p.router.net.bw.inbound.15s",
+                "i2p.router.net.bw.outbound.1s",
+                "i2p.router.net.bw.outbound.15s",
+                "i2p.router.net.tunnels.participating",
+                "i2p.router.netdb.activepeers",
+                "i2p.router.netdb.knownpeers",
+            ])
+            .await?;
+
+        let string = |key: &str| result.get(key).and_then(Value::as_str).unwrap_or_default();
+        let number = |key: &str| result.get(key).and_then(Value::as_f64).unwrap_or_default();
+
+        Ok(RouterStatus {
+            version: string("i2p.router.version").to_string(),
+            status: string("i2p.router.status").to_string(),
+            uptime: Duration::from_millis(number("i2p.router.uptime") as u64),
+            network_status: number("i2p.router.net.status") as u32,
+            bandwidth: bandwidth(&result),
+            participating_tunnels: number("i2p.router.net.tunnels.participating") as u64,
+            active_peers: number("i2p.router.netdb.activepeers") as u64,
+            known_peers: number("i2p.router.netdb.knownpeers") as u64,
+        })
+    }
+
+    /// Get the bandwidth of the router.
+    pub async fn bandwidth(&self) -> crate::Result<Bandwidth> {
+        let result = self
+            .router_info(&[
+                "i2p.router.net.bw.inbound.1s",
+                "i2p.router.net.bw.inbound.15s",
+                "i2p.router.net.bw.outbound.1s",
+                "i2p.router.net.bw.outbound.15s",
+            ])
+            .await?;
+
+        Ok(bandwidth(&result))
+    }
+
+    /// Get the number of tunnels the router participates in.
+    pub async fn participating_tunnels(&self) -> crate::Result<u64> {
+        let result = self.router_info(&["i2p.router.net.tunnels.participating"]).await?;
+
+        Ok(result
+            .get("i2p.router.net.tunnels.participating")
+            .and_then(Value::as_f64)
+            .unwrap_or_default() as u64)
+    }
+
+    /// Query `keys` with the `RouterInfo` method.
+    async fn router_info(&self, keys: &[&str]) -> crate::Result<Value> {
+        let params = keys.iter().map(|key| (key.to_string(), Value::Null)).collect();
+
+        self.call_authenticated("RouterInfo", params).await.inspect_err(report_error)
+    }
+
+    /// Authenticate and store the authentication token.
+    async fn authenticate_inner(&self) -> crate::Result<String> {
+        let result = self
+            .call(
+                "Authenticate",
+                vec![
+                    ("API".to_string(), Value::Number(API_VERSION)),
+                    ("Password".to_string(), Value::String(self.password.clone())),
+                ],
+            )
+            .await?;
+        let token = result.get("Token").and_then(Value::as_str).ok_or(Error::Malformed)?;
+
+        tracing::debug!(
+            target: LOG_TARGET,
+            address = %self.address,
+            "authenticated with i2pcontrol",
+        );
+        *self.token.lock().unwrap_or_else(PoisonError::into_inner) = Some(token.to_string());
+
+        Ok(token.to_string())
+    }
+
+    /// Call `method` with the authentication token added to `params`.
+    ///
+    /// If the router rejects the token, the client authenticates again and retries once.
+    async fn call_authenticated(
+        &self,
+        method: &str,
+        params: Vec<(String, Value)>,
+    ) -> crate::Result<Value> {
+        let token = self.token.lock().unwrap_or_else(PoisonError::into_inner).clone();
+        let token = match token {
+            Some(token) => token,
+            None => self.authenticate_inner().await?,
+        };
+
+        let with_token = |token: String| {
+            let mut params = params.clone();
+            params.push(("Token".to_string(), Value::String(token)));
+            params
+        };
+
+        match self.call(method, with_token(token)).await {
+            Err(Error::RouterControl { code, .. }) if TOKEN_ERRORS.contains(&code) => {
+                tracing::debug!(
+                    target: LOG_TARGET,
+                    ?code,
+                    "i2pcontrol token rejected, authenticating again",
+                );
+                let token = self.authenticate_inner().await?;
+
+                self.call(method, with_token(token)).await
+            }
+            result => result,
+        }
+    }
+
+    /// Call `method` with `params` and return its result.
+    async fn call(&self, method: &str, params: Vec<(String, Value)>) -> crate::Result<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = Value::Object(vec![
+            ("id".to_string(), Value::Number(id as f64)),
+            ("method".to_string(), Value::String(method.to_string())),
+            ("params".to_string(), Value::Object(params)),
+            ("jsonrpc".to_string(), Value::String("2.0".to_string())),
+        ]);
+
+        tracing::trace!(
+            target: LOG_TARGET,
+            ?id,
+            %method,
+            "send i2pcontrol request",
+        );
+
+        let body = tokio::time::timeout(self.timeout, self.post(&request.to_string()))
+            .await
+            .map_err(|_| std::io::Error::from(std::io::ErrorKind::TimedOut))??;
+        let response = json::parse(&body).ok_or(Error::Malformed)?;
+
+        if let Some(error) = response.get("error") {
+            let code = error.get("code").and_then(Value::as_f64).ok_or(Error::Malformed)?;
+            let message = error.get("message").and_then(Value::as_str).unwrap_or_default();
+
+            return Err(Error::RouterControl {
+                code: code as i64,
+                message: message.to_string(),
+            });
+        }
+
+        response.get("result").cloned().ok_or(Error::Malformed)
+    }
+
+    /// Send `body` to the I2PControl API and return the body of the response.
+    ///
+    /// HTTP/1.0 is used so the response is neither chunked nor kept alive.
+    async fn post(&self, body: &str) -> crate::Result<String> {
+        let mut stream = TcpStream::connect(self.address).await?;
+        let request = format!(
+            "POST {} HTTP/1.0\r\nHost: {}\r\nContent-Type: application/json\r\n\
+            Content-Length: {}\r\n\r\n{body}",
+            self.path,
+            self.address,
+            body.len(),
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        (&mut stream)
+            .take(MAX_RESPONSE_SIZE as u64 + 1)
+            .read_to_end(&mut response)
+            .await?;
+        if response.len() > MAX_RESPONSE_SIZE {
+            return Err(Error::ResponseTooLong(MAX_RESPONSE_SIZE));
+        }
+
+        let response = String::from_utf8(response).map_err(|_| Error::Malformed)?;
+        let (head, body) = response.split_once("\r\n\r\n").ok_or(Error::Malformed)?;
+        let status_line = head.lines().next().unwrap_or_default();
+        let status = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse::<i64>().ok())
+            .ok_or(Error::Malformed)?;
+
+        if status != 200 {
+            return Err(Error::RouterControl {
+                code: status,
+                message: status_line.to_string(),
+            });
+        }
+
+        Ok(body.to_string())
+    }
+}
+
+/// Get [`Bandwidth`] from the result of `RouterInfo`.
+fn bandwidth(result: &Value) -> Bandwidth {
+    let number = |key: &str| result.get(key).and_then(Value::as_f64).unwrap_or_default();
+
+    Bandwidth {
+        inbound_1s: number("i2p.router.net.bw.inbound.1s"),
+        inbound_15s: number("i2p.router.net.bw.inbound.15s"),
+        outbound_1s: number("i2p.router.net.bw.outbound.1s"),
+        outbound_15s: number("i2p.router.net.bw.outbound.15s"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Serve one HTTP request with a JSON-RPC response built from the request by `respond`.
+    async fn serve(listener: &TcpListener, respond: impl FnOnce(&Value) -> String) -> Value {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+
+        // the client doesn't close its write half so read until the body is complete
+        let body = loop {
+            let mut buffer = [0u8; 1024];
+            let nread = stream.read(&mut buffer).await.unwrap();
+            request.extend_from_slice(&buffer[..nread]);
+
+            let request = std::str::from_utf8(&request).unwrap();
+            if let Some((_, body)) = request.split_once("\r\n\r\n") {
+                if let Some(value) = json::parse(body) {
+                    break value;
+                }
+            }
+        };
+
+        let response = respond(&body);
+        stream
+            .write_all(
+                format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\r\n{response}")
+                    .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        body
+    }
+
+    #[tokio::test]
+    async fn token_refreshed() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control = RouterControl::new("itoopie").with_address(listener.local_addr().unwrap());
+
+        let server = async {
+            let request = serve(&listener, |_| {
+                r#"{"id":1,"result":{"API":1,"Token":"first"},"jsonrpc":"2.0"}"#.to_string()
+            })
+            .await;
+            let params = request.get("params").unwrap();
+            assert_eq!(
+                params.get("Password").and_then(Value::as_str),
+                Some("itoopie")
+            );
+
+            let request = serve(&listener, |_| {
+                r#"{"id":2,"error":{"code":-32003,"message":"Token expired"},"jsonrpc":"2.0"}"#
+                    .to_string()
+            })
+            .await;
+            let params = request.get("params").unwrap();
+            assert_eq!(params.get("Token").and_then(Value::as_str), Some("first"));
+
+            serve(&listener, |_| {
+                r#"{"id":3,"result":{"API":1,"Token":"second"},"jsonrpc":"2.0"}"#.to_string()
+            })
+            .await;
+
+            let request = serve(&listener, |_| {
+                r#"{"id":4,"result":{"i2p.router.version":"2.7.0","i2p.router.uptime":60000,
+                "i2p.router.net.bw.outbound.15s":1024.5,
+                "i2p.router.net.tunnels.participating":12},"jsonrpc":"2.0"}"#
+                    .to_string()
+            })
+            .await;
+            let params = request.get("params").unwrap();
+            assert_eq!(params.get("Token").and_then(Value::as_str), Some("second"));
+        };
+
+        let (status, ()) = tokio::join!(control.router_status(), server);
+        let status = status.unwrap();
+
+        assert_eq!(status.version, "2.7.0");
+        assert_eq!(status.uptime, Duration::from_secs(60));
+        assert_eq!(status.bandwidth.outbound_15s, 1024.5);
+        assert_eq!(status.participating_tunnels, 12);
+    }
+
+    #[tokio::test]
+    async fn authentication_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let control = RouterControl::new("wrong").with_address(listener.local_addr().unwrap());
+
+        let server = serve(&listener, |_| {
+            r#"{"id":1,"error":{"code":-32001,"message":"Invalid password"},"jsonrpc":"2.0"}"#
+                .to_string()
+        });
+
+        let (result, _) = tokio::join!(control.authenticate(), server);
+        assert!(matches!(
+            result,
+            Err(Error::RouterControl { code: -32001, .. })
+        ));
+    }
+}
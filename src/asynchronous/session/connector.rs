@@ -0,0 +1,219 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Outbound virtual streams opened without borrowing the session.
+
+use crate::{
+    asynchronous::{control::ControlSocket, events::Events, stream::Stream},
+    backoff::{dial_delay, BackoffCache},
+    error::report_error,
+    idle::Activity,
+    options::{SessionOptions, StreamOptions},
+    ports::{assign_port, PortAllocator},
+    proto::session::StreamController,
+    slots::{acquire_slot, StreamSlots},
+    throttle::RateLimiter,
+    tracing::{Instrument, Span},
+};
+
+use futures::future::BoxFuture;
+
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Opens outbound virtual streams with a stream controller of their own.
+///
+/// Each stream is opened over a control connection of its own, independently of the streams
+/// opened by the session.
+#[derive(Clone)]
+pub(crate) struct Connector {
+    /// Stream controller.
+    pub(super) controller: StreamController,
+
+    /// Session options.
+    pub(super) options: SessionOptions,
+
+    /// Session events.
+    pub(super) events: Events,
+
+    /// Activity of the session.
+    pub(super) activity: Activity,
+
+    /// Allocator of ephemeral ports, if the session has an ephemeral port range.
+    pub(super) ports: Option<PortAllocator>,
+
+    /// Rate limiter of the streams, if the session has rate limits.
+    pub(super) limiter: Option<RateLimiter>,
+
+    /// Stream slots, if the session limits the number of streams.
+    pub(super) slots: Option<StreamSlots>,
+
+    /// Backoff cache of unreachable destinations, if enabled for the session.
+    pub(super) backoff: Option<BackoffCache>,
+}
+
+impl Connector {
+    /// Open virtual stream to `destination`, optionally to a specific `port`.
+    ///
+    /// If `destination` is in backoff, the call fails or waits for the backoff to end.
+    pub(crate) async fn connect(
+        self,
+        destination: String,
+        port: Option<u16>,
+    ) -> crate::Result<Stream> {
+        let backoff = self.backoff.clone();
+
+        if let Some(delay) = dial_delay(backoff.as_ref(), &destination)? {
+            tokio::time::sleep(delay).await;
+        }
+
+        let result = self.open_stream(destination.clone(), port).await;
+        if let Some(backoff) = &backoff {
+            backoff.record(&destination, &result);
+        }
+
+        result
+    }
+
+    /// Open virtual stream to `destination` over a new control connection.
+    async fn open_stream(
+        mut self,
+        destination: String,
+        port: Option<u16>,
+    ) -> crate::Result<Stream> {
+        self.activity.record();
+
+        let slot = acquire_slot(self.slots.as_ref()).await?;
+        let options = StreamOptions::default();
+        let (options, local_port) = assign_port(self.ports.as_ref(), &options)?;
+
+        let mut stream = ControlSocket::from_options(&self.options)
+            .await?
+            .with_events(self.events.clone());
+        let command = self.controller.handshake_stream()?;
+
+        if !self.options.pipeline_handshake {
+            stream.write_command(command).await?;
+
+            let response = stream.read_response().await?;
+            self.controller.handle_response(response)?;
+        }
+
+        let command = match (port, options.from_port) {
+            (port, Some(_)) =>
+                self.controller.create_stream_with_options(&destination, port, &options)?,
+            (Some(port), None) => self.controller.create_stream_to_port(&destination, port)?,
+            (None, None) => self.controller.create_stream(&destination)?,
+        };
+        stream.write_command(command).await?;
+
+        if self.options.pipeline_handshake {
+            let response = stream.read_response().await?;
+            self.controller.handle_response(response)?;
+        }
+
+        let response = stream.read_response().await?;
+        self.controller.handle_response(response)?;
+
+        Ok(Stream::from_stream(
+            stream.into_inner(),
+            destination,
+            self.options.stream_write_buffer_size,
+        )
+        .with_port(local_port)
+        .with_slot(slot)
+        .with_throttles(self.limiter.as_ref()))
+    }
+}
+
+/// Future returned by [`Session::connect_detached()`](crate::Session::connect_detached).
+///
+/// Resolves to the opened [`Stream`]. The future doesn't borrow the session so it can be stored
+/// in a struct or spawned into a task of its own.
+#[must_use = "futures do nothing unless polled"]
+pub struct ConnectFuture {
+    /// Connection attempt.
+    future: BoxFuture<'static, crate::Result<Stream>>,
+}
+
+impl ConnectFuture {
+    /// Create new [`ConnectFuture`] which opens a stream to `destination` with `connector`.
+    pub(crate) fn new(
+        connector: Connector,
+        destination: String,
+        port: Option<u16>,
+        span: Span,
+    ) -> Self {
+        Self {
+            future: Box::pin(
+                async move { connector.connect(destination, port).await.inspect_err(report_error) }
+                    .instrument(span),
+            ),
+        }
+    }
+}
+
+impl Future for ConnectFuture {
+    type Output = crate::Result<Stream>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.future.as_mut().poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        style,
+        testing::{with_new_nickname, MockSam},
+        Session,
+    };
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn connect_detached() {
+        let sam = MockSam::new().unwrap();
+        let options = sam.session_options();
+
+        let mut server = Session::<style::Stream>::new(with_new_nickname(&options)).await.unwrap();
+        let mut client = Session::<style::Stream>::new(with_new_nickname(&options)).await.unwrap();
+        let destination = server.public_destination().to_string();
+
+        // the future doesn't borrow the session so it can be spawned
+        let future = client.connect_detached(&destination);
+        let handle = tokio::spawn(future);
+
+        let mut server_stream = server.accept().await.unwrap();
+        let mut client_stream = handle.await.unwrap().unwrap();
+        assert_eq!(client_stream.remote_destination(), destination);
+
+        client_stream.write_all(b"ping").await.unwrap();
+        let mut buffer = [0u8; 4];
+        server_stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        // the session can still open streams of its own
+        let (_, stream) = futures::future::try_join(server.accept(), client.connect(&destination))
+            .await
+            .unwrap();
+        assert_eq!(stream.remote_destination(), destination);
+    }
+}
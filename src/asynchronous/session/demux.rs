@@ -0,0 +1,310 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Demultiplexing of inbound datagrams by port.
+
+use crate::{
+    asynchronous::gate::{Gate, Ports},
+    error::{report_error, Error},
+    idle::Activity,
+    proto::parser::{parse_datagram_header, parse_datagram_to_port},
+    tracing,
+};
+
+use tokio::{
+    net::UdpSocket,
+    sync::mpsc::{self, error::TrySendError, Receiver, Sender},
+    task::JoinHandle,
+};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Range,
+    sync::{Arc, Mutex, PoisonError},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::session::demux";
+
+/// Number of datagrams queued for a port before further datagrams are dropped.
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Datagram received by the demultiplexer.
+struct Datagram {
+    /// Destination of the sender.
+    destination: String,
+
+    /// Datagram, including the header.
+    datagram: Vec<u8>,
+
+    /// Range of `datagram` holding the payload.
+    payload: Range<usize>,
+}
+
+impl Datagram {
+    /// Copy the payload into `buf` and return the number of bytes copied and the sender.
+    ///
+    /// The payload is truncated if `buf` is too small.
+    fn copy_to(self, buf: &mut [u8]) -> (usize, String) {
+        let payload = &self.datagram[self.payload];
+        let len = payload.len().min(buf.len());
+        buf[..len].copy_from_slice(&payload[..len]);
+
+        (len, self.destination)
+    }
+}
+
+/// Channels of the ports.
+type Channels = Arc<Mutex<HashMap<u16, Sender<Datagram>>>>;
+
+/// Demultiplexer of inbound datagrams.
+///
+/// Reads the datagram socket of the session in a background task and routes each datagram to the
+/// channel of its `TO_PORT`. Datagrams sent to ports without a channel are queued for the
+/// session. The task is stopped when the demultiplexer is dropped.
+pub(crate) struct Demux {
+    /// Channels of the ports.
+    channels: Channels,
+
+    /// Datagrams sent to ports without a channel.
+    unrouted: Receiver<crate::Result<Datagram>>,
+
+    /// Reader task.
+    task: JoinHandle<()>,
+}
+
+impl Demux {
+    /// Start demultiplexing datagrams received on `socket`.
+    ///
+    /// Datagrams longer than `buffer_size` are truncated and datagrams denied by `gate` are
+    /// dropped.
+    pub(crate) fn new(socket: Arc<UdpSocket>, buffer_size: usize, gate: Gate) -> Self {
+        let channels = Channels::default();
+        let (unrouted_tx, unrouted) = mpsc::channel(CHANNEL_CAPACITY);
+        let task = tokio::spawn(read_datagrams(
+            socket,
+            buffer_size,
+            Arc::clone(&channels),
+            unrouted_tx,
+            gate,
+        ));
+
+        Self {
+            channels,
+            unrouted,
+            task,
+        }
+    }
+
+    /// Create channel for datagrams sent to `port`.
+    ///
+    /// A previously created channel of `port` stops receiving datagrams.
+    pub(crate) fn channel(&self, port: u16, activity: Activity) -> PortChannel {
+        let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+        self.channels
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(port, sender);
+
+        PortChannel {
+            port,
+            receiver,
+            activity,
+        }
+    }
+
+    /// Receive datagram sent to a port without a channel.
+    pub(crate) async fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, String)> {
+        match self.unrouted.recv().await {
+            Some(datagram) => datagram.map(|datagram| datagram.copy_to(buf)),
+            None => Err(Error::IoError(std::io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+}
+
+impl Drop for Demux {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Read datagrams from `socket` and route them to the channels of their ports.
+///
+/// Read errors are passed to the session which ends the task.
+async fn read_datagrams(
+    socket: Arc<UdpSocket>,
+    buffer_size: usize,
+    channels: Channels,
+    unrouted: Sender<crate::Result<Datagram>>,
+    gate: Gate,
+) {
+    let mut buffer = vec![0u8; buffer_size];
+
+    loop {
+        let nread = match socket.recv(&mut buffer).await {
+            Ok(nread) => nread,
+            Err(error) => {
+                let error = Error::from(error);
+                report_error(&error);
+                let _ = unrouted.send(Err(error)).await;
+                return;
+            }
+        };
+        let datagram = &buffer[..nread];
+
+        let (Some((destination, offset)), Some(port)) = (
+            parse_datagram_header(datagram),
+            parse_datagram_to_port(datagram),
+        ) else {
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?nread,
+                "discarding malformed datagram",
+            );
+            continue;
+        };
+
+        if !gate
+            .admits(
+                destination,
+                Ports::from_datagram_header(&datagram[..offset]),
+            )
+            .await
+        {
+            continue;
+        }
+
+        let mut datagram = Datagram {
+            destination: destination.to_owned(),
+            datagram: datagram.to_vec(),
+            payload: offset..nread,
+        };
+
+        let sender = channels.lock().unwrap_or_else(PoisonError::into_inner).get(&port).cloned();
+        if let Some(sender) = sender {
+            match sender.try_send(datagram) {
+                Ok(()) => continue,
+                Err(TrySendError::Full(_)) => {
+                    tracing::debug!(
+                        target: LOG_TARGET,
+                        ?port,
+                        "port channel full, dropping datagram",
+                    );
+                    continue;
+                }
+                Err(TrySendError::Closed(returned)) => {
+                    let mut channels = channels.lock().unwrap_or_else(PoisonError::into_inner);
+
+                    // the channel may have been replaced while the lock was released
+                    if channels.get(&port).is_some_and(|current| current.same_channel(&sender)) {
+                        channels.remove(&port);
+                    }
+                    datagram = returned;
+                }
+            }
+        }
+
+        if let Err(TrySendError::Full(_)) = unrouted.try_send(Ok(datagram)) {
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?port,
+                "session queue full, dropping datagram",
+            );
+        }
+    }
+}
+
+/// Channel of datagrams sent to a port of a session.
+///
+/// Created with [`Session::port_channel()`](crate::Session::port_channel). Datagrams which
+/// arrive while the channel is full are dropped. Once the channel is dropped, datagrams sent to
+/// its port are received by the session again.
+pub struct PortChannel {
+    /// Port of the channel.
+    port: u16,
+
+    /// Datagrams sent to the port.
+    receiver: Receiver<Datagram>,
+
+    /// Activity of the session.
+    activity: Activity,
+}
+
+impl fmt::Debug for PortChannel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PortChannel").field("port", &self.port).finish_non_exhaustive()
+    }
+}
+
+impl PortChannel {
+    /// Get the port of the channel.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Receive a single datagram sent to the port.
+    ///
+    /// The datagram is truncated if `buf` is too small to hold it.
+    ///
+    /// Returns the number of bytes read and the destination who sent the datagram. Fails if the
+    /// session has been dropped or another channel has been created for the port.
+    pub async fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, String)> {
+        let datagram = self
+            .receiver
+            .recv()
+            .await
+            .ok_or(Error::IoError(std::io::ErrorKind::BrokenPipe.into()))?;
+        self.activity.record();
+
+        Ok(datagram.copy_to(buf))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{style, testing::MockSam};
+
+    #[tokio::test]
+    async fn port_channels() {
+        let sam = MockSam::new().unwrap();
+        let mut session = sam.session::<style::Repliable>().await.unwrap();
+        let sender = sam.session::<style::Repliable>().await.unwrap();
+        let mut dns = session.port_channel(53);
+
+        // the router adds `TO_PORT` requested by the sender to the header
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        for (port, payload) in [(53, "query"), (80, "request")] {
+            let datagram = format!(
+                "3.0 {} {} TO_PORT={port}\n{payload}",
+                sender.nickname(),
+                session.public_destination()
+            );
+            socket.send_to(datagram.as_bytes(), ("127.0.0.1", sam.udp_port())).unwrap();
+        }
+
+        let mut buffer = [0u8; 64];
+        let (nread, destination) = dns.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..nread], b"query");
+        assert_eq!(destination, sender.public_destination());
+
+        // datagrams to ports without a channel are received by the session
+        let (nread, _) = session.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..nread], b"request");
+    }
+}
@@ -0,0 +1,261 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Object-safe session traits.
+
+use crate::{
+    asynchronous::{
+        session::{style, Session},
+        stream::Stream,
+    },
+    status::SessionStatus,
+};
+
+use futures::future::BoxFuture;
+
+/// ## Object-safe session of virtual streams.
+///
+/// Implemented by [`Session<Stream>`](Session) so sessions can be stored behind trait objects,
+/// such as `Box<dyn DynStreamSession>`, without naming the session style. The futures returned
+/// by the methods are boxed.
+///
+/// ```no_run
+/// use yosemite::{style::Stream, DynStreamSession, Session};
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let mut sessions: Vec<Box<dyn DynStreamSession>> = Vec::new();
+///
+///     for _ in 0..2 {
+///         sessions.push(Box::new(Session::<Stream>::new(Default::default()).await?));
+///     }
+///
+///     for session in &mut sessions {
+///         let _stream = session.connect("host.i2p").await?;
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub trait DynStreamSession: Send {
+    /// Get destination of the session, see [`Session::destination()`].
+    fn destination(&self) -> &str;
+
+    /// Get public destination of the session, see [`Session::public_destination()`].
+    fn public_destination(&self) -> &str;
+
+    /// Get nickname of the session.
+    fn nickname(&self) -> &str;
+
+    /// Get status of the session, see [`Session::status()`].
+    fn status(&self) -> SessionStatus;
+
+    /// Create new outbound virtual stream to `destination`, see [`Session::connect()`].
+    fn connect<'a>(&'a mut self, destination: &'a str) -> BoxFuture<'a, crate::Result<Stream>>;
+
+    /// Accept inbound virtual stream, see [`Session::accept()`].
+    fn accept(&mut self) -> BoxFuture<'_, crate::Result<Stream>>;
+
+    /// Forward inbound virtual streams to a TCP listener at `port`, see [`Session::forward()`].
+    fn forward(&mut self, port: u16) -> BoxFuture<'_, crate::Result<()>>;
+}
+
+/// ## Object-safe session of datagrams.
+///
+/// Implemented by [`Session<Repliable>`](Session) and [`Session<Anonymous>`](Session) so sessions
+/// of either datagram style can be stored behind trait objects, such as
+/// `Box<dyn DynDatagramSession>`. The futures returned by the methods are boxed.
+///
+/// [`Session<Anonymous>`](Session) receives datagrams without the destination of the sender so
+/// [`DynDatagramSession::recv_from()`] returns it only for repliable datagrams.
+pub trait DynDatagramSession: Send {
+    /// Get destination of the session, see [`Session::destination()`].
+    fn destination(&self) -> &str;
+
+    /// Get public destination of the session, see [`Session::public_destination()`].
+    fn public_destination(&self) -> &str;
+
+    /// Get nickname of the session.
+    fn nickname(&self) -> &str;
+
+    /// Get status of the session, see [`Session::status()`].
+    fn status(&self) -> SessionStatus;
+
+    /// Send datagram `buf` to `destination`.
+    fn send_to<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+        destination: &'a str,
+    ) -> BoxFuture<'a, crate::Result<()>>;
+
+    /// Receive datagram into `buf`.
+    ///
+    /// Returns the number of bytes read and, for repliable datagrams, the destination who sent the
+    /// datagram.
+    fn recv_from<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, crate::Result<(usize, Option<String>)>>;
+}
+
+// inherent methods of `Session` take precedence over the trait methods of the same name
+
+impl DynStreamSession for Session<style::Stream> {
+    fn destination(&self) -> &str {
+        self.destination()
+    }
+
+    fn public_destination(&self) -> &str {
+        self.public_destination()
+    }
+
+    fn nickname(&self) -> &str {
+        self.nickname()
+    }
+
+    fn status(&self) -> SessionStatus {
+        self.status()
+    }
+
+    fn connect<'a>(&'a mut self, destination: &'a str) -> BoxFuture<'a, crate::Result<Stream>> {
+        Box::pin(self.connect(destination))
+    }
+
+    fn accept(&mut self) -> BoxFuture<'_, crate::Result<Stream>> {
+        Box::pin(self.accept())
+    }
+
+    fn forward(&mut self, port: u16) -> BoxFuture<'_, crate::Result<()>> {
+        Box::pin(self.forward(port))
+    }
+}
+
+impl DynDatagramSession for Session<style::Repliable> {
+    fn destination(&self) -> &str {
+        self.destination()
+    }
+
+    fn public_destination(&self) -> &str {
+        self.public_destination()
+    }
+
+    fn nickname(&self) -> &str {
+        self.nickname()
+    }
+
+    fn status(&self) -> SessionStatus {
+        self.status()
+    }
+
+    fn send_to<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+        destination: &'a str,
+    ) -> BoxFuture<'a, crate::Result<()>> {
+        Box::pin(self.send_to(buf, destination))
+    }
+
+    fn recv_from<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, crate::Result<(usize, Option<String>)>> {
+        Box::pin(async move {
+            let (nread, destination) = self.recv_from(buf).await?;
+
+            Ok((nread, Some(destination)))
+        })
+    }
+}
+
+impl DynDatagramSession for Session<style::Anonymous> {
+    fn destination(&self) -> &str {
+        self.destination()
+    }
+
+    fn public_destination(&self) -> &str {
+        self.public_destination()
+    }
+
+    fn nickname(&self) -> &str {
+        self.nickname()
+    }
+
+    fn status(&self) -> SessionStatus {
+        self.status()
+    }
+
+    fn send_to<'a>(
+        &'a mut self,
+        buf: &'a [u8],
+        destination: &'a str,
+    ) -> BoxFuture<'a, crate::Result<()>> {
+        Box::pin(self.send_to(buf, destination))
+    }
+
+    fn recv_from<'a>(
+        &'a mut self,
+        buf: &'a mut [u8],
+    ) -> BoxFuture<'a, crate::Result<(usize, Option<String>)>> {
+        Box::pin(async move { Ok((self.recv(buf).await?, None)) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{style, testing::MockSam};
+
+    #[tokio::test]
+    async fn dyn_sessions() {
+        use crate::{DynDatagramSession, DynStreamSession};
+
+        let sam = MockSam::new().unwrap();
+        let mut streams: Vec<Box<dyn DynStreamSession>> = Vec::new();
+        let mut datagrams: Vec<Box<dyn DynDatagramSession>> = Vec::new();
+
+        for _ in 0..2 {
+            streams.push(Box::new(sam.session::<style::Stream>().await.unwrap()));
+        }
+        datagrams.push(Box::new(sam.session::<style::Repliable>().await.unwrap()));
+        datagrams.push(Box::new(sam.session::<style::Anonymous>().await.unwrap()));
+
+        let [client, server] = &mut streams[..] else {
+            unreachable!()
+        };
+        let destination = server.public_destination().to_string();
+        let (stream, accepted) =
+            futures::future::join(client.connect(&destination), server.accept()).await;
+        assert_eq!(stream.unwrap().remote_destination(), destination);
+        assert_eq!(
+            accepted.unwrap().remote_destination(),
+            client.public_destination()
+        );
+
+        let [repliable, anonymous] = &mut datagrams[..] else {
+            unreachable!()
+        };
+        let mut buffer = [0u8; 16];
+
+        repliable.send_to(b"ping", anonymous.public_destination()).await.unwrap();
+        assert_eq!(anonymous.recv_from(&mut buffer).await.unwrap(), (4, None));
+
+        anonymous.send_to(b"pong", repliable.public_destination()).await.unwrap();
+        let (nread, sender) = repliable.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..nread], b"pong");
+        assert_eq!(sender.as_deref(), Some(anonymous.public_destination()));
+    }
+}
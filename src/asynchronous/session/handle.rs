@@ -0,0 +1,229 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Cloneable handle to a session.
+
+use crate::{
+    address::split_port,
+    asynchronous::{
+        session::{
+            connector::Connector,
+            style::{self, DatagramSender},
+        },
+        stream::Stream,
+    },
+    error::report_error,
+    idle::Activity,
+    ports::{datagram_port, PortAllocator},
+};
+
+use std::{collections::HashMap, marker::PhantomData, sync::Arc};
+
+/// ### Session handle.
+///
+/// Cheap, cloneable handle to a [`Session`](crate::Session), created with
+/// [`Session::handle()`](crate::Session::handle).
+///
+/// Unlike [`Session`](crate::Session), the handle takes `&self` and can be shared between tasks:
+/// each [`SessionHandle::connect()`] opens the stream over a control connection of its own and
+/// `send_to()` shares the datagram socket of the session, so any number of operations can be in
+/// progress concurrently.
+///
+/// Aliases registered with the session before the handle was created are resolved by the handle.
+/// Operations of the handle are not included in the metrics of the session but they count as its
+/// activity, see [`SessionOptions::idle_threshold`](crate::SessionOptions::idle_threshold). They
+/// fail once the session has been dropped.
+///
+/// ```no_run
+/// use yosemite::{style::Stream, Session};
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let session = Session::<Stream>::new(Default::default()).await?;
+///     let handle = session.handle();
+///
+///     let tasks = ["host1.i2p", "host2.i2p"].map(|host| {
+///         let handle = handle.clone();
+///         tokio::spawn(async move { handle.connect(host).await })
+///     });
+///
+///     for task in tasks {
+///         let _stream = task.await.expect("to succeed")?;
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub struct SessionHandle<S> {
+    /// Stream connector of the session.
+    connector: Connector,
+
+    /// Datagram sender of the session, if it's a datagram session.
+    datagrams: Option<DatagramSender>,
+
+    /// Aliases of remote destinations.
+    aliases: Arc<HashMap<String, String>>,
+
+    /// Activity of the session.
+    activity: Activity,
+
+    /// Allocator of ephemeral ports, if the session has an ephemeral port range.
+    ports: Option<PortAllocator>,
+
+    /// Session style.
+    _style: PhantomData<fn() -> S>,
+}
+
+impl<S> Clone for SessionHandle<S> {
+    fn clone(&self) -> Self {
+        Self {
+            connector: self.connector.clone(),
+            datagrams: self.datagrams.clone(),
+            aliases: Arc::clone(&self.aliases),
+            activity: self.activity.clone(),
+            ports: self.ports.clone(),
+            _style: PhantomData,
+        }
+    }
+}
+
+impl<S> SessionHandle<S> {
+    /// Create new [`SessionHandle`].
+    pub(crate) fn new(
+        connector: Connector,
+        datagrams: Option<DatagramSender>,
+        aliases: HashMap<String, String>,
+        activity: Activity,
+        ports: Option<PortAllocator>,
+    ) -> Self {
+        Self {
+            connector,
+            datagrams,
+            aliases: Arc::new(aliases),
+            activity,
+            ports,
+            _style: PhantomData,
+        }
+    }
+
+    /// Resolve `destination` if it's a registered alias.
+    fn resolve<'a>(&'a self, destination: &'a str) -> &'a str {
+        self.aliases.get(destination).map_or(destination, String::as_str)
+    }
+
+    /// Send `buf` to `destination` over the datagram socket of the session.
+    async fn send_datagram(&self, buf: &[u8], destination: &str) -> crate::Result<()> {
+        self.activity.record();
+        let from_port = datagram_port(self.ports.as_ref()).inspect_err(report_error)?;
+
+        self.datagrams
+            .as_ref()
+            .expect("datagram session")
+            .send_to(buf, self.resolve(destination), from_port)
+            .await
+            .inspect_err(report_error)
+    }
+}
+
+impl SessionHandle<style::Stream> {
+    /// Create new outbound virtual stream to `destination`.
+    ///
+    /// See [`Session::connect()`](crate::Session::connect) for supported destination formats.
+    pub async fn connect(&self, destination: &str) -> crate::Result<Stream> {
+        let (destination, port) = split_port(destination);
+
+        self.connector
+            .clone()
+            .connect(self.resolve(destination).to_string(), port)
+            .await
+            .inspect_err(report_error)
+    }
+
+    /// Create new outbound virtual stream to `port` of `destination`.
+    pub async fn connect_to_port(&self, destination: &str, port: u16) -> crate::Result<Stream> {
+        self.connector
+            .clone()
+            .connect(self.resolve(destination).to_string(), Some(port))
+            .await
+            .inspect_err(report_error)
+    }
+}
+
+impl SessionHandle<style::Repliable> {
+    /// Send data on the socket to given `destination`.
+    pub async fn send_to(&self, buf: &[u8], destination: &str) -> crate::Result<()> {
+        self.send_datagram(buf, destination).await
+    }
+}
+
+impl SessionHandle<style::Anonymous> {
+    /// Send data on the socket to given `destination`.
+    pub async fn send_to(&self, buf: &[u8], destination: &str) -> crate::Result<()> {
+        self.send_datagram(buf, destination).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        style,
+        testing::{datagram_pair, with_new_nickname, MockSam},
+        Session,
+    };
+
+    #[tokio::test]
+    async fn session_handle() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let sam = MockSam::new().unwrap();
+        let options = sam.session_options();
+
+        let mut server = Session::<style::Stream>::new(with_new_nickname(&options)).await.unwrap();
+        let mut client = Session::<style::Stream>::new(with_new_nickname(&options)).await.unwrap();
+        client.add_alias("server", server.public_destination());
+        let handle = client.handle();
+        assert_send_sync(&handle);
+
+        let connects = (0..2).map(|_| {
+            let handle = handle.clone();
+            tokio::spawn(async move { handle.connect("server").await })
+        });
+        let connects = futures::future::join_all(connects.collect::<Vec<_>>());
+        let accepts = async {
+            (
+                server.accept().await.unwrap(),
+                server.accept().await.unwrap(),
+            )
+        };
+
+        let (streams, _) = futures::future::join(connects, accepts).await;
+        for stream in streams {
+            assert_eq!(
+                stream.unwrap().unwrap().remote_destination(),
+                server.public_destination()
+            );
+        }
+
+        let (first, mut second) = datagram_pair(sam.session_options()).await.unwrap();
+        first.handle().send_to(b"ping", second.public_destination()).await.unwrap();
+
+        let mut buffer = [0u8; 16];
+        let (nread, _) = second.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..nread], b"ping");
+    }
+}
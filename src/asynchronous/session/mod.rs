@@ -19,20 +19,75 @@
 //! Asynchronous SAMv3 session.
 
 use crate::{
-    asynchronous::{session::style::SessionStyle, stream::Stream},
-    error::{Error, ProtocolError},
-    options::SessionOptions,
-    proto::session::SessionController,
+    address::split_port,
+    asynchronous::{
+        control::ControlSocket,
+        events::Events,
+        gate::{Gate, GateDecision, Ports},
+        session::{connector::Connector, style::SessionStyle},
+        stream::Stream,
+    },
+    backoff::{dial_delay, BackoffCache},
+    error::{report_error, Error, I2pError, ProtocolError},
+    events::SessionEvent,
+    idle::Activity,
+    metrics::MetricsSnapshot,
+    options::{DestinationKind, LookupCredentials, SessionOptions, StreamOptions},
+    ports::{assign_port, datagram_port, PortAllocator},
+    proto::session::{SessionController, StreamController},
+    redaction::{RedactedLine, RedactedValue},
+    router_info::RouterInfo,
+    slots::{acquire_slot, StreamSlots},
+    status::{SessionState, SessionStatus, SocketAddrs},
+    throttle::RateLimiter,
+    tracing::{self, Instrument, Span},
 };
 
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt, Interest},
-    net::TcpStream,
+use futures::{channel::mpsc::UnboundedReceiver, AsyncReadExt, AsyncWriteExt};
+
+use std::{
+    collections::HashMap,
+    fmt,
+    future::Future,
+    ops::Range,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use tokio_util::compat::{TokioAsyncReadCompatExt, TokioAsyncWriteCompatExt};
 
+pub use connector::ConnectFuture;
+pub use demux::PortChannel;
+pub use dynamic::{DynDatagramSession, DynStreamSession};
+pub use handle::SessionHandle;
+
+mod connector;
+mod demux;
+mod dynamic;
+mod handle;
 pub mod style;
 
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::session";
+
+/// How many times creating the session is retried in [`Session::reconfigure()`] while the router
+/// still has the old session.
+const RECONFIGURE_ATTEMPTS: usize = 10;
+
+/// How long [`Session::reconfigure()`] waits before retrying to create the session.
+const RECONFIGURE_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// How long the self-test of a datagram session waits for the test datagram.
+const SELF_TEST_DATAGRAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Create payload for a self-test of the session.
+///
+/// The payload is made unique with the current time so it can't be confused with data sent by
+/// other destinations or by an earlier self-test.
+fn self_test_payload(nickname: &str) -> Vec<u8> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+
+    format!("yosemite self-test {nickname} {nanos}").into_bytes()
+}
+
 /// ### SAMv3 session.
 ///
 /// `SessionStyle` defines the protocol of the session and can be one of three types:
@@ -117,8 +172,16 @@ pub mod style;
 /// }
 /// ```
 ///
+/// ### Closing the session
+///
+/// [`Session::close()`] sends `QUIT` and waits for the router to close the connection. A session
+/// that is dropped without closing it doesn't send `QUIT`: `Drop` cannot wait for the control
+/// socket to become writable, and a non-blocking write could leave a partial command on the
+/// connection. The control connection is closed instead and the router destroys the session once
+/// it notices that the connection has been closed.
+///
 /// See [examples](https://github.com/altonen/yosemite/tree/master/examples) for more details on how to use `yosemite`.
-pub struct Session<S> {
+pub struct Session<S: SessionStyle> {
     /// Session controller.
     controller: SessionController,
 
@@ -127,6 +190,55 @@ pub struct Session<S> {
 
     /// Context for session style.
     context: S,
+
+    /// Session events.
+    events: Events,
+
+    /// Session metrics.
+    metrics: MetricsSnapshot,
+
+    /// Activity of the session.
+    activity: Activity,
+
+    /// Allocator of ephemeral ports, if the session has an ephemeral port range.
+    ports: Option<PortAllocator>,
+
+    /// Rate limiter of the streams, if the session has rate limits.
+    limiter: Option<RateLimiter>,
+
+    /// Stream slots, if the session limits the number of streams.
+    slots: Option<StreamSlots>,
+
+    /// Backoff cache of unreachable destinations, if enabled for the session.
+    backoff: Option<BackoffCache>,
+
+    /// Gate of inbound streams and datagrams.
+    gate: Gate,
+
+    /// Span that log events of the session are attached to.
+    span: Span,
+
+    /// ID of the most recently opened virtual stream.
+    stream_id: usize,
+
+    /// Aliases of remote destinations.
+    aliases: HashMap<String, String>,
+
+    /// State of the session.
+    state: SessionState,
+}
+
+impl<S: SessionStyle + fmt::Debug> fmt::Debug for Session<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("nickname", &self.options.nickname)
+            .field(
+                "destination",
+                &format_args!("{}", RedactedValue(self.controller.destination())),
+            )
+            .field("style", &self.context)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<S: SessionStyle> Session<S> {
@@ -134,39 +246,604 @@ impl<S: SessionStyle> Session<S> {
     ///
     /// See [`SessionOptions`] for more details on how to configure the session.
     pub async fn new(options: SessionOptions) -> crate::Result<Self> {
-        let mut controller = SessionController::new(options.clone())?;
+        let span = tracing::info_span!(
+            target: LOG_TARGET,
+            "session",
+            nickname = %options.nickname,
+            destination = tracing::field::Empty,
+        );
+        let ports = options
+            .ephemeral_ports
+            .clone()
+            .map(PortAllocator::new)
+            .transpose()
+            .inspect_err(report_error)?;
+        let limiter = RateLimiter::new(&options).inspect_err(report_error)?;
+        let slots = StreamSlots::new(&options).inspect_err(report_error)?;
+        let backoff = options
+            .dial_backoff
+            .map(BackoffCache::new)
+            .transpose()
+            .inspect_err(report_error)?;
+        let (controller, context) = Self::create_session(&options)
+            .instrument(span.clone())
+            .await
+            .inspect_err(report_error)?;
+        span.record(
+            "destination",
+            tracing::field::display(RedactedValue(controller.destination())),
+        );
+
+        let session = Self {
+            controller,
+            options,
+            context,
+            events: Events::default(),
+            metrics: MetricsSnapshot::default(),
+            activity: Activity::new(),
+            ports,
+            limiter,
+            slots,
+            backoff,
+            gate: Gate::default(),
+            span,
+            stream_id: 0usize,
+            aliases: HashMap::new(),
+            state: SessionState::Active,
+        };
+
+        if let Some(threshold) = session.options.idle_threshold {
+            session.watch_idle(threshold);
+        }
+
+        Ok(session)
+    }
+
+    /// Report the session idle after `threshold` without activity.
+    ///
+    /// The watcher task exits once the session has been dropped.
+    fn watch_idle(&self, threshold: Duration) {
+        let mut watcher = self.activity.watcher(threshold);
+        let events = self.events.clone();
+
+        tokio::spawn(
+            async move {
+                while let Some(delay) = watcher.poll(|event| events.emit(event)) {
+                    tokio::time::sleep(delay).await;
+                }
+            }
+            .instrument(self.span.clone()),
+        );
+    }
+
+    /// Handshake with the router and create the session.
+    async fn create_session(options: &SessionOptions) -> crate::Result<(SessionController, S)> {
+        options.check_signature_type()?;
+
         let mut context = S::new(options.clone()).await?;
+        let controller = Self::create_session_on(options, &mut context).await?;
+
+        Ok((controller, context))
+    }
+
+    /// Handshake with the router over the control socket of `context` and create the session.
+    async fn create_session_on(
+        options: &SessionOptions,
+        context: &mut S,
+    ) -> crate::Result<SessionController> {
+        let mut controller = SessionController::new(options.clone())?;
 
         // send handhake to router
+        //
+        // if handshakes are pipelined, the handshake is sent together with `SESSION CREATE`
         let command = controller.handshake_session()?;
-        context.write_command(&command).await?;
 
-        // read handshake response and create new session
-        let response = context.read_command().await?;
-        controller.handle_response(&response)?;
+        if !options.pipeline_handshake {
+            context.write_command(command).await?;
+
+            let response = context.read_command().await?;
+            controller.handle_response(response)?;
+        }
 
         // create new session
         let command = controller.create_session(context.create_session())?;
-        context.write_command(&command).await?;
+        context.write_command(command).await?;
 
-        // read handshake response and create new session
+        if options.pipeline_handshake {
+            let response = context.read_command().await?;
+            controller.handle_response(response)?;
+        }
+
+        // read session status
         let response = context.read_command().await?;
-        controller.handle_response(&response)?;
+        controller.handle_response(response)?;
 
-        Ok(Self {
-            controller,
-            options,
-            context,
-        })
+        Ok(controller)
     }
 
     /// Get destination of the [`Session`].
+    ///
+    /// The destination is followed by the private keys of the session and it can be used to
+    /// recreate the session with [`DestinationKind::Persistent`]. It must not be shared with peers,
+    /// see [`Session::public_destination()`].
     pub fn destination(&self) -> &str {
         self.controller.destination()
     }
+
+    /// Get public destination of the [`Session`].
+    ///
+    /// This is the destination peers see for streams and datagrams of the session and which they
+    /// connect to. Unlike [`Session::destination()`], it doesn't contain the private keys.
+    pub fn public_destination(&self) -> &str {
+        self.controller.public_destination()
+    }
+
+    /// Get router details reported during the handshake of the [`Session`].
+    ///
+    /// The negotiated version can be used to enable features only supported by newer routers.
+    pub fn router_info(&self) -> &RouterInfo {
+        self.controller.router_info().expect("session to be handshaked")
+    }
+
+    /// Get options the [`Session`] was created with.
+    pub fn options(&self) -> &SessionOptions {
+        &self.options
+    }
+
+    /// Get nickname of the [`Session`].
+    ///
+    /// The nickname is the ID of the session in SAMv3 commands.
+    pub fn nickname(&self) -> &str {
+        &self.options.nickname
+    }
+
+    /// Get a new stream controller for a control connection of the session.
+    fn stream_controller(&self) -> StreamController {
+        self.controller.stream_controller().expect("session to be active")
+    }
+
+    /// Get a new connector which opens streams independently of the session.
+    fn connector(&self) -> Connector {
+        Connector {
+            controller: self.stream_controller(),
+            options: self.options.clone(),
+            events: self.events.clone(),
+            activity: self.activity.clone(),
+            ports: self.ports.clone(),
+            limiter: self.limiter.clone(),
+            slots: self.slots.clone(),
+            backoff: self.backoff.clone(),
+        }
+    }
+
+    /// Get a cloneable handle to the [`Session`].
+    ///
+    /// The handle can open streams and send datagrams concurrently from multiple tasks, see
+    /// [`SessionHandle`]. Aliases registered after the handle has been created are not resolved
+    /// by it.
+    pub fn handle(&self) -> SessionHandle<S> {
+        SessionHandle::new(
+            self.connector(),
+            self.context.datagram_sender(),
+            self.aliases.clone(),
+            self.activity.clone(),
+            self.ports.clone(),
+        )
+    }
+
+    /// Register `alias` for `destination`.
+    ///
+    /// Aliases are resolved by [`Session::connect()`], [`Session::connect_to_port()`] and
+    /// `send_to()` before the destination is passed to the router, so peers discovered at runtime
+    /// can be referred to with short, stable names. The alias can be suffixed with `:port` in
+    /// [`Session::connect()`].
+    ///
+    /// Returns the previous destination of `alias`, if any.
+    pub fn add_alias(
+        &mut self,
+        alias: impl Into<String>,
+        destination: impl Into<String>,
+    ) -> Option<String> {
+        self.aliases.insert(alias.into(), destination.into())
+    }
+
+    /// Remove `alias`, returning its destination.
+    pub fn remove_alias(&mut self, alias: &str) -> Option<String> {
+        self.aliases.remove(alias)
+    }
+
+    /// Get destination of `alias`.
+    pub fn alias(&self, alias: &str) -> Option<&str> {
+        self.aliases.get(alias).map(String::as_str)
+    }
+
+    /// Get snapshot of the metrics of the [`Session`].
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        self.metrics
+    }
+
+    /// Get snapshot of the status of the [`Session`].
+    pub fn status(&self) -> SessionStatus {
+        SessionStatus {
+            state: self.state,
+            style: self.context.create_session().style,
+            destination: self.public_destination().to_string(),
+            b32_address: crate::b32_address(self.public_destination()),
+            nickname: self.options.nickname.clone(),
+            forwarding: self.context.forwarding(),
+        }
+    }
+
+    /// Get local and peer address of the control connection of the [`Session`].
+    ///
+    /// Virtual streams and forwarding use connections of their own, see
+    /// [`Stream::socket_addrs()`].
+    pub fn control_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.context.control_socket_addrs()
+    }
+
+    /// Get the time elapsed since the last activity of the [`Session`].
+    ///
+    /// See [`SessionOptions::idle_threshold`] for what counts as activity.
+    pub fn idle_time(&self) -> Duration {
+        self.activity.idle_time()
+    }
+
+    /// Call `hook` with the idle time of the [`Session`] when it becomes idle.
+    ///
+    /// The hook is called from a background task once [`SessionOptions::idle_threshold`] has
+    /// passed without activity, before [`SessionEvent::Idle`] is emitted. It replaces any
+    /// previously set hook and it's never called if the threshold is not set.
+    pub fn set_idle_hook(&mut self, hook: impl Fn(Duration) + Send + Sync + 'static) {
+        self.activity.set_hook(Arc::new(hook));
+    }
+
+    /// Admit inbound streams and datagrams with `gate`.
+    ///
+    /// The gate is called with the destination and the ports of each stream accepted with
+    /// [`Session::accept()`] after [`SessionOptions::accept_filter`], and of each repliable
+    /// datagram received by the session or its port channels. Denied streams are closed and
+    /// counted in [`MetricsSnapshot::streams_rejected`] and denied datagrams are dropped, and in
+    /// both cases the session keeps waiting for the next one. This allows admission control based
+    /// on, e.g., the reputation or the recent activity of the remote destination.
+    ///
+    /// Datagrams are received by port channels in the order they arrive, so a slow gate delays the
+    /// datagrams behind it. The gate replaces any previously set gate.
+    ///
+    /// ```no_run
+    /// use yosemite::{style::Stream, GateDecision, Session};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> yosemite::Result<()> {
+    ///     let mut session = Session::<Stream>::new(Default::default()).await?;
+    ///
+    ///     session.set_gate(|destination, ports| async move {
+    ///         match ports.to_port {
+    ///             22 => GateDecision::Deny,
+    ///             _ => GateDecision::Allow,
+    ///         }
+    ///     });
+    ///
+    ///     let stream = session.accept().await?;
+    ///     # Ok(())
+    /// }
+    /// ```
+    pub fn set_gate<F, Fut>(&mut self, gate: F)
+    where
+        F: Fn(String, Ports) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = GateDecision> + Send + 'static,
+    {
+        self.gate.set(gate);
+    }
+
+    /// Subscribe to events of the [`Session`].
+    ///
+    /// Each call returns a new receiver which receives the events emitted after the call.
+    pub fn events(&self) -> UnboundedReceiver<SessionEvent> {
+        self.events.subscribe(SessionEvent::Created {
+            destination: self.public_destination().to_string(),
+        })
+    }
+
+    /// Send raw `command` on the control connection of the session and return the reply of the
+    /// router, without the line terminator.
+    ///
+    /// This is meant for experimenting with SAMv3 commands that `yosemite` doesn't support. Line
+    /// breaks cannot be represented in a SAMv3 command and are replaced with spaces.
+    ///
+    /// ### Warning
+    ///
+    /// The command bypasses the state machine of the session. A command that changes the state of
+    /// the session on the router, such as `SESSION ADD` or `QUIT`, or a command for which the
+    /// router doesn't send exactly one reply leaves the session out of sync with the router and
+    /// subsequent calls may fail or hang.
+    pub async fn send_raw_command(&mut self, command: &str) -> crate::Result<String> {
+        let command = command.trim_end().replace(['\r', '\n'], " ");
+
+        tracing::debug!(
+            target: LOG_TARGET,
+            parent: &self.span,
+            command = %RedactedLine(&command),
+            "send raw command",
+        );
+
+        let span = self.span.clone();
+
+        let result = async {
+            self.context.write_command(format!("{command}\n").as_bytes()).await?;
+            Ok(self.context.read_command().await?.to_string())
+        }
+        .instrument(span)
+        .await;
+
+        if let Err(error) = &result {
+            report_error(error);
+
+            if let Error::IoError(_) = error {
+                self.state = SessionState::Disconnected;
+                self.events.emit(SessionEvent::Disconnected);
+            }
+        }
+
+        result
+    }
+
+    /// Recreate the session with the tunnel parameters of `options`.
+    ///
+    /// The session is recreated with the same nickname and destination keys, so the destination
+    /// stays the same and handles of the session, such as [`SessionHandle`] and [`PortChannel`],
+    /// keep working. Only the tunnel parameters of `options` are applied:
+    /// [`SessionOptions::inbound_len`], [`SessionOptions::inbound_len_variance`],
+    /// [`SessionOptions::inbound_quantity`], their outbound counterparts and
+    /// [`SessionOptions::publish`]. Other options keep the values the session was created with.
+    ///
+    /// Virtual streams of the old session are closed by the router and stream forwarding must be
+    /// requested again with [`Session::forward()`]. If recreating the session fails, the old
+    /// session may already have been closed and the session should be dropped.
+    ///
+    /// ```no_run
+    /// use yosemite::{style::Stream, Session, SessionOptions};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> yosemite::Result<()> {
+    ///     let mut session = Session::<Stream>::new(SessionOptions::low_latency()).await?;
+    ///
+    ///     // switch to longer tunnels without changing the destination
+    ///     session.reconfigure(SessionOptions::high_anonymity()).await
+    /// }
+    /// ```
+    pub async fn reconfigure(&mut self, options: SessionOptions) -> crate::Result<()> {
+        let span = self.span.clone();
+
+        self.reconfigure_inner(options).instrument(span).await.inspect_err(report_error)
+    }
+
+    async fn reconfigure_inner(&mut self, options: SessionOptions) -> crate::Result<()> {
+        let options = self.options.with_tunnel_parameters(&options);
+        let create_options = SessionOptions {
+            destination: DestinationKind::Persistent {
+                private_key: match &options.destination {
+                    DestinationKind::Persistent { private_key } => private_key.clone(),
+                    DestinationKind::Transient => self.controller.destination().to_string(),
+                },
+            },
+            ..options.clone()
+        };
+        let mut attempts = 0;
+
+        tracing::info!(
+            target: LOG_TARGET,
+            inbound_len = %options.inbound_len,
+            inbound_quantity = %options.inbound_quantity,
+            outbound_len = %options.outbound_len,
+            outbound_quantity = %options.outbound_quantity,
+            publish = %options.publish,
+            "reconfigure session",
+        );
+
+        // the router destroys the old session once its control connection has been closed but
+        // possibly not before the new session with the same nickname and keys is created
+        let controller = loop {
+            self.context.reconnect(create_options.clone()).await?;
+
+            match Self::create_session_on(&create_options, &mut self.context).await {
+                Err(Error::Protocol(ProtocolError::Router(
+                    I2pError::DuplicateId | I2pError::DuplicateDest,
+                ))) if attempts < RECONFIGURE_ATTEMPTS => {
+                    attempts += 1;
+                    tokio::time::sleep(RECONFIGURE_RETRY_DELAY).await;
+                }
+                result => break result?,
+            }
+        };
+
+        self.controller = controller;
+        self.options = options;
+        self.state = SessionState::Active;
+
+        Ok(())
+    }
+
+    /// Close the session.
+    ///
+    /// `QUIT` is sent to the router, which destroys the session and releases its nickname, and the
+    /// connections of the session to the router are closed. Inbound virtual streams are no longer
+    /// forwarded and channels of the session, such as [`PortChannel`], are closed. The call returns
+    /// once the router has closed the control connection of the session.
+    ///
+    /// `QUIT` was introduced in SAMv3.2, older routers destroy the session when its control
+    /// connection is closed. Closing a session that is already closed is a no-op.
+    ///
+    /// Virtual streams that have already been opened are not closed and operations on a closed
+    /// session fail since the router no longer knows the session. If the session is dropped
+    /// without closing it, its control connection is closed without sending `QUIT` and the router
+    /// destroys the session once it notices that the connection has been closed.
+    pub async fn close(&mut self) -> crate::Result<()> {
+        let span = self.span.clone();
+
+        self.close_inner().instrument(span).await.inspect_err(report_error)
+    }
+
+    async fn close_inner(&mut self) -> crate::Result<()> {
+        if self.state == SessionState::Closed {
+            return Ok(());
+        }
+
+        tracing::info!(
+            target: LOG_TARGET,
+            "close session",
+        );
+
+        let result = match self.state {
+            SessionState::Active => self.quit().await,
+            _ => Ok(()),
+        };
+
+        self.context.close().await;
+        self.state = SessionState::Closed;
+
+        result
+    }
+
+    /// Send `QUIT` over the control connection, if the router supports it.
+    ///
+    /// I/O errors are ignored since they mean that the router has already closed the connection.
+    async fn quit(&mut self) -> crate::Result<()> {
+        let command = match self.controller.quit() {
+            Ok(command) => command,
+            Err(ProtocolError::UnsupportedByRouter { .. }) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        if self.context.write_command(command).await.is_err() {
+            return Ok(());
+        }
+
+        // the router may close the connection without acknowledging `QUIT`
+        match self.context.read_command().await {
+            Ok(response) => self.controller.handle_response(response).map_err(From::from),
+            Err(_) => Ok(()),
+        }
+    }
 }
 
 impl Session<style::Stream> {
+    /// Create span for the next virtual stream of the session.
+    fn stream_span(&mut self, destination: Option<&str>) -> Span {
+        self.stream_id += 1;
+
+        tracing::debug_span!(
+            target: LOG_TARGET,
+            parent: &self.span,
+            "stream",
+            id = self.stream_id,
+            destination = destination.map(|destination| tracing::field::display(RedactedValue(destination))),
+        )
+    }
+
+    /// Open control connection for a stream and handshake it.
+    ///
+    /// If handshakes are pipelined, the handshake is sent with the next command instead.
+    async fn handshake_stream(&mut self) -> crate::Result<ControlSocket> {
+        let mut stream = ControlSocket::from_options(&self.options)
+            .await?
+            .with_events(self.events.clone());
+        let command = self.controller.handshake_stream()?;
+
+        if !self.options.pipeline_handshake {
+            stream.write_command(command).await?;
+
+            let response = stream.read_response().await?;
+            self.controller.handle_response(response)?;
+        }
+
+        Ok(stream)
+    }
+
+    /// Open virtual stream to `destination` over a pooled control connection, if there is one.
+    ///
+    /// Returns `Ok(None)` if the pool is empty or if the pooled connection has been closed, in
+    /// which case the connection is discarded and the stream must be opened over a new one.
+    async fn connect_pooled(
+        &mut self,
+        destination: &str,
+        port: Option<u16>,
+        options: &StreamOptions,
+    ) -> crate::Result<Option<ControlSocket>> {
+        let Some(mut stream) = self.context.take_pooled() else {
+            return Ok(None);
+        };
+
+        self.controller.resume_stream()?;
+        let command = self.connect_command(destination, port, options)?;
+
+        let result = match stream.write_command(command).await {
+            Ok(()) => stream.read_response().await,
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(response) => {
+                self.controller.handle_response(response)?;
+                Ok(Some(stream))
+            }
+            Err(error) => {
+                tracing::debug!(
+                    target: LOG_TARGET,
+                    ?error,
+                    "pooled control connection failed, discarding",
+                );
+                report_error(&error);
+                self.controller.reset_stream()?;
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Fill the pool of handshaked control connections up to `connect_pool_size`.
+    async fn fill_pool(&mut self) -> crate::Result<()> {
+        while self.context.pooled() < self.options.connect_pool_size {
+            let mut stream = ControlSocket::from_options(&self.options)
+                .await?
+                .with_events(self.events.clone());
+            let command = self.controller.handshake_stream()?;
+            stream.write_command(command).await?;
+
+            let response = stream.read_response().await?;
+            self.controller.handle_response(response)?;
+            self.controller.reset_stream()?;
+
+            self.context.store_pooled(stream);
+        }
+
+        Ok(())
+    }
+
+    /// Read reply to `STREAM CONNECT`/`STREAM ACCEPT`/`STREAM FORWARD`.
+    ///
+    /// If handshakes are pipelined, the handshake reply is read first. Silent commands have no
+    /// reply so only the handshake reply, if any, is read.
+    async fn read_stream_status(
+        &mut self,
+        stream: &mut ControlSocket,
+        silent: bool,
+    ) -> crate::Result<()> {
+        if self.options.pipeline_handshake {
+            let response = stream.read_response().await?;
+            self.controller.handle_response(response)?;
+        }
+
+        if silent {
+            return Ok(());
+        }
+
+        let response = stream.read_response().await?;
+        self.controller.handle_response(response)?;
+
+        Ok(())
+    }
+
     /// Create new outbound virtual stream to `destination`.
     ///
     /// Destination can
@@ -174,105 +851,388 @@ impl Session<style::Stream> {
     ///  * base32-encoded session received from
     ///    [`RouterApi::lookup_name()`](crate::RouterApi::lookup_name)
     ///  * base64-encoded string received from, e.g., [`Session::new()`]
+    ///
+    /// Host names and base32 addresses can be suffixed with `:port`, e.g., `service.i2p:8080`, to
+    /// connect to a specific port of the destination like [`Session::connect_to_port()`].
     pub async fn connect(&mut self, destination: &str) -> crate::Result<Stream> {
-        let mut stream =
-            TcpStream::connect(format!("127.0.0.1:{}", self.options.samv3_tcp_port)).await?;
-        let command = self.controller.handshake_stream()?;
-        stream.write_all(&command).await?;
+        let (destination, port) = split_port(destination);
+        let alias = self.aliases.get(destination).cloned();
+
+        let options = StreamOptions::default();
+
+        self.connect_inner(alias.as_deref().unwrap_or(destination), port, &options)
+            .await
+    }
+
+    /// Create new outbound virtual stream to `port` of `destination`.
+    ///
+    /// See [`Session::connect()`] for supported destination formats.
+    pub async fn connect_to_port(&mut self, destination: &str, port: u16) -> crate::Result<Stream> {
+        let alias = self.aliases.get(destination).cloned();
+
+        let options = StreamOptions::default();
 
-        let (mut stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+        self.connect_inner(
+            alias.as_deref().unwrap_or(destination),
+            Some(port),
+            &options,
+        )
+        .await
+    }
 
-        let command = self.controller.create_stream(&destination)?;
-        stream.write_all(&command).await?;
+    /// Create new outbound virtual stream to `destination` without borrowing the session.
+    ///
+    /// The returned [`ConnectFuture`] opens the stream over a control connection of its own,
+    /// independently of the stream state of the session, so the session can be used and more
+    /// streams can be opened while the future is pending. Streams opened this way don't use the
+    /// control connection pool and are not included in the metrics of the session.
+    ///
+    /// The future fails if the session is dropped before the stream has been opened.
+    ///
+    /// See [`Session::connect()`] for supported destination formats.
+    pub fn connect_detached(&mut self, destination: &str) -> ConnectFuture {
+        let (destination, port) = split_port(destination);
+        let destination =
+            self.aliases.get(destination).map_or(destination, String::as_str).to_string();
+        let span = self.stream_span(Some(&destination));
+        ConnectFuture::new(self.connector(), destination, port, span)
+    }
 
-        let (stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+    /// Create new outbound virtual stream to encrypted `destination`.
+    ///
+    /// `credentials` are used by the router to look up the lease set of `destination` if it's
+    /// hosted with [`LeaseSetType::Encrypted`](crate::LeaseSetType::Encrypted) and requires a
+    /// lookup secret or client authorization. If the router rejects the credentials,
+    /// [`Error::CredentialsRejected`] is returned.
+    ///
+    /// See [`Session::connect()`] for supported destination formats.
+    pub async fn connect_with_credentials(
+        &mut self,
+        destination: &str,
+        credentials: &LookupCredentials,
+    ) -> crate::Result<Stream> {
+        let (destination, port) = split_port(destination);
+        let alias = self.aliases.get(destination).cloned();
 
-        let compat = TokioAsyncReadCompatExt::compat(stream).into_inner();
-        let stream = TokioAsyncWriteCompatExt::compat_write(compat);
+        let options = StreamOptions {
+            credentials: Some(credentials.clone()),
+            ..Default::default()
+        };
+
+        self.connect_inner(alias.as_deref().unwrap_or(destination), port, &options)
+            .await
+    }
+
+    /// Create new outbound virtual stream to `destination` with stream `options`.
+    ///
+    /// If [`StreamOptions::silent`] is set, the stream is returned as soon as `STREAM CONNECT` has
+    /// been sent and connection errors are only seen when using the stream.
+    ///
+    /// See [`Session::connect()`] for supported destination formats.
+    pub async fn connect_with_options(
+        &mut self,
+        destination: &str,
+        options: &StreamOptions,
+    ) -> crate::Result<Stream> {
+        let (destination, port) = split_port(destination);
+        let alias = self.aliases.get(destination).cloned();
 
-        Ok(Stream::from_stream(stream, destination.to_string()))
+        self.connect_inner(alias.as_deref().unwrap_or(destination), port, options).await
+    }
+
+    /// Serialize `STREAM CONNECT` to `destination`.
+    fn connect_command(
+        &mut self,
+        destination: &str,
+        port: Option<u16>,
+        options: &StreamOptions,
+    ) -> Result<&[u8], ProtocolError> {
+        let with_options = options.silent || options.from_port.is_some();
+
+        match (port, with_options, &options.credentials) {
+            (port, false, Some(credentials)) =>
+                self.controller.create_stream_with_credentials(destination, port, credentials),
+            (Some(port), false, None) => self.controller.create_stream_to_port(destination, port),
+            (None, false, None) => self.controller.create_stream(destination),
+            (port, true, _) =>
+                self.controller.create_stream_with_options(destination, port, options),
+        }
+    }
+
+    /// Create new outbound virtual stream and record the result.
+    async fn connect_inner(
+        &mut self,
+        destination: &str,
+        port: Option<u16>,
+        options: &StreamOptions,
+    ) -> crate::Result<Stream> {
+        let span = self.stream_span(Some(destination));
+        let mut result = self.open_stream(destination, port, options).instrument(span).await;
+        if let Some(backoff) = &self.backoff {
+            backoff.record(destination, &result);
+        }
+
+        if options.credentials.is_some() {
+            result = result.map_err(Error::into_credentials_error);
+        }
+        self.metrics.record_connect(&result);
+        self.activity.record();
+
+        if let Err(error) = &result {
+            report_error(error);
+        }
+
+        result
     }
 
     /// Accept inbound virtual stream.
     ///
     /// The function call will fail if [`Session::forward()`] has been called before.
     pub async fn accept(&mut self) -> crate::Result<Stream> {
-        let mut stream =
-            TcpStream::connect(format!("127.0.0.1:{}", self.options.samv3_tcp_port)).await?;
-        let command = self.controller.handshake_stream()?;
-        stream.write_all(&command).await?;
+        self.accept_with_options(&StreamOptions::default()).await
+    }
+
+    /// Accept inbound virtual stream with stream `options`.
+    ///
+    /// If [`StreamOptions::silent`] is set, the router doesn't send the destination of the remote
+    /// peer and [`Stream::remote_destination()`] of the accepted stream is empty.
+    ///
+    /// The function call will fail if [`Session::forward()`] has been called before.
+    pub async fn accept_with_options(&mut self, options: &StreamOptions) -> crate::Result<Stream> {
+        let span = self.stream_span(None);
+        let result = self.accept_stream(options).instrument(span).await;
+        self.metrics.record_accept(&result);
+        self.activity.record();
 
-        let (mut stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+        match &result {
+            Ok(stream) => self.events.emit(SessionEvent::StreamAccepted {
+                destination: stream.remote_destination().to_string(),
+            }),
+            Err(error) => report_error(error),
+        }
 
-        let command = self.controller.accept_stream()?;
-        stream.write_all(&command).await?;
+        result
+    }
 
-        let (mut stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+    /// Open outbound virtual stream to `destination`, optionally to a specific `port`.
+    ///
+    /// Silent streams are not opened over pooled connections as a closed pooled connection
+    /// cannot be detected without a reply from the router.
+    ///
+    /// If the session has an ephemeral port range, the stream is opened from a port leased from it
+    /// unless `options` specify the port.
+    ///
+    /// If the session limits the number of streams, a slot is taken before the stream is opened.
+    /// If `destination` is in backoff, the call fails or waits for the backoff to end.
+    async fn open_stream(
+        &mut self,
+        destination: &str,
+        port: Option<u16>,
+        options: &StreamOptions,
+    ) -> crate::Result<Stream> {
+        if let Some(delay) = dial_delay(self.backoff.as_ref(), destination)? {
+            tokio::time::sleep(delay).await;
+        }
 
-        // read accept response from the socket
-        //
-        // the server may have bundled data after the newline but that should not be read by this
-        // function as it's inteded for the client to read
-        let response = {
-            let mut response = [0u8; 1024];
-
-            let destination = loop {
-                let ready = stream.ready(Interest::READABLE).await?;
-
-                if ready.is_readable() {
-                    let nread = stream.peek(&mut response).await?;
-
-                    if let Some(newline) = response[..nread].iter().position(|c| c == &b'\n') {
-                        let _ = stream.read_exact(&mut response[..newline + 1]).await?;
-                        break std::str::from_utf8(&response[..newline])
-                            .map_err(|_| Error::Protocol(ProtocolError::InvalidMessage))?
-                            .to_string();
-                    }
-                }
-            };
+        let slot = acquire_slot(self.slots.as_ref()).await?;
+        let (options, local_port) = assign_port(self.ports.as_ref(), options)?;
+        let options = options.as_ref();
 
-            destination
+        let pooled = match options.silent {
+            true => None,
+            false => self.connect_pooled(destination, port, options).await?,
         };
 
-        let compat = TokioAsyncReadCompatExt::compat(stream).into_inner();
-        let stream = TokioAsyncWriteCompatExt::compat_write(compat);
+        let stream = match pooled {
+            Some(stream) => stream,
+            None => {
+                let mut stream = self.handshake_stream().await?;
+
+                let command = self.connect_command(destination, port, options)?;
+                stream.write_command(command).await?;
+                self.read_stream_status(&mut stream, options.silent).await?;
+
+                stream
+            }
+        };
+
+        if let Err(error) = self.fill_pool().await {
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?error,
+                "failed to fill control connection pool",
+            );
+            report_error(&error);
+            self.controller.reset_stream()?;
+        }
+
+        Ok(Stream::from_stream(
+            stream.into_inner(),
+            destination.to_string(),
+            self.options.stream_write_buffer_size,
+        )
+        .with_port(local_port)
+        .with_slot(slot)
+        .with_throttles(self.limiter.as_ref()))
+    }
+
+    /// Wait for inbound virtual stream.
+    ///
+    /// Streams rejected by [`SessionOptions::accept_filter`] are closed and the next stream is
+    /// accepted in their place.
+    async fn accept_stream(&mut self, options: &StreamOptions) -> crate::Result<Stream> {
+        let slot = acquire_slot(self.slots.as_ref()).await?;
+
+        loop {
+            let mut stream = self.handshake_stream().await?;
+
+            let command = self.controller.accept_stream_with_options(options)?;
+            stream.write_command(command).await?;
+            self.read_stream_status(&mut stream, options.silent).await?;
+
+            if options.silent {
+                return Ok(Stream::from_stream(
+                    stream.into_inner(),
+                    String::new(),
+                    self.options.stream_write_buffer_size,
+                )
+                .with_slot(slot)
+                .with_throttles(self.limiter.as_ref()));
+            }
+
+            // read remote's destination which signals that the connection is open
+            //
+            // the server may have bundled data after the newline but it stays in the reader's
+            // buffer and is returned to the client on the first read from the stream
+            let line = stream.read_response().await?.trim_end();
+            let (remote_destination, ports) = line.split_once(' ').unwrap_or((line, ""));
+            let (remote_destination, ports) = (remote_destination.to_string(), Ports::parse(ports));
+
+            if self
+                .options
+                .accept_filter
+                .as_ref()
+                .is_some_and(|filter| !filter.accepts(&remote_destination))
+            {
+                tracing::debug!(
+                    target: LOG_TARGET,
+                    remote_destination = %RedactedValue(&remote_destination),
+                    "inbound stream rejected by accept filter",
+                );
+                self.metrics.record_rejected();
+                continue;
+            }
 
-        Ok(Stream::from_stream(stream, response.to_string()))
+            if !self.gate.admits(&remote_destination, ports).await {
+                self.metrics.record_rejected();
+                continue;
+            }
+
+            return Ok(Stream::from_stream(
+                stream.into_inner(),
+                remote_destination,
+                self.options.stream_write_buffer_size,
+            )
+            .with_slot(slot)
+            .with_throttles(self.limiter.as_ref()));
+        }
     }
 
     /// Forward inbound virtual streams to a TCP listener at `port`.
     ///
     /// The function call will fail if [`Session::accept()`] has been called before.
     pub async fn forward(&mut self, port: u16) -> crate::Result<()> {
-        let mut stream =
-            TcpStream::connect(format!("127.0.0.1:{}", self.options.samv3_tcp_port)).await?;
-        let command = self.controller.handshake_stream()?;
-        stream.write_all(&command).await?;
+        let span = self.stream_span(None);
+
+        self.forward_stream(port).instrument(span).await.inspect_err(report_error)
+    }
 
-        let (mut stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+    /// Forward inbound virtual streams to `port`.
+    async fn forward_stream(&mut self, port: u16) -> crate::Result<()> {
+        let mut stream = self.handshake_stream().await?;
 
         let command = self.controller.forward_stream(port)?;
-        stream.write_all(&command).await?;
-
-        let (stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+        stream.write_command(command).await?;
+        self.read_stream_status(&mut stream, false).await?;
 
         // store the command stream into the session context so the router keeps forwarding streams
-        style::Stream::store_forwarded(&mut self.context, stream);
+        style::Stream::store_forwarded(&mut self.context, stream, port, self.events.clone());
 
         Ok(())
     }
+
+    /// Test the session by opening a virtual stream to its own destination.
+    ///
+    /// Data written to the stream is echoed back over the inbound stream and compared to what was
+    /// sent, which checks that the tunnels of the session are built, its lease set has been
+    /// published and the SAM bridge relays data.
+    ///
+    /// The test accepts one inbound stream, so it should be run before the session starts
+    /// accepting streams. If another destination connects to the session while the test is
+    /// running, its stream is dropped and the test fails with [`Error::SelfTestFailed`]. The test
+    /// has no timeout of its own and should be wrapped in [`tokio::time::timeout()`].
+    ///
+    /// Returns the round-trip time of the test data.
+    pub async fn self_test(&mut self) -> crate::Result<Duration> {
+        self.self_test_stream().await.inspect_err(report_error)
+    }
+
+    /// Run self-test over a virtual stream.
+    async fn self_test_stream(&mut self) -> crate::Result<Duration> {
+        let destination = self.public_destination().to_string();
+        let payload = self_test_payload(&self.options.nickname);
+
+        let connect = self.connect_detached(&destination);
+        let (mut inbound, mut outbound) = futures::future::try_join(self.accept(), connect).await?;
+
+        if inbound.remote_destination() != destination {
+            return Err(Error::SelfTestFailed(
+                "stream accepted from another destination",
+            ));
+        }
+
+        let started = Instant::now();
+        let mut buffer = vec![0u8; payload.len()];
+
+        outbound.write_all(&payload).await?;
+        outbound.flush().await?;
+        inbound.read_exact(&mut buffer).await?;
+        inbound.write_all(&buffer).await?;
+        inbound.flush().await?;
+
+        buffer.fill(0);
+        outbound.read_exact(&mut buffer).await?;
+
+        if buffer != payload {
+            return Err(Error::SelfTestFailed("echoed data doesn't match"));
+        }
+
+        Ok(started.elapsed())
+    }
 }
 
 impl Session<style::Repliable> {
+    /// Get local address of the datagram socket of the [`Session`] and the address of the
+    /// SAMv3 UDP port datagrams are sent to.
+    pub fn datagram_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.context.datagram_socket_addrs()
+    }
+
     /// Send data on the socket to given `destination`.
+    ///
+    /// `destination` can be an alias registered with [`Session::add_alias()`]. If the session has
+    /// [`SessionOptions::ephemeral_ports`], the datagram is sent from the next port of the range.
     pub async fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
-        style::Repliable::send_to(&mut self.context, buf, destination).await
+        let destination = self.aliases.get(destination).map_or(destination, String::as_str);
+        let from_port = datagram_port(self.ports.as_ref()).inspect_err(report_error)?;
+
+        style::Repliable::send_to(&mut self.context, buf, destination, from_port)
+            .await
+            .inspect_err(report_error)?;
+        self.metrics.record_datagram_sent(buf.len());
+        self.activity.record();
+
+        Ok(())
     }
 
     /// Receive a single datagram on the socket.
@@ -281,14 +1241,134 @@ impl Session<style::Repliable> {
     ///
     /// Returns the number of bytes read and the destination who sent the datagram.
     pub async fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, String)> {
-        style::Repliable::recv_from(&mut self.context, buf).await
+        let (nread, destination) = style::Repliable::recv_from(&mut self.context, buf, &self.gate)
+            .await
+            .inspect_err(report_error)?;
+        self.metrics.record_datagram_received(nread);
+        self.activity.record();
+
+        Ok((nread, destination))
+    }
+
+    /// Receive a single datagram on the socket without copying it.
+    ///
+    /// The datagram is received directly into `buf` which must be of sufficient size to hold
+    /// both the datagram header and the entire datagram.
+    ///
+    /// Returns the range of `buf` holding the datagram and the destination who sent the datagram.
+    pub async fn recv_from_in_place(
+        &mut self,
+        buf: &mut [u8],
+    ) -> crate::Result<(Range<usize>, String)> {
+        let (range, destination) =
+            style::Repliable::recv_from_in_place(&mut self.context, buf, &self.gate)
+                .await
+                .inspect_err(report_error)?;
+        self.metrics.record_datagram_received(range.len());
+        self.activity.record();
+
+        Ok((range, destination))
+    }
+
+    /// Test the session by sending a datagram to its own destination.
+    ///
+    /// The datagram is received back and compared to what was sent, which checks that the tunnels
+    /// of the session are built, its lease set has been published and the SAM bridge relays
+    /// datagrams.
+    ///
+    /// Datagrams received from other destinations while the test is running are discarded, so the
+    /// test should be run before the session starts receiving datagrams. Datagrams are not
+    /// reliable and if the test datagram isn't received within 60 seconds, the test fails with
+    /// [`Error::SelfTestFailed`].
+    ///
+    /// Returns the round-trip time of the datagram.
+    pub async fn self_test(&mut self) -> crate::Result<Duration> {
+        let destination = self.public_destination().to_string();
+        let payload = self_test_payload(&self.options.nickname);
+        let mut buffer = vec![0u8; payload.len() + 1];
+
+        let started = Instant::now();
+        self.send_to(&payload, &destination).await?;
+
+        let received = tokio::time::timeout(SELF_TEST_DATAGRAM_TIMEOUT, async {
+            loop {
+                let (nread, sender) = self.recv_from(&mut buffer).await?;
+
+                if sender == destination && buffer[..nread] == payload[..] {
+                    return Ok(started.elapsed());
+                }
+            }
+        });
+
+        match received.await {
+            Ok(result) => result,
+            Err(_) => Err(Error::SelfTestFailed("test datagram wasn't received"))
+                .inspect_err(report_error),
+        }
+    }
+
+    /// Create channel for datagrams sent to `port` of the session.
+    ///
+    /// Once a channel has been created, inbound datagrams are read by a background task which
+    /// routes each datagram to the channel of its `TO_PORT`, so one session can serve several
+    /// protocols concurrently. Datagrams sent to ports without a channel, including datagrams sent
+    /// without a port, are still received with [`Session::recv_from()`], unless a channel has
+    /// been created for port `0`. Datagrams received with [`Session::recv_from_in_place()`] start
+    /// at the beginning of `buf`.
+    ///
+    /// Creating a channel for a port which already has one closes the previous channel.
+    ///
+    /// ```no_run
+    /// use yosemite::{style::Repliable, Session};
+    ///
+    /// #[tokio::main]
+    /// async fn main() -> yosemite::Result<()> {
+    ///     let mut session = Session::<Repliable>::new(Default::default()).await?;
+    ///     let mut dns = session.port_channel(53);
+    ///
+    ///     tokio::spawn(async move {
+    ///         let mut buffer = vec![0u8; 4096];
+    ///
+    ///         while let Ok((nread, destination)) = dns.recv_from(&mut buffer).await {
+    ///             // handle query
+    ///         }
+    ///     });
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn port_channel(&mut self, port: u16) -> PortChannel {
+        style::Repliable::port_channel(
+            &mut self.context,
+            port,
+            self.activity.clone(),
+            self.gate.clone(),
+        )
     }
 }
 
 impl Session<style::Anonymous> {
+    /// Get local address of the datagram socket of the [`Session`] and the address of the
+    /// SAMv3 UDP port datagrams are sent to.
+    pub fn datagram_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.context.datagram_socket_addrs()
+    }
+
     /// Send data on the socket to given `destination`.
+    ///
+    /// `destination` can be an alias registered with [`Session::add_alias()`]. If the session has
+    /// [`SessionOptions::ephemeral_ports`], the datagram is sent from the next port of the range.
     pub async fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
-        style::Anonymous::send_to(&mut self.context, buf, destination).await
+        let destination = self.aliases.get(destination).map_or(destination, String::as_str);
+        let from_port = datagram_port(self.ports.as_ref()).inspect_err(report_error)?;
+
+        style::Anonymous::send_to(&mut self.context, buf, destination, from_port)
+            .await
+            .inspect_err(report_error)?;
+        self.metrics.record_datagram_sent(buf.len());
+        self.activity.record();
+
+        Ok(())
     }
 
     /// Receive a single datagram on the socket.
@@ -297,6 +1377,580 @@ impl Session<style::Anonymous> {
     ///
     /// Returns the number of bytes read.
     pub async fn recv(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
-        style::Anonymous::recv(&mut self.context, buf).await
+        let nread =
+            style::Anonymous::recv(&mut self.context, buf).await.inspect_err(report_error)?;
+        self.metrics.record_datagram_received(nread);
+        self.activity.record();
+
+        Ok(nread)
+    }
+
+    /// Test the session by sending a datagram to its own destination.
+    ///
+    /// The datagram is received back and compared to what was sent, which checks that the tunnels
+    /// of the session are built, its lease set has been published and the SAM bridge relays
+    /// datagrams.
+    ///
+    /// Datagrams received while the test is running are discarded until the test datagram
+    /// arrives, so the test should be run before the session starts receiving datagrams.
+    /// Datagrams are not reliable and if the test datagram isn't received within 60 seconds, the
+    /// test fails with [`Error::SelfTestFailed`].
+    ///
+    /// Returns the round-trip time of the datagram.
+    pub async fn self_test(&mut self) -> crate::Result<Duration> {
+        let destination = self.public_destination().to_string();
+        let payload = self_test_payload(&self.options.nickname);
+        let mut buffer = vec![0u8; payload.len() + 1];
+
+        let started = Instant::now();
+        self.send_to(&payload, &destination).await?;
+
+        let received = tokio::time::timeout(SELF_TEST_DATAGRAM_TIMEOUT, async {
+            loop {
+                let nread = self.recv(&mut buffer).await?;
+
+                if buffer[..nread] == payload[..] {
+                    return Ok(started.elapsed());
+                }
+            }
+        });
+
+        match received.await {
+            Ok(result) => result,
+            Err(_) => Err(Error::SelfTestFailed("test datagram wasn't received"))
+                .inspect_err(report_error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        datagram_pair, random_destination, stream_pair, with_new_nickname, MockSam,
+    };
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    async fn connect_and_accept() {
+        let sam = MockSam::new().unwrap();
+        let mut server = sam.session::<style::Stream>().await.unwrap();
+        let mut client = sam.session::<style::Stream>().await.unwrap();
+        let destination = server.public_destination().to_string();
+        let client_destination = client.public_destination().to_string();
+
+        let handle = tokio::spawn(async move {
+            let mut stream = server.accept().await.unwrap();
+            assert_eq!(stream.remote_destination(), client_destination);
+
+            let mut buffer = [0u8; 5];
+            stream.read_exact(&mut buffer).await.unwrap();
+            stream.write_all(&buffer).await.unwrap();
+        });
+
+        let mut stream = client.connect(&destination).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+
+        let mut buffer = [0u8; 5];
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn forward() {
+        let sam = MockSam::new().unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut server = sam.session::<style::Stream>().await.unwrap();
+        let mut client = sam.session::<style::Stream>().await.unwrap();
+        server.forward(port).await.unwrap();
+        sam.add_name("server.i2p", server.public_destination());
+
+        let mut stream = client.connect("server.i2p").await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+
+        let (mut forwarded, _) = listener.accept().await.unwrap();
+        let expected = format!("{}\nhello", client.public_destination());
+        let mut buffer = vec![0u8; expected.len()];
+        tokio::io::AsyncReadExt::read_exact(&mut forwarded, &mut buffer).await.unwrap();
+        assert_eq!(buffer, expected.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn status() {
+        let sam = MockSam::new().unwrap();
+        let options = sam.session_options();
+        let mut session = Session::<style::Stream>::new(options.clone()).await.unwrap();
+
+        let status = session.status();
+        assert_eq!(status.state, crate::SessionState::Active);
+        assert_eq!(status.style, "STREAM");
+        assert_eq!(status.destination, session.public_destination());
+        assert_eq!(
+            status.b32_address,
+            crate::b32_address(session.destination())
+        );
+        assert_eq!(status.nickname, options.nickname);
+        assert_eq!(status.forwarding, crate::ForwardingState::Inactive);
+
+        session.forward(8888).await.unwrap();
+        assert_eq!(
+            session.status().forwarding,
+            crate::ForwardingState::Active { port: 8888 }
+        );
+
+        let session = sam.session::<style::Repliable>().await.unwrap();
+        assert_eq!(session.status().style, "DATAGRAM");
+    }
+
+    #[tokio::test]
+    async fn socket_addrs() {
+        let sam = MockSam::new().unwrap();
+        let pair = stream_pair(sam.session_options()).await.unwrap();
+
+        let control = pair.client.control_socket_addrs().unwrap();
+        let stream = pair.client_stream.socket_addrs().unwrap();
+        assert_eq!(control.peer.port(), sam.tcp_port());
+        assert_eq!(stream.peer.port(), sam.tcp_port());
+        assert_ne!(control.local, stream.local);
+
+        let session = sam.session::<style::Repliable>().await.unwrap();
+        let datagram = session.datagram_socket_addrs().unwrap();
+        assert_eq!(datagram.peer.port(), sam.udp_port());
+        assert_ne!(datagram.local.port(), 0);
+    }
+
+    #[tokio::test]
+    async fn repliable_datagrams() {
+        let sam = MockSam::new().unwrap();
+        let mut first = sam.session::<style::Repliable>().await.unwrap();
+        let mut second = sam.session::<style::Repliable>().await.unwrap();
+
+        first.send_to(b"ping", second.public_destination()).await.unwrap();
+
+        let mut buffer = [0u8; 16];
+        let (nread, destination) = second.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..nread], b"ping");
+        assert_eq!(destination, first.public_destination());
+    }
+
+    #[tokio::test]
+    async fn connect_host_port() {
+        let sam = MockSam::new().unwrap();
+        sam.respond_to("STREAM CONNECT", "STREAM STATUS RESULT=PEER_NOT_FOUND");
+
+        let mut session = sam.session::<style::Stream>().await.unwrap();
+        assert!(session.connect("service.i2p:8080").await.is_err());
+        assert!(
+            sam.commands().iter().any(|command| command.starts_with("STREAM CONNECT")
+                && command.contains("DESTINATION=service.i2p SILENT=false TO_PORT=8080"))
+        );
+    }
+
+    #[tokio::test]
+    async fn aliases() {
+        let sam = MockSam::new().unwrap();
+        sam.respond_to("STREAM CONNECT", "STREAM STATUS RESULT=PEER_NOT_FOUND");
+
+        let mut session = sam.session::<style::Stream>().await.unwrap();
+        assert_eq!(session.add_alias("api", "service.i2p"), None);
+        assert_eq!(session.alias("api"), Some("service.i2p"));
+
+        assert!(session.connect("api:8080").await.is_err());
+        assert!(
+            sam.commands().iter().any(|command| command.starts_with("STREAM CONNECT")
+                && command.contains("DESTINATION=service.i2p SILENT=false TO_PORT=8080"))
+        );
+
+        assert_eq!(session.remove_alias("api").as_deref(), Some("service.i2p"));
+        assert_eq!(session.alias("api"), None);
+
+        let (mut first, mut second) = datagram_pair(sam.session_options()).await.unwrap();
+        first.add_alias("peer", second.public_destination());
+        first.send_to(b"ping", "peer").await.unwrap();
+
+        let mut buffer = [0u8; 16];
+        let (nread, _) = second.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..nread], b"ping");
+    }
+
+    #[tokio::test]
+    async fn debug_output() {
+        let sam = MockSam::new().unwrap();
+        let pair = stream_pair(sam.session_options()).await.unwrap();
+        let destination = pair.server.public_destination();
+
+        let session = format!("{:?}", pair.server);
+        assert!(session.contains(&pair.server.options().nickname));
+        assert!(session.contains("peer_addr"));
+        assert!(!session.contains(destination));
+
+        let stream = format!("{:?}", pair.client_stream);
+        assert!(stream.contains(&destination[..8]));
+        assert!(!stream.contains(destination));
+        assert!(stream.contains("local_addr"));
+
+        let (first, _) = datagram_pair(sam.session_options()).await.unwrap();
+        assert!(format!("{first:?}").contains("server_address"));
+    }
+
+    #[tokio::test]
+    async fn silent_streams() {
+        let sam = MockSam::new().unwrap();
+        let options = sam.session_options();
+        let silent = crate::StreamOptions {
+            silent: true,
+            ..Default::default()
+        };
+
+        let mut server = Session::<style::Stream>::new(with_new_nickname(&options)).await.unwrap();
+        let mut client = Session::<style::Stream>::new(with_new_nickname(&options)).await.unwrap();
+        let destination = server.public_destination().to_string();
+
+        let (mut server_stream, mut client_stream) = futures::future::try_join(
+            server.accept_with_options(&silent),
+            client.connect_with_options(&destination, &silent),
+        )
+        .await
+        .unwrap();
+        assert_eq!(server_stream.remote_destination(), "");
+
+        client_stream.write_all(b"ping").await.unwrap();
+        let mut buffer = [0u8; 4];
+        server_stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"ping");
+
+        assert!(
+            sam.commands()
+                .iter()
+                .any(|command| command.starts_with("STREAM ACCEPT")
+                    && command.contains("SILENT=true"))
+        );
+        assert!(sam.commands().iter().any(
+            |command| command.starts_with("STREAM CONNECT") && command.contains("SILENT=true")
+        ));
+    }
+
+    #[tokio::test]
+    async fn lookup_credentials() {
+        let sam = MockSam::new().unwrap();
+        let credentials = crate::LookupCredentials {
+            secret: Some("secret".to_string()),
+            auth: None,
+        };
+
+        sam.respond_to("STREAM CONNECT", "STREAM STATUS RESULT=INVALID_KEY");
+        let mut session = sam.session::<style::Stream>().await.unwrap();
+
+        assert!(matches!(
+            session.connect_with_credentials("host.i2p", &credentials).await,
+            Err(crate::Error::CredentialsRejected(I2pError::InvalidKey))
+        ));
+        assert!(
+            sam.commands().iter().any(|command| command.starts_with("STREAM CONNECT")
+                && command.contains("i2cp.leaseSetSecret=c2VjcmV0"))
+        );
+
+        sam.respond_to(
+            "NAMING LOOKUP",
+            "NAMING REPLY RESULT=INVALID_KEY NAME=host.i2p",
+        );
+        assert!(matches!(
+            crate::RouterApi::new(sam.tcp_port())
+                .lookup_name_with_credentials("host.i2p", &credentials)
+                .await,
+            Err(crate::Error::CredentialsRejected(I2pError::InvalidKey))
+        ));
+    }
+
+    #[tokio::test]
+    async fn session_options() {
+        let sam = MockSam::new().unwrap();
+        let options = SessionOptions {
+            nickname: "options-test".to_string(),
+            ..sam.session_options()
+        };
+        let session = Session::<style::Stream>::new(options.clone()).await.unwrap();
+
+        assert_eq!(session.options(), &options);
+        assert_eq!(session.nickname(), "options-test");
+        assert!(session.router_info().supports(3, 1));
+    }
+
+    #[tokio::test]
+    async fn idle_session() {
+        use crate::SessionEvent;
+        use futures::StreamExt;
+
+        let sam = MockSam::new().unwrap();
+        let mut session = Session::<style::Stream>::new(SessionOptions {
+            idle_threshold: Some(Duration::from_millis(50)),
+            ..sam.session_options()
+        })
+        .await
+        .unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        session.set_idle_hook(move |idle_for| tx.send(idle_for).unwrap());
+        let mut events = session.events();
+
+        assert!(matches!(
+            events.next().await,
+            Some(SessionEvent::Created { destination })
+                if destination == session.public_destination()
+        ));
+        assert!(matches!(
+            events.next().await,
+            Some(SessionEvent::Idle { idle_for }) if idle_for >= Duration::from_millis(50)
+        ));
+        assert!(rx.try_recv().unwrap() >= Duration::from_millis(50));
+
+        // opening a stream is activity, even if it fails
+        sam.respond_to("STREAM CONNECT", "STREAM STATUS RESULT=PEER_NOT_FOUND");
+        assert!(session.connect("host.i2p").await.is_err());
+        assert!(session.idle_time() < Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn ephemeral_ports() {
+        let sam = MockSam::new().unwrap();
+        let mut server = sam.session::<style::Stream>().await.unwrap();
+        let mut client = Session::<style::Stream>::new(SessionOptions {
+            ephemeral_ports: Some(50000..=50009),
+            ..sam.session_options()
+        })
+        .await
+        .unwrap();
+        let destination = server.public_destination().to_string();
+
+        let (inbound, outbound) =
+            futures::future::try_join(server.accept(), client.connect(&destination))
+                .await
+                .unwrap();
+
+        assert_eq!(outbound.local_port(), Some(50000));
+        assert_eq!(inbound.local_port(), None);
+        assert!(sam
+            .commands()
+            .iter()
+            .any(|command| command.starts_with("STREAM CONNECT")
+                && command.contains("FROM_PORT=50000")));
+    }
+
+    #[tokio::test]
+    async fn rate_limited_streams() {
+        let sam = MockSam::new().unwrap();
+        let mut pair = stream_pair(SessionOptions {
+            stream_rate_limit: Some(crate::RateLimit::new(1000).with_burst(100)),
+            ..sam.session_options()
+        })
+        .await
+        .unwrap();
+
+        // the burst is written at once and the rest at the rate of the limit
+        let started = std::time::Instant::now();
+        let payload = [0xaau8; 300];
+        let (_, buffer) = futures::future::join(
+            async { pair.client_stream.write_all(&payload).await.unwrap() },
+            async {
+                let mut buffer = [0u8; 300];
+                pair.server_stream.read_exact(&mut buffer).await.unwrap();
+                buffer
+            },
+        )
+        .await;
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        assert_eq!(buffer, payload);
+
+        // limits must be non-zero
+        assert!(Session::<style::Stream>::new(SessionOptions {
+            session_rate_limit: Some(crate::RateLimit::new(0)),
+            ..sam.session_options()
+        })
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn stream_limit() {
+        let sam = MockSam::new().unwrap();
+        let pair = stream_pair(SessionOptions {
+            max_streams: Some(1),
+            stream_limit_policy: crate::StreamLimitPolicy::Reject,
+            ..sam.session_options()
+        })
+        .await
+        .unwrap();
+        let (mut client, mut server) = (pair.client, pair.server);
+        let destination = server.public_destination().to_string();
+
+        assert!(matches!(
+            client.connect(&destination).await,
+            Err(crate::Error::TooManyStreams)
+        ));
+        assert!(matches!(
+            server.accept().await,
+            Err(crate::Error::TooManyStreams)
+        ));
+
+        // dropped streams release their slots
+        drop((pair.client_stream, pair.server_stream));
+        futures::future::try_join(server.accept(), client.connect(&destination))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn accept_filter() {
+        let sam = MockSam::new().unwrap();
+        let options = sam.session_options();
+        let mut denied = Session::<style::Stream>::new(with_new_nickname(&options)).await.unwrap();
+        let mut allowed = Session::<style::Stream>::new(with_new_nickname(&options)).await.unwrap();
+        let mut server = Session::<style::Stream>::new(SessionOptions {
+            accept_filter: Some(crate::AcceptFilter::Allow(vec![crate::b32_address(
+                allowed.destination(),
+            )
+            .unwrap()])),
+            ..with_new_nickname(&options)
+        })
+        .await
+        .unwrap();
+        let destination = server.public_destination().to_string();
+
+        // the rejected stream is closed and the server keeps accepting
+        let (stream, _, _) =
+            futures::future::try_join3(server.accept(), denied.connect(&destination), async {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                allowed.connect(&destination).await
+            })
+            .await
+            .unwrap();
+        assert_eq!(stream.remote_destination(), allowed.public_destination());
+        assert_eq!(server.metrics_snapshot().streams_rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn dial_backoff() {
+        let sam = MockSam::new().unwrap();
+        let mut session = Session::<style::Stream>::new(SessionOptions {
+            dial_backoff: Some(Default::default()),
+            ..sam.session_options()
+        })
+        .await
+        .unwrap();
+        let destination = random_destination();
+
+        assert!(matches!(
+            session.connect(&destination).await,
+            Err(crate::Error::Protocol(crate::ProtocolError::Router(
+                I2pError::CantReachPeer
+            )))
+        ));
+        let connects = || {
+            sam.commands()
+                .iter()
+                .filter(|command| command.starts_with("STREAM CONNECT"))
+                .count()
+        };
+        assert_eq!(connects(), 1);
+
+        // the destination is in backoff for the session and its handles
+        assert!(matches!(
+            session.connect(&destination).await,
+            Err(crate::Error::DialBackoff { .. })
+        ));
+        assert!(matches!(
+            session.handle().connect(&destination).await,
+            Err(crate::Error::DialBackoff { .. })
+        ));
+        assert_eq!(connects(), 1);
+    }
+
+    #[tokio::test]
+    async fn self_test() {
+        let sam = MockSam::new().unwrap();
+
+        let mut session = sam.session::<style::Stream>().await.unwrap();
+        session.self_test().await.unwrap();
+        assert_eq!(session.metrics_snapshot().streams_accepted, 1);
+
+        let mut session = sam.session::<style::Repliable>().await.unwrap();
+        session.self_test().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconfigure() {
+        let sam = MockSam::new().unwrap();
+        let mut server = sam.session::<style::Stream>().await.unwrap();
+        let mut client = sam.session::<style::Stream>().await.unwrap();
+        let handle = client.handle();
+        let private_key = server.destination().to_string();
+        let destination = server.public_destination().to_string();
+
+        server.reconfigure(SessionOptions::low_latency()).await.unwrap();
+        client.reconfigure(SessionOptions::high_anonymity()).await.unwrap();
+
+        assert_eq!(server.destination(), private_key);
+        assert_eq!(server.public_destination(), destination);
+        assert_eq!(server.options().inbound_len, 1);
+        assert_eq!(server.options().samv3_tcp_port, sam.tcp_port());
+        assert!(sam.commands().iter().any(|command| {
+            command.starts_with(&format!(
+                "SESSION CREATE STYLE=STREAM ID={}",
+                server.nickname()
+            )) && command.contains(&format!("DESTINATION={private_key}"))
+                && command.contains("inbound.length=1")
+        }));
+
+        // handle created before the client was reconfigured opens streams over the new session
+        let (stream, accepted) =
+            futures::future::join(handle.connect(&destination), server.accept()).await;
+        assert_eq!(stream.unwrap().remote_destination(), destination);
+        assert_eq!(
+            accepted.unwrap().remote_destination(),
+            client.public_destination()
+        );
+    }
+
+    #[tokio::test]
+    async fn close_session() {
+        let sam = MockSam::new().unwrap();
+        let options = SessionOptions {
+            nickname: "closed".to_string(),
+            ..sam.session_options()
+        };
+        let mut session = Session::<style::Stream>::new(options.clone()).await.unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        session.forward(listener.local_addr().unwrap().port()).await.unwrap();
+
+        session.close().await.unwrap();
+        assert_eq!(session.status().state, crate::SessionState::Closed);
+        assert_eq!(
+            session.status().forwarding,
+            crate::ForwardingState::Inactive
+        );
+        assert!(sam.commands().contains(&"QUIT".to_string()));
+
+        // closing the session again is a no-op and the nickname has been released
+        session.close().await.unwrap();
+        let session = Session::<style::Stream>::new(options.clone()).await.unwrap();
+
+        // dropped sessions close the control connection without writing to it, so a partially
+        // written `QUIT` can't be left on the connection if the send buffer is full
+        drop(session);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while Session::<style::Stream>::new(options.clone()).await.is_err() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(
+            sam.commands().iter().filter(|command| *command == "QUIT").count(),
+            1
+        );
     }
 }
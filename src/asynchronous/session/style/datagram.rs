@@ -16,20 +16,85 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-#![cfg(all(feature = "async", not(feature = "sync")))]
+#![cfg(feature = "async")]
 
 use crate::{
+    asynchronous::{
+        control::ControlSocket,
+        gate::{Gate, Ports},
+        session::{
+            demux::{Demux, PortChannel},
+            style::{private, SessionStyle},
+        },
+    },
+    idle::Activity,
     options::SessionOptions,
-    style::{private, SessionStyle},
+    proto::{
+        command::{Command, CommandWriter, DatagramHeader},
+        parser::parse_datagram_header,
+    },
+    status::SocketAddrs,
     Error,
 };
 
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{TcpStream, UdpSocket},
-};
+use tokio::net::UdpSocket;
+
+use std::{fmt, net::SocketAddr, ops::Range, sync::Arc};
+
+/// Send `buf` to `destination` over the SAMv3 UDP port of the router at `server_address`,
+/// optionally from a specific port.
+async fn send_datagram(
+    socket: &UdpSocket,
+    server_address: SocketAddr,
+    nickname: &str,
+    buf: &[u8],
+    destination: &str,
+    from_port: Option<u16>,
+) -> crate::Result<()> {
+    let mut writer = CommandWriter::new();
+    let header = DatagramHeader {
+        id: nickname,
+        destination,
+        from_port,
+    };
+    let mut datagram = header.write(&mut writer).to_vec();
+    datagram.extend_from_slice(buf);
+
+    socket.send_to(&datagram, server_address).await.map(|_| ()).map_err(From::from)
+}
+
+/// Datagram sender which shares the datagram socket of a session.
+#[derive(Clone)]
+pub struct DatagramSender {
+    /// Nickname of the session.
+    nickname: String,
+
+    /// Server UDP address.
+    server_address: SocketAddr,
 
-use std::{future::Future, net::SocketAddr};
+    /// Datagram socket of the session.
+    socket: Arc<UdpSocket>,
+}
+
+impl DatagramSender {
+    /// Send `buf` to `destination`.
+    pub(crate) async fn send_to(
+        &self,
+        buf: &[u8],
+        destination: &str,
+        from_port: Option<u16>,
+    ) -> crate::Result<()> {
+        send_datagram(
+            &self.socket,
+            self.server_address,
+            &self.nickname,
+            buf,
+            destination,
+            from_port,
+        )
+        .await
+    }
+}
 
 /// Repliable datagrams.
 pub struct Repliable {
@@ -43,99 +108,188 @@ pub struct Repliable {
     server_address: SocketAddr,
 
     /// Datagram socket.
-    socket: UdpSocket,
+    socket: Arc<UdpSocket>,
+
+    /// Control socket used to communicate with the router.
+    stream: ControlSocket,
 
-    /// TCP stream used to communicate with the router.
-    stream: BufReader<TcpStream>,
+    /// Demultiplexer of inbound datagrams, if port channels have been created.
+    demux: Option<Demux>,
 }
 
 impl Repliable {
-    pub(crate) async fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
-        let mut datagram =
-            format!("3.0 {} {}\n", self.options.nickname, destination).as_bytes().to_vec();
-        datagram.extend_from_slice(buf);
+    /// Get local address of the datagram socket and the address of the SAMv3 UDP port.
+    pub(crate) fn datagram_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        Ok(SocketAddrs {
+            local: self.socket.local_addr()?,
+            peer: self.server_address,
+        })
+    }
 
-        self.socket
-            .send_to(&datagram, &self.server_address)
-            .await
-            .map(|_| ())
-            .map_err(From::from)
+    pub(crate) async fn send_to(
+        &mut self,
+        buf: &[u8],
+        destination: &str,
+        from_port: Option<u16>,
+    ) -> crate::Result<()> {
+        send_datagram(
+            &self.socket,
+            self.server_address,
+            &self.options.nickname,
+            buf,
+            destination,
+            from_port,
+        )
+        .await
     }
 
-    pub(crate) async fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, String)> {
-        let nread = self.socket.recv(&mut self.buffer).await?;
+    /// Receive datagram admitted by `gate`.
+    ///
+    /// Datagrams received by the demultiplexer have already been admitted by it.
+    pub(crate) async fn recv_from(
+        &mut self,
+        buf: &mut [u8],
+        gate: &Gate,
+    ) -> crate::Result<(usize, String)> {
+        if let Some(demux) = &mut self.demux {
+            return demux.recv_from(buf).await;
+        }
+
+        loop {
+            let nread = self.socket.recv(&mut self.buffer).await?;
+            let (destination, offset) =
+                parse_datagram_header(&self.buffer[..nread]).ok_or(Error::Malformed)?;
 
-        let destination = {
-            let destination_end =
-                self.buffer[..nread].iter().position(|byte| byte == &b' ').unwrap();
+            let ports = Ports::from_datagram_header(&self.buffer[..offset]);
+            if !gate.admits(destination, ports).await {
+                continue;
+            }
 
-            std::str::from_utf8(&self.buffer[..destination_end])
-                .map_err(|_| Error::Malformed)?
-                .to_owned()
-        };
+            let datagram_len = (nread - offset).min(buf.len());
+            buf[..datagram_len].copy_from_slice(&self.buffer[offset..offset + datagram_len]);
 
-        let nread = {
-            let header_end = self.buffer[..nread].iter().position(|byte| byte == &b'\n').unwrap();
-            let datagram_len = nread - header_end - 1;
-            buf[..datagram_len].copy_from_slice(&self.buffer[header_end + 1..nread]);
+            return Ok((datagram_len, destination.to_owned()));
+        }
+    }
+
+    /// Receive datagram admitted by `gate` into `buf`.
+    pub(crate) async fn recv_from_in_place(
+        &mut self,
+        buf: &mut [u8],
+        gate: &Gate,
+    ) -> crate::Result<(Range<usize>, String)> {
+        // datagrams received by the demultiplexer have already been copied out of the socket
+        if let Some(demux) = &mut self.demux {
+            return demux.recv_from(buf).await.map(|(nread, destination)| (0..nread, destination));
+        }
 
-            datagram_len
-        };
+        loop {
+            let nread = self.socket.recv(buf).await?;
+            let (destination, offset) =
+                parse_datagram_header(&buf[..nread]).ok_or(Error::Malformed)?;
 
-        Ok((nread, destination))
+            if gate.admits(destination, Ports::from_datagram_header(&buf[..offset])).await {
+                return Ok((offset..nread, destination.to_owned()));
+            }
+        }
+    }
+
+    /// Create channel for datagrams sent to `port`, starting the demultiplexer if needed.
+    pub(crate) fn port_channel(
+        &mut self,
+        port: u16,
+        activity: Activity,
+        gate: Gate,
+    ) -> PortChannel {
+        self.demux
+            .get_or_insert_with(|| {
+                Demux::new(
+                    Arc::clone(&self.socket),
+                    self.options.datagram_buffer_size,
+                    gate,
+                )
+            })
+            .channel(port, activity)
     }
 }
 
 impl private::SessionStyle for Repliable {
-    fn new(options: SessionOptions) -> impl Future<Output = crate::Result<Self>>
+    async fn new(options: SessionOptions) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        async {
-            let socket = UdpSocket::bind(format!("127.0.0.1:{}", options.datagram_port)).await?;
-            let stream = BufReader::new(
-                TcpStream::connect(format!("127.0.0.1:{}", options.samv3_tcp_port)).await?,
-            );
-            let server_address =
-                format!("127.0.0.1:{}", options.samv3_udp_port).parse().expect("to succeed");
-
-            Ok(Self {
-                buffer: vec![0u8; 0xfff],
-                options,
-                server_address,
-                socket,
-                stream,
-            })
-        }
+        let socket = Arc::new(UdpSocket::bind(options.datagram_address()?).await?);
+        let stream = ControlSocket::from_options(&options).await?;
+        let server_address =
+            format!("127.0.0.1:{}", options.samv3_udp_port).parse().expect("to succeed");
+
+        Ok(Self {
+            buffer: vec![0u8; options.datagram_buffer_size],
+            options,
+            server_address,
+            socket,
+            stream,
+            demux: None,
+        })
     }
 
-    fn write_command(&mut self, command: &[u8]) -> impl Future<Output = crate::Result<()>> {
-        async { self.stream.write_all(command).await.map_err(From::from) }
+    async fn reconnect(&mut self, options: SessionOptions) -> crate::Result<()> {
+        self.stream = ControlSocket::from_options(&options).await?;
+        self.options = options;
+
+        Ok(())
     }
 
-    fn read_command(&mut self) -> impl Future<Output = crate::Result<String>> {
-        async {
-            let mut response = String::new();
+    async fn write_command(&mut self, command: &[u8]) -> crate::Result<()> {
+        self.stream.write_command(command).await
+    }
 
-            self.stream.read_line(&mut response).await.map(|_| response).map_err(From::from)
-        }
+    async fn read_command(&mut self) -> crate::Result<&str> {
+        self.stream.read_response().await
+    }
+
+    async fn close(&mut self) {
+        self.demux = None;
+        self.stream.close().await;
+    }
+
+    fn datagram_sender(&self) -> Option<DatagramSender> {
+        Some(DatagramSender {
+            nickname: self.options.nickname.clone(),
+            server_address: self.server_address,
+            socket: Arc::clone(&self.socket),
+        })
     }
 
     fn create_session(&self) -> private::SessionParameters {
-        let port = self.socket.local_addr().expect("to succeed").port();
+        let address = self.socket.local_addr().expect("to succeed");
 
         private::SessionParameters {
             style: "DATAGRAM".to_string(),
             options: Vec::from_iter([
-                ("PORT".to_string(), port.to_string()),
-                ("HOST".to_string(), "127.0.0.1".to_string()),
+                ("PORT".to_string(), address.port().to_string()),
+                ("HOST".to_string(), address.ip().to_string()),
             ]),
         }
     }
+
+    fn control_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.stream.socket_addrs()
+    }
 }
 
 impl SessionStyle for Repliable {}
 
+impl fmt::Debug for Repliable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Repliable")
+            .field("local_addr", &self.socket.local_addr().ok())
+            .field("server_address", &self.server_address)
+            .field("control", &self.stream)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Anonymous datagrams.
 pub struct Anonymous {
     /// Session options.
@@ -145,23 +299,36 @@ pub struct Anonymous {
     server_address: SocketAddr,
 
     /// Datagram socket.
-    socket: UdpSocket,
+    socket: Arc<UdpSocket>,
 
-    /// TCP stream used to communicate with the router.
-    stream: BufReader<TcpStream>,
+    /// Control socket used to communicate with the router.
+    stream: ControlSocket,
 }
 
 impl Anonymous {
-    pub(crate) async fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
-        let mut datagram =
-            format!("3.0 {} {}\n", self.options.nickname, destination).as_bytes().to_vec();
-        datagram.extend_from_slice(buf);
+    /// Get local address of the datagram socket and the address of the SAMv3 UDP port.
+    pub(crate) fn datagram_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        Ok(SocketAddrs {
+            local: self.socket.local_addr()?,
+            peer: self.server_address,
+        })
+    }
 
-        self.socket
-            .send_to(&datagram, &self.server_address)
-            .await
-            .map(|_| ())
-            .map_err(From::from)
+    pub(crate) async fn send_to(
+        &mut self,
+        buf: &[u8],
+        destination: &str,
+        from_port: Option<u16>,
+    ) -> crate::Result<()> {
+        send_datagram(
+            &self.socket,
+            self.server_address,
+            &self.options.nickname,
+            buf,
+            destination,
+            from_port,
+        )
+        .await
     }
 
     pub(crate) async fn recv(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
@@ -170,50 +337,75 @@ impl Anonymous {
 }
 
 impl private::SessionStyle for Anonymous {
-    fn new(options: SessionOptions) -> impl Future<Output = crate::Result<Self>>
+    async fn new(options: SessionOptions) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        async {
-            let socket = UdpSocket::bind(format!("127.0.0.1:{}", options.datagram_port)).await?;
-            let stream = BufReader::new(
-                TcpStream::connect(format!("127.0.0.1:{}", options.samv3_tcp_port)).await?,
-            );
-            let server_address =
-                format!("127.0.0.1:{}", options.samv3_udp_port).parse().expect("to succeed");
-
-            Ok(Self {
-                options,
-                server_address,
-                socket,
-                stream,
-            })
-        }
+        let socket = Arc::new(UdpSocket::bind(options.datagram_address()?).await?);
+        let stream = ControlSocket::from_options(&options).await?;
+        let server_address =
+            format!("127.0.0.1:{}", options.samv3_udp_port).parse().expect("to succeed");
+
+        Ok(Self {
+            options,
+            server_address,
+            socket,
+            stream,
+        })
     }
 
-    fn write_command(&mut self, command: &[u8]) -> impl Future<Output = crate::Result<()>> {
-        async { self.stream.write_all(command).await.map_err(From::from) }
+    async fn reconnect(&mut self, options: SessionOptions) -> crate::Result<()> {
+        self.stream = ControlSocket::from_options(&options).await?;
+        self.options = options;
+
+        Ok(())
     }
 
-    fn read_command(&mut self) -> impl Future<Output = crate::Result<String>> {
-        async {
-            let mut response = String::new();
+    async fn write_command(&mut self, command: &[u8]) -> crate::Result<()> {
+        self.stream.write_command(command).await
+    }
 
-            self.stream.read_line(&mut response).await.map(|_| response).map_err(From::from)
-        }
+    async fn read_command(&mut self) -> crate::Result<&str> {
+        self.stream.read_response().await
+    }
+
+    async fn close(&mut self) {
+        self.stream.close().await;
+    }
+
+    fn datagram_sender(&self) -> Option<DatagramSender> {
+        Some(DatagramSender {
+            nickname: self.options.nickname.clone(),
+            server_address: self.server_address,
+            socket: Arc::clone(&self.socket),
+        })
     }
 
     fn create_session(&self) -> private::SessionParameters {
-        let port = self.socket.local_addr().expect("to succeed").port();
+        let address = self.socket.local_addr().expect("to succeed");
 
         private::SessionParameters {
             style: "RAW".to_string(),
             options: Vec::from_iter([
-                ("PORT".to_string(), port.to_string()),
-                ("HOST".to_string(), "127.0.0.1".to_string()),
+                ("PORT".to_string(), address.port().to_string()),
+                ("HOST".to_string(), address.ip().to_string()),
             ]),
         }
     }
+
+    fn control_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.stream.socket_addrs()
+    }
 }
 
 impl SessionStyle for Anonymous {}
+
+impl fmt::Debug for Anonymous {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Anonymous")
+            .field("local_addr", &self.socket.local_addr().ok())
+            .field("server_address", &self.server_address)
+            .field("control", &self.stream)
+            .finish_non_exhaustive()
+    }
+}
@@ -18,8 +18,9 @@
 
 //! Session style.
 
-#![cfg(all(feature = "async", not(feature = "sync")))]
+#![cfg(feature = "async")]
 
+pub(crate) use datagram::DatagramSender;
 pub use datagram::{Anonymous, Repliable};
 pub use stream::Stream;
 
@@ -27,14 +28,7 @@ mod datagram;
 mod stream;
 
 pub(crate) mod private {
-    /// Session parameters.
-    pub struct SessionParameters {
-        /// Session style.
-        pub(crate) style: String,
-
-        /// Session options.
-        pub(crate) options: Vec<(String, String)>,
-    }
+    pub use crate::proto::session::SessionParameters;
 
     pub trait SessionStyle {
         /// Create new `SessionStyle` object.
@@ -44,6 +38,16 @@ pub(crate) mod private {
         where
             Self: Sized;
 
+        /// Replace the control socket with a new connection to the router, used to recreate the
+        /// session with `options`.
+        ///
+        /// Datagram sockets are kept so the recreated session delivers datagrams to the same
+        /// socket.
+        fn reconnect(
+            &mut self,
+            options: crate::options::SessionOptions,
+        ) -> impl std::future::Future<Output = crate::Result<()>>;
+
         /// Send command to router.
         fn write_command(
             &mut self,
@@ -51,10 +55,26 @@ pub(crate) mod private {
         ) -> impl std::future::Future<Output = crate::Result<()>>;
 
         /// Read command from router.
-        fn read_command(&mut self) -> impl std::future::Future<Output = crate::Result<String>>;
+        fn read_command(&mut self) -> impl std::future::Future<Output = crate::Result<&str>>;
+
+        /// Close the connections of the session to the router and stop the tasks of the session.
+        fn close(&mut self) -> impl std::future::Future<Output = ()>;
 
         /// Get `SESSION CREATE` command for this session style.
         fn create_session(&self) -> SessionParameters;
+
+        /// Get local and peer address of the control socket.
+        fn control_socket_addrs(&self) -> crate::Result<crate::SocketAddrs>;
+
+        /// Get sender for the datagrams of the session, if the session style has datagrams.
+        fn datagram_sender(&self) -> Option<super::DatagramSender> {
+            None
+        }
+
+        /// Get the state of stream forwarding of the session.
+        fn forwarding(&self) -> crate::ForwardingState {
+            crate::ForwardingState::Inactive
+        }
     }
 }
 
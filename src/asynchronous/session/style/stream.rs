@@ -16,65 +16,135 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-#![cfg(all(feature = "async", not(feature = "sync")))]
+#![cfg(feature = "async")]
+
+use std::{collections::VecDeque, fmt};
 
 use crate::{
+    asynchronous::{
+        control::ControlSocket,
+        events::Events,
+        session::style::{private, SessionStyle},
+    },
+    error::report_error,
+    events::SessionEvent,
     options::SessionOptions,
-    style::{private, SessionStyle},
+    redaction::RedactedLine,
+    status::{ForwardingState, SocketAddrs},
+    tracing::{self, Instrument, Span},
 };
 
-use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::TcpStream,
-};
+use tokio::task::JoinHandle;
 
-use std::future::Future;
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::session::stream";
 
 /// Virtual streams.
 pub struct Stream {
-    /// TCP stream used to communicate with router.
-    stream: BufReader<TcpStream>,
+    /// Control socket used to communicate with the router.
+    stream: ControlSocket,
 
     /// Session options.
     _options: SessionOptions,
 
-    /// Socket that was sent the forwarding request, if any.
-    _forwarding_stream: Option<TcpStream>,
+    /// Forwarded port and the task watching the socket that was sent the forwarding request, if
+    /// any.
+    forwarding: Option<(u16, JoinHandle<()>)>,
+
+    /// Handshaked control connections ready to be used for outbound streams.
+    pool: VecDeque<ControlSocket>,
 }
 
 impl Stream {
-    /// Store the TCP used to send the forwarding command into [`Stream`]'s context.
-    pub(crate) fn store_forwarded(&mut self, stream: TcpStream) {
-        self._forwarding_stream = Some(stream);
+    /// Keep the socket used to send the forwarding command to `port` open.
+    ///
+    /// The socket is watched by a background task which answers `PING`s from the router and emits
+    /// [`SessionEvent::ForwardingBroken`] if the router closes the socket. The task and the socket
+    /// are closed when [`Stream`] is dropped.
+    pub(crate) fn store_forwarded(&mut self, mut stream: ControlSocket, port: u16, events: Events) {
+        let task = async move {
+            // the router doesn't send anything else after the reply to `STREAM FORWARD`
+            let error = loop {
+                match stream.read_response().await {
+                    Ok(response) => tracing::debug!(
+                        target: LOG_TARGET,
+                        response = %RedactedLine(response),
+                        "ignoring unexpected message on forwarding connection",
+                    ),
+                    Err(error) => break error,
+                }
+            };
+
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?error,
+                "forwarding connection closed",
+            );
+            report_error(&error);
+            events.emit(SessionEvent::ForwardingBroken { port });
+        };
+
+        self.forwarding = Some((port, tokio::spawn(task.instrument(Span::current()))));
+    }
+
+    /// Take a handshaked control connection from the pool, if there is one.
+    pub(crate) fn take_pooled(&mut self) -> Option<ControlSocket> {
+        self.pool.pop_front()
+    }
+
+    /// Store a handshaked control connection into the pool.
+    pub(crate) fn store_pooled(&mut self, stream: ControlSocket) {
+        self.pool.push_back(stream);
+    }
+
+    /// Get the number of pooled control connections.
+    pub(crate) fn pooled(&self) -> usize {
+        self.pool.len()
     }
 }
 
 impl private::SessionStyle for Stream {
-    fn new(_options: SessionOptions) -> impl Future<Output = crate::Result<Self>>
+    async fn new(_options: SessionOptions) -> crate::Result<Self>
     where
         Self: Sized,
     {
-        async {
-            Ok(Self {
-                stream: BufReader::new(
-                    TcpStream::connect(format!("127.0.0.1:{}", _options.samv3_tcp_port)).await?,
-                ),
-                _options,
-                _forwarding_stream: None,
-            })
+        Ok(Self {
+            stream: ControlSocket::from_options(&_options).await?,
+            _options,
+            forwarding: None,
+            pool: VecDeque::new(),
+        })
+    }
+
+    async fn reconnect(&mut self, options: SessionOptions) -> crate::Result<()> {
+        self.stream = ControlSocket::from_options(&options).await?;
+        self._options = options;
+        self.pool.clear();
+
+        // forwarding was requested for the old session which the router closes with it
+        if let Some((_, forwarding)) = self.forwarding.take() {
+            forwarding.abort();
         }
+
+        Ok(())
     }
 
-    fn write_command(&mut self, command: &[u8]) -> impl Future<Output = crate::Result<()>> {
-        async { self.stream.write_all(command).await.map_err(From::from) }
+    async fn write_command(&mut self, command: &[u8]) -> crate::Result<()> {
+        self.stream.write_command(command).await
     }
 
-    fn read_command(&mut self) -> impl Future<Output = crate::Result<String>> {
-        async {
-            let mut response = String::new();
+    async fn read_command(&mut self) -> crate::Result<&str> {
+        self.stream.read_response().await
+    }
+
+    async fn close(&mut self) {
+        self.pool.clear();
 
-            self.stream.read_line(&mut response).await.map(|_| response).map_err(From::from)
+        if let Some((_, forwarding)) = self.forwarding.take() {
+            forwarding.abort();
         }
+
+        self.stream.close().await;
     }
 
     fn create_session(&self) -> private::SessionParameters {
@@ -83,6 +153,36 @@ impl private::SessionStyle for Stream {
             options: Vec::new(),
         }
     }
+
+    fn control_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.stream.socket_addrs()
+    }
+
+    fn forwarding(&self) -> ForwardingState {
+        match &self.forwarding {
+            None => ForwardingState::Inactive,
+            Some((port, task)) if task.is_finished() => ForwardingState::Broken { port: *port },
+            Some((port, _)) => ForwardingState::Active { port: *port },
+        }
+    }
 }
 
 impl SessionStyle for Stream {}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field("control", &self.stream)
+            .field("forwarding", &self.forwarding.is_some())
+            .field("pooled", &self.pool.len())
+            .finish()
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        if let Some((_, forwarding)) = self.forwarding.take() {
+            forwarding.abort();
+        }
+    }
+}
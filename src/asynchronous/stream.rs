@@ -18,37 +18,285 @@
 
 #![cfg(feature = "async")]
 
-use futures::{AsyncRead, AsyncWrite};
-use tokio::net::TcpStream;
-use tokio_util::compat::Compat;
+use crate::{
+    ports::PortLease,
+    redaction::RedactedValue,
+    slots::StreamSlot,
+    status::SocketAddrs,
+    throttle::{RateLimiter, Throttle},
+    tracing,
+};
+
+use futures::{ready, AsyncRead, AsyncReadExt as _, AsyncWrite, AsyncWriteExt as _};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader, BufWriter, ReadBuf},
+    net::TcpStream,
+    time::Sleep,
+};
 
 use std::{
+    fmt,
+    future::Future,
+    io,
+    ops::{Deref, DerefMut},
     pin::Pin,
     task::{Context, Poll},
 };
 
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::stream";
+
+/// Size of the buffers used to bridge a stream to a TCP connection.
+const BRIDGE_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Throttle of one direction of a stream.
+struct Throttled {
+    /// Throttle.
+    throttle: Throttle,
+
+    /// Delay before the throttle grants more bytes, if any.
+    delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl Throttled {
+    /// Create new [`Throttled`].
+    fn new(throttle: Throttle) -> Self {
+        Self {
+            throttle,
+            delay: None,
+        }
+    }
+
+    /// Poll the number of bytes, at most `wanted`, that can be transferred.
+    fn poll_grant(&mut self, cx: &mut Context<'_>, wanted: usize) -> Poll<usize> {
+        loop {
+            if let Some(delay) = &mut self.delay {
+                ready!(delay.as_mut().poll(cx));
+                self.delay = None;
+            }
+
+            match self.throttle.grant(wanted) {
+                Ok(granted) => return Poll::Ready(granted),
+                Err(delay) => self.delay = Some(Box::pin(tokio::time::sleep(delay))),
+            }
+        }
+    }
+}
+
+/// Connection of a stream which sends its buffered writes when dropped.
+///
+/// As much of the buffered data as the socket accepts without blocking is written in `Drop`. If
+/// some of it remains, it's written by a background task, which requires the stream to be dropped
+/// within a Tokio runtime. Otherwise the remaining data is lost and a warning is logged.
+struct FlushOnDrop(Option<BufWriter<BufReader<TcpStream>>>);
+
+impl Deref for FlushOnDrop {
+    type Target = BufWriter<BufReader<TcpStream>>;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().expect("connection to exist until dropped")
+    }
+}
+
+impl DerefMut for FlushOnDrop {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut().expect("connection to exist until dropped")
+    }
+}
+
+impl Drop for FlushOnDrop {
+    fn drop(&mut self) {
+        let Some(stream) = self.0.take().filter(|stream| !stream.buffer().is_empty()) else {
+            return;
+        };
+        let buffer = stream.buffer().to_vec();
+        let mut written = 0usize;
+
+        while written < buffer.len() {
+            match stream.get_ref().get_ref().try_write(&buffer[written..]) {
+                Ok(0) | Err(_) => break,
+                Ok(nwritten) => written += nwritten,
+            }
+        }
+
+        if written == buffer.len() {
+            return;
+        }
+
+        // the connection is closed once the rest of the buffered data has been written
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                let mut stream = stream.into_inner();
+
+                handle.spawn(async move {
+                    if stream.write_all(&buffer[written..]).await.is_ok() {
+                        let _ = stream.shutdown().await;
+                    }
+                });
+            }
+            Err(_) => tracing::warn!(
+                target: LOG_TARGET,
+                lost = buffer.len() - written,
+                "stream dropped outside of a tokio runtime, buffered data was lost",
+            ),
+        }
+    }
+}
+
 /// Asynchronous virtual stream.
+///
+/// The stream implements [`futures::AsyncRead`] and [`futures::AsyncWrite`] directly so it can
+/// be used with `futures`-based runtimes without compatibility wrappers.
+///
+/// If writes are buffered, see
+/// [`SessionOptions::stream_write_buffer_size`](crate::SessionOptions::stream_write_buffer_size),
+/// the buffered data is sent when the stream is flushed, closed or dropped. If the socket can't
+/// take all data buffered by a dropped stream without blocking, the rest is sent in the background,
+/// which requires the stream to be dropped within a Tokio runtime. Otherwise the rest is lost, so
+/// streams dropped outside of a runtime should be flushed first.
+///
+/// If the session has rate limits, see
+/// [`SessionOptions::stream_rate_limit`](crate::SessionOptions::stream_rate_limit), reads and
+/// writes are delayed once the stream exceeds them.
 pub struct Stream {
     /// Data stream.
-    stream: Compat<TcpStream>,
+    stream: FlushOnDrop,
 
     /// Remote destination.
     remote_destination: String,
+
+    /// Local port of the stream, if it was opened from a specific port.
+    port: Option<PortLease>,
+
+    /// Slot of the stream, if the session limits the number of streams.
+    slot: Option<StreamSlot>,
+
+    /// Read throttle, if the session has rate limits.
+    read_throttle: Option<Throttled>,
+
+    /// Write throttle, if the session has rate limits.
+    write_throttle: Option<Throttled>,
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field(
+                "remote_destination",
+                &format_args!("{}", RedactedValue(&self.remote_destination)),
+            )
+            .field(
+                "local_addr",
+                &self.stream.get_ref().get_ref().local_addr().ok(),
+            )
+            .field("local_port", &self.local_port())
+            .field(
+                "peer_addr",
+                &self.stream.get_ref().get_ref().peer_addr().ok(),
+            )
+            .finish_non_exhaustive()
+    }
 }
 
 impl Stream {
     /// Create new [`Stream`] from an inbound connection.
-    pub(crate) fn from_stream(stream: Compat<TcpStream>, remote_destination: String) -> Self {
+    ///
+    /// Writes smaller than `write_buffer_size` are buffered.
+    pub(crate) fn from_stream(
+        stream: BufReader<TcpStream>,
+        remote_destination: String,
+        write_buffer_size: usize,
+    ) -> Self {
         Self {
-            stream,
+            stream: FlushOnDrop(Some(BufWriter::with_capacity(write_buffer_size, stream))),
             remote_destination,
+            port: None,
+            slot: None,
+            read_throttle: None,
+            write_throttle: None,
+        }
+    }
+
+    /// Hold `slot` until the stream is dropped.
+    pub(crate) fn with_slot(mut self, slot: Option<StreamSlot>) -> Self {
+        self.slot = slot;
+        self
+    }
+
+    /// Throttle reads and writes with the rate limits of `limiter`, if any.
+    pub(crate) fn with_throttles(mut self, limiter: Option<&RateLimiter>) -> Self {
+        if let Some((read, write)) = limiter.map(RateLimiter::throttles) {
+            self.read_throttle = Some(Throttled::new(read));
+            self.write_throttle = Some(Throttled::new(write));
         }
+        self
+    }
+
+    /// Hold `port` as the local port of the stream.
+    ///
+    /// Ports leased from the ephemeral port range of the session are released when the stream is
+    /// dropped.
+    pub(crate) fn with_port(mut self, port: Option<PortLease>) -> Self {
+        self.port = port;
+        self
     }
 
     /// Get reference to remote destination.
     pub fn remote_destination(&self) -> &str {
         &self.remote_destination
     }
+
+    /// Get the local port the stream was opened from.
+    ///
+    /// Returns `None` for inbound streams and for outbound streams opened without
+    /// [`StreamOptions::from_port`](crate::StreamOptions::from_port) if the session has no
+    /// [`SessionOptions::ephemeral_ports`](crate::SessionOptions::ephemeral_ports).
+    pub fn local_port(&self) -> Option<u16> {
+        self.port.as_ref().map(PortLease::port)
+    }
+
+    /// Get local and peer address of the connection of the stream to the router.
+    pub fn socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        let socket = self.stream.get_ref().get_ref();
+
+        Ok(SocketAddrs {
+            local: socket.local_addr()?,
+            peer: socket.peer_addr()?,
+        })
+    }
+
+    /// Copy data between the stream and `socket` until both directions are closed.
+    pub(crate) async fn bridge(self, socket: TcpStream) -> io::Result<()> {
+        let (mut socket_read, mut socket_write) = socket.into_split();
+        let (mut stream_read, mut stream_write) = self.split();
+
+        let outbound = async {
+            let mut buffer = vec![0u8; BRIDGE_BUFFER_SIZE];
+
+            loop {
+                match socket_read.read(&mut buffer).await? {
+                    0 => return stream_write.close().await,
+                    nread => {
+                        stream_write.write_all(&buffer[..nread]).await?;
+                        stream_write.flush().await?;
+                    }
+                }
+            }
+        };
+
+        let inbound = async {
+            let mut buffer = vec![0u8; BRIDGE_BUFFER_SIZE];
+
+            loop {
+                match stream_read.read(&mut buffer).await? {
+                    0 => return socket_write.shutdown().await,
+                    nread => socket_write.write_all(&buffer[..nread]).await?,
+                }
+            }
+        };
+
+        futures::future::try_join(outbound, inbound).await.map(|_| ())
+    }
 }
 
 impl AsyncRead for Stream {
@@ -57,7 +305,24 @@ impl AsyncRead for Stream {
         cx: &mut Context<'_>,
         buf: &mut [u8],
     ) -> Poll<std::io::Result<usize>> {
-        std::pin::pin!(&mut self.stream).poll_read(cx, buf)
+        let this = &mut *self;
+        let len = match &mut this.read_throttle {
+            Some(throttled) if !buf.is_empty() => ready!(throttled.poll_grant(cx, buf.len())),
+            _ => buf.len(),
+        };
+        let mut buf = ReadBuf::new(&mut buf[..len]);
+
+        match tokio::io::AsyncRead::poll_read(Pin::new(&mut *this.stream), cx, &mut buf) {
+            Poll::Ready(Ok(())) => {
+                if let Some(throttled) = &mut this.read_throttle {
+                    throttled.throttle.consume(buf.filled().len());
+                }
+
+                Poll::Ready(Ok(buf.filled().len()))
+            }
+            Poll::Ready(Err(error)) => Poll::Ready(Err(error)),
+            Poll::Pending => Poll::Pending,
+        }
     }
 }
 
@@ -67,7 +332,17 @@ impl AsyncWrite for Stream {
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        std::pin::pin!(&mut self.stream).as_mut().poll_write(cx, buf)
+        let this = &mut *self;
+        let Some(throttled) = this.write_throttle.as_mut().filter(|_| !buf.is_empty()) else {
+            return tokio::io::AsyncWrite::poll_write(Pin::new(&mut *this.stream), cx, buf);
+        };
+        let len = ready!(throttled.poll_grant(cx, buf.len()));
+
+        let stream = Pin::new(&mut *this.stream);
+        let nwritten = ready!(tokio::io::AsyncWrite::poll_write(stream, cx, &buf[..len]))?;
+        throttled.throttle.consume(nwritten);
+
+        Poll::Ready(Ok(nwritten))
     }
 
     fn poll_write_vectored(
@@ -75,14 +350,67 @@ impl AsyncWrite for Stream {
         cx: &mut Context<'_>,
         bufs: &[std::io::IoSlice<'_>],
     ) -> Poll<std::io::Result<usize>> {
-        std::pin::pin!(&mut self.stream).as_mut().poll_write_vectored(cx, bufs)
+        if self.write_throttle.is_none() {
+            let stream = Pin::new(&mut *self.stream);
+            return tokio::io::AsyncWrite::poll_write_vectored(stream, cx, bufs);
+        }
+
+        match bufs.iter().find(|buf| !buf.is_empty()) {
+            Some(buf) => self.poll_write(cx, buf),
+            None => Poll::Ready(Ok(0)),
+        }
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        std::pin::pin!(&mut self.stream).as_mut().poll_flush(cx)
+        tokio::io::AsyncWrite::poll_flush(Pin::new(&mut *self.stream), cx)
     }
 
     fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
-        std::pin::pin!(&mut self.stream).poll_close(cx)
+        tokio::io::AsyncWrite::poll_shutdown(Pin::new(&mut *self.stream), cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        options::SessionOptions,
+        testing::{stream_pair, MockSam},
+    };
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn buffered_data_sent_on_drop() {
+        let sam = MockSam::new().unwrap();
+        let mut pair = stream_pair(SessionOptions {
+            stream_write_buffer_size: 64,
+            ..sam.session_options()
+        })
+        .await
+        .unwrap();
+
+        pair.client_stream.write_all(b"hello").await.unwrap();
+        drop(pair.client_stream);
+
+        let mut buffer = Vec::new();
+        pair.server_stream.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(buffer, b"hello");
+    }
+
+    #[tokio::test]
+    async fn buffered_data_sent_on_drop_outside_runtime() {
+        let sam = MockSam::new().unwrap();
+        let mut pair = stream_pair(SessionOptions {
+            stream_write_buffer_size: 64,
+            ..sam.session_options()
+        })
+        .await
+        .unwrap();
+
+        pair.client_stream.write_all(b"hello").await.unwrap();
+        std::thread::spawn(move || drop(pair.client_stream)).join().unwrap();
+
+        let mut buffer = Vec::new();
+        pair.server_stream.read_to_end(&mut buffer).await.unwrap();
+        assert_eq!(buffer, b"hello");
     }
 }
@@ -0,0 +1,404 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Tunnels between local TCP ports and I2P destinations.
+
+use crate::{
+    asynchronous::session::{style, Session, SessionHandle},
+    error::Error,
+    options::SessionOptions,
+    redaction::RedactedValue,
+    tracing,
+};
+
+use tokio::{
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+    sync::Semaphore,
+};
+
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::tunnel";
+
+/// Default maximum number of concurrent connections of a server tunnel.
+const DEFAULT_MAX_CONNECTIONS: usize = 64;
+
+/// How long a server tunnel waits before retrying after a failure.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// ## Client tunnel.
+///
+/// Listens on a local TCP port and pipes each accepted connection to a remote destination over a
+/// shared [`Session`], like an i2ptunnel client tunnel. Created with [`client_tunnel()`].
+pub struct ClientTunnel {
+    /// TCP listener for local connections.
+    listener: TcpListener,
+
+    /// Session used to open virtual streams.
+    ///
+    /// Streams are opened with `handle`, the session is kept alive for it.
+    _session: Session<style::Stream>,
+
+    /// Handle of the session, cloned for each connection.
+    handle: SessionHandle<style::Stream>,
+
+    /// Remote destination.
+    remote_destination: String,
+
+    /// Port of the remote destination.
+    remote_port: u16,
+}
+
+impl ClientTunnel {
+    /// Get the local address the tunnel is listening on.
+    pub fn local_addr(&self) -> crate::Result<SocketAddr> {
+        Ok(self.listener.local_addr()?)
+    }
+
+    /// Accept local connections and pipe them to the remote destination.
+    ///
+    /// Each connection is served in a task of its own, including opening its virtual stream. If a
+    /// virtual stream cannot be opened, the local connection is closed.
+    ///
+    /// Returns only if accepting a local connection fails.
+    pub async fn run(self) -> crate::Result<()> {
+        let remote_destination = Arc::new(self.remote_destination);
+
+        loop {
+            let (socket, address) = self.listener.accept().await?;
+            let handle = self.handle.clone();
+            let remote_destination = Arc::clone(&remote_destination);
+            let remote_port = self.remote_port;
+
+            tokio::spawn(async move {
+                let result = match handle.connect_to_port(&remote_destination, remote_port).await {
+                    Ok(stream) => stream.bridge(socket).await.map_err(From::from),
+                    Err(error) => Err(error),
+                };
+
+                if let Err(error) = result {
+                    tracing::debug!(
+                        target: LOG_TARGET,
+                        ?address,
+                        remote_destination = %RedactedValue(&remote_destination),
+                        ?error,
+                        "client tunnel connection failed",
+                    );
+                }
+            });
+        }
+    }
+}
+
+/// Create a client tunnel from `local_addr` to `remote_port` of `remote_destination`.
+///
+/// Connections accepted on `local_addr` are piped to the remote destination over `session` once
+/// [`ClientTunnel::run()`] is called. See [`Session::connect()`] for supported destination
+/// formats.
+///
+/// ```no_run
+/// use yosemite::{style::Stream, tunnel, Session};
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let session = Session::<Stream>::new(Default::default()).await?;
+///
+///     tunnel::client_tunnel(session, "127.0.0.1:8080", "host.i2p", 80)
+///         .await?
+///         .run()
+///         .await
+/// }
+/// ```
+pub async fn client_tunnel(
+    session: Session<style::Stream>,
+    local_addr: impl ToSocketAddrs,
+    remote_destination: impl Into<String>,
+    remote_port: u16,
+) -> crate::Result<ClientTunnel> {
+    Ok(ClientTunnel {
+        listener: TcpListener::bind(local_addr).await?,
+        handle: session.handle(),
+        _session: session,
+        remote_destination: remote_destination.into(),
+        remote_port,
+    })
+}
+
+/// ## Server tunnel.
+///
+/// Accepts inbound virtual streams of a session and pipes each of them to a local TCP backend,
+/// like an i2ptunnel server tunnel. Created with [`server_tunnel()`].
+///
+/// At most [`ServerTunnel::with_max_connections()`] streams are served at a time and no new
+/// streams are accepted until a connection closes. If the connection to the router is lost, the
+/// session is recreated. Unless the session uses a
+/// [`DestinationKind::Persistent`](crate::DestinationKind::Persistent) destination, the recreated
+/// session has a new destination.
+pub struct ServerTunnel {
+    /// Session options, used to recreate the session.
+    options: SessionOptions,
+
+    /// Session which accepts inbound streams.
+    session: Session<style::Stream>,
+
+    /// Address of the TCP backend.
+    target_addr: String,
+
+    /// Maximum number of concurrent connections.
+    max_connections: usize,
+}
+
+impl ServerTunnel {
+    /// Get destination of the tunnel, see [`Session::destination()`].
+    pub fn destination(&self) -> &str {
+        self.session.destination()
+    }
+
+    /// Get public destination of the tunnel, which clients connect to.
+    pub fn public_destination(&self) -> &str {
+        self.session.public_destination()
+    }
+
+    /// Serve at most `max_connections` streams at a time.
+    ///
+    /// Defaults to 64.
+    pub fn with_max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// Accept inbound streams and pipe them to the backend.
+    ///
+    /// Each stream is served in a task of its own. If the backend cannot be reached, the stream is
+    /// closed. Never returns unless the task is cancelled.
+    pub async fn run(mut self) -> crate::Result<()> {
+        let permits = Arc::new(Semaphore::new(self.max_connections));
+        let target_addr = Arc::new(self.target_addr);
+
+        loop {
+            let permit =
+                Arc::clone(&permits).acquire_owned().await.expect("semaphore is never closed");
+
+            let stream = match self.session.accept().await {
+                Ok(stream) => stream,
+                Err(error) => {
+                    tracing::debug!(
+                        target: LOG_TARGET,
+                        ?error,
+                        "server tunnel failed to accept stream",
+                    );
+                    tokio::time::sleep(RETRY_DELAY).await;
+
+                    if let Error::IoError(_) = error {
+                        self.session = Self::reconnect(&self.options).await;
+                    }
+
+                    continue;
+                }
+            };
+            let target_addr = Arc::clone(&target_addr);
+
+            tokio::spawn(async move {
+                let result = match TcpStream::connect(target_addr.as_str()).await {
+                    Ok(socket) => stream.bridge(socket).await,
+                    Err(error) => Err(error),
+                };
+
+                if let Err(error) = result {
+                    tracing::debug!(
+                        target: LOG_TARGET,
+                        %target_addr,
+                        ?error,
+                        "server tunnel connection failed",
+                    );
+                }
+
+                drop(permit);
+            });
+        }
+    }
+
+    /// Recreate the session, retrying until it succeeds.
+    async fn reconnect(options: &SessionOptions) -> Session<style::Stream> {
+        loop {
+            match Session::new(options.clone()).await {
+                Ok(session) => {
+                    tracing::info!(
+                        target: LOG_TARGET,
+                        destination = %RedactedValue(session.destination()),
+                        "server tunnel session recreated",
+                    );
+
+                    return session;
+                }
+                Err(error) => {
+                    tracing::debug!(
+                        target: LOG_TARGET,
+                        ?error,
+                        "failed to recreate server tunnel session",
+                    );
+                    tokio::time::sleep(RETRY_DELAY).await;
+                }
+            }
+        }
+    }
+}
+
+/// Create a server tunnel which pipes inbound virtual streams to the TCP backend at
+/// `target_addr`.
+///
+/// The session of the tunnel is created from `options`. Streams are accepted once
+/// [`ServerTunnel::run()`] is called.
+///
+/// ```no_run
+/// use yosemite::tunnel;
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let tunnel = tunnel::server_tunnel(Default::default(), "127.0.0.1:8080").await?;
+///     println!("listening on {}", tunnel.destination());
+///
+///     tunnel.run().await
+/// }
+/// ```
+pub async fn server_tunnel(
+    options: SessionOptions,
+    target_addr: impl Into<String>,
+) -> crate::Result<ServerTunnel> {
+    Ok(ServerTunnel {
+        session: Session::new(options.clone()).await?,
+        options,
+        target_addr: target_addr.into(),
+        max_connections: DEFAULT_MAX_CONNECTIONS,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockSam;
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn client_tunnel() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let sam = MockSam::new().unwrap();
+        let mut server = sam.session::<style::Stream>().await.unwrap();
+        let client = sam.session::<style::Stream>().await.unwrap();
+        sam.add_name("server.i2p", server.public_destination());
+
+        let tunnel = crate::tunnel::client_tunnel(client, "127.0.0.1:0", "server.i2p", 80)
+            .await
+            .unwrap();
+        let address = tunnel.local_addr().unwrap();
+        tokio::spawn(tunnel.run());
+
+        let handle = tokio::spawn(async move {
+            let mut stream = server.accept().await.unwrap();
+            let mut buffer = [0u8; 5];
+
+            stream.read_exact(&mut buffer).await.unwrap();
+            stream.write_all(&buffer).await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        let mut socket = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut buffer = [0u8; 5];
+        socket.write_all(b"hello").await.unwrap();
+        socket.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        handle.await.unwrap();
+        assert!(sam.commands().iter().any(|command| {
+            command.starts_with("STREAM CONNECT") && command.contains("TO_PORT=80")
+        }));
+    }
+
+    #[tokio::test]
+    async fn concurrent_connects() {
+        use crate::testing::Fault;
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let sam = MockSam::new().unwrap();
+        let mut server = sam.session::<style::Stream>().await.unwrap();
+        let client = sam.session::<style::Stream>().await.unwrap();
+        sam.add_name("server.i2p", server.public_destination());
+
+        let tunnel = crate::tunnel::client_tunnel(client, "127.0.0.1:0", "server.i2p", 80)
+            .await
+            .unwrap();
+        let address = tunnel.local_addr().unwrap();
+        tokio::spawn(tunnel.run());
+        tokio::spawn(async move {
+            while let Ok(mut stream) = server.accept().await {
+                tokio::spawn(async move {
+                    let mut buffer = [0u8; 5];
+
+                    stream.read_exact(&mut buffer).await.unwrap();
+                    stream.write_all(&buffer).await.unwrap();
+                    stream.flush().await.unwrap();
+                });
+            }
+        });
+
+        // a virtual stream which is slow to open doesn't hold up the next connection
+        sam.inject("STREAM CONNECT", Fault::Delay(Duration::from_secs(5)));
+        let _slow = tokio::net::TcpStream::connect(address).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut socket = tokio::net::TcpStream::connect(address).await.unwrap();
+        let mut buffer = [0u8; 5];
+        socket.write_all(b"hello").await.unwrap();
+        tokio::time::timeout(Duration::from_secs(2), socket.read_exact(&mut buffer))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buffer, b"hello");
+    }
+
+    #[tokio::test]
+    async fn server_tunnel() {
+        use tokio::io::{AsyncReadExt as _, AsyncWriteExt as _};
+
+        let sam = MockSam::new().unwrap();
+        let backend = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target_addr = backend.local_addr().unwrap().to_string();
+
+        let tunnel = crate::tunnel::server_tunnel(sam.session_options(), target_addr)
+            .await
+            .unwrap()
+            .with_max_connections(1);
+        let destination = tunnel.public_destination().to_string();
+        tokio::spawn(tunnel.run());
+
+        let mut client = sam.session::<style::Stream>().await.unwrap();
+        let mut stream = client.connect(&destination).await.unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        stream.flush().await.unwrap();
+
+        let (mut socket, _) = backend.accept().await.unwrap();
+        let mut buffer = [0u8; 5];
+        socket.read_exact(&mut buffer).await.unwrap();
+        socket.write_all(&buffer).await.unwrap();
+
+        stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+    }
+}
@@ -0,0 +1,260 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Typed repliable datagrams.
+
+use crate::{
+    asynchronous::session::{style::Repliable, Session},
+    error::report_error,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use std::{fmt, marker::PhantomData};
+
+/// Maximum size of a datagram.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// Codec of a [`TypedDatagramSession`].
+///
+/// Both sides of a typed datagram session must use the same codec.
+pub trait Codec {
+    /// Encode `value` into the payload of a datagram.
+    fn encode<T: Serialize>(&self, value: &T) -> crate::Result<Vec<u8>>;
+
+    /// Decode value from the payload of a datagram.
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> crate::Result<T>;
+}
+
+/// JSON codec.
+///
+/// Available with the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, value: &T) -> crate::Result<Vec<u8>> {
+        serde_json::to_vec(value).map_err(|error| crate::Error::Codec(error.into()))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> crate::Result<T> {
+        serde_json::from_slice(payload).map_err(|error| crate::Error::Codec(error.into()))
+    }
+}
+
+/// Bincode codec.
+///
+/// Values are encoded with the default options of `bincode`, i.e., with variable-length integers.
+/// Decoding is limited to the maximum size of a datagram so a malicious length prefix can't make
+/// the decoder allocate more memory than that.
+///
+/// Available with the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Bincode {
+    /// Get options of the codec.
+    fn options() -> impl bincode::Options {
+        use bincode::Options;
+
+        bincode::options().with_limit(MAX_DATAGRAM_SIZE as u64)
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize>(&self, value: &T) -> crate::Result<Vec<u8>> {
+        bincode::Options::serialize(Self::options(), value)
+            .map_err(|error| crate::Error::Codec(error))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, payload: &[u8]) -> crate::Result<T> {
+        bincode::Options::deserialize(Self::options(), payload)
+            .map_err(|error| crate::Error::Codec(error))
+    }
+}
+
+/// ## Typed datagram session.
+///
+/// `TypedDatagramSession` sends and receives values of `T` over a session of
+/// [`Repliable`] datagrams, each value in a datagram of its own. The values are encoded and decoded
+/// with codec `C`, such as [`Json`] or [`Bincode`], or with an application-defined [`Codec`].
+///
+/// The module is available with the `serde` feature and the built-in codecs with the `json` and
+/// `bincode` features.
+///
+/// ```no_run
+/// use serde::{Deserialize, Serialize};
+/// use yosemite::{style::Repliable, typed::{Json, TypedDatagramSession}, Session};
+///
+/// #[derive(Serialize, Deserialize)]
+/// enum Message {
+///     Ping(u64),
+///     Pong(u64),
+/// }
+///
+/// #[tokio::main]
+/// async fn main() -> yosemite::Result<()> {
+///     let session = Session::<Repliable>::new(Default::default()).await?;
+///     let mut session = TypedDatagramSession::<Message, _>::new(session, Json);
+///
+///     while let Ok((message, destination)) = session.recv().await {
+///         if let Message::Ping(nonce) = message {
+///             session.send(&Message::Pong(nonce), &destination).await?;
+///         }
+///     }
+///
+///     Ok(())
+/// }
+/// ```
+pub struct TypedDatagramSession<T, C> {
+    /// Session of repliable datagrams.
+    session: Session<Repliable>,
+
+    /// Codec of the session.
+    codec: C,
+
+    /// Receive buffer.
+    buffer: Vec<u8>,
+
+    /// Marker for the message type.
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, C> fmt::Debug for TypedDatagramSession<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedDatagramSession")
+            .field("session", &self.session)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned, C: Codec> TypedDatagramSession<T, C> {
+    /// Create new [`TypedDatagramSession`] from `session`, using `codec` for the datagrams.
+    pub fn new(session: Session<Repliable>, codec: C) -> Self {
+        Self {
+            session,
+            codec,
+            buffer: vec![0u8; MAX_DATAGRAM_SIZE],
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get mutable reference to the underlying session.
+    pub fn session(&mut self) -> &mut Session<Repliable> {
+        &mut self.session
+    }
+
+    /// Consume the [`TypedDatagramSession`] and return the underlying session.
+    pub fn into_inner(self) -> Session<Repliable> {
+        self.session
+    }
+
+    /// Send `message` to `destination`.
+    ///
+    /// See [`Session::send_to()`] for supported destination formats.
+    pub async fn send(&mut self, message: &T, destination: &str) -> crate::Result<()> {
+        let payload = self.codec.encode(message).inspect_err(report_error)?;
+
+        self.session.send_to(&payload, destination).await
+    }
+
+    /// Receive message.
+    ///
+    /// Returns the message and the destination who sent it. If the datagram can't be decoded,
+    /// [`Error::Codec`](crate::Error::Codec) is returned and the session can be used to receive
+    /// the next message.
+    pub async fn recv(&mut self) -> crate::Result<(T, String)> {
+        let (nread, destination) = self.session.recv_from(&mut self.buffer).await?;
+        let message = self.codec.decode(&self.buffer[..nread]).inspect_err(report_error)?;
+
+        Ok((message, destination))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{style, testing::MockSam};
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_round_trip() {
+        let value = (42u64, "hello".to_string(), vec![1u8, 2, 3]);
+        let payload = Bincode.encode(&value).unwrap();
+
+        assert_eq!(
+            Bincode.decode::<(u64, String, Vec<u8>)>(&payload).unwrap(),
+            value
+        );
+        assert!(matches!(
+            Bincode.decode::<(u64, String, Vec<u8>)>(&payload[..payload.len() - 1]),
+            Err(crate::Error::Codec(_))
+        ));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn bincode_length_limited() {
+        // varint length prefix of `u64::MAX` elements
+        let payload = [0xfd, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+
+        assert!(matches!(
+            Bincode.decode::<Vec<u8>>(&payload),
+            Err(crate::Error::Codec(_))
+        ));
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn typed_datagrams() {
+        use crate::typed::{Json, TypedDatagramSession};
+
+        #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+        struct Request {
+            id: u64,
+            method: String,
+        }
+
+        let sam = MockSam::new().unwrap();
+        let first = sam.session::<style::Repliable>().await.unwrap();
+        let mut second = sam.session::<style::Repliable>().await.unwrap();
+        let destination = second.public_destination().to_string();
+        let mut first = TypedDatagramSession::<Request, _>::new(first, Json);
+
+        let request = Request {
+            id: 1,
+            method: "ping".to_string(),
+        };
+        first.send(&request, &destination).await.unwrap();
+
+        let mut typed = TypedDatagramSession::<Request, _>::new(second, Json);
+        let (received, sender) = typed.recv().await.unwrap();
+        assert_eq!(received, request);
+        assert_eq!(sender, first.session().public_destination());
+
+        // malformed datagrams are reported without closing the session
+        second = typed.into_inner();
+        second.send_to(b"{", first.session().public_destination()).await.unwrap();
+        assert!(matches!(first.recv().await, Err(crate::Error::Codec(_))));
+    }
+}
@@ -0,0 +1,220 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Backoff of destinations which couldn't be reached.
+
+use crate::{
+    error::{Error, I2pError, ProtocolError},
+    options::DialBackoff,
+    tracing,
+};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::backoff";
+
+/// Backoff of a destination.
+struct Backoff {
+    /// Number of consecutive failed connection attempts.
+    failures: u32,
+
+    /// When does the backoff end.
+    until: Instant,
+}
+
+/// Cache of destinations which couldn't be reached.
+///
+/// Shared by the session and its handles. A destination enters the cache when opening a stream to
+/// it fails with `CANT_REACH_PEER` or `TIMEOUT` and leaves it when a stream is opened to it. The
+/// backoff starts at [`DialBackoff::initial`] and doubles after each consecutive failure, up to
+/// [`DialBackoff::max`].
+#[derive(Clone)]
+pub(crate) struct BackoffCache {
+    /// Backoff configuration.
+    config: DialBackoff,
+
+    /// Destinations in the cache.
+    destinations: Arc<Mutex<HashMap<String, Backoff>>>,
+}
+
+impl BackoffCache {
+    /// Create new [`BackoffCache`].
+    ///
+    /// Fails with [`Error::InvalidOption`] if the capacity of the cache is zero.
+    pub(crate) fn new(config: DialBackoff) -> crate::Result<Self> {
+        if config.capacity == 0 {
+            return Err(Error::InvalidOption {
+                key: "dial_backoff".to_string(),
+                value: format!("{config:?}"),
+            });
+        }
+
+        Ok(Self {
+            config,
+            destinations: Default::default(),
+        })
+    }
+
+    /// Lock the destinations.
+    fn destinations(&self) -> MutexGuard<'_, HashMap<String, Backoff>> {
+        self.destinations.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Check if `destination` can be dialed.
+    ///
+    /// Returns how long to wait before dialing if the destination is in backoff and
+    /// [`DialBackoff::wait`] is set, and fails with [`Error::DialBackoff`] if it isn't.
+    pub(crate) fn check(&self, destination: &str) -> crate::Result<Option<Duration>> {
+        let Some(retry_in) = self
+            .destinations()
+            .get(destination)
+            .map(|backoff| backoff.until.saturating_duration_since(Instant::now()))
+            .filter(|retry_in| !retry_in.is_zero())
+        else {
+            return Ok(None);
+        };
+
+        match self.config.wait {
+            true => Ok(Some(retry_in)),
+            false => Err(Error::DialBackoff { retry_in }),
+        }
+    }
+
+    /// Record the `result` of dialing `destination`.
+    pub(crate) fn record<T>(&self, destination: &str, result: &crate::Result<T>) {
+        let unreachable = match result {
+            Ok(_) => {
+                self.destinations().remove(destination);
+                return;
+            }
+            Err(Error::I2p(error) | Error::Protocol(ProtocolError::Router(error))) =>
+                matches!(error, I2pError::CantReachPeer | I2pError::Timeout),
+            Err(_) => false,
+        };
+
+        if !unreachable {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut destinations = self.destinations();
+
+        if !destinations.contains_key(destination) && destinations.len() >= self.config.capacity {
+            destinations.retain(|_, backoff| backoff.until > now);
+
+            if destinations.len() >= self.config.capacity {
+                let oldest = destinations
+                    .iter()
+                    .min_by_key(|(_, backoff)| backoff.until)
+                    .map(|(destination, _)| destination.clone());
+                oldest.map(|destination| destinations.remove(&destination));
+            }
+        }
+
+        let backoff = destinations.entry(destination.to_string()).or_insert(Backoff {
+            failures: 0u32,
+            until: now,
+        });
+        backoff.failures = backoff.failures.saturating_add(1);
+
+        let delay = self
+            .config
+            .initial
+            .saturating_mul(2u32.saturating_pow(backoff.failures - 1))
+            .min(self.config.max);
+        backoff.until = now + delay;
+
+        tracing::debug!(
+            target: LOG_TARGET,
+            failures = ?backoff.failures,
+            ?delay,
+            "destination unreachable, backing off",
+        );
+    }
+}
+
+/// Check if `destination` can be dialed, if the session has a backoff cache.
+pub(crate) fn dial_delay(
+    backoff: Option<&BackoffCache>,
+    destination: &str,
+) -> crate::Result<Option<Duration>> {
+    backoff.map_or(Ok(None), |backoff| backoff.check(destination))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unreachable() -> crate::Result<()> {
+        Err(Error::Protocol(ProtocolError::Router(
+            I2pError::CantReachPeer,
+        )))
+    }
+
+    #[test]
+    fn backoff_doubles_until_success() {
+        let cache = BackoffCache::new(DialBackoff {
+            initial: Duration::from_secs(10),
+            max: Duration::from_secs(15),
+            ..Default::default()
+        })
+        .unwrap();
+
+        cache.record("host", &unreachable());
+        let Err(Error::DialBackoff { retry_in }) = cache.check("host") else {
+            panic!("destination to be in backoff");
+        };
+        assert!(retry_in > Duration::from_secs(9));
+
+        // the backoff is capped
+        cache.record("host", &unreachable());
+        assert!(matches!(
+            cache.check("host"),
+            Err(Error::DialBackoff { retry_in }) if retry_in <= Duration::from_secs(15)
+        ));
+
+        // other errors don't affect the backoff
+        cache.record::<()>("other", &Err(Error::Malformed));
+        assert!(cache.check("other").unwrap().is_none());
+
+        cache.record("host", &Ok(()));
+        assert!(cache.check("host").unwrap().is_none());
+    }
+
+    #[test]
+    fn capacity() {
+        let cache = BackoffCache::new(DialBackoff {
+            capacity: 1,
+            wait: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        cache.record("first", &unreachable());
+        assert!(cache.check("first").unwrap().is_some());
+
+        cache.record("second", &unreachable());
+        assert!(cache.check("first").unwrap().is_none());
+        assert!(cache.check("second").unwrap().is_some());
+    }
+}
@@ -0,0 +1,367 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Command-line tool for common SAMv3 tasks, available with the `cli` feature.
+//!
+//!    cargo run --features cli -- lookup <name>
+
+use yosemite::{style, tunnel, DestinationKind, Error, RouterApi, Session, SessionOptions};
+
+use std::{
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitCode,
+    time::Instant,
+};
+
+/// Usage of the tool.
+const USAGE: &str = "\
+usage: yosemite [--sam-port <port>] <command>
+
+commands:
+    lookup <name>                                 resolve name into a destination
+    dest generate [--key <file>]                  generate a destination
+    tunnel client <local-addr> <dest> [port]      pipe local connections to a destination
+    tunnel server <target-addr> [--key <file>]    pipe inbound streams to a local backend
+    probe <dest> [port]                           open a virtual stream to a destination
+
+options:
+    --sam-port <port>    TCP port of the SAMv3 bridge [default: 7656]
+    --key <file>         private key of a persistent destination, as base64 or as a binary
+                         key file such as eepPriv.dat";
+
+/// Command given on the command line.
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    /// Resolve `name` into a destination.
+    Lookup {
+        /// Name to resolve.
+        name: String,
+    },
+
+    /// Generate a destination.
+    ///
+    /// If `key_file` is given, the private key is written to it instead of standard output.
+    DestGenerate {
+        /// File where the private key is written to.
+        key_file: Option<PathBuf>,
+    },
+
+    /// Pipe connections accepted on `local_addr` to `port` of `destination`.
+    TunnelClient {
+        /// Local address to listen on.
+        local_addr: String,
+
+        /// Remote destination.
+        destination: String,
+
+        /// Port of the remote destination.
+        port: u16,
+    },
+
+    /// Pipe inbound virtual streams to `target_addr`.
+    TunnelServer {
+        /// Address of the local backend.
+        target_addr: String,
+
+        /// File of the private key of the destination, if the destination is persistent.
+        key_file: Option<PathBuf>,
+    },
+
+    /// Open a virtual stream to `port` of `destination` and report how long it took.
+    Probe {
+        /// Destination to probe.
+        destination: String,
+
+        /// Port of the destination.
+        port: u16,
+    },
+}
+
+/// Parsed command line.
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    /// TCP port of the SAMv3 bridge.
+    sam_port: u16,
+
+    /// Command to run.
+    command: Command,
+}
+
+impl Args {
+    /// Parse command line from `args`, excluding the name of the binary.
+    fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut sam_port = SessionOptions::default().samv3_tcp_port;
+        let mut key_file = None;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--sam-port" => {
+                    let port = args.next().ok_or("--sam-port requires a value")?;
+                    sam_port = port.parse().map_err(|_| format!("invalid port: {port}"))?;
+                }
+                "--key" => {
+                    key_file = Some(PathBuf::from(args.next().ok_or("--key requires a value")?));
+                }
+                flag if flag.starts_with("--") => return Err(format!("unknown option: {flag}")),
+                _ => positional.push(arg),
+            }
+        }
+
+        let port = |port: Option<&&str>| match port {
+            None => Ok(0),
+            Some(port) => port.parse::<u16>().map_err(|_| format!("invalid port: {port}")),
+        };
+        let positional = positional.iter().map(String::as_str).collect::<Vec<_>>();
+
+        let command = match positional.as_slice() {
+            ["lookup", name] => Command::Lookup {
+                name: name.to_string(),
+            },
+            ["dest", "generate"] => Command::DestGenerate {
+                key_file: key_file.take(),
+            },
+            ["tunnel", "client", local_addr, destination, rest @ ..] if rest.len() <= 1 =>
+                Command::TunnelClient {
+                    local_addr: local_addr.to_string(),
+                    destination: destination.to_string(),
+                    port: port(rest.first())?,
+                },
+            ["tunnel", "server", target_addr] => Command::TunnelServer {
+                target_addr: target_addr.to_string(),
+                key_file: key_file.take(),
+            },
+            ["probe", destination, rest @ ..] if rest.len() <= 1 => Command::Probe {
+                destination: destination.to_string(),
+                port: port(rest.first())?,
+            },
+            [] => return Err("no command given".to_string()),
+            _ => return Err(format!("invalid command: {}", positional.join(" "))),
+        };
+
+        if key_file.is_some() {
+            return Err("--key is only supported by `dest generate` and `tunnel server`".into());
+        }
+
+        Ok(Self { sam_port, command })
+    }
+
+    /// Get session options for the SAMv3 bridge.
+    fn session_options(&self) -> SessionOptions {
+        SessionOptions {
+            samv3_tcp_port: self.sam_port,
+            ..Default::default()
+        }
+    }
+}
+
+/// Run the command of `args`, writing its output to `out`.
+///
+/// Tunnel commands return only if the tunnel fails.
+async fn run(args: Args, out: &mut impl Write) -> yosemite::Result<()> {
+    let options = args.session_options();
+
+    match args.command {
+        Command::Lookup { name } => {
+            let destination = RouterApi::new(args.sam_port).lookup_name(&name).await?;
+
+            writeln!(out, "{destination}")?;
+        }
+        Command::DestGenerate { key_file } => {
+            let (destination, private_key) =
+                RouterApi::new(args.sam_port).generate_destination().await?;
+
+            match key_file {
+                Some(path) => {
+                    std::fs::write(&path, format!("{private_key}\n"))?;
+                    writeln!(out, "{destination}")?;
+                }
+                None => writeln!(out, "{destination}\n{private_key}")?,
+            }
+        }
+        Command::TunnelClient {
+            local_addr,
+            destination,
+            port,
+        } => {
+            let session = Session::<style::Stream>::new(options).await?;
+            let tunnel = tunnel::client_tunnel(session, local_addr, destination, port).await?;
+
+            writeln!(out, "listening on {}", tunnel.local_addr()?)?;
+            out.flush()?;
+
+            tunnel.run().await?;
+        }
+        Command::TunnelServer {
+            target_addr,
+            key_file,
+        } => {
+            let options = match key_file {
+                Some(path) => SessionOptions {
+                    destination: DestinationKind::from_key_file(path)?,
+                    ..options
+                },
+                None => options,
+            };
+            let tunnel = tunnel::server_tunnel(options, target_addr).await?;
+
+            writeln!(out, "{}", tunnel.public_destination())?;
+            out.flush()?;
+
+            tunnel.run().await?;
+        }
+        Command::Probe { destination, port } => {
+            let mut session = Session::<style::Stream>::new(options).await?;
+            let started = Instant::now();
+            let stream = session.connect_to_port(&destination, port).await?;
+            let elapsed = started.elapsed();
+            let remote = stream.remote_destination();
+
+            writeln!(
+                out,
+                "connected to {} in {} ms",
+                yosemite::b32_address(remote).unwrap_or_else(|| remote.to_string()),
+                elapsed.as_millis(),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    if std::env::args().skip(1).any(|arg| arg == "-h" || arg == "--help") {
+        println!("{USAGE}");
+        return ExitCode::SUCCESS;
+    }
+
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(error) => {
+            eprintln!("{error}\n\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = tokio::runtime::Runtime::new()
+        .map_err(Error::from)
+        .and_then(|runtime| runtime.block_on(run(args, &mut io::stdout())));
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, String> {
+        Args::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parse_args() {
+        assert_eq!(
+            parse("--sam-port 7000 lookup host.i2p").unwrap(),
+            Args {
+                sam_port: 7000,
+                command: Command::Lookup {
+                    name: "host.i2p".to_string()
+                },
+            }
+        );
+        assert_eq!(
+            parse("tunnel client 127.0.0.1:8080 host.i2p 80").unwrap().command,
+            Command::TunnelClient {
+                local_addr: "127.0.0.1:8080".to_string(),
+                destination: "host.i2p".to_string(),
+                port: 80,
+            }
+        );
+        assert_eq!(
+            parse("tunnel server 127.0.0.1:8080 --key key.dat").unwrap().command,
+            Command::TunnelServer {
+                target_addr: "127.0.0.1:8080".to_string(),
+                key_file: Some(PathBuf::from("key.dat")),
+            }
+        );
+        assert_eq!(
+            parse("probe host.i2p").unwrap().command,
+            Command::Probe {
+                destination: "host.i2p".to_string(),
+                port: 0,
+            }
+        );
+
+        assert!(parse("").is_err());
+        assert!(parse("lookup").is_err());
+        assert!(parse("dest generate extra").is_err());
+        assert!(parse("probe host.i2p port").is_err());
+        assert!(parse("--sam-port").is_err());
+        assert!(parse("--verbose lookup host.i2p").is_err());
+        assert!(parse("lookup host.i2p --key key.dat").is_err());
+    }
+
+    #[cfg(feature = "testing")]
+    #[tokio::test]
+    async fn commands() {
+        use yosemite::testing::MockSam;
+
+        let sam = MockSam::new().unwrap();
+        let cli = |command: &str| {
+            let args = parse(&format!("--sam-port {} {command}", sam.tcp_port())).unwrap();
+
+            async move {
+                let mut out = Vec::new();
+                run(args, &mut out).await.map(|()| String::from_utf8(out).unwrap())
+            }
+        };
+
+        let output = cli("dest generate").await.unwrap();
+        let (destination, private_key) = output.trim_end().split_once('\n').unwrap();
+        assert!(private_key.starts_with(destination));
+
+        sam.add_name("host.i2p", destination);
+        assert_eq!(
+            cli("lookup host.i2p").await.unwrap(),
+            format!("{destination}\n")
+        );
+        assert!(cli("lookup unknown.i2p").await.is_err());
+
+        let mut server = Session::<style::Stream>::new(sam.session_options()).await.unwrap();
+        let server_destination = server.public_destination().to_string();
+        sam.add_name("server.i2p", server_destination.clone());
+        tokio::spawn(async move { while server.accept().await.is_ok() {} });
+
+        let output = cli("probe server.i2p").await.unwrap();
+        assert!(output.starts_with("connected to server.i2p in "));
+
+        let output = cli(&format!("probe {server_destination}")).await.unwrap();
+        assert!(output.starts_with(&format!(
+            "connected to {} in ",
+            yosemite::b32_address(&server_destination).unwrap()
+        )));
+    }
+}
@@ -16,7 +16,45 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use std::fmt;
+use std::{
+    fmt,
+    sync::{Arc, PoisonError, RwLock},
+    time::Duration,
+};
+
+/// Callback invoked with errors generated by `yosemite`.
+type ErrorHook = Arc<dyn Fn(&Error) + Send + Sync>;
+
+/// Global error hook.
+static ERROR_HOOK: RwLock<Option<ErrorHook>> = RwLock::new(None);
+
+/// Register `hook` to be called with every error generated by `yosemite`.
+///
+/// In addition to errors returned by [`Session`](crate::Session) and
+/// [`RouterApi`](crate::RouterApi), the hook is called with errors which are otherwise only
+/// logged, such as a pooled control connection having been closed by the router or the connection
+/// used for `STREAM FORWARD` failing in the background.
+///
+/// The hook is global, applies to all sessions and replaces any previously registered hook. It's
+/// called on the thread where the error occurred, so it shouldn't block.
+pub fn set_error_hook(hook: impl Fn(&Error) + Send + Sync + 'static) {
+    *ERROR_HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(hook));
+}
+
+/// Remove the error hook registered with [`set_error_hook()`].
+pub fn clear_error_hook() {
+    *ERROR_HOOK.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// Report `error` to the registered error hook, if any.
+#[cfg(any(feature = "async", feature = "sync"))]
+pub(crate) fn report_error(error: &Error) {
+    let hook = ERROR_HOOK.read().unwrap_or_else(PoisonError::into_inner).clone();
+
+    if let Some(hook) = hook {
+        hook(error);
+    }
+}
 
 /// `yosemite` error type.
 #[derive(Debug, thiserror::Error)]
@@ -36,6 +74,107 @@ pub enum Error {
     /// Response is malformed.
     #[error("response is malformed")]
     Malformed,
+
+    /// Response is longer than the maximum allowed length.
+    #[error("response exceeds maximum length of {0} bytes")]
+    ResponseTooLong(usize),
+
+    /// Router rejected the lookup credentials of an encrypted destination.
+    #[error("lookup credentials rejected by the router: `{0}`")]
+    CredentialsRejected(I2pError),
+
+    /// Router doesn't support a feature that was requested.
+    #[error("`{feature}` requires SAMv{required} but the router supports SAMv{version}")]
+    UnsupportedByRouter {
+        /// Requested feature.
+        feature: &'static str,
+
+        /// SAMv3 version which introduced the feature.
+        required: &'static str,
+
+        /// SAMv3 version negotiated with the router.
+        version: String,
+    },
+
+    /// I2PControl error, received from the router.
+    ///
+    /// `code` is the JSON-RPC error code or, if the HTTP request itself failed, the HTTP status
+    /// code.
+    #[error("i2pcontrol error {code}: `{message}`")]
+    RouterControl {
+        /// Error code.
+        code: i64,
+
+        /// Error message.
+        message: String,
+    },
+
+    /// Key store could not be decrypted.
+    ///
+    /// The passphrase or the key file is wrong or the key file has been tampered with.
+    #[error("key store could not be decrypted")]
+    DecryptionFailed,
+
+    /// Self-test of the session failed.
+    ///
+    /// The data received from the own destination of the session didn't match the data sent or
+    /// a test datagram wasn't received in time.
+    #[error("self-test failed: {0}")]
+    SelfTestFailed(&'static str),
+
+    /// All ports of [`SessionOptions::ephemeral_ports`](crate::SessionOptions::ephemeral_ports)
+    /// are in use.
+    #[error("all ephemeral ports are in use")]
+    PortsExhausted,
+
+    /// The destination couldn't be reached recently and it's in backoff.
+    ///
+    /// See [`SessionOptions::dial_backoff`](crate::SessionOptions::dial_backoff).
+    #[error("destination is in backoff for {retry_in:?}")]
+    DialBackoff {
+        /// Time until the backoff ends.
+        retry_in: Duration,
+    },
+
+    /// The session has [`SessionOptions::max_streams`](crate::SessionOptions::max_streams)
+    /// virtual streams open and
+    /// [`StreamLimitPolicy::Reject`](crate::StreamLimitPolicy::Reject) is used.
+    #[error("too many open streams")]
+    TooManyStreams,
+
+    /// Key file is not a valid private key of a destination.
+    #[error("invalid key file: {0}")]
+    InvalidKeyFile(&'static str),
+
+    /// Message could not be encoded or decoded by the codec of a typed datagram session.
+    #[error("codec error: `{0}`")]
+    Codec(Box<dyn std::error::Error + Send + Sync>),
+
+    /// Invalid session option.
+    #[error("invalid option: `{key}={value}`")]
+    InvalidOption {
+        /// Option key.
+        key: String,
+
+        /// Option value.
+        value: String,
+    },
+}
+
+impl Error {
+    /// Convert an error returned for a request made with lookup credentials.
+    ///
+    /// The router rejects credentials it cannot use to decrypt the lease set with `INVALID_KEY`,
+    /// which is reported as [`Error::CredentialsRejected`]. Other errors are returned as-is.
+    #[cfg(any(feature = "async", feature = "sync"))]
+    pub(crate) fn into_credentials_error(self) -> Self {
+        match self {
+            Error::I2p(I2pError::InvalidKey)
+            | Error::Protocol(ProtocolError::Router(I2pError::InvalidKey)) =>
+                Error::CredentialsRejected(I2pError::InvalidKey),
+            error => error,
+        }
+    }
 }
 
 /// Protocol error.
@@ -49,6 +188,18 @@ pub enum ProtocolError {
 
     /// Router error.
     Router(I2pError),
+
+    /// Router doesn't support a feature that was requested.
+    UnsupportedByRouter {
+        /// Requested feature.
+        feature: &'static str,
+
+        /// SAMv3 version which introduced the feature.
+        required: &'static str,
+
+        /// SAMv3 version negotiated with the router.
+        version: String,
+    },
 }
 
 impl fmt::Display for ProtocolError {
@@ -57,13 +208,32 @@ impl fmt::Display for ProtocolError {
             Self::InvalidState => write!(f, "invalid state"),
             Self::InvalidMessage => write!(f, "invalid message from router"),
             Self::Router(error) => write!(f, "router error: {error:?}"),
+            Self::UnsupportedByRouter {
+                feature,
+                required,
+                version,
+            } => write!(
+                f,
+                "`{feature}` requires SAMv{required} but the router supports SAMv{version}"
+            ),
         }
     }
 }
 
 impl From<ProtocolError> for Error {
     fn from(value: ProtocolError) -> Self {
-        Error::Protocol(value)
+        match value {
+            ProtocolError::UnsupportedByRouter {
+                feature,
+                required,
+                version,
+            } => Error::UnsupportedByRouter {
+                feature,
+                required,
+                version,
+            },
+            error => Error::Protocol(error),
+        }
     }
 }
 
@@ -93,6 +263,21 @@ pub enum I2pError {
 
     /// Timeout while waiting for an event (e.g. peer answer).
     Timeout,
+
+    /// The router requires authentication but no credentials were sent in `HELLO`.
+    AuthRequired,
+
+    /// The router rejected the credentials sent in `HELLO`.
+    AuthFailed,
+
+    /// Result code that `yosemite` doesn't recognize.
+    Other {
+        /// Result code.
+        result: String,
+
+        /// Message, if any.
+        message: Option<String>,
+    },
 }
 
 impl fmt::Display for I2pError {
@@ -109,26 +294,84 @@ impl fmt::Display for I2pError {
             Self::PeerNotFound => write!(f, "the peer cannot be found on the network"),
             Self::Timeout => write!(f, "timeout while waiting for an event (e.g. peer answer)"),
             Self::DuplicateId => write!(f, "duplicate id"),
+            Self::AuthRequired => write!(f, "the router requires authentication"),
+            Self::AuthFailed => write!(f, "the router rejected the credentials"),
+            Self::Other { result, message } =>
+                write!(f, "unrecognized result `{result}`: {message:?}"),
         }
     }
 }
 
-impl TryFrom<(&str, Option<&str>)> for I2pError {
-    type Error = ();
-
-    fn try_from(value: (&str, Option<&str>)) -> Result<Self, Self::Error> {
+impl From<(&str, Option<&str>)> for I2pError {
+    fn from(value: (&str, Option<&str>)) -> Self {
         match value.0 {
-            "CANT_REACH_PEER" => Ok(I2pError::CantReachPeer),
-            "DUPLICATE_DEST" => Ok(I2pError::DuplicateDest),
-            "I2P_ERROR" => Ok(I2pError::I2pError(
-                value.1.map(|message| message.to_string()),
-            )),
-            "INVALID_KEY" => Ok(I2pError::InvalidKey),
-            "KEY_NOT_FOUND" => Ok(I2pError::KeyNotFound),
-            "PEER_NOT_FOUND" => Ok(I2pError::PeerNotFound),
-            "TIMEOUT" => Ok(I2pError::Timeout),
-            "DUPLICATE_ID" => Ok(I2pError::DuplicateId),
-            _ => Err(()),
+            "CANT_REACH_PEER" => I2pError::CantReachPeer,
+            "DUPLICATE_DEST" => I2pError::DuplicateDest,
+            "I2P_ERROR" => I2pError::I2pError(value.1.map(|message| message.to_string())),
+            "INVALID_KEY" => I2pError::InvalidKey,
+            "KEY_NOT_FOUND" => I2pError::KeyNotFound,
+            "PEER_NOT_FOUND" => I2pError::PeerNotFound,
+            "TIMEOUT" => I2pError::Timeout,
+            "DUPLICATE_ID" => I2pError::DuplicateId,
+            result => I2pError::Other {
+                result: result.to_string(),
+                message: value.1.map(|message| message.to_string()),
+            },
         }
     }
 }
+
+impl I2pError {
+    /// Convert the result of a failed `HELLO`.
+    ///
+    /// Routers with authentication enabled reject `HELLO` with a generic `I2P_ERROR` and only the
+    /// message tells whether the credentials were missing or invalid.
+    pub(crate) fn from_hello(result: &str, message: Option<&str>) -> Self {
+        match (result, message) {
+            ("I2P_ERROR", Some(message))
+                if message.ends_with("required")
+                    && (message.contains("USER") || message.contains("PASSWORD")) =>
+                I2pError::AuthRequired,
+            ("I2P_ERROR", Some(message)) if message.starts_with("Authorization failed") =>
+                I2pError::AuthFailed,
+            _ => I2pError::from((result, message)),
+        }
+    }
+}
+
+#[cfg(all(test, any(feature = "async", feature = "sync")))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn error_hook() {
+        static REPORTED: AtomicUsize = AtomicUsize::new(0);
+
+        report_error(&Error::Malformed);
+        assert_eq!(REPORTED.load(Ordering::Relaxed), 0);
+
+        set_error_hook(|error| {
+            assert!(matches!(error, Error::Malformed));
+            REPORTED.fetch_add(1, Ordering::Relaxed);
+        });
+        report_error(&Error::Malformed);
+        assert_eq!(REPORTED.load(Ordering::Relaxed), 1);
+
+        clear_error_hook();
+        report_error(&Error::Malformed);
+        assert_eq!(REPORTED.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn credentials_rejected() {
+        assert!(matches!(
+            Error::Protocol(ProtocolError::Router(I2pError::InvalidKey)).into_credentials_error(),
+            Error::CredentialsRejected(I2pError::InvalidKey)
+        ));
+        assert!(matches!(
+            Error::Protocol(ProtocolError::Router(I2pError::PeerNotFound)).into_credentials_error(),
+            Error::Protocol(ProtocolError::Router(I2pError::PeerNotFound))
+        ));
+    }
+}
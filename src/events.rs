@@ -0,0 +1,78 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use std::time::Duration;
+
+/// Session event.
+///
+/// Events are received from the router or generated by the session outside of the calls made by
+/// the user. See `Session::events()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// Router sent `PING` on a control connection which was answered with `PONG`.
+    Ping {
+        /// Data sent by the router, if any.
+        data: Option<String>,
+    },
+
+    /// Router sent `PONG` on a control connection.
+    Pong {
+        /// Data sent by the router, if any.
+        data: Option<String>,
+    },
+
+    /// Session has been created.
+    ///
+    /// This is the first event received by each subscriber.
+    Created {
+        /// Public destination of the session.
+        ///
+        /// The destination doesn't contain the private keys of the session.
+        destination: String,
+    },
+
+    /// Inbound virtual stream has been accepted.
+    StreamAccepted {
+        /// Destination of the remote peer.
+        destination: String,
+    },
+
+    /// Router closed the control connection used to forward inbound virtual streams to `port`.
+    ///
+    /// Inbound streams are no longer forwarded.
+    ForwardingBroken {
+        /// Port of the TCP listener where streams were forwarded to.
+        port: u16,
+    },
+
+    /// No virtual streams have been opened or accepted and no datagrams have been sent or
+    /// received for [`SessionOptions::idle_threshold`](crate::SessionOptions::idle_threshold).
+    ///
+    /// Emitted once per idle period: the next event is emitted when the session has been active
+    /// again and has then been idle for the threshold.
+    Idle {
+        /// Time since the last activity of the session.
+        idle_for: Duration,
+    },
+
+    /// Router closed the control connection of the session.
+    ///
+    /// The control connection is read only when a command is sent over it so the event is emitted
+    /// when the next such command fails.
+    Disconnected,
+}
@@ -0,0 +1,907 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! C API.
+//!
+//! The functions of this module expose stream sessions, virtual streams and repliable datagram
+//! sessions over a C ABI so applications written in other languages can embed `yosemite`. The
+//! declarations are in `include/yosemite.h` and the library can be built for linking with
+//!
+//! ```text
+//! cargo rustc --release --lib --features ffi --crate-type staticlib
+//! ```
+//!
+//! or with `--crate-type cdylib` for a shared library.
+//!
+//! All calls block the calling thread. They are driven by a runtime which is started by the first
+//! call and shared by all objects. Failing calls return `NULL` or a negative value and the error
+//! can be read with [`yosemite_last_error()`] on the same thread. Panics don't unwind into the
+//! caller but are reported as errors of the call.
+//!
+//! The module is available with the `ffi` feature.
+
+use crate::{
+    asynchronous::{
+        session::{style, Session, SessionHandle},
+        stream::Stream,
+    },
+    options::{DestinationKind, SessionOptions},
+    tracing,
+};
+
+use futures::{
+    future::{self, Either},
+    AsyncReadExt, AsyncWriteExt,
+};
+use tokio::{runtime::Runtime, sync::oneshot};
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, c_void, CStr, CString},
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    pin::pin,
+    ptr, slice,
+    sync::OnceLock,
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::ffi";
+
+/// Runtime driving the sessions and streams.
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+thread_local! {
+    /// Error of the most recent failed call made by the thread.
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+/// Callback invoked with inbound virtual streams, see [`yosemite_session_set_accept_callback()`].
+pub type YosemiteAcceptCallback =
+    extern "C" fn(user_data: *mut c_void, stream: *mut YosemiteStream);
+
+/// Get the runtime, starting it if it's not running.
+fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("yosemite-ffi")
+            .build()
+            .expect("to be able to start runtime")
+    })
+}
+
+/// Store `error` as the last error of the thread.
+fn set_error(error: impl fmt::Display) {
+    let error = CString::new(error.to_string().replace('\0', " ")).unwrap_or_default();
+
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(error));
+}
+
+/// Call `f`, storing the message as the last error and returning `default` if it panics.
+fn catch_panic<T>(default: T, f: impl FnOnce() -> T) -> T {
+    panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = match (
+            payload.downcast_ref::<&str>(),
+            payload.downcast_ref::<String>(),
+        ) {
+            (Some(message), _) => message,
+            (None, Some(message)) => message.as_str(),
+            (None, None) => "unknown error",
+        };
+        set_error(format_args!("panic: {message}"));
+
+        default
+    })
+}
+
+/// Convert `string` into a C string.
+///
+/// Destinations and nicknames don't contain NUL bytes.
+fn c_string(string: &str) -> CString {
+    CString::new(string).unwrap_or_default()
+}
+
+/// Read string argument `name`, storing an error if it's `NULL` or not UTF-8.
+///
+/// # Safety
+///
+/// `string` must be `NULL` or point to a NUL-terminated string.
+unsafe fn string_arg<'a>(string: *const c_char, name: &str) -> Option<&'a str> {
+    if string.is_null() {
+        set_error(format_args!("`{name}` is null"));
+        return None;
+    }
+
+    match CStr::from_ptr(string).to_str() {
+        Ok(string) => Some(string),
+        Err(_) => {
+            set_error(format_args!("`{name}` is not valid utf-8"));
+            None
+        }
+    }
+}
+
+/// Read buffer argument `buf` of `len` bytes, storing an error if it's `NULL`.
+///
+/// # Safety
+///
+/// `buf` must be `NULL` or valid for reads of `len` bytes.
+unsafe fn buf_arg<'a>(buf: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (len, buf.is_null()) {
+        (0, _) => Some(&[]),
+        (_, true) => {
+            set_error("`buf` is null");
+            None
+        }
+        (_, false) => Some(slice::from_raw_parts(buf, len)),
+    }
+}
+
+/// Read mutable buffer argument `buf` of `len` bytes, storing an error if it's `NULL`.
+///
+/// # Safety
+///
+/// `buf` must be `NULL` or valid for writes of `len` bytes.
+unsafe fn buf_arg_mut<'a>(buf: *mut u8, len: usize) -> Option<&'a mut [u8]> {
+    match (len, buf.is_null()) {
+        (0, _) => Some(&mut []),
+        (_, true) => {
+            set_error("`buf` is null");
+            None
+        }
+        (_, false) => Some(slice::from_raw_parts_mut(buf, len)),
+    }
+}
+
+/// Get the value of `result`, storing the error if the call failed.
+fn ok_or_set_error<T>(result: crate::Result<T>) -> Option<T> {
+    result.map_err(set_error).ok()
+}
+
+/// Session options.
+///
+/// Zero ports and `NULL` strings use the defaults of [`SessionOptions`].
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct YosemiteOptions {
+    /// TCP port of the SAMv3 bridge.
+    pub samv3_tcp_port: u16,
+
+    /// UDP port of the SAMv3 bridge.
+    pub samv3_udp_port: u16,
+
+    /// Nickname of the session.
+    pub nickname: *const c_char,
+
+    /// Private key of a persistent destination, in the format of
+    /// [`DestinationKind::Persistent`].
+    ///
+    /// If `NULL`, the session has a transient destination.
+    pub private_key: *const c_char,
+}
+
+impl YosemiteOptions {
+    /// Convert [`YosemiteOptions`] into [`SessionOptions`].
+    ///
+    /// # Safety
+    ///
+    /// The strings of the options must be `NULL` or point to NUL-terminated strings.
+    unsafe fn to_session_options(self) -> Option<SessionOptions> {
+        let mut options = SessionOptions::default();
+
+        if self.samv3_tcp_port != 0 {
+            options.samv3_tcp_port = self.samv3_tcp_port;
+        }
+        if self.samv3_udp_port != 0 {
+            options.samv3_udp_port = self.samv3_udp_port;
+        }
+        if !self.nickname.is_null() {
+            options.nickname = string_arg(self.nickname, "nickname")?.to_string();
+        }
+        if !self.private_key.is_null() {
+            options.destination = DestinationKind::Persistent {
+                private_key: string_arg(self.private_key, "private_key")?.to_string(),
+            };
+        }
+
+        Some(options)
+    }
+}
+
+/// Read session options from `options`, using the defaults if it's `NULL`.
+///
+/// # Safety
+///
+/// `options` must be `NULL` or point to valid [`YosemiteOptions`].
+unsafe fn session_options(options: *const YosemiteOptions) -> Option<SessionOptions> {
+    match options.as_ref() {
+        None => Some(SessionOptions::default()),
+        Some(options) => options.to_session_options(),
+    }
+}
+
+/// Session of virtual streams.
+pub struct YosemiteSession {
+    /// Session, unless it has been moved to the accept thread.
+    session: Option<Session<style::Stream>>,
+
+    /// Handle of the session.
+    handle: SessionHandle<style::Stream>,
+
+    /// Destination of the session.
+    destination: CString,
+
+    /// Public destination of the session.
+    public_destination: CString,
+
+    /// Shutdown signal of the accept thread, if it's running.
+    acceptor: Option<oneshot::Sender<()>>,
+}
+
+/// Virtual stream.
+pub struct YosemiteStream {
+    /// Virtual stream.
+    stream: Stream,
+
+    /// Remote destination of the stream.
+    remote_destination: CString,
+}
+
+impl YosemiteStream {
+    /// Box `stream` for the caller.
+    fn boxed(stream: Stream) -> *mut YosemiteStream {
+        let remote_destination = c_string(stream.remote_destination());
+
+        Box::into_raw(Box::new(Self {
+            stream,
+            remote_destination,
+        }))
+    }
+}
+
+/// Session of repliable datagrams.
+pub struct YosemiteDatagramSession {
+    /// Session.
+    session: Session<style::Repliable>,
+
+    /// Destination of the session.
+    destination: CString,
+
+    /// Public destination of the session.
+    public_destination: CString,
+
+    /// Sender of the most recently received datagram.
+    sender: CString,
+}
+
+/// User data passed to the accept callback.
+struct UserData(*mut c_void);
+
+// SAFETY: the caller of `yosemite_session_set_accept_callback()` guarantees that the user data can
+// be used from the accept thread
+unsafe impl Send for UserData {}
+
+/// Get the error of the most recent failed call made by the calling thread.
+///
+/// The string is valid until the next failed call made by the thread. Returns `NULL` if no call
+/// has failed.
+#[no_mangle]
+pub extern "C" fn yosemite_last_error() -> *const c_char {
+    catch_panic(ptr::null(), || {
+        LAST_ERROR.with(|last_error| {
+            last_error.borrow().as_ref().map_or(ptr::null(), |error| error.as_ptr())
+        })
+    })
+}
+
+/// Get default session options.
+#[no_mangle]
+pub extern "C" fn yosemite_options_default() -> YosemiteOptions {
+    YosemiteOptions {
+        samv3_tcp_port: 0,
+        samv3_udp_port: 0,
+        nickname: ptr::null(),
+        private_key: ptr::null(),
+    }
+}
+
+/// Create new session of virtual streams.
+///
+/// Returns `NULL` on error. The session must be freed with [`yosemite_session_free()`].
+///
+/// # Safety
+///
+/// `options` must be `NULL` or point to valid [`YosemiteOptions`].
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_session_new(
+    options: *const YosemiteOptions,
+) -> *mut YosemiteSession {
+    catch_panic(ptr::null_mut(), || {
+        let Some(options) = session_options(options) else {
+            return ptr::null_mut();
+        };
+        let Some(session) = ok_or_set_error(runtime().block_on(Session::new(options))) else {
+            return ptr::null_mut();
+        };
+
+        Box::into_raw(Box::new(YosemiteSession {
+            handle: session.handle(),
+            destination: c_string(session.destination()),
+            public_destination: c_string(session.public_destination()),
+            session: Some(session),
+            acceptor: None,
+        }))
+    })
+}
+
+/// Get destination of the session.
+///
+/// The destination is followed by the private keys of the session and must not be shared with
+/// peers, see [`yosemite_session_public_destination()`]. The string is valid until the session is
+/// freed.
+///
+/// # Safety
+///
+/// `session` must be a valid session.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_session_destination(
+    session: *const YosemiteSession,
+) -> *const c_char {
+    catch_panic(ptr::null(), || (*session).destination.as_ptr())
+}
+
+/// Get public destination of the session, which peers connect to.
+///
+/// The string is valid until the session is freed.
+///
+/// # Safety
+///
+/// `session` must be a valid session.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_session_public_destination(
+    session: *const YosemiteSession,
+) -> *const c_char {
+    catch_panic(ptr::null(), || (*session).public_destination.as_ptr())
+}
+
+/// Open virtual stream to `destination`.
+///
+/// Returns `NULL` on error. The stream must be freed with [`yosemite_stream_free()`].
+///
+/// # Safety
+///
+/// `session` must be a valid session and `destination` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_session_connect(
+    session: *mut YosemiteSession,
+    destination: *const c_char,
+) -> *mut YosemiteStream {
+    catch_panic(ptr::null_mut(), || {
+        let Some(destination) = string_arg(destination, "destination") else {
+            return ptr::null_mut();
+        };
+
+        match ok_or_set_error(runtime().block_on((*session).handle.connect(destination))) {
+            Some(stream) => YosemiteStream::boxed(stream),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Accept inbound virtual stream.
+///
+/// Returns `NULL` on error, including when an accept callback has been set. The stream must be
+/// freed with [`yosemite_stream_free()`].
+///
+/// # Safety
+///
+/// `session` must be a valid session.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_session_accept(
+    session: *mut YosemiteSession,
+) -> *mut YosemiteStream {
+    catch_panic(ptr::null_mut(), || {
+        let Some(session) = (*session).session.as_mut() else {
+            set_error("streams are accepted by the accept callback");
+            return ptr::null_mut();
+        };
+
+        match ok_or_set_error(runtime().block_on(session.accept())) {
+            Some(stream) => YosemiteStream::boxed(stream),
+            None => ptr::null_mut(),
+        }
+    })
+}
+
+/// Accept inbound virtual streams in the background and pass them to `callback`.
+///
+/// The callback is called from a background thread with `user_data` and the accepted stream,
+/// which the callback owns and must free with [`yosemite_stream_free()`]. If accepting fails, the
+/// callback is called once more with a `NULL` stream and no further streams are accepted.
+///
+/// Once the callback has been set, [`yosemite_session_accept()`] can no longer be used. Streams
+/// are accepted until the session is freed. Returns `0` on success and `-1` if a callback has
+/// already been set.
+///
+/// # Safety
+///
+/// `session` must be a valid session and `user_data` must be usable from another thread.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_session_set_accept_callback(
+    session: *mut YosemiteSession,
+    callback: YosemiteAcceptCallback,
+    user_data: *mut c_void,
+) -> c_int {
+    catch_panic(-1, || {
+        let session = &mut *session;
+        let Some(mut inner) = session.session.take() else {
+            set_error("accept callback already set");
+            return -1;
+        };
+        let (tx, mut rx) = oneshot::channel::<()>();
+        let user_data = UserData(user_data);
+        session.acceptor = Some(tx);
+
+        // the callback is called from a thread of its own so it can make blocking calls
+        std::thread::spawn(move || {
+            let user_data = user_data;
+
+            loop {
+                let result = runtime().block_on(async {
+                    match future::select(pin!(inner.accept()), &mut rx).await {
+                        Either::Left((result, _)) => Some(result),
+                        // session has been freed
+                        Either::Right(_) => None,
+                    }
+                });
+
+                match result {
+                    None => break,
+                    Some(Ok(stream)) => callback(user_data.0, YosemiteStream::boxed(stream)),
+                    Some(Err(error)) => {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            ?error,
+                            "failed to accept stream",
+                        );
+                        callback(user_data.0, ptr::null_mut());
+                        break;
+                    }
+                }
+            }
+
+            let _guard = runtime().enter();
+            drop(inner);
+        });
+
+        0
+    })
+}
+
+/// Close the session and free it.
+///
+/// Streams of the session can still be used and must be freed separately.
+///
+/// # Safety
+///
+/// `session` must be `NULL` or a valid session which is not used after the call.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_session_free(session: *mut YosemiteSession) {
+    catch_panic((), || {
+        if !session.is_null() {
+            let _guard = runtime().enter();
+            drop(Box::from_raw(session));
+        }
+    })
+}
+
+/// Get remote destination of the stream.
+///
+/// The string is valid until the stream is freed.
+///
+/// # Safety
+///
+/// `stream` must be a valid stream.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_stream_remote_destination(
+    stream: *const YosemiteStream,
+) -> *const c_char {
+    catch_panic(ptr::null(), || (*stream).remote_destination.as_ptr())
+}
+
+/// Read at most `len` bytes from the stream into `buf`.
+///
+/// Returns the number of bytes read, `0` if the stream has been closed or `len` is `0` and `-1` on
+/// error.
+///
+/// # Safety
+///
+/// `stream` must be a valid stream and `buf` must be valid for writes of `len` bytes, or `NULL` if
+/// `len` is `0`.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_stream_read(
+    stream: *mut YosemiteStream,
+    buf: *mut u8,
+    len: usize,
+) -> isize {
+    catch_panic(-1, || {
+        let Some(buf) = buf_arg_mut(buf, len) else {
+            return -1;
+        };
+        if buf.is_empty() {
+            return 0;
+        }
+
+        match runtime().block_on((*stream).stream.read(buf)) {
+            Ok(nread) => nread as isize,
+            Err(error) => {
+                set_error(error);
+                -1
+            }
+        }
+    })
+}
+
+/// Write `len` bytes from `buf` to the stream.
+///
+/// All of the data is written and flushed before the call returns. Returns `0` on success and `-1`
+/// on error.
+///
+/// # Safety
+///
+/// `stream` must be a valid stream and `buf` must be valid for reads of `len` bytes, or `NULL` if
+/// `len` is `0`.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_stream_write(
+    stream: *mut YosemiteStream,
+    buf: *const u8,
+    len: usize,
+) -> c_int {
+    catch_panic(-1, || {
+        let Some(buf) = buf_arg(buf, len) else {
+            return -1;
+        };
+        if buf.is_empty() {
+            return 0;
+        }
+        let stream = &mut (*stream).stream;
+
+        match runtime().block_on(async {
+            stream.write_all(buf).await?;
+            stream.flush().await
+        }) {
+            Ok(()) => 0,
+            Err(error) => {
+                set_error(error);
+                -1
+            }
+        }
+    })
+}
+
+/// Close the stream and free it.
+///
+/// # Safety
+///
+/// `stream` must be `NULL` or a valid stream which is not used after the call.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_stream_free(stream: *mut YosemiteStream) {
+    catch_panic((), || {
+        if !stream.is_null() {
+            let _guard = runtime().enter();
+            drop(Box::from_raw(stream));
+        }
+    })
+}
+
+/// Create new session of repliable datagrams.
+///
+/// Returns `NULL` on error. The session must be freed with [`yosemite_datagram_session_free()`].
+///
+/// # Safety
+///
+/// `options` must be `NULL` or point to valid [`YosemiteOptions`].
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_datagram_session_new(
+    options: *const YosemiteOptions,
+) -> *mut YosemiteDatagramSession {
+    catch_panic(ptr::null_mut(), || {
+        let Some(options) = session_options(options) else {
+            return ptr::null_mut();
+        };
+        let Some(session) = ok_or_set_error(runtime().block_on(Session::new(options))) else {
+            return ptr::null_mut();
+        };
+
+        Box::into_raw(Box::new(YosemiteDatagramSession {
+            destination: c_string(session.destination()),
+            public_destination: c_string(session.public_destination()),
+            session,
+            sender: CString::default(),
+        }))
+    })
+}
+
+/// Get destination of the datagram session.
+///
+/// The destination is followed by the private keys of the session and must not be shared with
+/// peers, see [`yosemite_datagram_session_public_destination()`]. The string is valid until the
+/// session is freed.
+///
+/// # Safety
+///
+/// `session` must be a valid datagram session.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_datagram_session_destination(
+    session: *const YosemiteDatagramSession,
+) -> *const c_char {
+    catch_panic(ptr::null(), || (*session).destination.as_ptr())
+}
+
+/// Get public destination of the datagram session, which peers send datagrams to.
+///
+/// The string is valid until the session is freed.
+///
+/// # Safety
+///
+/// `session` must be a valid datagram session.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_datagram_session_public_destination(
+    session: *const YosemiteDatagramSession,
+) -> *const c_char {
+    catch_panic(ptr::null(), || (*session).public_destination.as_ptr())
+}
+
+/// Send `len` bytes from `buf` to `destination` in a datagram.
+///
+/// Returns `0` on success and `-1` on error.
+///
+/// # Safety
+///
+/// `session` must be a valid datagram session, `buf` must be valid for reads of `len` bytes or
+/// `NULL` if `len` is `0` and `destination` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_datagram_session_send(
+    session: *mut YosemiteDatagramSession,
+    buf: *const u8,
+    len: usize,
+    destination: *const c_char,
+) -> c_int {
+    catch_panic(-1, || {
+        let Some(destination) = string_arg(destination, "destination") else {
+            return -1;
+        };
+        let Some(buf) = buf_arg(buf, len) else {
+            return -1;
+        };
+
+        match ok_or_set_error(runtime().block_on((*session).session.send_to(buf, destination))) {
+            Some(()) => 0,
+            None => -1,
+        }
+    })
+}
+
+/// Receive datagram into `buf` of `len` bytes.
+///
+/// Datagrams longer than `len` are truncated. If `sender` is not `NULL`, it's set to the
+/// destination who sent the datagram, which is valid until the next datagram is received or the
+/// session is freed.
+///
+/// Returns the number of bytes received or `-1` on error.
+///
+/// # Safety
+///
+/// `session` must be a valid datagram session, `buf` must be valid for writes of `len` bytes or
+/// `NULL` if `len` is `0` and `sender` must be `NULL` or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_datagram_session_recv(
+    session: *mut YosemiteDatagramSession,
+    buf: *mut u8,
+    len: usize,
+    sender: *mut *const c_char,
+) -> isize {
+    catch_panic(-1, || {
+        let session = &mut *session;
+        let Some(buf) = buf_arg_mut(buf, len) else {
+            return -1;
+        };
+
+        match ok_or_set_error(runtime().block_on(session.session.recv_from(buf))) {
+            Some((nread, destination)) => {
+                session.sender = c_string(&destination);
+
+                if !sender.is_null() {
+                    *sender = session.sender.as_ptr();
+                }
+
+                nread as isize
+            }
+            None => -1,
+        }
+    })
+}
+
+/// Close the datagram session and free it.
+///
+/// # Safety
+///
+/// `session` must be `NULL` or a valid datagram session which is not used after the call.
+#[no_mangle]
+pub unsafe extern "C" fn yosemite_datagram_session_free(session: *mut YosemiteDatagramSession) {
+    catch_panic((), || {
+        if !session.is_null() {
+            let _guard = runtime().enter();
+            drop(Box::from_raw(session));
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{random_destination, MockSam};
+    use std::sync::mpsc;
+
+    #[test]
+    fn ffi() {
+        extern "C" fn on_accept(user_data: *mut c_void, stream: *mut YosemiteStream) {
+            let tx = unsafe { &*(user_data as *const mpsc::Sender<usize>) };
+            tx.send(stream as usize).unwrap();
+        }
+
+        let sam = MockSam::new().unwrap();
+        let options = YosemiteOptions {
+            samv3_tcp_port: sam.tcp_port(),
+            samv3_udp_port: sam.udp_port(),
+            ..yosemite_options_default()
+        };
+        let (tx, rx) = mpsc::channel::<usize>();
+
+        unsafe {
+            let server = yosemite_session_new(&options);
+            let client = yosemite_session_new(&options);
+            assert!(!server.is_null() && !client.is_null());
+
+            let tx_ptr = &tx as *const mpsc::Sender<usize> as *mut c_void;
+            assert_eq!(
+                yosemite_session_set_accept_callback(server, on_accept, tx_ptr),
+                0
+            );
+            assert_eq!(
+                yosemite_session_set_accept_callback(server, on_accept, tx_ptr),
+                -1
+            );
+            assert!(yosemite_session_accept(server).is_null());
+
+            let destination = yosemite_session_public_destination(server);
+            let outbound = yosemite_session_connect(client, destination);
+            assert!(!outbound.is_null());
+            assert_eq!(yosemite_stream_write(outbound, b"hello".as_ptr(), 5), 0);
+
+            let inbound = rx.recv().unwrap() as *mut YosemiteStream;
+            assert!(!inbound.is_null());
+            assert_eq!(
+                CStr::from_ptr(yosemite_stream_remote_destination(inbound)),
+                CStr::from_ptr(yosemite_session_public_destination(client)),
+            );
+
+            let mut buffer = [0u8; 5];
+            assert_eq!(yosemite_stream_read(inbound, buffer.as_mut_ptr(), 5), 5);
+            assert_eq!(&buffer, b"hello");
+
+            // errors are reported on the calling thread
+            let unknown = CString::new(random_destination()).unwrap();
+            assert!(yosemite_session_connect(client, unknown.as_ptr()).is_null());
+            assert!(!yosemite_last_error().is_null());
+
+            yosemite_stream_free(inbound);
+            yosemite_stream_free(outbound);
+            yosemite_session_free(client);
+            yosemite_session_free(server);
+
+            let first = yosemite_datagram_session_new(&options);
+            let second = yosemite_datagram_session_new(&options);
+            assert!(!first.is_null() && !second.is_null());
+
+            let destination = yosemite_datagram_session_public_destination(second);
+            assert_eq!(
+                yosemite_datagram_session_send(first, b"ping".as_ptr(), 4, destination),
+                0
+            );
+
+            let mut buffer = [0u8; 16];
+            let mut sender = ptr::null();
+            let nread =
+                yosemite_datagram_session_recv(second, buffer.as_mut_ptr(), 16, &mut sender);
+            assert_eq!(&buffer[..nread as usize], b"ping");
+            assert_eq!(
+                CStr::from_ptr(sender),
+                CStr::from_ptr(yosemite_datagram_session_public_destination(first)),
+            );
+
+            yosemite_datagram_session_free(first);
+            yosemite_datagram_session_free(second);
+        }
+    }
+
+    #[test]
+    fn null_buffers() {
+        let sam = MockSam::new().unwrap();
+        let options = YosemiteOptions {
+            samv3_tcp_port: sam.tcp_port(),
+            samv3_udp_port: sam.udp_port(),
+            ..yosemite_options_default()
+        };
+
+        unsafe {
+            let server = yosemite_session_new(&options);
+            let client = yosemite_session_new(&options);
+            let destination = yosemite_session_public_destination(server);
+            let handle = std::thread::spawn({
+                let server = server as usize;
+                move || yosemite_session_accept(server as *mut YosemiteSession) as usize
+            });
+
+            let stream = yosemite_session_connect(client, destination);
+            assert!(!stream.is_null());
+            let inbound = handle.join().unwrap() as *mut YosemiteStream;
+
+            // empty buffers don't need to point anywhere
+            assert_eq!(yosemite_stream_write(stream, ptr::null(), 0), 0);
+            assert_eq!(yosemite_stream_read(stream, ptr::null_mut(), 0), 0);
+
+            assert_eq!(yosemite_stream_write(stream, ptr::null(), 5), -1);
+            assert_eq!(
+                CStr::from_ptr(yosemite_last_error()).to_bytes(),
+                b"`buf` is null"
+            );
+            assert_eq!(yosemite_stream_read(stream, ptr::null_mut(), 5), -1);
+
+            let session = yosemite_datagram_session_new(&options);
+            let destination = yosemite_datagram_session_public_destination(session);
+            assert_eq!(
+                yosemite_datagram_session_send(session, ptr::null(), 4, destination),
+                -1
+            );
+            assert_eq!(
+                yosemite_datagram_session_recv(session, ptr::null_mut(), 4, ptr::null_mut()),
+                -1
+            );
+
+            yosemite_stream_free(stream);
+            yosemite_stream_free(inbound);
+            yosemite_session_free(client);
+            yosemite_session_free(server);
+            yosemite_datagram_session_free(session);
+        }
+    }
+
+    #[tokio::test]
+    async fn panics_are_reported() {
+        // blocking on the runtime of the module from within another runtime panics
+        let session = unsafe { yosemite_session_new(ptr::null()) };
+
+        assert!(session.is_null());
+        assert!(unsafe { CStr::from_ptr(yosemite_last_error()) }
+            .to_str()
+            .unwrap()
+            .starts_with("panic: "));
+    }
+}
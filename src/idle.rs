@@ -0,0 +1,195 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Idle tracking of sessions.
+
+use crate::{events::SessionEvent, tracing};
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak},
+    time::{Duration, Instant},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::idle";
+
+/// Callback invoked with the idle time of a session when it becomes idle.
+pub(crate) type IdleHook = Arc<dyn Fn(Duration) + Send + Sync>;
+
+/// Activity state of a session.
+struct ActivityState {
+    /// When was the session last active.
+    last_active: Instant,
+
+    /// Idle hook, if any.
+    hook: Option<IdleHook>,
+}
+
+/// Activity of a session.
+///
+/// Recorded by the session and its handles when virtual streams are opened or accepted and when
+/// datagrams are sent or received.
+#[derive(Clone)]
+pub(crate) struct Activity {
+    /// Activity state.
+    state: Arc<Mutex<ActivityState>>,
+}
+
+impl Activity {
+    /// Create new [`Activity`], starting from the current time.
+    pub(crate) fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(ActivityState {
+                last_active: Instant::now(),
+                hook: None,
+            })),
+        }
+    }
+
+    /// Lock the activity state.
+    fn state(&self) -> MutexGuard<'_, ActivityState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record activity of the session.
+    pub(crate) fn record(&self) {
+        self.state().last_active = Instant::now();
+    }
+
+    /// Get the time elapsed since the last activity of the session.
+    pub(crate) fn idle_time(&self) -> Duration {
+        self.state().last_active.elapsed()
+    }
+
+    /// Call `hook` when the session becomes idle, replacing any previously set hook.
+    pub(crate) fn set_hook(&self, hook: IdleHook) {
+        self.state().hook = Some(hook);
+    }
+
+    /// Create watcher which reports the session idle after `threshold` without activity.
+    pub(crate) fn watcher(&self, threshold: Duration) -> IdleWatcher {
+        IdleWatcher {
+            state: Arc::downgrade(&self.state),
+            threshold,
+            reported: None,
+        }
+    }
+}
+
+/// Idle watcher of a session.
+///
+/// The watcher is polled by a background task of the session which sleeps for the duration
+/// returned by [`IdleWatcher::poll()`] between the polls. The task exits once the session has
+/// been dropped.
+pub(crate) struct IdleWatcher {
+    /// Activity state of the session.
+    state: Weak<Mutex<ActivityState>>,
+
+    /// Time without activity after which the session is idle.
+    threshold: Duration,
+
+    /// Last activity before the most recently reported idle period, if any.
+    reported: Option<Instant>,
+}
+
+impl IdleWatcher {
+    /// Report the session idle if `threshold` has passed since its last activity.
+    ///
+    /// Each idle period is reported once by calling the idle hook and passing
+    /// [`SessionEvent::Idle`] to `emit`.
+    ///
+    /// Returns how long to wait before the next poll or `None` if the session has been dropped.
+    pub(crate) fn poll(&mut self, emit: impl FnOnce(SessionEvent)) -> Option<Duration> {
+        let state = self.state.upgrade()?;
+        let (last_active, hook) = {
+            let state = state.lock().unwrap_or_else(PoisonError::into_inner);
+            (state.last_active, state.hook.clone())
+        };
+        let idle_for = last_active.elapsed();
+
+        if idle_for < self.threshold {
+            return Some(self.threshold - idle_for);
+        }
+
+        if self.reported != Some(last_active) {
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?idle_for,
+                "session idle",
+            );
+            self.reported = Some(last_active);
+
+            if let Some(hook) = hook {
+                hook(idle_for);
+            }
+            emit(SessionEvent::Idle { idle_for });
+        }
+
+        // activity doesn't wake up the watcher so it's checked again after one threshold
+        Some(self.threshold)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn idle_periods_reported_once() {
+        let activity = Activity::new();
+        let hook_calls = Arc::new(AtomicUsize::new(0));
+        activity.set_hook({
+            let hook_calls = Arc::clone(&hook_calls);
+            Arc::new(move |_| {
+                hook_calls.fetch_add(1, Ordering::Relaxed);
+            })
+        });
+
+        let mut watcher = activity.watcher(Duration::ZERO);
+        let mut events = Vec::new();
+
+        assert!(watcher.poll(|event| events.push(event)).is_some());
+        assert!(watcher.poll(|event| events.push(event)).is_some());
+        assert_eq!(events.len(), 1);
+        assert_eq!(hook_calls.load(Ordering::Relaxed), 1);
+
+        // new activity starts a new idle period
+        activity.record();
+        assert!(watcher.poll(|event| events.push(event)).is_some());
+        assert!(matches!(
+            events[..],
+            [SessionEvent::Idle { .. }, SessionEvent::Idle { .. }]
+        ));
+        assert_eq!(hook_calls.load(Ordering::Relaxed), 2);
+
+        // watcher stops once the session is gone
+        drop(activity);
+        assert!(watcher.poll(|event| events.push(event)).is_none());
+    }
+
+    #[test]
+    fn active_session_not_reported() {
+        let activity = Activity::new();
+        let mut watcher = activity.watcher(Duration::from_secs(60));
+
+        let delay = watcher.poll(|_| panic!("session is not idle")).unwrap();
+        assert!(delay <= Duration::from_secs(60));
+        assert!(activity.idle_time() < Duration::from_secs(60));
+    }
+}
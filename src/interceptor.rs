@@ -0,0 +1,128 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use std::{fmt, sync::Arc};
+
+/// Hooks invoked with the traffic of the control connections of a session.
+///
+/// The interceptor sees every command `yosemite` sends to the router and every response line it
+/// reads from the router, including `PING`/`PONG` and the commands sent with
+/// `Session::send_raw_command()`, on both the session's own control connection and the control
+/// connections of its virtual streams. Data exchanged over virtual streams and datagrams is not
+/// intercepted.
+///
+/// The hooks are called on the task that performs the I/O so they should return quickly.
+///
+/// Both hooks do nothing by default.
+pub trait Interceptor: Send + Sync + 'static {
+    /// Called with each command line before it's sent to the router, without the terminating
+    /// newline.
+    fn on_command(&self, command: &str) {
+        let _ = command;
+    }
+
+    /// Called with each response line read from the router, without its terminator.
+    fn on_response(&self, response: &str) {
+        let _ = response;
+    }
+}
+
+/// Shared handle to an [`Interceptor`].
+///
+/// Two handles are equal if they point to the same interceptor.
+#[derive(Clone)]
+pub struct SharedInterceptor(Arc<dyn Interceptor>);
+
+impl SharedInterceptor {
+    /// Create new [`SharedInterceptor`] from `interceptor`.
+    pub fn new(interceptor: impl Interceptor) -> Self {
+        Self(Arc::new(interceptor))
+    }
+
+    /// Call [`Interceptor::on_command()`] for each line of `command`.
+    pub fn on_command(&self, command: &[u8]) {
+        command
+            .split(|byte| byte == &b'\n')
+            .filter(|line| !line.is_empty())
+            .for_each(|line| self.0.on_command(&String::from_utf8_lossy(line)));
+    }
+
+    /// Call [`Interceptor::on_response()`] for `response`.
+    pub fn on_response(&self, response: &str) {
+        self.0.on_response(response);
+    }
+}
+
+impl<T: Interceptor> From<Arc<T>> for SharedInterceptor {
+    fn from(interceptor: Arc<T>) -> Self {
+        Self(interceptor)
+    }
+}
+
+impl fmt::Debug for SharedInterceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedInterceptor").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for SharedInterceptor {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(Arc::as_ptr(&self.0), Arc::as_ptr(&other.0))
+    }
+}
+
+impl Eq for SharedInterceptor {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        commands: Mutex<Vec<String>>,
+    }
+
+    impl Interceptor for Recorder {
+        fn on_command(&self, command: &str) {
+            self.commands.lock().unwrap().push(command.to_string());
+        }
+    }
+
+    #[test]
+    fn pipelined_command_is_split_into_lines() {
+        let recorder = Arc::new(Recorder::default());
+        let interceptor = SharedInterceptor::from(Arc::clone(&recorder));
+
+        interceptor.on_command(b"HELLO VERSION\nSESSION CREATE STYLE=STREAM\n");
+        interceptor.on_response("HELLO REPLY RESULT=OK VERSION=3.3");
+
+        assert_eq!(
+            *recorder.commands.lock().unwrap(),
+            vec!["HELLO VERSION", "SESSION CREATE STYLE=STREAM"],
+        );
+    }
+
+    #[test]
+    fn equality() {
+        let interceptor = SharedInterceptor::new(Recorder::default());
+
+        assert_eq!(interceptor, interceptor.clone());
+        assert_ne!(interceptor, SharedInterceptor::new(Recorder::default()));
+    }
+}
@@ -0,0 +1,357 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Minimal JSON support for the I2PControl API.
+
+use std::fmt::{self, Write};
+
+/// Maximum nesting depth of arrays and objects accepted by the parser.
+const MAX_DEPTH: usize = 32;
+
+/// JSON value.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    /// `null`.
+    Null,
+
+    /// Boolean.
+    Bool(bool),
+
+    /// Number.
+    Number(f64),
+
+    /// String.
+    String(String),
+
+    /// Array.
+    Array(Vec<Value>),
+
+    /// Object, members in the order they were received.
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Get member `key` of an object.
+    pub(crate) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) =>
+                members.iter().find(|(member, _)| member == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a string.
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Get the value as a number.
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(value) => write!(f, "{value}"),
+            Value::Number(value) => write!(f, "{value}"),
+            Value::String(value) => write_string(f, value),
+            Value::Array(values) => {
+                f.write_char('[')?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(members) => {
+                f.write_char('{')?;
+                for (i, (key, value)) in members.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{value}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+/// Write `value` as a quoted JSON string.
+fn write_string(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in value.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if c.is_control() => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+/// Parse JSON document.
+///
+/// Returns `None` if `input` is not a single valid JSON value.
+pub(crate) fn parse(input: &str) -> Option<Value> {
+    let mut parser = Parser {
+        input: input.as_bytes(),
+        offset: 0,
+    };
+    let value = parser.value(0)?;
+
+    parser.skip_whitespace();
+    (parser.offset == input.len()).then_some(value)
+}
+
+/// JSON parser.
+struct Parser<'a> {
+    /// Input.
+    input: &'a [u8],
+
+    /// Offset of the next byte.
+    offset: usize,
+}
+
+impl Parser<'_> {
+    /// Skip whitespace.
+    fn skip_whitespace(&mut self) {
+        while self.input.get(self.offset).is_some_and(u8::is_ascii_whitespace) {
+            self.offset += 1;
+        }
+    }
+
+    /// Get the next byte which isn't whitespace without consuming it.
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_whitespace();
+        self.input.get(self.offset).copied()
+    }
+
+    /// Consume `expected` if it's the next byte which isn't whitespace.
+    fn eat(&mut self, expected: u8) -> bool {
+        let found = self.peek() == Some(expected);
+        self.offset += usize::from(found);
+        found
+    }
+
+    /// Consume `literal`.
+    fn literal(&mut self, literal: &str) -> Option<()> {
+        let end = self.offset + literal.len();
+
+        (self.input.get(self.offset..end)? == literal.as_bytes()).then(|| self.offset = end)
+    }
+
+    /// Parse value, nested `depth` levels deep.
+    fn value(&mut self, depth: usize) -> Option<Value> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+
+        match self.peek()? {
+            b'n' => self.literal("null").map(|_| Value::Null),
+            b't' => self.literal("true").map(|_| Value::Bool(true)),
+            b'f' => self.literal("false").map(|_| Value::Bool(false)),
+            b'"' => self.string().map(Value::String),
+            b'[' => {
+                self.offset += 1;
+                let mut values = Vec::new();
+
+                if !self.eat(b']') {
+                    loop {
+                        values.push(self.value(depth + 1)?);
+
+                        if self.eat(b']') {
+                            break;
+                        }
+                        self.eat(b',').then_some(())?;
+                    }
+                }
+
+                Some(Value::Array(values))
+            }
+            b'{' => {
+                self.offset += 1;
+                let mut members = Vec::new();
+
+                if !self.eat(b'}') {
+                    loop {
+                        (self.peek()? == b'"').then_some(())?;
+                        let key = self.string()?;
+                        self.eat(b':').then_some(())?;
+                        members.push((key, self.value(depth + 1)?));
+
+                        if self.eat(b'}') {
+                            break;
+                        }
+                        self.eat(b',').then_some(())?;
+                    }
+                }
+
+                Some(Value::Object(members))
+            }
+            _ => self.number().map(Value::Number),
+        }
+    }
+
+    /// Parse number.
+    fn number(&mut self) -> Option<f64> {
+        let start = self.offset;
+
+        while self
+            .input
+            .get(self.offset)
+            .is_some_and(|byte| matches!(byte, b'0'..=b'9' | b'-' | b'+' | b'.' | b'e' | b'E'))
+        {
+            self.offset += 1;
+        }
+
+        std::str::from_utf8(&self.input[start..self.offset]).ok()?.parse().ok()
+    }
+
+    /// Parse string, starting at the opening quote.
+    fn string(&mut self) -> Option<String> {
+        self.offset += 1;
+        let mut value = Vec::new();
+
+        loop {
+            let byte = *self.input.get(self.offset)?;
+            self.offset += 1;
+
+            match byte {
+                b'"' => return String::from_utf8(value).ok(),
+                b'\\' => {
+                    let escaped = *self.input.get(self.offset)?;
+                    self.offset += 1;
+
+                    let c = match escaped {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\u{8}',
+                        b'f' => '\u{c}',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => self.unicode_escape()?,
+                        _ => return None,
+                    };
+                    value.extend_from_slice(c.encode_utf8(&mut [0u8; 4]).as_bytes());
+                }
+                byte => value.push(byte),
+            }
+        }
+    }
+
+    /// Parse the code point of `\u` escape, including the low surrogate of a surrogate pair.
+    fn unicode_escape(&mut self) -> Option<char> {
+        let high = self.hex4()?;
+
+        if !(0xd800..0xdc00).contains(&high) {
+            return char::from_u32(high);
+        }
+
+        self.literal("\\u")?;
+        let low = self.hex4()?;
+        (0xdc00..0xe000).contains(&low).then_some(())?;
+
+        char::from_u32(0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00))
+    }
+
+    /// Parse four hexadecimal digits.
+    fn hex4(&mut self) -> Option<u32> {
+        let digits = self.input.get(self.offset..self.offset + 4)?;
+        self.offset += 4;
+
+        u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_values() {
+        let value = parse(
+            r#" {"id": 1, "result": {"Token": "abc\"dé😀",
+            "list": [true, false, null, -1.5e3]}, "jsonrpc": "2.0"} "#,
+        )
+        .unwrap();
+
+        assert_eq!(value.get("id").and_then(Value::as_f64), Some(1.0));
+        let result = value.get("result").unwrap();
+        assert_eq!(
+            result.get("Token").and_then(Value::as_str),
+            Some("abc\"d\u{e9}\u{1f600}")
+        );
+        assert_eq!(
+            result.get("list"),
+            Some(&Value::Array(vec![
+                Value::Bool(true),
+                Value::Bool(false),
+                Value::Null,
+                Value::Number(-1500.0),
+            ]))
+        );
+
+        assert!(parse("{\"id\": 1").is_none());
+        assert!(parse("[1,]").is_none());
+        assert!(parse("{} {}").is_none());
+        assert!(parse(&"[".repeat(MAX_DEPTH + 2)).is_none());
+    }
+
+    #[test]
+    fn serialize_values() {
+        let value = Value::Object(vec![
+            (
+                "method".to_string(),
+                Value::String("Authenticate".to_string()),
+            ),
+            (
+                "params".to_string(),
+                Value::Array(vec![Value::Null, Value::Number(1.0)]),
+            ),
+            (
+                "password".to_string(),
+                Value::String("a\"b\\c\n\u{1}".to_string()),
+            ),
+        ]);
+
+        assert_eq!(
+            value.to_string(),
+            r#"{"method":"Authenticate","params":[null,1],"password":"a\"b\\c\n\u0001"}"#
+        );
+        assert_eq!(parse(&value.to_string()), Some(value));
+    }
+}
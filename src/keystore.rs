@@ -0,0 +1,670 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Persistent storage of destination keys.
+
+#[cfg(feature = "encryption")]
+use crate::error::Error;
+
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    Key, XChaCha20Poly1305, XNonce,
+};
+
+use std::{
+    fmt, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Header of an encrypted key file.
+#[cfg(feature = "encryption")]
+const MAGIC: &[u8] = b"yosemite-keystore-v1\n";
+
+/// Length of the passphrase salt.
+#[cfg(feature = "encryption")]
+const SALT_LEN: usize = 16;
+
+/// Length of the XChaCha20-Poly1305 nonce.
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 24;
+
+/// Length of the encryption key and of key files.
+#[cfg(feature = "encryption")]
+const KEY_LEN: usize = 32;
+
+/// How the encryption key of a key store is derived.
+#[cfg(feature = "encryption")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kdf {
+    /// Key is read from a key file.
+    KeyFile = 0,
+
+    /// Key is derived from a passphrase with Argon2id.
+    Argon2id = 1,
+}
+
+/// Encryption of a key store.
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+struct Encryption {
+    /// How the key was derived.
+    kdf: Kdf,
+
+    /// Salt of the passphrase, zero for key files.
+    salt: [u8; SALT_LEN],
+
+    /// Encryption key.
+    key: Key,
+}
+
+#[cfg(feature = "encryption")]
+impl fmt::Debug for Encryption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Encryption").field("kdf", &self.kdf).finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Encryption {
+    /// Derive encryption key from `passphrase` and `salt`.
+    fn from_passphrase(passphrase: &str, salt: [u8; SALT_LEN]) -> crate::Result<Self> {
+        let mut key = Key::default();
+        argon2::Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error.to_string()))?;
+
+        Ok(Self {
+            kdf: Kdf::Argon2id,
+            salt,
+            key,
+        })
+    }
+
+    /// Read encryption key from `key_file`.
+    fn from_key_file(key_file: &Path) -> crate::Result<Self> {
+        let key = fs::read(key_file)?;
+        if key.len() != KEY_LEN {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidData, "key file must hold 32 bytes").into(),
+            );
+        }
+
+        Ok(Self {
+            kdf: Kdf::KeyFile,
+            salt: [0u8; SALT_LEN],
+            key: *Key::from_slice(&key),
+        })
+    }
+
+    /// Header of a key file encrypted with this encryption, authenticated with the contents.
+    fn header(&self) -> Vec<u8> {
+        let mut header = MAGIC.to_vec();
+        header.push(self.kdf as u8);
+        header.extend_from_slice(&self.salt);
+        header
+    }
+
+    /// Encrypt `contents` of a key file.
+    fn seal(&self, contents: &[u8]) -> crate::Result<Vec<u8>> {
+        let header = self.header();
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = XChaCha20Poly1305::new(&self.key)
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: contents,
+                    aad: &header,
+                },
+            )
+            .map_err(|_| Error::DecryptionFailed)?;
+
+        Ok([header, nonce.to_vec(), ciphertext].concat())
+    }
+
+    /// Decrypt encrypted key file `contents`.
+    fn open(&self, contents: &[u8]) -> crate::Result<String> {
+        let header = self.header();
+        let nonce = contents.get(header.len()..header.len() + NONCE_LEN);
+
+        let (Some(nonce), true) = (nonce, contents.starts_with(&header)) else {
+            return Err(Error::DecryptionFailed);
+        };
+        let plaintext = XChaCha20Poly1305::new(&self.key)
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: &contents[header.len() + NONCE_LEN..],
+                    aad: &header,
+                },
+            )
+            .map_err(|_| Error::DecryptionFailed)?;
+
+        String::from_utf8(plaintext).map_err(|_| Error::DecryptionFailed)
+    }
+}
+
+/// Get the salt of an encrypted key file encrypted with a passphrase, if it's one.
+#[cfg(feature = "encryption")]
+fn passphrase_salt(contents: &[u8]) -> Option<[u8; SALT_LEN]> {
+    let rest = contents.strip_prefix(MAGIC)?.strip_prefix(&[Kdf::Argon2id as u8])?;
+
+    rest.get(..SALT_LEN)?.try_into().ok()
+}
+
+/// State of a stored key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyState {
+    /// Key of the current destination.
+    Active,
+
+    /// Key of a previous destination which is still served during a grace period.
+    Retiring,
+
+    /// Key of a previous destination which is no longer served.
+    Retired,
+}
+
+impl KeyState {
+    /// Get the name of the state in the key file.
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Retiring => "retiring",
+            Self::Retired => "retired",
+        }
+    }
+
+    /// Parse state from its name in the key file.
+    fn from_str(state: &str) -> Option<Self> {
+        match state {
+            "active" => Some(Self::Active),
+            "retiring" => Some(Self::Retiring),
+            "retired" => Some(Self::Retired),
+            _ => None,
+        }
+    }
+}
+
+/// Key stored in a [`KeyStore`].
+#[derive(Clone, PartialEq, Eq)]
+pub struct StoredKey {
+    /// Private key of the destination, usable with
+    /// [`DestinationKind::Persistent`](crate::DestinationKind::Persistent).
+    pub private_key: String,
+
+    /// State of the key.
+    pub state: KeyState,
+
+    /// When did the key enter its current state.
+    pub since: SystemTime,
+}
+
+impl fmt::Debug for StoredKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StoredKey")
+            .field("state", &self.state)
+            .field("since", &self.since)
+            .finish_non_exhaustive()
+    }
+}
+
+/// ## Key store.
+///
+/// `KeyStore` keeps the private keys of a service's destinations in a file and tracks which key
+/// is active and which keys have been retired, see
+/// [`KeyRotation`](crate::rotation::KeyRotation) for rotating the key of a running service.
+///
+/// Changes are only written to the file by [`KeyStore::save()`]. The file is readable only by the
+/// owner on Unix.
+///
+/// With the `encryption` feature, which is enabled by default, the key file can be encrypted with
+/// XChaCha20-Poly1305 using a key derived from a passphrase with Argon2id, see
+/// [`KeyStore::open_with_passphrase()`], or read from a key file, see
+/// [`KeyStore::open_with_key_file()`]. Opening an unencrypted key file this way encrypts it when
+/// the key store is next saved.
+///
+/// ```no_run
+/// use yosemite::{DestinationKind, KeyStore, SessionOptions};
+///
+/// # fn main() -> yosemite::Result<()> {
+/// let keystore = KeyStore::open("service.keys")?;
+///
+/// if let Some(key) = keystore.active() {
+///     let options = SessionOptions {
+///         destination: DestinationKind::Persistent {
+///             private_key: key.private_key.clone(),
+///         },
+///         ..Default::default()
+///     };
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct KeyStore {
+    /// Path of the key file.
+    path: PathBuf,
+
+    /// Stored keys.
+    keys: Vec<StoredKey>,
+
+    /// Encryption of the key file, if it's encrypted.
+    #[cfg(feature = "encryption")]
+    encryption: Option<Encryption>,
+}
+
+impl KeyStore {
+    /// Open key store at `path`.
+    ///
+    /// If the file doesn't exist, the key store is empty and the file is created when the key
+    /// store is first saved.
+    ///
+    /// Encrypted key files must be opened with [`KeyStore::open_with_passphrase()`] or
+    /// [`KeyStore::open_with_key_file()`].
+    pub fn open(path: impl Into<PathBuf>) -> crate::Result<Self> {
+        let path = path.into();
+        let keys = match read_key_file(&path)? {
+            #[cfg(feature = "encryption")]
+            Some(contents) if contents.starts_with(MAGIC) =>
+                return Err(
+                    io::Error::new(io::ErrorKind::InvalidData, "key file is encrypted").into(),
+                ),
+            Some(contents) => parse_plaintext(&contents)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            path,
+            keys,
+            #[cfg(feature = "encryption")]
+            encryption: None,
+        })
+    }
+
+    /// Open key store at `path`, encrypted with a key derived from `passphrase`.
+    ///
+    /// If the file doesn't exist, the key store is empty and the file is created when the key
+    /// store is first saved. If the file is not encrypted, it's encrypted when the key store is
+    /// next saved.
+    ///
+    /// Fails with [`Error::DecryptionFailed`] if the passphrase is wrong or the file has been
+    /// tampered with.
+    #[cfg(feature = "encryption")]
+    pub fn open_with_passphrase(path: impl Into<PathBuf>, passphrase: &str) -> crate::Result<Self> {
+        let path = path.into();
+        let contents = read_key_file(&path)?;
+
+        let salt = match contents.as_deref() {
+            Some(contents) if contents.starts_with(MAGIC) =>
+                passphrase_salt(contents).ok_or(Error::DecryptionFailed)?,
+            _ => {
+                let mut salt = [0u8; SALT_LEN];
+                OsRng.fill_bytes(&mut salt);
+                salt
+            }
+        };
+
+        Self::open_encrypted(
+            path,
+            contents,
+            Encryption::from_passphrase(passphrase, salt)?,
+        )
+    }
+
+    /// Open key store at `path`, encrypted with the key stored in `key_file`.
+    ///
+    /// `key_file` must hold 32 bytes, see [`KeyStore::generate_key_file()`]. If the file at `path`
+    /// doesn't exist, the key store is empty and the file is created when the key store is first
+    /// saved. If the file is not encrypted, it's encrypted when the key store is next saved.
+    ///
+    /// Fails with [`Error::DecryptionFailed`] if the key is wrong or the file has been tampered
+    /// with.
+    #[cfg(feature = "encryption")]
+    pub fn open_with_key_file(
+        path: impl Into<PathBuf>,
+        key_file: impl AsRef<Path>,
+    ) -> crate::Result<Self> {
+        let path = path.into();
+        let contents = read_key_file(&path)?;
+
+        Self::open_encrypted(
+            path,
+            contents,
+            Encryption::from_key_file(key_file.as_ref())?,
+        )
+    }
+
+    /// Generate a random key for [`KeyStore::open_with_key_file()`] and write it to `key_file`.
+    ///
+    /// Fails if `key_file` already exists. The file is readable only by the owner on Unix.
+    #[cfg(feature = "encryption")]
+    pub fn generate_key_file(key_file: impl AsRef<Path>) -> crate::Result<()> {
+        let key = XChaCha20Poly1305::generate_key(&mut OsRng);
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(key_file)?;
+        file.write_all(&key)?;
+        file.sync_all()?;
+
+        Ok(())
+    }
+
+    /// Create key store from `contents` of the file at `path`, decrypting them with `encryption`
+    /// if they're encrypted.
+    #[cfg(feature = "encryption")]
+    fn open_encrypted(
+        path: PathBuf,
+        contents: Option<Vec<u8>>,
+        encryption: Encryption,
+    ) -> crate::Result<Self> {
+        let keys = match contents {
+            Some(contents) if contents.starts_with(MAGIC) =>
+                parse_keys(&encryption.open(&contents)?)?,
+            Some(contents) => parse_plaintext(&contents)?,
+            None => Vec::new(),
+        };
+
+        Ok(Self {
+            path,
+            keys,
+            encryption: Some(encryption),
+        })
+    }
+
+    /// Check if the key file is encrypted when the key store is saved.
+    #[cfg(feature = "encryption")]
+    pub fn is_encrypted(&self) -> bool {
+        self.encryption.is_some()
+    }
+
+    /// Get path of the key file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the active key, if any.
+    pub fn active(&self) -> Option<&StoredKey> {
+        self.keys.iter().find(|key| key.state == KeyState::Active)
+    }
+
+    /// Get all stored keys, oldest first.
+    pub fn keys(&self) -> &[StoredKey] {
+        &self.keys
+    }
+
+    /// Store `private_key` as the active key.
+    ///
+    /// The previously active key, if any, is moved to [`KeyState::Retiring`].
+    pub fn activate(&mut self, private_key: impl Into<String>) {
+        let now = SystemTime::now();
+
+        for key in self.keys.iter_mut().filter(|key| key.state == KeyState::Active) {
+            key.state = KeyState::Retiring;
+            key.since = now;
+        }
+
+        self.keys.push(StoredKey {
+            private_key: private_key.into(),
+            state: KeyState::Active,
+            since: now,
+        });
+    }
+
+    /// Move `private_key` to [`KeyState::Retired`].
+    ///
+    /// Returns `false` if the key is not stored.
+    pub fn retire(&mut self, private_key: &str) -> bool {
+        match self.keys.iter_mut().find(|key| key.private_key == private_key) {
+            Some(key) => {
+                key.state = KeyState::Retired;
+                key.since = SystemTime::now();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove `private_key` from the key store.
+    ///
+    /// Returns `false` if the key is not stored.
+    pub fn remove(&mut self, private_key: &str) -> bool {
+        let len = self.keys.len();
+        self.keys.retain(|key| key.private_key != private_key);
+
+        self.keys.len() != len
+    }
+
+    /// Write the key store to its file.
+    ///
+    /// The keys are written to a temporary file first which then replaces the key file, so the
+    /// key file is never left partially written. If the key store is encrypted, the file is
+    /// encrypted with a new nonce each time it's saved.
+    pub fn save(&self) -> crate::Result<()> {
+        let mut contents = String::new();
+
+        for key in &self.keys {
+            let since = key.since.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+            contents.push_str(&format!(
+                "{} {since} {}\n",
+                key.state.as_str(),
+                key.private_key
+            ));
+        }
+
+        #[cfg(feature = "encryption")]
+        let contents = match &self.encryption {
+            Some(encryption) => encryption.seal(contents.as_bytes())?,
+            None => contents.into_bytes(),
+        };
+
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+        let mut file = options.open(&temporary)?;
+        file.write_all(contents.as_ref())?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+
+        Ok(())
+    }
+}
+
+/// Read the contents of the key file at `path`.
+///
+/// Returns `None` if the file doesn't exist.
+fn read_key_file(path: &Path) -> crate::Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Parse keys from the contents of an unencrypted key file.
+fn parse_plaintext(contents: &[u8]) -> crate::Result<Vec<StoredKey>> {
+    parse_keys(
+        std::str::from_utf8(contents)
+            .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?,
+    )
+}
+
+/// Parse keys from the contents of a key file.
+fn parse_keys(contents: &str) -> crate::Result<Vec<StoredKey>> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            parse_key(line).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "malformed key file entry").into()
+            })
+        })
+        .collect()
+}
+
+/// Parse one line of a key file.
+///
+/// Each line holds the state of the key, the unix timestamp of the state change and the private
+/// key, separated by spaces.
+fn parse_key(line: &str) -> Option<StoredKey> {
+    let mut fields = line.split_whitespace();
+    let state = KeyState::from_str(fields.next()?)?;
+    let since = UNIX_EPOCH + Duration::from_secs(fields.next()?.parse().ok()?);
+    let private_key = fields.next()?.to_string();
+
+    fields.next().is_none().then_some(StoredKey {
+        private_key,
+        state,
+        since,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_key_file() {
+        let keys = parse_keys("retired 10 AAAA\n\nactive 20 BBBB\n").unwrap();
+
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].state, KeyState::Retired);
+        assert_eq!(keys[0].private_key, "AAAA");
+        assert_eq!(keys[1].state, KeyState::Active);
+        assert_eq!(keys[1].since, UNIX_EPOCH + Duration::from_secs(20));
+
+        assert!(parse_keys("active AAAA\n").is_err());
+        assert!(parse_keys("revoked 10 AAAA\n").is_err());
+    }
+
+    #[test]
+    fn activate_and_retire() {
+        let path = std::env::temp_dir().join(format!(
+            "yosemite-keystore-{}-{:?}",
+            std::process::id(),
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
+        ));
+
+        let mut keystore = KeyStore::open(&path).unwrap();
+        assert!(keystore.active().is_none());
+
+        keystore.activate("AAAA");
+        keystore.activate("BBBB");
+        assert_eq!(keystore.active().unwrap().private_key, "BBBB");
+        assert_eq!(keystore.keys()[0].state, KeyState::Retiring);
+
+        assert!(keystore.retire("AAAA"));
+        assert!(!keystore.retire("CCCC"));
+        keystore.save().unwrap();
+
+        let keystore = KeyStore::open(&path).unwrap();
+        assert_eq!(
+            keystore.keys().iter().map(|key| key.state).collect::<Vec<_>>(),
+            [KeyState::Retired, KeyState::Active]
+        );
+        assert!(!format!("{keystore:?}").contains("AAAA"));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_key_file() {
+        let path = std::env::temp_dir().join(format!(
+            "yosemite-keystore-encrypted-{}-{:?}",
+            std::process::id(),
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
+        ));
+        let key_file = path.with_extension("key");
+        let other_key_file = path.with_extension("other");
+        KeyStore::generate_key_file(&key_file).unwrap();
+        KeyStore::generate_key_file(&other_key_file).unwrap();
+        assert!(KeyStore::generate_key_file(&key_file).is_err());
+
+        // unencrypted key file is encrypted when saved
+        let mut keystore = KeyStore::open(&path).unwrap();
+        keystore.activate("AAAA");
+        keystore.save().unwrap();
+
+        let keystore = KeyStore::open_with_key_file(&path, &key_file).unwrap();
+        assert!(keystore.is_encrypted());
+        keystore.save().unwrap();
+        assert!(!fs::read_to_string(&path).unwrap_or_default().contains("AAAA"));
+
+        let keystore = KeyStore::open_with_key_file(&path, &key_file).unwrap();
+        assert_eq!(keystore.active().unwrap().private_key, "AAAA");
+        assert!(matches!(
+            KeyStore::open_with_key_file(&path, &other_key_file),
+            Err(Error::DecryptionFailed)
+        ));
+        assert!(matches!(
+            KeyStore::open(&path),
+            Err(Error::IoError(error)) if error.kind() == io::ErrorKind::InvalidData
+        ));
+
+        // tampered file is rejected
+        let mut contents = fs::read(&path).unwrap();
+        *contents.last_mut().unwrap() ^= 1;
+        fs::write(&path, contents).unwrap();
+        assert!(matches!(
+            KeyStore::open_with_key_file(&path, &key_file),
+            Err(Error::DecryptionFailed)
+        ));
+
+        for file in [&path, &key_file, &other_key_file] {
+            fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn passphrase() {
+        let path = std::env::temp_dir().join(format!(
+            "yosemite-keystore-passphrase-{}-{:?}",
+            std::process::id(),
+            SystemTime::now().duration_since(UNIX_EPOCH).unwrap(),
+        ));
+
+        let mut keystore = KeyStore::open_with_passphrase(&path, "hunter2").unwrap();
+        keystore.activate("AAAA");
+        keystore.save().unwrap();
+
+        let keystore = KeyStore::open_with_passphrase(&path, "hunter2").unwrap();
+        assert_eq!(keystore.active().unwrap().private_key, "AAAA");
+        assert!(!format!("{keystore:?}").contains("hunter2"));
+        assert!(matches!(
+            KeyStore::open_with_passphrase(&path, "hunter3"),
+            Err(Error::DecryptionFailed)
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -18,35 +18,146 @@
 
 #![doc = include_str!("../README.md")]
 
-#[cfg(all(feature = "sync", feature = "async"))]
-compile_error!("feature \"sync\" and feature \"async\" cannot be enabled at the same time");
-
+mod address;
+#[cfg(any(feature = "async", feature = "sync"))]
+mod backoff;
 mod error;
+mod events;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(any(feature = "async", feature = "sync"))]
+mod idle;
+mod interceptor;
+#[cfg(feature = "router-control")]
+mod json;
+mod keystore;
+mod metrics;
 mod options;
+#[cfg(any(feature = "async", feature = "sync"))]
+mod ports;
+mod redaction;
+mod router_info;
+#[cfg(any(feature = "async", feature = "sync"))]
+mod slots;
+mod status;
+#[cfg(any(feature = "async", feature = "sync"))]
+mod throttle;
+mod tracing;
+
+/// Sans-io implementation of the SAMv3 protocol.
+///
+/// The controllers in this module implement the SAMv3 state machines without performing any I/O.
+/// Each call that starts a request returns the serialized command which the caller must write to
+/// the socket of the router, and each line received from the router is passed back to the
+/// controller with `handle_response()`. Reading the lines is up to the caller, [`LineBuffer`] can
+/// be used to assemble them from raw socket reads.
+///
+/// [`SessionController`] drives the control connection of a session and the connections of its
+/// virtual streams (`STREAM CONNECT`, `STREAM ACCEPT` and `STREAM FORWARD`) and
+/// [`RouterApiController`] drives `NAMING LOOKUP` and `DEST GENERATE`. Connections of virtual
+/// streams that are set up concurrently, such as a pending `STREAM ACCEPT` and a `STREAM CONNECT`,
+/// each need a [`StreamController`] of their own.
+///
+/// The module is available with the `sans-io` feature.
+///
+/// ```
+/// use yosemite::proto::router::RouterApiController;
+///
+/// let mut controller = RouterApiController::new();
+///
+/// // write `HELLO` to the router and pass the reply back to the controller
+/// assert_eq!(controller.handshake_router_api().unwrap(), b"HELLO VERSION\n");
+/// controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").unwrap();
+///
+/// let command = controller.lookup_name("host.i2p").unwrap();
+/// assert!(command.starts_with(b"NAMING LOOKUP NAME=host.i2p"));
+/// controller
+///     .handle_response("NAMING REPLY RESULT=OK NAME=host.i2p VALUE=abcd\n")
+///     .unwrap();
+///
+/// assert_eq!(controller.destination(), "abcd");
+/// ```
+///
+/// [`LineBuffer`]: line::LineBuffer
+/// [`SessionController`]: session::SessionController
+/// [`StreamController`]: session::StreamController
+/// [`RouterApiController`]: router::RouterApiController
+#[cfg(feature = "sans-io")]
+pub mod proto;
+#[cfg(not(feature = "sans-io"))]
 mod proto;
 
-pub use error::{Error, I2pError, ProtocolError};
-pub use options::{DestinationKind, SessionOptions};
+pub mod protocol;
+pub mod record;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+pub use address::{b32_address, blinded_b32_address};
+pub use error::{clear_error_hook, set_error_hook, Error, I2pError, ProtocolError};
+pub use events::SessionEvent;
+pub use interceptor::{Interceptor, SharedInterceptor};
+pub use keystore::{KeyState, KeyStore, StoredKey};
+pub use metrics::MetricsSnapshot;
+pub use options::{
+    AcceptFilter, DestinationKind, DialBackoff, LeaseSetAuth, LeaseSetType, LookupCredentials,
+    RateLimit, ReduceOnIdle, RouterCompatibility, SessionOptions, SignatureType, StreamLimitPolicy,
+    StreamOptions,
+};
+pub use redaction::{log_redaction, set_log_redaction, LogRedaction};
+pub use router_info::RouterInfo;
+pub use status::{ForwardingState, SessionState, SessionStatus, SocketAddrs};
 
 #[cfg(feature = "async")]
 mod asynchronous;
 
-#[cfg(all(feature = "async", not(feature = "sync")))]
+#[cfg(feature = "async")]
 pub use {
+    asynchronous::gate::{GateDecision, Ports},
+    asynchronous::hidden_service::{HiddenService, HiddenServiceBuilder},
+    asynchronous::manager,
+    asynchronous::proxy,
+    asynchronous::rotation,
     asynchronous::router::RouterApi,
-    asynchronous::session::{style, Session},
+    asynchronous::session::{
+        style, ConnectFuture, DynDatagramSession, DynStreamSession, PortChannel, Session,
+        SessionHandle,
+    },
     asynchronous::stream::Stream,
+    asynchronous::tunnel,
 };
 
+#[cfg(feature = "router-control")]
+pub use asynchronous::router_control;
+
+#[cfg(feature = "yamux")]
+pub use asynchronous::mux;
+
+#[cfg(feature = "serde")]
+pub use asynchronous::typed;
+
 #[cfg(feature = "sync")]
 mod synchronous;
 
 #[cfg(all(feature = "sync", not(feature = "async")))]
 pub use {
     synchronous::router::RouterApi,
-    synchronous::session::{style, Session},
+    synchronous::session::{style, DynDatagramSession, DynStreamSession, Session},
     synchronous::stream::Stream,
 };
 
+/// Blocking API.
+///
+/// Available with the `sync` feature. If only `sync` is enabled, the blocking API is also exported
+/// at the crate root. If `async` is enabled as well, the crate root exports the asynchronous API
+/// and the blocking API is only available here, which allows both to be compiled into one binary.
+#[cfg(feature = "sync")]
+pub mod blocking {
+    pub use crate::synchronous::{
+        router::RouterApi,
+        session::{style, DynDatagramSession, DynStreamSession, Session},
+        stream::Stream,
+    };
+}
+
 /// Result type of the crate.
 pub type Result<T> = core::result::Result<T, error::Error>;
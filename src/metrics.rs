@@ -0,0 +1,118 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+/// Snapshot of session metrics.
+///
+/// The counters are cumulative since the session was created. See `Session::metrics_snapshot()`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Number of outbound virtual streams opened.
+    pub streams_opened: u64,
+
+    /// Number of inbound virtual streams accepted.
+    pub streams_accepted: u64,
+
+    /// Number of virtual streams that couldn't be opened or accepted.
+    pub streams_failed: u64,
+
+    /// Number of inbound virtual streams rejected by
+    /// [`SessionOptions::accept_filter`](crate::SessionOptions::accept_filter) or by the gate of
+    /// the session, see `Session::set_gate()`.
+    pub streams_rejected: u64,
+
+    /// Number of datagrams sent.
+    pub datagrams_sent: u64,
+
+    /// Number of datagrams received.
+    pub datagrams_received: u64,
+
+    /// Number of payload bytes sent in datagrams.
+    pub datagram_bytes_sent: u64,
+
+    /// Number of payload bytes received in datagrams.
+    pub datagram_bytes_received: u64,
+}
+
+#[cfg(any(feature = "async", feature = "sync"))]
+impl MetricsSnapshot {
+    /// Record the result of opening an outbound virtual stream.
+    pub(crate) fn record_connect<T>(&mut self, result: &crate::Result<T>) {
+        match result {
+            Ok(_) => self.streams_opened += 1,
+            Err(_) => self.streams_failed += 1,
+        }
+    }
+
+    /// Record the result of accepting an inbound virtual stream.
+    pub(crate) fn record_accept<T>(&mut self, result: &crate::Result<T>) {
+        match result {
+            Ok(_) => self.streams_accepted += 1,
+            Err(_) => self.streams_failed += 1,
+        }
+    }
+
+    /// Record an inbound virtual stream that was rejected.
+    pub(crate) fn record_rejected(&mut self) {
+        self.streams_rejected += 1;
+    }
+
+    /// Record a datagram of `len` bytes that was sent.
+    pub(crate) fn record_datagram_sent(&mut self, len: usize) {
+        self.datagrams_sent += 1;
+        self.datagram_bytes_sent += len as u64;
+    }
+
+    /// Record a datagram of `len` bytes that was received.
+    pub(crate) fn record_datagram_received(&mut self, len: usize) {
+        self.datagrams_received += 1;
+        self.datagram_bytes_received += len as u64;
+    }
+}
+
+#[cfg(all(test, any(feature = "async", feature = "sync")))]
+mod tests {
+    use super::*;
+    use crate::Error;
+
+    #[test]
+    fn record() {
+        let mut metrics = MetricsSnapshot::default();
+
+        metrics.record_connect(&Ok(()));
+        metrics.record_connect::<()>(&Err(Error::Malformed));
+        metrics.record_accept(&Ok(()));
+        metrics.record_rejected();
+        metrics.record_datagram_sent(16);
+        metrics.record_datagram_received(32);
+        metrics.record_datagram_received(8);
+
+        assert_eq!(
+            metrics,
+            MetricsSnapshot {
+                streams_opened: 1,
+                streams_accepted: 1,
+                streams_failed: 1,
+                streams_rejected: 1,
+                datagrams_sent: 1,
+                datagrams_received: 2,
+                datagram_bytes_sent: 16,
+                datagram_bytes_received: 40,
+            }
+        );
+    }
+}
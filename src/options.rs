@@ -16,12 +16,37 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+#[cfg(feature = "rand")]
 use rand::{
     distributions::{Alphanumeric, DistString},
-    thread_rng,
+    rngs::StdRng,
+    thread_rng, Rng, SeedableRng,
 };
 
-use std::fmt;
+use crate::{
+    address::{decode_base64, encode_base64, encode_private_key},
+    error::Error,
+    interceptor::SharedInterceptor,
+    router_info::RouterInfo,
+    tracing,
+};
+
+#[cfg(feature = "serde")]
+use crate::redaction::REDACTED;
+
+use std::{fmt, ops::RangeInclusive, path::Path, str::FromStr, time::Duration};
+
+#[cfg(any(feature = "async", feature = "sync"))]
+use std::net::{IpAddr, SocketAddr};
+
+#[cfg(not(feature = "rand"))]
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::options";
 
 /// Default port for UDP.
 pub(crate) const SAMV3_UDP_PORT: u16 = 7655;
@@ -29,8 +54,34 @@ pub(crate) const SAMV3_UDP_PORT: u16 = 7655;
 /// Default port for TCP.
 pub(crate) const SAMV3_TCP_PORT: u16 = 7656;
 
+/// Default size of the read buffer of a control connection.
+pub(crate) const DEFAULT_CONTROL_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Default size of the datagram receive buffer.
+const DEFAULT_DATAGRAM_BUFFER_SIZE: usize = 0xfff;
+
+/// Default initial capacity of the response buffer.
+pub(crate) const DEFAULT_RESPONSE_BUFFER_SIZE: usize = 256;
+
+/// Default tunnel length.
+const DEFAULT_TUNNEL_LEN: usize = 3;
+
+/// Default tunnel quantity.
+const DEFAULT_TUNNEL_QUANTITY: usize = 2;
+
+/// Default value of `i2cp.closeIdleTime` used by the router.
+const DEFAULT_CLOSE_IDLE_TIME: Duration = Duration::from_secs(30 * 60);
+
+/// Idle threshold of [`SessionOptions::ephemeral_client()`].
+const EPHEMERAL_CLIENT_IDLE_THRESHOLD: Duration = Duration::from_secs(5 * 60);
+
+/// Length of generated nicknames.
+const NICKNAME_LEN: usize = 16;
+
 /// Destination kind.
 #[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum DestinationKind {
     /// Transient session.
     Transient,
@@ -43,6 +94,58 @@ pub enum DestinationKind {
     },
 }
 
+impl DestinationKind {
+    /// Load persistent destination from the key file at `path`.
+    ///
+    /// Supports the binary key files written by routers, such as `eepPriv.dat` of Java I2P's hidden
+    /// services and `.dat` key files of i2pd, including keys with offline signatures, and files
+    /// which contain the private key as base64, such as the output of `DEST GENERATE`. Binary keys
+    /// are converted to the base64 form used by SAMv3.
+    ///
+    /// ```no_run
+    /// use yosemite::{DestinationKind, SessionOptions};
+    ///
+    /// # fn main() -> yosemite::Result<()> {
+    /// let options = SessionOptions {
+    ///     destination: DestinationKind::from_key_file("eepsite/eepPriv.dat")?,
+    ///     ..Default::default()
+    /// };
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn from_key_file(path: impl AsRef<Path>) -> crate::Result<Self> {
+        Self::from_key_bytes(&std::fs::read(path)?)
+    }
+
+    /// Create persistent destination from the contents of a key file.
+    ///
+    /// See [`DestinationKind::from_key_file()`] for supported formats.
+    pub fn from_key_bytes(contents: &[u8]) -> crate::Result<Self> {
+        let decoded = std::str::from_utf8(contents)
+            .ok()
+            .and_then(|contents| decode_base64(contents.trim()));
+        let private_key = encode_private_key(decoded.as_deref().unwrap_or(contents))
+            .map_err(Error::InvalidKeyFile)?;
+
+        Ok(Self::Persistent { private_key })
+    }
+
+    /// Get copy of the destination with the private key replaced with `<redacted>`.
+    ///
+    /// Private keys are serialized as-is so that options can be stored in configuration files.
+    /// Serialize the redacted copy instead when the options are written elsewhere, such as into
+    /// logs or a diagnostics endpoint.
+    #[cfg(feature = "serde")]
+    pub fn redacted(&self) -> Self {
+        match self {
+            Self::Transient => Self::Transient,
+            Self::Persistent { .. } => Self::Persistent {
+                private_key: REDACTED.to_string(),
+            },
+        }
+    }
+}
+
 impl fmt::Debug for DestinationKind {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -53,9 +156,643 @@ impl fmt::Debug for DestinationKind {
     }
 }
 
+/// Signature type of a destination.
+///
+/// Only [`SignatureType::EdDsaSha512Ed25519`] and [`SignatureType::RedDsaSha512Ed25519`]
+/// destinations can be blinded, which is required for [`LeaseSetType::Encrypted`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum SignatureType {
+    /// DSA-SHA1, deprecated.
+    DsaSha1,
+
+    /// ECDSA-SHA256-P256.
+    EcdsaSha256P256,
+
+    /// ECDSA-SHA384-P384.
+    EcdsaSha384P384,
+
+    /// ECDSA-SHA512-P521.
+    EcdsaSha512P521,
+
+    /// RSA-SHA256-2048, for offline signing only.
+    RsaSha256_2048,
+
+    /// RSA-SHA384-3072, for offline signing only.
+    RsaSha384_3072,
+
+    /// RSA-SHA512-4096, for offline signing only.
+    RsaSha512_4096,
+
+    /// EdDSA-SHA512-Ed25519.
+    #[default]
+    EdDsaSha512Ed25519,
+
+    /// EdDSA-SHA512-Ed25519ph, for offline signing only.
+    EdDsaSha512Ed25519ph,
+
+    /// RedDSA-SHA512-Ed25519.
+    RedDsaSha512Ed25519,
+}
+
+impl SignatureType {
+    /// Get the numeric code of the signature type, as sent in `SIGNATURE_TYPE`.
+    pub fn code(self) -> u16 {
+        match self {
+            Self::DsaSha1 => 0,
+            Self::EcdsaSha256P256 => 1,
+            Self::EcdsaSha384P384 => 2,
+            Self::EcdsaSha512P521 => 3,
+            Self::RsaSha256_2048 => 4,
+            Self::RsaSha384_3072 => 5,
+            Self::RsaSha512_4096 => 6,
+            Self::EdDsaSha512Ed25519 => 7,
+            Self::EdDsaSha512Ed25519ph => 8,
+            Self::RedDsaSha512Ed25519 => 11,
+        }
+    }
+
+    /// Get the signature type of numeric `code`.
+    ///
+    /// Returns `None` if `code` is unknown.
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            0 => Some(Self::DsaSha1),
+            1 => Some(Self::EcdsaSha256P256),
+            2 => Some(Self::EcdsaSha384P384),
+            3 => Some(Self::EcdsaSha512P521),
+            4 => Some(Self::RsaSha256_2048),
+            5 => Some(Self::RsaSha384_3072),
+            6 => Some(Self::RsaSha512_4096),
+            7 => Some(Self::EdDsaSha512Ed25519),
+            8 => Some(Self::EdDsaSha512Ed25519ph),
+            11 => Some(Self::RedDsaSha512Ed25519),
+            _ => None,
+        }
+    }
+
+    /// Can destinations of the signature type be blinded.
+    pub fn is_blindable(self) -> bool {
+        matches!(self, Self::EdDsaSha512Ed25519 | Self::RedDsaSha512Ed25519)
+    }
+
+    /// Get length of a signing public key of the signature type.
+    pub(crate) fn public_key_len(self) -> usize {
+        match self {
+            Self::DsaSha1 => 128,
+            Self::EcdsaSha256P256 => 64,
+            Self::EcdsaSha384P384 => 96,
+            Self::EcdsaSha512P521 => 132,
+            Self::RsaSha256_2048 => 256,
+            Self::RsaSha384_3072 => 384,
+            Self::RsaSha512_4096 => 512,
+            Self::EdDsaSha512Ed25519 | Self::EdDsaSha512Ed25519ph | Self::RedDsaSha512Ed25519 => 32,
+        }
+    }
+
+    /// Get length of a signing private key of the signature type.
+    pub(crate) fn private_key_len(self) -> usize {
+        match self {
+            Self::DsaSha1 => 20,
+            Self::EcdsaSha256P256 => 32,
+            Self::EcdsaSha384P384 => 48,
+            Self::EcdsaSha512P521 => 66,
+            Self::RsaSha256_2048 => 512,
+            Self::RsaSha384_3072 => 768,
+            Self::RsaSha512_4096 => 1024,
+            Self::EdDsaSha512Ed25519 | Self::EdDsaSha512Ed25519ph | Self::RedDsaSha512Ed25519 => 32,
+        }
+    }
+
+    /// Get length of a signature of the signature type.
+    pub(crate) fn signature_len(self) -> usize {
+        match self {
+            Self::DsaSha1 => 40,
+            Self::EcdsaSha256P256 => 64,
+            Self::EcdsaSha384P384 => 96,
+            Self::EcdsaSha512P521 => 132,
+            Self::RsaSha256_2048 => 256,
+            Self::RsaSha384_3072 => 384,
+            Self::RsaSha512_4096 => 512,
+            Self::EdDsaSha512Ed25519 | Self::EdDsaSha512Ed25519ph | Self::RedDsaSha512Ed25519 => 64,
+        }
+    }
+}
+
+impl fmt::Display for SignatureType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// Client authorization of an encrypted lease set.
+///
+/// Servers list the clients that are authorized to decrypt the lease set of the session and
+/// clients supply the key used to decrypt the lease set of a server. Both sides must use the same
+/// authorization type.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LeaseSetAuth {
+    /// Server: authorize clients by their base64-encoded X25519 public keys.
+    ///
+    /// Each client is a `(name, public key)` pair where the name is an arbitrary label which must
+    /// not contain `:`.
+    DhClients(Vec<(String, String)>),
+
+    /// Server: authorize clients by base64-encoded pre-shared keys.
+    ///
+    /// Each client is a `(name, key)` pair where the name is an arbitrary label which must not
+    /// contain `:`.
+    PskClients(Vec<(String, String)>),
+
+    /// Client: base64-encoded X25519 private key of the client.
+    DhKey(String),
+
+    /// Client: base64-encoded pre-shared key of the client.
+    PskKey(String),
+}
+
+impl LeaseSetAuth {
+    /// Get I2CP options of the authorization.
+    pub(crate) fn i2cp_options(&self) -> Vec<(String, String)> {
+        let clients = |kind: &str, clients: &[(String, String)]| {
+            clients
+                .iter()
+                .enumerate()
+                .map(|(index, (name, key))| {
+                    (
+                        format!("i2cp.leaseSetClient.{kind}.{index}"),
+                        format!("{name}:{key}"),
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let (auth_type, options) = match self {
+            Self::DhClients(list) => ("1", clients("dh", list)),
+            Self::PskClients(list) => ("2", clients("psk", list)),
+            Self::DhKey(key) => ("1", vec![("i2cp.leaseSetPrivKey".to_string(), key.clone())]),
+            Self::PskKey(key) => ("2", vec![("i2cp.leaseSetPrivKey".to_string(), key.clone())]),
+        };
+
+        std::iter::once(("i2cp.leaseSetAuthType".to_string(), auth_type.to_string()))
+            .chain(options)
+            .collect()
+    }
+}
+
+impl fmt::Debug for LeaseSetAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = |clients: &[(String, String)]| {
+            clients.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>()
+        };
+
+        match self {
+            Self::DhClients(clients) =>
+                f.debug_tuple("LeaseSetAuth::DhClients").field(&names(clients)).finish(),
+            Self::PskClients(clients) =>
+                f.debug_tuple("LeaseSetAuth::PskClients").field(&names(clients)).finish(),
+            Self::DhKey(_) => f.debug_struct("LeaseSetAuth::DhKey").finish_non_exhaustive(),
+            Self::PskKey(_) => f.debug_struct("LeaseSetAuth::PskKey").finish_non_exhaustive(),
+        }
+    }
+}
+
+/// Type of the lease set published for the session.
+#[derive(Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum LeaseSetType {
+    /// Type chosen by the router.
+    #[default]
+    Default,
+
+    /// LeaseSet2.
+    Standard,
+
+    /// Encrypted LeaseSet2.
+    ///
+    /// The lease set is published under a blinded destination and can only be looked up with the
+    /// blinded `.b32.i2p` address of the session, see [`blinded_b32_address()`]. Combine with
+    /// [`SessionOptions::lease_set_auth`] to limit which clients can decrypt the lease set.
+    ///
+    /// [`blinded_b32_address()`]: crate::blinded_b32_address
+    Encrypted {
+        /// Secret required to look up the lease set, if any.
+        secret: Option<String>,
+
+        /// Signature type of the blinded destination.
+        ///
+        /// Must be blindable, routers use [`SignatureType::RedDsaSha512Ed25519`] by default.
+        blinded_signature_type: SignatureType,
+    },
+}
+
+impl LeaseSetType {
+    /// Get I2CP options of the lease set type.
+    pub(crate) fn i2cp_options(&self) -> Vec<(String, String)> {
+        match self {
+            Self::Default => Vec::new(),
+            Self::Standard => vec![("i2cp.leaseSetType".to_string(), "3".to_string())],
+            Self::Encrypted {
+                secret,
+                blinded_signature_type,
+            } => {
+                let mut options = vec![
+                    ("i2cp.leaseSetType".to_string(), "5".to_string()),
+                    (
+                        "i2cp.leaseSetBlindedType".to_string(),
+                        blinded_signature_type.to_string(),
+                    ),
+                ];
+
+                if let Some(secret) = secret {
+                    options.push((
+                        "i2cp.leaseSetSecret".to_string(),
+                        encode_base64(secret.as_bytes()),
+                    ));
+                }
+
+                options
+            }
+        }
+    }
+}
+
+impl fmt::Debug for LeaseSetType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.debug_struct("LeaseSetType::Default").finish(),
+            Self::Standard => f.debug_struct("LeaseSetType::Standard").finish(),
+            Self::Encrypted {
+                secret,
+                blinded_signature_type,
+            } => f
+                .debug_struct("LeaseSetType::Encrypted")
+                .field("secret", &secret.as_ref().map(|_| "<redacted>"))
+                .field("blinded_signature_type", blinded_signature_type)
+                .finish(),
+        }
+    }
+}
+
+/// Credentials for looking up the encrypted lease set of a remote destination.
+///
+/// Passed to [`Session::connect_with_credentials()`] and
+/// [`RouterApi::lookup_name_with_credentials()`] when the remote destination is hosted with
+/// [`LeaseSetType::Encrypted`].
+///
+/// [`Session::connect_with_credentials()`]: crate::Session::connect_with_credentials
+/// [`RouterApi::lookup_name_with_credentials()`]: crate::RouterApi::lookup_name_with_credentials
+#[derive(Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LookupCredentials {
+    /// Secret required to look up the lease set, if any.
+    pub secret: Option<String>,
+
+    /// Client key, if the lease set requires client authorization.
+    ///
+    /// Must be [`LeaseSetAuth::DhKey`] or [`LeaseSetAuth::PskKey`], client lists are ignored.
+    pub auth: Option<LeaseSetAuth>,
+}
+
+impl LookupCredentials {
+    /// Get I2CP options of the credentials.
+    pub(crate) fn i2cp_options(&self) -> Vec<(String, String)> {
+        let mut options = Vec::new();
+
+        if let Some(secret) = &self.secret {
+            options.push((
+                "i2cp.leaseSetSecret".to_string(),
+                encode_base64(secret.as_bytes()),
+            ));
+        }
+
+        if let Some(auth @ (LeaseSetAuth::DhKey(_) | LeaseSetAuth::PskKey(_))) = &self.auth {
+            options.extend(auth.i2cp_options());
+        }
+
+        options
+    }
+}
+
+impl fmt::Debug for LookupCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LookupCredentials")
+            .field("secret", &self.secret.as_ref().map(|_| "<redacted>"))
+            .field("auth", &self.auth)
+            .finish()
+    }
+}
+
+/// Router implementation `yosemite` should accommodate.
+///
+/// i2pd deviates from the Java router in ways that affect clients: it sends the `MESSAGE` of an
+/// error unquoted, even if it contains spaces, and it supports the non-standard `CRYPTO_TYPE`
+/// option of `DEST GENERATE` and `SESSION CREATE` which selects the encryption type of the
+/// generated destination.
+///
+/// When compatible with i2pd, unquoted messages are read until the end of the line and
+/// destinations are generated with `CRYPTO_TYPE=4` (ECIES-X25519).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum RouterCompatibility {
+    /// Detect the router implementation from the `IMPLEMENTATION` reported in `HELLO REPLY`.
+    ///
+    /// If the router doesn't report its implementation, or the handshake is pipelined and the
+    /// command is sent before the reply has been received, the router is treated as the Java
+    /// router.
+    #[default]
+    Auto,
+
+    /// Java router, commands and replies follow the SAMv3 specification.
+    Java,
+
+    /// i2pd.
+    I2pd,
+}
+
+impl RouterCompatibility {
+    /// Should i2pd deviations be accommodated for a router which reported `router_info`.
+    pub(crate) fn i2pd(self, router_info: Option<&RouterInfo>) -> bool {
+        match self {
+            Self::Auto => router_info.is_some_and(RouterInfo::is_i2pd),
+            Self::Java => false,
+            Self::I2pd => true,
+        }
+    }
+}
+
+/// Backoff of destinations which couldn't be reached.
+///
+/// See [`SessionOptions::dial_backoff`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DialBackoff {
+    /// Backoff after the first failure, doubled after each consecutive failure.
+    ///
+    /// Defaults to 10 seconds.
+    pub initial: Duration,
+
+    /// Maximum backoff.
+    ///
+    /// Defaults to 10 minutes.
+    pub max: Duration,
+
+    /// Maximum number of destinations tracked by the session.
+    ///
+    /// Once full, the destination whose backoff ends first is forgotten. Must be non-zero.
+    ///
+    /// Defaults to `256`.
+    pub capacity: usize,
+
+    /// Should streams to a destination in backoff be opened once the backoff ends.
+    ///
+    /// If not set, opening a stream fails immediately with [`Error::DialBackoff`].
+    ///
+    /// Defaults to `false`.
+    pub wait: bool,
+}
+
+impl Default for DialBackoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(10),
+            max: Duration::from_secs(10 * 60),
+            capacity: 256usize,
+            wait: false,
+        }
+    }
+}
+
+/// Reduction of the number of tunnels of an idle session.
+///
+/// See [`SessionOptions::reduce_on_idle`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReduceOnIdle {
+    /// Time without activity after which the number of tunnels is reduced.
+    ///
+    /// Corresponds to `i2cp.reduceIdleTime`.
+    ///
+    /// Defaults to 20 minutes.
+    pub idle_time: Duration,
+
+    /// Number of tunnels in each direction while the session is idle.
+    ///
+    /// Corresponds to `i2cp.reduceQuantity`.
+    ///
+    /// Defaults to `1`.
+    pub quantity: usize,
+}
+
+impl Default for ReduceOnIdle {
+    fn default() -> Self {
+        Self {
+            idle_time: Duration::from_secs(20 * 60),
+            quantity: 1usize,
+        }
+    }
+}
+
+/// Filter of inbound virtual streams.
+///
+/// Entries are either full base64 destinations or `.b32.i2p` addresses, the `.b32.i2p` suffix may
+/// be omitted. See [`SessionOptions::accept_filter`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum AcceptFilter {
+    /// Accept streams only from the listed destinations.
+    Allow(Vec<String>),
+
+    /// Accept streams from all but the listed destinations.
+    Deny(Vec<String>),
+}
+
+#[cfg(any(feature = "async", feature = "sync"))]
+impl AcceptFilter {
+    /// Should a stream from `destination` be accepted.
+    pub(crate) fn accepts(&self, destination: &str) -> bool {
+        let (entries, allow) = match self {
+            Self::Allow(entries) => (entries, true),
+            Self::Deny(entries) => (entries, false),
+        };
+
+        // the address of the remote peer is computed only if an entry isn't a full destination
+        let mut remote = None;
+        let listed = entries.iter().any(|entry| {
+            entry == destination || {
+                let remote = remote.get_or_insert_with(|| {
+                    crate::address::b32_address(destination)
+                        .map(|address| address.trim_end_matches(".b32.i2p").to_string())
+                });
+                let entry = entry.strip_suffix(".b32.i2p").unwrap_or(entry);
+
+                remote.as_deref().is_some_and(|remote| entry.eq_ignore_ascii_case(remote))
+            }
+        });
+
+        listed == allow
+    }
+}
+
+/// Bandwidth limit of virtual streams.
+///
+/// Enforced with a token bucket which holds up to `burst` bytes and is refilled at
+/// `bytes_per_second`. Reads and writes have buckets of their own so the limit applies to each
+/// direction separately.
+///
+/// See [`SessionOptions::stream_rate_limit`] and [`SessionOptions::session_rate_limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateLimit {
+    /// Sustained rate in bytes per second.
+    pub bytes_per_second: u64,
+
+    /// Number of bytes that can be transferred at once after the bucket has been idle.
+    pub burst: u64,
+}
+
+impl RateLimit {
+    /// Create new [`RateLimit`] of `bytes_per_second` with a burst of one second.
+    pub fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            burst: bytes_per_second,
+        }
+    }
+
+    /// Allow bursts of `burst` bytes.
+    pub fn with_burst(mut self, burst: u64) -> Self {
+        self.burst = burst;
+        self
+    }
+}
+
+/// What to do when a virtual stream is opened or accepted while the session has
+/// [`SessionOptions::max_streams`] streams open.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum StreamLimitPolicy {
+    /// Wait until one of the open streams is dropped.
+    ///
+    /// Blocking sessions block the calling thread, so the stream must be dropped by another
+    /// thread.
+    #[default]
+    Queue,
+
+    /// Fail with [`Error::TooManyStreams`].
+    Reject,
+}
+
+/// Options of a virtual stream.
+///
+/// Passed to [`Session::connect_with_options()`] and [`Session::accept_with_options()`].
+///
+/// [`Session::connect_with_options()`]: crate::Session::connect_with_options
+/// [`Session::accept_with_options()`]: crate::Session::accept_with_options
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct StreamOptions {
+    /// Open the stream with `SILENT=true`.
+    ///
+    /// The router doesn't reply to `STREAM CONNECT`/`STREAM ACCEPT` which saves a round trip when
+    /// connecting, and an accepted stream doesn't start with the destination of the remote peer.
+    /// Errors are not reported: if the stream cannot be opened, the router closes the connection
+    /// which is seen as EOF when reading from the stream.
+    ///
+    /// Defaults to `false`.
+    pub silent: bool,
+
+    /// Credentials for looking up the remote destination, see [`LookupCredentials`].
+    ///
+    /// Only used for outbound streams.
+    pub credentials: Option<LookupCredentials>,
+
+    /// Local port the stream is opened from.
+    ///
+    /// Corresponds to `FROM_PORT`. If not set and [`SessionOptions::ephemeral_ports`] is set, a
+    /// port is assigned by the session.
+    ///
+    /// Only used for outbound streams.
+    pub from_port: Option<u16>,
+}
+
 /// Session options.
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
 pub struct SessionOptions {
+    /// Filter of inbound virtual streams.
+    ///
+    /// Streams from destinations rejected by the filter are closed by `Session::accept()` before
+    /// they're returned to the application, which keeps waiting for the next stream, and are
+    /// counted in [`MetricsSnapshot::streams_rejected`](crate::MetricsSnapshot::streams_rejected).
+    /// Silent streams and streams forwarded with `Session::forward()` don't carry the destination
+    /// of the remote peer and are not filtered.
+    ///
+    /// Defaults to `None` (all streams are accepted).
+    pub accept_filter: Option<AcceptFilter>,
+
+    /// Time without activity after which the router closes the tunnels of the session.
+    ///
+    /// The tunnels are rebuilt when the session becomes active again, which delays the first
+    /// stream or datagram after the idle period. Only recommended for clients, a server whose
+    /// tunnels are closed cannot be reached.
+    ///
+    /// Corresponds to `i2cp.closeOnIdle` and `i2cp.closeIdleTime`.
+    ///
+    /// Defaults to `None` (tunnels are not closed).
+    pub close_on_idle: Option<Duration>,
+
+    /// Router implementation whose deviations from the specification are accommodated.
+    ///
+    /// See [`RouterCompatibility`].
+    ///
+    /// Defaults to [`RouterCompatibility::Auto`].
+    pub compatibility: RouterCompatibility,
+
+    /// Number of handshaked control connections kept ready for outbound streams.
+    ///
+    /// If non-zero, the pool is filled after `Session::connect()` has opened a stream and the next
+    /// call takes a pooled connection, so only `STREAM CONNECT` needs to be exchanged with the
+    /// router. A pooled connection that the router has closed is discarded and the stream is
+    /// opened over a new connection instead.
+    ///
+    /// Defaults to `0`.
+    pub connect_pool_size: usize,
+
+    /// Size of the read buffer of a control connection.
+    ///
+    /// The buffer of a control connection used to open or accept a virtual stream becomes the
+    /// read buffer of the [`Stream`](crate::Stream).
+    ///
+    /// Defaults to `8192`.
+    pub control_buffer_size: usize,
+
+    /// Size of the buffer repliable datagrams are received into.
+    ///
+    /// The buffer must fit both the datagram header and the payload, otherwise the datagram is
+    /// truncated.
+    ///
+    /// Defaults to `4095`.
+    pub datagram_buffer_size: usize,
+
+    /// Address where the datagram socket should be bound to.
+    ///
+    /// The router forwards received datagrams to this address, so it must be an IP address of
+    /// this host that's reachable by the router, e.g., an address on a network shared with a
+    /// router running on another host. Unspecified addresses such as `0.0.0.0` are rejected.
+    ///
+    /// Defaults to `127.0.0.1`.
+    pub datagram_host: String,
+
     /// Port where the datagram socket should be bound to.
     ///
     /// By default, the socket is bound to a random port assigned by the OS.
@@ -66,13 +803,170 @@ pub struct SessionOptions {
     /// By default, `yosemite` creates a transient session.
     pub destination: DestinationKind,
 
+    /// Backoff of destinations which couldn't be reached.
+    ///
+    /// If set, a destination is put in backoff when opening a stream to it fails with
+    /// `CANT_REACH_PEER` or `TIMEOUT`, and until the backoff ends, streams to it either fail fast
+    /// with [`Error::DialBackoff`] or wait for the backoff to end, see [`DialBackoff::wait`]. This
+    /// keeps an application from spending tunnel bandwidth and connection attempts on peers which
+    /// are known to be offline. Opening a stream to the destination resets its backoff.
+    ///
+    /// Defaults to `None` (connection attempts are not limited).
+    pub dial_backoff: Option<DialBackoff>,
+
+    /// Range of local ports assigned as `FROM_PORT` to outbound streams and datagrams.
+    ///
+    /// If set, each outbound stream is opened from a port of the range which isn't used by another
+    /// stream of the session, so the remote destination can tell the streams apart and address
+    /// replies to them. The port is released when the stream is dropped and
+    /// [`Stream::local_port()`](crate::Stream::local_port) returns it. Datagrams are sent from the
+    /// ports of the range in turn, skipping the ports held by streams. Ports are assigned
+    /// round-robin so a released port isn't reused until the rest of the range has been used.
+    ///
+    /// If all ports of the range are held by streams, opening a stream fails with
+    /// [`Error::PortsExhausted`]. The range must not be empty or contain port `0`, and it
+    /// requires SAMv3.2.
+    ///
+    /// Defaults to `None` (ports are not assigned).
+    pub ephemeral_ports: Option<RangeInclusive<u16>>,
+
+    /// Length of inbound tunnels.
+    ///
+    /// Corresponds to `inbound.length`.
+    ///
+    /// Defaults to `3`.
+    pub inbound_len: usize,
+
+    /// Random variance of the length of inbound tunnels.
+    ///
+    /// A positive variance `n` adds `0..=n` hops to each tunnel and a negative variance `-n`
+    /// adds `-n..=n` hops, which makes the tunnels harder to correlate by their length.
+    ///
+    /// Corresponds to `inbound.lengthVariance`.
+    ///
+    /// Defaults to `0`.
+    pub inbound_len_variance: isize,
+
+    /// Number of inbound tunnels.
+    ///
+    /// Corresponds to `inbound.quantity`.
+    ///
+    /// Defaults to `2`.
+    pub inbound_quantity: usize,
+
+    /// Time without activity after which the session is reported idle.
+    ///
+    /// The session is active when it, or one of its handles, opens or accepts a virtual stream or
+    /// sends or receives a datagram. Data exchanged over open virtual streams is not tracked. Once
+    /// the threshold passes, the idle hook set with `Session::set_idle_hook()` is called and
+    /// [`SessionEvent::Idle`](crate::SessionEvent::Idle) is emitted, so the application can, e.g.,
+    /// recreate the session with fewer tunnels.
+    ///
+    /// Defaults to `None` (idleness is not reported).
+    pub idle_threshold: Option<Duration>,
+
+    /// Interceptor for commands and responses exchanged with the router.
+    ///
+    /// See [`Interceptor`](crate::Interceptor) for more details.
+    ///
+    /// Defaults to `None`.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub interceptor: Option<SharedInterceptor>,
+
+    /// Type of the lease set published for the session.
+    ///
+    /// Corresponds to `i2cp.leaseSetType`, `i2cp.leaseSetBlindedType` and `i2cp.leaseSetSecret`.
+    ///
+    /// Defaults to [`LeaseSetType::Default`].
+    pub lease_set_type: LeaseSetType,
+
+    /// Client authorization of encrypted lease sets.
+    ///
+    /// For servers, lists the clients authorized to decrypt the lease set of the session. For
+    /// clients, the key used to decrypt the lease sets of the servers the session connects to.
+    /// See [`LeaseSetAuth`].
+    ///
+    /// Corresponds to `i2cp.leaseSetAuthType`, `i2cp.leaseSetClient.dh.<n>`,
+    /// `i2cp.leaseSetClient.psk.<n>` and `i2cp.leaseSetPrivKey`.
+    ///
+    /// Defaults to `None`.
+    pub lease_set_auth: Option<LeaseSetAuth>,
+
+    /// Maximum number of virtual streams of the session open at the same time.
+    ///
+    /// Streams which are still being opened or accepted count towards the limit and each stream
+    /// holds its slot until it's dropped. Limiting the streams keeps a burst of connections from
+    /// exhausting the resources of the router or the file descriptors of the process. What
+    /// happens to streams beyond the limit is decided by [`SessionOptions::stream_limit_policy`].
+    /// Streams forwarded with `Session::forward()` are not counted. The limit must be non-zero.
+    ///
+    /// Defaults to `None` (the number of streams is not limited).
+    pub max_streams: Option<usize>,
+
+    /// Maximum SAMv3 version accepted from the router, e.g., `3.3`.
+    ///
+    /// Sent as `MAX` in `HELLO VERSION`. Together with [`SessionOptions::min_version`], the
+    /// version can be pinned so the behavior of the session doesn't change when the router is
+    /// upgraded.
+    ///
+    /// Defaults to `None` (the router selects the version).
+    pub max_version: Option<String>,
+
+    /// Minimum SAMv3 version accepted from the router, e.g., `3.1`.
+    ///
+    /// Sent as `MIN` in `HELLO VERSION`. If the router doesn't support any version in the range,
+    /// it replies with `NOVERSION` and the handshake fails.
+    ///
+    /// Defaults to `None` (the router selects the version).
+    pub min_version: Option<String>,
+
     /// Nickname.
     ///
     /// Name that uniquely identifies the session.
     ///
-    /// If not specified, `yosemite` generates a random alphanmeric nickname.
+    /// If not specified, `yosemite` generates a random alphanmeric nickname. See
+    /// [`SessionOptions::from_seed()`] for generating a reproducible nickname.
+    ///
+    /// Without the `rand` feature, the nickname is derived from the process ID and the current
+    /// time instead.
     pub nickname: String,
 
+    /// Length of outbound tunnels.
+    ///
+    /// Corresponds to `outbound.length`.
+    ///
+    /// Defaults to `3`.
+    pub outbound_len: usize,
+
+    /// Random variance of the length of outbound tunnels.
+    ///
+    /// See [`SessionOptions::inbound_len_variance`].
+    ///
+    /// Corresponds to `outbound.lengthVariance`.
+    ///
+    /// Defaults to `0`.
+    pub outbound_len_variance: isize,
+
+    /// Number of outbound tunnels.
+    ///
+    /// Corresponds to `outbound.quantity`.
+    ///
+    /// Defaults to `2`.
+    pub outbound_quantity: usize,
+
+    /// Should handshakes be pipelined.
+    ///
+    /// If set to true, `HELLO VERSION` is sent together with the command that follows it
+    /// (`SESSION CREATE`, `STREAM CONNECT`, `STREAM ACCEPT` or `STREAM FORWARD`) in one write and
+    /// both replies are read afterwards, saving one round trip to the router per control
+    /// connection.
+    ///
+    /// The SAMv3 specification doesn't guarantee that routers accept a command before they have
+    /// replied to `HELLO VERSION`, so this is opt-in.
+    ///
+    /// Defaults to `false`.
+    pub pipeline_handshake: bool,
+
     /// Should the session's lease set be published to NetDb.
     ///
     /// Outbound-only sessions (clients) shouldn't be published whereas servers (accepting inbound
@@ -83,6 +977,33 @@ pub struct SessionOptions {
     /// Defaults to `true`.
     pub publish: bool,
 
+    /// Should destinations, private keys and credentials be redacted from the wire trace.
+    ///
+    /// Only used with the `wire-trace` feature, which logs every command sent to and every
+    /// response received from the router under the `yosemite::wire` target at `TRACE` level.
+    ///
+    /// Defaults to `true`.
+    pub redact_wire_trace: bool,
+
+    /// Reduction of the number of tunnels while the session is idle.
+    ///
+    /// If set, the router reduces the number of inbound and outbound tunnels of the session to
+    /// [`ReduceOnIdle::quantity`] once the session has been idle for [`ReduceOnIdle::idle_time`],
+    /// and restores them when the session becomes active again.
+    ///
+    /// Corresponds to `i2cp.reduceOnIdle`, `i2cp.reduceIdleTime` and `i2cp.reduceQuantity`.
+    ///
+    /// Defaults to `None` (the number of tunnels is not reduced).
+    pub reduce_on_idle: Option<ReduceOnIdle>,
+
+    /// Initial capacity of the buffer that router responses are read into.
+    ///
+    /// This includes the destination of the remote peer read after a virtual stream has been
+    /// accepted. The buffer grows if a response doesn't fit.
+    ///
+    /// Defaults to `256`.
+    pub response_buffer_size: usize,
+
     /// TCP port of the listening SAMv3 server.
     ///
     /// Defaults to `7656`.
@@ -93,6 +1014,25 @@ pub struct SessionOptions {
     /// Defaults to `7655`
     pub samv3_udp_port: u16,
 
+    /// Bandwidth limit shared by all virtual streams of the session.
+    ///
+    /// Protects the tunnels of the session as a whole, e.g., a hidden service can limit the total
+    /// bandwidth it serves regardless of how many clients are connected. Applies in addition to
+    /// [`SessionOptions::stream_rate_limit`]. The rate and the burst must be non-zero.
+    ///
+    /// Defaults to `None` (streams of the session are not limited).
+    pub session_rate_limit: Option<RateLimit>,
+
+    /// Signature type of transient destinations.
+    ///
+    /// Corresponds to `SIGNATURE_TYPE`. Persistent destinations keep the signature type they
+    /// were generated with. If [`SessionOptions::lease_set_type`] is
+    /// [`LeaseSetType::Encrypted`], the signature type must be blindable and creating the
+    /// session fails with [`Error::InvalidOption`] otherwise.
+    ///
+    /// Defaults to [`SignatureType::EdDsaSha512Ed25519`].
+    pub signature_type: SignatureType,
+
     /// Should `STREAM FORWARD` be silent.
     ///
     /// If set to false (default), the first message read from the TCP stream accepted by the TCP
@@ -102,18 +1042,1051 @@ pub struct SessionOptions {
     /// destination to be read from the socket, the forwarded stream can be set to silent. This
     /// means, however, that destination of the connecting peer cannot be recovered.
     pub silent_forward: bool,
+
+    /// Threshold after which a slow router response is reported.
+    ///
+    /// If set, a warning is emitted when the router takes longer than this to respond to a
+    /// handshake, session creation or stream command. The warning includes the state the session
+    /// controller was waiting in, which helps to tell slow tunnel builds apart from bugs in the
+    /// crate.
+    ///
+    /// Defaults to `None` (slow operations are not reported).
+    pub slow_operation_threshold: Option<Duration>,
+
+    /// What to do with streams beyond [`SessionOptions::max_streams`].
+    ///
+    /// Defaults to [`StreamLimitPolicy::Queue`].
+    pub stream_limit_policy: StreamLimitPolicy,
+
+    /// Bandwidth limit of each virtual stream of the session.
+    ///
+    /// Reads from and writes to a [`Stream`](crate::Stream) are delayed once the stream exceeds
+    /// the limit so a single greedy peer cannot use up the bandwidth of the tunnels. Only data
+    /// exchanged over the stream is counted and data already buffered by the router is not
+    /// dropped, the router applies backpressure to the remote peer instead. Streams forwarded with
+    /// `Session::forward()` are not limited. The rate and the burst must be non-zero.
+    ///
+    /// Defaults to `None` (streams are not limited).
+    pub stream_rate_limit: Option<RateLimit>,
+
+    /// Size of the write buffer of a virtual stream.
+    ///
+    /// Each write to a [`Stream`](crate::Stream) is sent to the router as-is and usually becomes
+    /// its own I2P streaming packet. If set to a non-zero value, small writes are coalesced into
+    /// a buffer of this size which is sent when it's full or when the stream is flushed, closed or
+    /// dropped, so protocols that send many small frames should flush after each complete message.
+    /// Data buffered by an asynchronous stream which is dropped outside of a Tokio runtime may be
+    /// lost, see [`Stream`](crate::Stream).
+    ///
+    /// Defaults to `0` (writes are not buffered).
+    pub stream_write_buffer_size: usize,
 }
 
 impl Default for SessionOptions {
     fn default() -> Self {
         Self {
+            accept_filter: None,
+            close_on_idle: None,
+            compatibility: RouterCompatibility::Auto,
+            connect_pool_size: 0usize,
+            control_buffer_size: DEFAULT_CONTROL_BUFFER_SIZE,
+            datagram_buffer_size: DEFAULT_DATAGRAM_BUFFER_SIZE,
+            datagram_host: "127.0.0.1".to_string(),
             datagram_port: 0u16,
             destination: DestinationKind::Transient,
-            nickname: Alphanumeric.sample_string(&mut thread_rng(), 16),
+            dial_backoff: None,
+            ephemeral_ports: None,
+            inbound_len: DEFAULT_TUNNEL_LEN,
+            inbound_len_variance: 0isize,
+            inbound_quantity: DEFAULT_TUNNEL_QUANTITY,
+            idle_threshold: None,
+            interceptor: None,
+            lease_set_auth: None,
+            lease_set_type: LeaseSetType::Default,
+            max_streams: None,
+            max_version: None,
+            min_version: None,
+            #[cfg(feature = "rand")]
+            nickname: generate_nickname(&mut thread_rng()),
+            #[cfg(not(feature = "rand"))]
+            nickname: generate_nickname(),
+            outbound_len: DEFAULT_TUNNEL_LEN,
+            outbound_len_variance: 0isize,
+            outbound_quantity: DEFAULT_TUNNEL_QUANTITY,
+            pipeline_handshake: false,
             publish: true,
+            redact_wire_trace: true,
+            reduce_on_idle: None,
+            response_buffer_size: DEFAULT_RESPONSE_BUFFER_SIZE,
             samv3_tcp_port: SAMV3_TCP_PORT,
             samv3_udp_port: SAMV3_UDP_PORT,
+            session_rate_limit: None,
+            signature_type: SignatureType::EdDsaSha512Ed25519,
             silent_forward: false,
+            slow_operation_threshold: None,
+            stream_limit_policy: StreamLimitPolicy::Queue,
+            stream_rate_limit: None,
+            stream_write_buffer_size: 0usize,
+        }
+    }
+}
+
+/// Generate random alphanumeric nickname.
+#[cfg(feature = "rand")]
+fn generate_nickname(rng: &mut impl Rng) -> String {
+    Alphanumeric.sample_string(rng, NICKNAME_LEN)
+}
+
+/// Generate nickname from the process ID and the current time.
+///
+/// A per-process counter is mixed in so options created within the same clock tick still get
+/// distinct nicknames.
+#[cfg(not(feature = "rand"))]
+fn generate_nickname() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0u64, |elapsed| elapsed.as_nanos() as u64);
+    let counter = COUNTER.fetch_add(1, Ordering::Relaxed);
+
+    let nickname = format!(
+        "{:06x}{:010x}",
+        std::process::id() & 0xff_ffff,
+        nanos.wrapping_add(counter) & 0xff_ffff_ffff
+    );
+    debug_assert_eq!(nickname.len(), NICKNAME_LEN);
+
+    nickname
+}
+
+impl SessionOptions {
+    /// Create default [`SessionOptions`] with a nickname generated from `seed`.
+    ///
+    /// The same seed always produces the same nickname, which makes the commands sent to the
+    /// router reproducible in tests.
+    ///
+    /// Available with the `rand` feature.
+    #[cfg(feature = "rand")]
+    pub fn from_seed(seed: u64) -> Self {
+        Self {
+            nickname: generate_nickname(&mut StdRng::seed_from_u64(seed)),
+            ..Default::default()
+        }
+    }
+
+    /// Create [`SessionOptions`] for latency-sensitive applications.
+    ///
+    /// Tunnels have one hop, which trades anonymity for round trip time, and there are three of
+    /// them in each direction so a single failing tunnel doesn't stall the session:
+    ///  * `inbound.length`/`outbound.length`: `1`
+    ///  * `inbound.quantity`/`outbound.quantity`: `3`
+    ///  * `inbound.lengthVariance`/`outbound.lengthVariance`: `0`
+    ///  * lease set is published
+    ///
+    /// Only use this when the peers don't need to be protected from each other, e.g., for
+    /// services whose operator is public anyway.
+    pub fn low_latency() -> Self {
+        Self {
+            inbound_len: 1,
+            inbound_len_variance: 0,
+            inbound_quantity: 3,
+            outbound_len: 1,
+            outbound_len_variance: 0,
+            outbound_quantity: 3,
+            publish: true,
+            ..Default::default()
+        }
+    }
+
+    /// Create [`SessionOptions`] for applications which need stronger anonymity than the
+    /// defaults.
+    ///
+    /// Tunnels are three to four hops long so their length doesn't reveal the position of a hop
+    /// in the tunnel:
+    ///  * `inbound.length`/`outbound.length`: `3`
+    ///  * `inbound.quantity`/`outbound.quantity`: `2`
+    ///  * `inbound.lengthVariance`/`outbound.lengthVariance`: `1`
+    ///  * lease set is published
+    pub fn high_anonymity() -> Self {
+        Self {
+            inbound_len: 3,
+            inbound_len_variance: 1,
+            inbound_quantity: 2,
+            outbound_len: 3,
+            outbound_len_variance: 1,
+            outbound_quantity: 2,
+            publish: true,
+            ..Default::default()
+        }
+    }
+
+    /// Create [`SessionOptions`] for short-lived clients which only open outbound streams or
+    /// send datagrams.
+    ///
+    /// The lease set isn't published since nobody connects to the session and the session is
+    /// reported idle after five minutes without activity so the application can close it, see
+    /// [`SessionOptions::idle_threshold`]:
+    ///  * `inbound.length`/`outbound.length`: `2`
+    ///  * `inbound.quantity`/`outbound.quantity`: `2`
+    ///  * `inbound.lengthVariance`/`outbound.lengthVariance`: `0`
+    ///  * lease set is not published
+    ///
+    /// Replies to datagrams and streams are still received over the inbound tunnels.
+    pub fn ephemeral_client() -> Self {
+        Self {
+            idle_threshold: Some(EPHEMERAL_CLIENT_IDLE_THRESHOLD),
+            inbound_len: 2,
+            inbound_len_variance: 0,
+            inbound_quantity: 2,
+            outbound_len: 2,
+            outbound_len_variance: 0,
+            outbound_quantity: 2,
+            publish: false,
+            ..Default::default()
+        }
+    }
+
+    /// Get copy of the options with private keys and lease set credentials replaced with
+    /// `<redacted>`.
+    ///
+    /// Redacts the private key of [`SessionOptions::destination`], the lease set secret of
+    /// [`SessionOptions::lease_set_type`] and the keys of [`SessionOptions::lease_set_auth`], see
+    /// [`DestinationKind::redacted()`].
+    ///
+    /// ```
+    /// use yosemite::{DestinationKind, SessionOptions};
+    ///
+    /// let options = SessionOptions {
+    ///     destination: DestinationKind::Persistent {
+    ///         private_key: "private key".to_string(),
+    ///     },
+    ///     ..Default::default()
+    /// };
+    ///
+    /// assert_eq!(
+    ///     options.redacted().destination,
+    ///     DestinationKind::Persistent {
+    ///         private_key: "<redacted>".to_string()
+    ///     }
+    /// );
+    /// ```
+    #[cfg(feature = "serde")]
+    pub fn redacted(&self) -> Self {
+        let redact_clients = |clients: &[(String, String)]| {
+            clients.iter().map(|(name, _)| (name.clone(), REDACTED.to_string())).collect()
+        };
+
+        Self {
+            destination: self.destination.redacted(),
+            lease_set_type: match &self.lease_set_type {
+                LeaseSetType::Encrypted {
+                    secret,
+                    blinded_signature_type,
+                } => LeaseSetType::Encrypted {
+                    secret: secret.as_ref().map(|_| REDACTED.to_string()),
+                    blinded_signature_type: *blinded_signature_type,
+                },
+                lease_set_type => lease_set_type.clone(),
+            },
+            lease_set_auth: self.lease_set_auth.as_ref().map(|auth| match auth {
+                LeaseSetAuth::DhClients(clients) =>
+                    LeaseSetAuth::DhClients(redact_clients(clients)),
+                LeaseSetAuth::PskClients(clients) =>
+                    LeaseSetAuth::PskClients(redact_clients(clients)),
+                LeaseSetAuth::DhKey(_) => LeaseSetAuth::DhKey(REDACTED.to_string()),
+                LeaseSetAuth::PskKey(_) => LeaseSetAuth::PskKey(REDACTED.to_string()),
+            }),
+            ..self.clone()
+        }
+    }
+
+    /// Get the address the datagram socket is bound to.
+    ///
+    /// Fails if `datagram_host` is not an IP address or if it's unspecified.
+    #[cfg(any(feature = "async", feature = "sync"))]
+    pub(crate) fn datagram_address(&self) -> crate::Result<SocketAddr> {
+        match self.datagram_host.parse::<IpAddr>() {
+            Ok(address) if !address.is_unspecified() =>
+                Ok(SocketAddr::new(address, self.datagram_port)),
+            _ => Err(Error::InvalidOption {
+                key: "datagram_host".to_string(),
+                value: self.datagram_host.clone(),
+            }),
+        }
+    }
+
+    /// Check that the signature type of a transient destination can be used with the lease set
+    /// type.
+    #[cfg(any(feature = "async", feature = "sync"))]
+    pub(crate) fn check_signature_type(&self) -> crate::Result<()> {
+        let LeaseSetType::Encrypted {
+            blinded_signature_type,
+            ..
+        } = &self.lease_set_type
+        else {
+            return Ok(());
+        };
+
+        let (key, signature_type) = match self.destination {
+            _ if !blinded_signature_type.is_blindable() =>
+                ("blinded_signature_type", blinded_signature_type),
+            DestinationKind::Transient if !self.signature_type.is_blindable() =>
+                ("signature_type", &self.signature_type),
+            _ => return Ok(()),
+        };
+
+        Err(Error::InvalidOption {
+            key: key.to_string(),
+            value: signature_type.to_string(),
+        })
+    }
+
+    /// Get copy of the options with the tunnel lengths, quantities and lease set publication
+    /// taken from `other`.
+    #[cfg(any(feature = "async", feature = "sync"))]
+    pub(crate) fn with_tunnel_parameters(&self, other: &SessionOptions) -> Self {
+        Self {
+            inbound_len: other.inbound_len,
+            inbound_len_variance: other.inbound_len_variance,
+            inbound_quantity: other.inbound_quantity,
+            outbound_len: other.outbound_len,
+            outbound_len_variance: other.outbound_len_variance,
+            outbound_quantity: other.outbound_quantity,
+            publish: other.publish,
+            ..self.clone()
+        }
+    }
+
+    /// Create [`SessionOptions`] from I2P-style properties.
+    ///
+    /// `input` is expected to contain `key=value` pairs, one per line, as found in router and
+    /// i2ptunnel configuration files. Empty lines and lines starting with `#` are ignored. Options
+    /// prefixed with `tunnel.<n>.option.`, as they are in `i2ptunnel.config`, are recognized with
+    /// the prefix removed.
+    ///
+    /// Supported options:
+    ///  * `inbound.length`
+    ///  * `inbound.lengthVariance`
+    ///  * `inbound.quantity`
+    ///  * `inbound.nickname`
+    ///  * `outbound.length`
+    ///  * `outbound.lengthVariance`
+    ///  * `outbound.quantity`
+    ///  * `i2cp.dontPublishLeaseSet`
+    ///  * `i2cp.reduceOnIdle`
+    ///  * `i2cp.reduceIdleTime`
+    ///  * `i2cp.reduceQuantity`
+    ///  * `i2cp.closeOnIdle`
+    ///  * `i2cp.closeIdleTime`
+    ///
+    /// The nickname becomes the ID of the session and must not be empty or contain whitespace,
+    /// `=` or `"`.
+    ///
+    /// Options `yosemite` doesn't recognize are ignored and fields not specified in `input` are
+    /// left to their default values.
+    pub fn from_i2cp_properties(input: &str) -> crate::Result<Self> {
+        let mut properties = Vec::new();
+
+        for line in input.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| Error::InvalidOption {
+                key: line.to_string(),
+                value: String::new(),
+            })?;
+            properties.push((key.trim().to_string(), value.trim().to_string()));
+        }
+
+        Self::from_properties(properties)
+    }
+
+    /// Create [`SessionOptions`] from a TOML file.
+    ///
+    /// Keys are the I2P option spellings supported by
+    /// [`SessionOptions::from_i2cp_properties()`], either as dotted keys or split into tables:
+    ///
+    /// ```toml
+    /// [inbound]
+    /// length = 2
+    /// nickname = "eepsite"
+    ///
+    /// [i2cp]
+    /// dontPublishLeaseSet = true
+    /// ```
+    ///
+    /// Only the subset of TOML needed for these options is understood: tables, bare and quoted
+    /// keys, strings, integers and booleans. Arrays, inline tables and multi-line strings are
+    /// rejected with [`Error::InvalidOption`].
+    pub fn from_toml_file(path: impl AsRef<Path>) -> crate::Result<Self> {
+        Self::from_toml(&std::fs::read_to_string(path)?)
+    }
+
+    /// Create [`SessionOptions`] from the contents of a TOML file.
+    ///
+    /// See [`SessionOptions::from_toml_file()`] for the supported format.
+    pub fn from_toml(input: &str) -> crate::Result<Self> {
+        let mut properties = Vec::new();
+        let mut table = String::new();
+
+        for line in input.lines().map(|line| strip_toml_comment(line).trim()) {
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = line.strip_prefix('[') {
+                table = match header.strip_suffix(']') {
+                    Some(header) if !header.starts_with('[') => parse_toml_key(header),
+                    _ => return Err(invalid_toml_line(line)),
+                };
+                continue;
+            }
+
+            let (key, value) = line.split_once('=').ok_or_else(|| invalid_toml_line(line))?;
+            let key = match table.is_empty() {
+                true => parse_toml_key(key),
+                false => format!("{table}.{}", parse_toml_key(key)),
+            };
+            let value = parse_toml_value(value.trim()).ok_or_else(|| Error::InvalidOption {
+                key: key.clone(),
+                value: value.trim().to_string(),
+            })?;
+
+            properties.push((key, value));
+        }
+
+        Self::from_properties(properties)
+    }
+
+    /// Create [`SessionOptions`] from I2P option `properties`.
+    fn from_properties(properties: Vec<(String, String)>) -> crate::Result<Self> {
+        let mut options = Self::default();
+
+        // idle options may be listed in any order so they're applied after all lines are parsed
+        let (mut reduce_on_idle, mut reduce) = (false, ReduceOnIdle::default());
+        let (mut close_on_idle, mut close_idle_time) = (false, DEFAULT_CLOSE_IDLE_TIME);
+
+        for (key, value) in &properties {
+            let (key, value) = (key.as_str(), value.as_str());
+
+            match strip_tunnel_prefix(key) {
+                "inbound.length" => options.inbound_len = parse_option(key, value)?,
+                "inbound.lengthVariance" =>
+                    options.inbound_len_variance = parse_option(key, value)?,
+                "inbound.quantity" => options.inbound_quantity = parse_option(key, value)?,
+                "inbound.nickname" => options.nickname = parse_nickname(key, value)?,
+                "outbound.length" => options.outbound_len = parse_option(key, value)?,
+                "outbound.lengthVariance" =>
+                    options.outbound_len_variance = parse_option(key, value)?,
+                "outbound.quantity" => options.outbound_quantity = parse_option(key, value)?,
+                "i2cp.dontPublishLeaseSet" => options.publish = !parse_option::<bool>(key, value)?,
+                "i2cp.reduceOnIdle" => reduce_on_idle = parse_option(key, value)?,
+                "i2cp.reduceIdleTime" =>
+                    reduce.idle_time = Duration::from_millis(parse_option(key, value)?),
+                "i2cp.reduceQuantity" => reduce.quantity = parse_option(key, value)?,
+                "i2cp.closeOnIdle" => close_on_idle = parse_option(key, value)?,
+                "i2cp.closeIdleTime" =>
+                    close_idle_time = Duration::from_millis(parse_option(key, value)?),
+                _ => tracing::debug!(
+                    target: LOG_TARGET,
+                    %key,
+                    "ignoring unrecognized option",
+                ),
+            }
+        }
+
+        options.reduce_on_idle = reduce_on_idle.then_some(reduce);
+        options.close_on_idle = close_on_idle.then_some(close_idle_time);
+
+        Ok(options)
+    }
+}
+
+/// Strip `tunnel.<n>.option.` prefix from `key`, if it exists.
+fn strip_tunnel_prefix(key: &str) -> &str {
+    let Some((index, option)) = key.strip_prefix("tunnel.").and_then(|key| key.split_once('.'))
+    else {
+        return key;
+    };
+
+    match (index.parse::<usize>(), option.strip_prefix("option.")) {
+        (Ok(_), Some(option)) => option,
+        _ => key,
+    }
+}
+
+/// Parse nickname from `value` of `key`.
+///
+/// The nickname is sent as the `ID` of `SESSION CREATE` so it cannot contain characters which
+/// would split it into several key-value pairs.
+fn parse_nickname(key: &str, value: &str) -> crate::Result<String> {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '=' || c == '"') {
+        return Err(Error::InvalidOption {
+            key: key.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    Ok(value.to_string())
+}
+
+/// Remove comment from a TOML `line`, ignoring `#` inside strings.
+fn strip_toml_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (index, c) in line.char_indices() {
+        match (quote, c) {
+            (Some('"'), '\\') if !escaped => {
+                escaped = true;
+                continue;
+            }
+            (Some(delimiter), c) if c == delimiter && !escaped => quote = None,
+            (None, '"' | '\'') => quote = Some(c),
+            (None, '#') => return &line[..index],
+            _ => {}
         }
+        escaped = false;
+    }
+
+    line
+}
+
+/// Parse dotted TOML key into an I2P option key, removing the quotes around its parts.
+fn parse_toml_key(key: &str) -> String {
+    let key = key.trim();
+
+    // a quoted key such as `"inbound.length"` is an option key as-is
+    if let Some(key) = key.strip_prefix('"').and_then(|key| key.strip_suffix('"')) {
+        if !key.contains('"') {
+            return key.to_string();
+        }
+    }
+
+    key.split('.')
+        .map(|part| part.trim().trim_matches(|c| c == '"' || c == '\''))
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Parse TOML string, integer or boolean `value` into its string representation.
+fn parse_toml_value(value: &str) -> Option<String> {
+    if let Some(string) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        let mut chars = string.chars();
+        let mut unescaped = String::with_capacity(string.len());
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next()? {
+                    c @ ('"' | '\\') => unescaped.push(c),
+                    _ => return None,
+                },
+                '"' => return None,
+                c => unescaped.push(c),
+            }
+        }
+
+        return Some(unescaped);
+    }
+
+    if let Some(string) = value.strip_prefix('\'').and_then(|value| value.strip_suffix('\'')) {
+        return (!string.contains('\'')).then(|| string.to_string());
+    }
+
+    match value {
+        "true" | "false" => Some(value.to_string()),
+        value => value.replace('_', "").parse::<i64>().ok().map(|value| value.to_string()),
+    }
+}
+
+/// Create error for a TOML `line` which cannot be parsed.
+fn invalid_toml_line(line: &str) -> Error {
+    Error::InvalidOption {
+        key: line.to_string(),
+        value: String::new(),
+    }
+}
+
+/// Parse `value` of `key` into `T`.
+fn parse_option<T: FromStr>(key: &str, value: &str) -> crate::Result<T> {
+    value.parse::<T>().map_err(|_| Error::InvalidOption {
+        key: key.to_string(),
+        value: value.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(feature = "async", feature = "sync"))]
+    #[test]
+    fn datagram_addresses() {
+        let mut options = SessionOptions {
+            datagram_port: 8888,
+            ..Default::default()
+        };
+        assert_eq!(
+            options.datagram_address().unwrap(),
+            "127.0.0.1:8888".parse().unwrap()
+        );
+
+        options.datagram_host = "::1".to_string();
+        assert_eq!(
+            options.datagram_address().unwrap(),
+            "[::1]:8888".parse().unwrap()
+        );
+
+        for host in ["0.0.0.0", "localhost", ""] {
+            options.datagram_host = host.to_string();
+            assert!(matches!(
+                options.datagram_address(),
+                Err(Error::InvalidOption { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn i2cp_properties() {
+        let options = SessionOptions::from_i2cp_properties(
+            "# tunnel settings\n\
+            inbound.length=1\n\
+            inbound.lengthVariance=-1\n\
+            inbound.quantity=4\n\
+            \n\
+            outbound.length = 2\n\
+            outbound.quantity=5\n\
+            inbound.nickname=test\n\
+            i2cp.dontPublishLeaseSet=true\n\
+            i2cp.unknownOption=1\n",
+        )
+        .unwrap();
+
+        assert_eq!(options.inbound_len, 1);
+        assert_eq!(options.inbound_len_variance, -1);
+        assert_eq!(options.inbound_quantity, 4);
+        assert_eq!(options.outbound_len, 2);
+        assert_eq!(options.outbound_len_variance, 0);
+        assert_eq!(options.outbound_quantity, 5);
+        assert_eq!(options.nickname, "test");
+        assert!(!options.publish);
+        assert_eq!(options.reduce_on_idle, None);
+        assert_eq!(options.close_on_idle, None);
+
+        let options = SessionOptions::from_i2cp_properties(
+            "i2cp.reduceIdleTime=600000\n\
+            i2cp.reduceOnIdle=true\n\
+            i2cp.closeOnIdle=true\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            options.reduce_on_idle,
+            Some(ReduceOnIdle {
+                idle_time: Duration::from_secs(10 * 60),
+                quantity: 1,
+            })
+        );
+        assert_eq!(options.close_on_idle, Some(DEFAULT_CLOSE_IDLE_TIME));
+    }
+
+    #[test]
+    fn presets() {
+        let options = SessionOptions::low_latency();
+        assert_eq!((options.inbound_len, options.outbound_len), (1, 1));
+        assert_eq!(
+            (options.inbound_quantity, options.outbound_quantity),
+            (3, 3)
+        );
+        assert!(options.publish);
+
+        let options = SessionOptions::high_anonymity();
+        assert_eq!((options.inbound_len, options.outbound_len), (3, 3));
+        assert_eq!(
+            (options.inbound_len_variance, options.outbound_len_variance),
+            (1, 1)
+        );
+
+        let options = SessionOptions::ephemeral_client();
+        assert!(!options.publish);
+        assert_eq!(
+            options.idle_threshold,
+            Some(EPHEMERAL_CLIENT_IDLE_THRESHOLD)
+        );
+
+        // presets get nicknames of their own
+        assert_ne!(
+            options.nickname,
+            SessionOptions::ephemeral_client().nickname
+        );
+    }
+
+    #[test]
+    fn i2ptunnel_config() {
+        let options = SessionOptions::from_i2cp_properties(
+            "tunnel.0.name=eepsite\n\
+            tunnel.0.option.inbound.length=2\n\
+            tunnel.0.option.outbound.quantity=3\n",
+        )
+        .unwrap();
+
+        assert_eq!(options.inbound_len, 2);
+        assert_eq!(options.outbound_quantity, 3);
+        assert_eq!(options.outbound_len, DEFAULT_TUNNEL_LEN);
+    }
+
+    #[test]
+    fn invalid_i2cp_properties() {
+        assert!(SessionOptions::from_i2cp_properties("inbound.length=abc").is_err());
+        assert!(SessionOptions::from_i2cp_properties("i2cp.dontPublishLeaseSet=1").is_err());
+        assert!(SessionOptions::from_i2cp_properties("inbound.length").is_err());
+    }
+
+    #[test]
+    fn invalid_nicknames() {
+        for nickname in ["", "my session", "a=b", "\"quoted\""] {
+            assert!(matches!(
+                SessionOptions::from_i2cp_properties(&format!("inbound.nickname={nickname}")),
+                Err(Error::InvalidOption { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn toml() {
+        let options = SessionOptions::from_toml(
+            "# tunnel settings\n\
+            \"outbound.quantity\" = 5\n\
+            \n\
+            [inbound]\n\
+            length = 1 # one hop\n\
+            lengthVariance = -1\n\
+            nickname = \"test#1\"\n\
+            \n\
+            [i2cp]\n\
+            dontPublishLeaseSet = true\n\
+            closeOnIdle = true\n\
+            closeIdleTime = 600_000\n\
+            unknownOption = 'value'\n\
+            \n\
+            [tunnel.0.option]\n\
+            outbound.length = 2\n",
+        )
+        .unwrap();
+
+        assert_eq!(options.inbound_len, 1);
+        assert_eq!(options.inbound_len_variance, -1);
+        assert_eq!(options.outbound_len, 2);
+        assert_eq!(options.outbound_quantity, 5);
+        assert_eq!(options.nickname, "test#1");
+        assert!(!options.publish);
+        assert_eq!(options.close_on_idle, Some(Duration::from_secs(10 * 60)));
+    }
+
+    #[test]
+    fn invalid_toml() {
+        for input in [
+            "[inbound\nlength = 1",
+            "[[inbound]]\nlength = 1",
+            "inbound.length",
+            "inbound.length = [1, 2]",
+            "inbound.length = \"1",
+            "inbound.nickname = \"a\\nb\"",
+            "inbound.nickname = \"my session\"",
+            "inbound.quantity = \"many\"",
+        ] {
+            assert!(matches!(
+                SessionOptions::from_toml(input),
+                Err(Error::InvalidOption { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn toml_file() {
+        let path = std::env::temp_dir().join(format!("yosemite-{}.toml", std::process::id()));
+        std::fs::write(&path, "[outbound]\nquantity = 4\n").unwrap();
+
+        let options = SessionOptions::from_toml_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(options.unwrap().outbound_quantity, 4);
+
+        assert!(matches!(
+            SessionOptions::from_toml_file(&path),
+            Err(Error::IoError(_))
+        ));
+    }
+
+    #[test]
+    fn lease_set_auth_options() {
+        let clients = vec![
+            ("alice".to_string(), "AAAA".to_string()),
+            ("bob".to_string(), "BBBB".to_string()),
+        ];
+
+        assert_eq!(
+            LeaseSetAuth::DhClients(clients.clone()).i2cp_options(),
+            vec![
+                ("i2cp.leaseSetAuthType".to_string(), "1".to_string()),
+                (
+                    "i2cp.leaseSetClient.dh.0".to_string(),
+                    "alice:AAAA".to_string()
+                ),
+                (
+                    "i2cp.leaseSetClient.dh.1".to_string(),
+                    "bob:BBBB".to_string()
+                ),
+            ]
+        );
+        assert_eq!(
+            LeaseSetAuth::PskClients(clients).i2cp_options()[2],
+            (
+                "i2cp.leaseSetClient.psk.1".to_string(),
+                "bob:BBBB".to_string()
+            )
+        );
+        assert_eq!(
+            LeaseSetAuth::PskKey("CCCC".to_string()).i2cp_options(),
+            vec![
+                ("i2cp.leaseSetAuthType".to_string(), "2".to_string()),
+                ("i2cp.leaseSetPrivKey".to_string(), "CCCC".to_string()),
+            ]
+        );
+
+        let debug = format!("{:?}", LeaseSetAuth::DhKey("secret".to_string()));
+        assert!(!debug.contains("secret"));
+    }
+
+    #[test]
+    fn lookup_credentials_options() {
+        assert!(LookupCredentials::default().i2cp_options().is_empty());
+        assert_eq!(
+            LookupCredentials {
+                secret: Some("my secret".to_string()),
+                auth: Some(LeaseSetAuth::PskKey("AAAA".to_string())),
+            }
+            .i2cp_options(),
+            vec![
+                (
+                    "i2cp.leaseSetSecret".to_string(),
+                    "bXkgc2VjcmV0".to_string()
+                ),
+                ("i2cp.leaseSetAuthType".to_string(), "2".to_string()),
+                ("i2cp.leaseSetPrivKey".to_string(), "AAAA".to_string()),
+            ]
+        );
+
+        // client lists are only meaningful for hosted sessions
+        assert!(LookupCredentials {
+            secret: None,
+            auth: Some(LeaseSetAuth::DhClients(vec![(
+                "alice".to_string(),
+                "AAAA".to_string()
+            )])),
+        }
+        .i2cp_options()
+        .is_empty());
+
+        let debug = format!(
+            "{:?}",
+            LookupCredentials {
+                secret: Some("hunter2".to_string()),
+                auth: None,
+            }
+        );
+        assert!(!debug.contains("hunter2"));
+    }
+
+    #[test]
+    fn signature_types() {
+        for code in (0u16..=8).chain([11]) {
+            assert_eq!(SignatureType::from_code(code).unwrap().code(), code);
+        }
+        assert_eq!(SignatureType::from_code(9), None);
+        assert_eq!(SignatureType::default().to_string(), "7");
+    }
+
+    #[cfg(any(feature = "async", feature = "sync"))]
+    #[test]
+    fn encrypted_lease_set_signature_types() {
+        let mut options = SessionOptions {
+            signature_type: SignatureType::EcdsaSha256P256,
+            ..Default::default()
+        };
+        assert!(options.check_signature_type().is_ok());
+
+        // encrypted lease sets require blindable signature types
+        options.lease_set_type = LeaseSetType::Encrypted {
+            secret: None,
+            blinded_signature_type: SignatureType::RedDsaSha512Ed25519,
+        };
+        match options.check_signature_type() {
+            Err(Error::InvalidOption { key, value }) =>
+                assert_eq!((key.as_str(), value.as_str()), ("signature_type", "1")),
+            result => panic!("unexpected result: {result:?}"),
+        }
+
+        options.signature_type = SignatureType::EdDsaSha512Ed25519;
+        assert!(options.check_signature_type().is_ok());
+
+        options.lease_set_type = LeaseSetType::Encrypted {
+            secret: None,
+            blinded_signature_type: SignatureType::DsaSha1,
+        };
+        assert!(options.check_signature_type().is_err());
+    }
+
+    #[test]
+    fn lease_set_type_options() {
+        assert!(LeaseSetType::Default.i2cp_options().is_empty());
+        assert_eq!(
+            LeaseSetType::Encrypted {
+                secret: Some("my secret".to_string()),
+                blinded_signature_type: SignatureType::RedDsaSha512Ed25519,
+            }
+            .i2cp_options(),
+            vec![
+                ("i2cp.leaseSetType".to_string(), "5".to_string()),
+                ("i2cp.leaseSetBlindedType".to_string(), "11".to_string()),
+                (
+                    "i2cp.leaseSetSecret".to_string(),
+                    "bXkgc2VjcmV0".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "rand")]
+    fn seeded_nickname() {
+        let nickname = SessionOptions::from_seed(1337).nickname;
+
+        assert_eq!(nickname.len(), NICKNAME_LEN);
+        assert_eq!(SessionOptions::from_seed(1337).nickname, nickname);
+        assert_ne!(SessionOptions::from_seed(1338).nickname, nickname);
+    }
+
+    #[test]
+    #[cfg(not(feature = "rand"))]
+    fn generated_nickname() {
+        let first = SessionOptions::default().nickname;
+        let second = SessionOptions::default().nickname;
+
+        assert_eq!(first.len(), NICKNAME_LEN);
+        assert!(first.chars().all(|c| c.is_ascii_alphanumeric()));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    #[cfg(any(feature = "async", feature = "sync"))]
+    fn accept_filter() {
+        let destination = format!("{}AAAA", "A".repeat(512));
+        let address = crate::b32_address(&destination).unwrap();
+        let hash = address.trim_end_matches(".b32.i2p").to_uppercase();
+
+        assert!(AcceptFilter::Allow(vec![destination.clone()]).accepts(&destination));
+        assert!(AcceptFilter::Allow(vec![address.clone()]).accepts(&destination));
+        assert!(!AcceptFilter::Allow(vec![]).accepts(&destination));
+        assert!(!AcceptFilter::Deny(vec![hash]).accepts(&destination));
+        assert!(AcceptFilter::Deny(vec![address]).accepts("invalid"));
+    }
+
+    #[test]
+    fn key_files() {
+        // Ed25519 destination with an ElGamal private key, as written by Java I2P and i2pd
+        let mut key = vec![0xaa; 384];
+        key.extend_from_slice(&[5, 0, 4, 0, 7, 0, 0]);
+        key.extend_from_slice(&[0xbb; 256 + 32]);
+
+        let DestinationKind::Persistent { private_key } =
+            DestinationKind::from_key_bytes(&key).unwrap()
+        else {
+            panic!("invalid destination kind");
+        };
+        assert_eq!(private_key, encode_base64(&key));
+        assert_eq!(
+            crate::b32_address(&private_key),
+            crate::b32_address(&encode_base64(&key[..391]))
+        );
+
+        // base64 keys are accepted as well
+        assert_eq!(
+            DestinationKind::from_key_bytes(format!("{private_key}\n").as_bytes()).unwrap(),
+            DestinationKind::Persistent {
+                private_key: private_key.clone()
+            },
+        );
+
+        assert!(matches!(
+            DestinationKind::from_key_bytes(&key[..key.len() - 1]),
+            Err(Error::InvalidKeyFile("truncated"))
+        ));
+        assert!(matches!(
+            DestinationKind::from_key_bytes(&[&key[..], &[0]].concat()),
+            Err(Error::InvalidKeyFile("trailing data"))
+        ));
+        assert!(matches!(
+            DestinationKind::from_key_bytes(b""),
+            Err(Error::InvalidKeyFile("truncated"))
+        ));
+
+        // offline keys: zeroed signing private key is followed by expiration, transient signature
+        // type and public key, offline signature and transient signing private key
+        let mut offline = key[..391 + 256].to_vec();
+        offline.extend_from_slice(&[0; 32]);
+        offline.extend_from_slice(&[0x66, 0x00, 0x00, 0x00, 0, 7]);
+        offline.extend_from_slice(&[0xcc; 32 + 64 + 32]);
+        assert!(DestinationKind::from_key_bytes(&offline).is_ok());
+        assert!(DestinationKind::from_key_bytes(&offline[..offline.len() - 32]).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn serde_options() {
+        let options = SessionOptions {
+            destination: DestinationKind::Persistent {
+                private_key: "private key".to_string(),
+            },
+            lease_set_auth: Some(LeaseSetAuth::DhClients(vec![(
+                "alice".to_string(),
+                "public key".to_string(),
+            )])),
+            ..SessionOptions::high_anonymity()
+        };
+
+        let serialized = serde_json::to_string(&options).unwrap();
+        assert!(serialized.contains("\"private key\""));
+        assert!(!serialized.contains("interceptor"));
+        assert_eq!(
+            serde_json::from_str::<SessionOptions>(&serialized).unwrap(),
+            options
+        );
+
+        let serialized = serde_json::to_string(&options.redacted()).unwrap();
+        assert!(!serialized.contains("private key"));
+        assert!(!serialized.contains("public key"));
+        assert!(serialized.contains("alice"));
+
+        // fields missing from the input are left to their default values
+        let options = serde_json::from_str::<SessionOptions>(
+            r#"{"inbound_len": 1, "destination": "transient", "stream_limit_policy": "reject"}"#,
+        )
+        .unwrap();
+        assert_eq!(options.inbound_len, 1);
+        assert_eq!(options.outbound_len, SessionOptions::default().outbound_len);
+        assert_eq!(options.stream_limit_policy, StreamLimitPolicy::Reject);
+
+        let options = StreamOptions {
+            silent: true,
+            from_port: Some(80),
+            ..Default::default()
+        };
+        let serialized = serde_json::to_string(&options).unwrap();
+        assert_eq!(
+            serde_json::from_str::<StreamOptions>(&serialized).unwrap(),
+            options
+        );
     }
 }
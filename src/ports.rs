@@ -0,0 +1,245 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Allocation of ephemeral `FROM_PORT` ports.
+
+use crate::{error::Error, options::StreamOptions};
+
+use std::{
+    borrow::Cow,
+    collections::HashSet,
+    ops::RangeInclusive,
+    sync::{Arc, Mutex, MutexGuard, PoisonError, Weak},
+};
+
+/// Ports of the ephemeral port range.
+struct Ports {
+    /// Ephemeral port range.
+    range: RangeInclusive<u16>,
+
+    /// Next port to try.
+    next: u16,
+
+    /// Ports held by streams.
+    leased: HashSet<u16>,
+}
+
+impl Ports {
+    /// Get the next port of the range which isn't held by a stream.
+    fn next(&mut self) -> crate::Result<u16> {
+        let len = usize::from(self.range.end() - self.range.start()) + 1;
+
+        for _ in 0..len {
+            let port = self.next;
+            self.next = if port == *self.range.end() {
+                *self.range.start()
+            } else {
+                port + 1
+            };
+
+            if !self.leased.contains(&port) {
+                return Ok(port);
+            }
+        }
+
+        Err(Error::PortsExhausted)
+    }
+}
+
+/// Allocator of ephemeral ports.
+///
+/// Ports are assigned round-robin from the range of
+/// [`SessionOptions::ephemeral_ports`](crate::SessionOptions::ephemeral_ports). Streams hold their
+/// port with a [`PortLease`] while datagrams are sent from the next free port of the range.
+#[derive(Clone)]
+pub(crate) struct PortAllocator {
+    /// Ports of the range.
+    ports: Arc<Mutex<Ports>>,
+}
+
+impl PortAllocator {
+    /// Create new [`PortAllocator`] for `range`.
+    ///
+    /// Fails with [`Error::InvalidOption`] if `range` is empty or contains port `0`.
+    pub(crate) fn new(range: RangeInclusive<u16>) -> crate::Result<Self> {
+        if range.is_empty() || *range.start() == 0 {
+            return Err(Error::InvalidOption {
+                key: "ephemeral_ports".to_string(),
+                value: format!("{range:?}"),
+            });
+        }
+
+        Ok(Self {
+            ports: Arc::new(Mutex::new(Ports {
+                next: *range.start(),
+                range,
+                leased: HashSet::new(),
+            })),
+        })
+    }
+
+    /// Lock the ports.
+    fn ports(&self) -> MutexGuard<'_, Ports> {
+        self.ports.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Lease a port for a stream.
+    ///
+    /// The port is held until the returned [`PortLease`] is dropped.
+    pub(crate) fn lease(&self) -> crate::Result<PortLease> {
+        let mut ports = self.ports();
+        let port = ports.next()?;
+        ports.leased.insert(port);
+
+        Ok(PortLease {
+            port,
+            ports: Arc::downgrade(&self.ports),
+        })
+    }
+
+    /// Get a port for a datagram.
+    pub(crate) fn next(&self) -> crate::Result<u16> {
+        self.ports().next()
+    }
+}
+
+/// Port held by a stream.
+///
+/// The port is released when the lease is dropped.
+pub(crate) struct PortLease {
+    /// Leased port.
+    port: u16,
+
+    /// Ports of the allocator.
+    ports: Weak<Mutex<Ports>>,
+}
+
+impl PortLease {
+    /// Create lease for `port` which was chosen by the caller and isn't held in an allocator.
+    pub(crate) fn fixed(port: u16) -> Self {
+        Self {
+            port,
+            ports: Weak::new(),
+        }
+    }
+
+    /// Get the leased port.
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for PortLease {
+    fn drop(&mut self) {
+        if let Some(ports) = self.ports.upgrade() {
+            ports.lock().unwrap_or_else(PoisonError::into_inner).leased.remove(&self.port);
+        }
+    }
+}
+
+/// Assign a local port to an outbound stream opened with `options`.
+///
+/// If `options` doesn't specify [`StreamOptions::from_port`], a port is leased from `ports`, if
+/// the session has an ephemeral port range.
+///
+/// Returns the options with the port set and the lease which the stream holds, if any.
+pub(crate) fn assign_port<'a>(
+    ports: Option<&PortAllocator>,
+    options: &'a StreamOptions,
+) -> crate::Result<(Cow<'a, StreamOptions>, Option<PortLease>)> {
+    match (options.from_port, ports) {
+        (Some(port), _) => Ok((Cow::Borrowed(options), Some(PortLease::fixed(port)))),
+        (None, Some(ports)) => {
+            let lease = ports.lease()?;
+            let options = StreamOptions {
+                from_port: Some(lease.port()),
+                ..options.clone()
+            };
+
+            Ok((Cow::Owned(options), Some(lease)))
+        }
+        (None, None) => Ok((Cow::Borrowed(options), None)),
+    }
+}
+
+/// Get the local port of an outbound datagram, if the session has an ephemeral port range.
+pub(crate) fn datagram_port(ports: Option<&PortAllocator>) -> crate::Result<Option<u16>> {
+    ports.map(PortAllocator::next).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leased_ports_skipped() {
+        let allocator = PortAllocator::new(50000..=50002).unwrap();
+
+        let first = allocator.lease().unwrap();
+        let second = allocator.lease().unwrap();
+        assert_eq!((first.port(), second.port()), (50000, 50001));
+
+        // datagrams use the free ports in turn
+        assert_eq!(allocator.next().unwrap(), 50002);
+        assert_eq!(allocator.next().unwrap(), 50002);
+
+        let third = allocator.lease().unwrap();
+        assert!(matches!(allocator.lease(), Err(Error::PortsExhausted)));
+        assert!(matches!(allocator.next(), Err(Error::PortsExhausted)));
+
+        // released port is reused
+        drop(second);
+        assert_eq!(allocator.lease().unwrap().port(), 50001);
+        drop((first, third));
+    }
+
+    #[test]
+    fn explicit_port_not_leased() {
+        let allocator = PortAllocator::new(50000..=50000).unwrap();
+
+        let options = StreamOptions {
+            from_port: Some(8080),
+            ..Default::default()
+        };
+        let (assigned, lease) = assign_port(Some(&allocator), &options).unwrap();
+        assert_eq!(assigned.from_port, Some(8080));
+        assert_eq!(lease.map(|lease| lease.port()), Some(8080));
+
+        let options = StreamOptions::default();
+        let (assigned, _lease) = assign_port(Some(&allocator), &options).unwrap();
+        assert_eq!(assigned.from_port, Some(50000));
+        assert!(matches!(
+            assign_port(Some(&allocator), &options),
+            Err(Error::PortsExhausted)
+        ));
+    }
+
+    #[test]
+    fn invalid_range() {
+        assert!(matches!(
+            PortAllocator::new(0..=10),
+            Err(Error::InvalidOption { .. })
+        ));
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 10..=9;
+        assert!(matches!(
+            PortAllocator::new(empty),
+            Err(Error::InvalidOption { .. })
+        ));
+    }
+}
@@ -0,0 +1,581 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! SAMv3 command serialization.
+
+use crate::options::SignatureType;
+
+use std::{
+    fmt::{Display, Write},
+    time::{Duration, Instant},
+};
+
+/// Initial capacity of the command buffer.
+const COMMAND_CAPACITY: usize = 1024;
+
+/// Command writer.
+///
+/// Builds SAMv3 commands into an internal buffer which is reused between commands so building a
+/// command doesn't allocate once the buffer has grown large enough.
+#[derive(Debug, Clone)]
+pub struct CommandWriter {
+    /// Command buffer.
+    buffer: String,
+
+    /// Should the next command be appended to the previous one.
+    pipelined: bool,
+
+    /// When was the most recent command finished.
+    finished_at: Option<Instant>,
+}
+
+impl Default for CommandWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CommandWriter {
+    /// Create new [`CommandWriter`].
+    pub fn new() -> Self {
+        Self {
+            buffer: String::with_capacity(COMMAND_CAPACITY),
+            pipelined: false,
+            finished_at: None,
+        }
+    }
+
+    /// Keep the previous command when the next command is started.
+    ///
+    /// The next call to [`CommandWriter::finish()`] returns both commands so they can be sent to
+    /// the router in one write.
+    pub fn pipeline(&mut self) -> &mut Self {
+        self.pipelined = true;
+        self
+    }
+
+    /// Start building new command, discarding the previous one unless
+    /// [`CommandWriter::pipeline()`] was called.
+    ///
+    /// `command` is the command and the subcommand, e.g., `SESSION CREATE`.
+    pub fn start(&mut self, command: &str) -> &mut Self {
+        if !std::mem::take(&mut self.pipelined) {
+            self.buffer.clear();
+        }
+        self.buffer.push_str(command);
+        self
+    }
+
+    /// Append `key=value` pair to the command.
+    ///
+    /// Values that are empty or contain whitespace or `"` are quoted, with `"` and `\\` escaped.
+    /// Base64 padding is sent as is since only the first `=` separates the key from the value.
+    /// Line breaks cannot be represented in a SAMv3 command and are replaced with spaces.
+    pub fn option(&mut self, key: &str, value: impl Display) -> &mut Self {
+        let _ = write!(self.buffer, " {key}=");
+        let start = self.buffer.len();
+        let _ = write!(self.buffer, "{value}");
+
+        let needs_quotes = start == self.buffer.len()
+            || self.buffer[start..].contains(|c: char| c.is_whitespace() || c == '"');
+
+        if needs_quotes {
+            let value = self.buffer.split_off(start);
+
+            self.buffer.push('"');
+            for c in value.chars() {
+                match c {
+                    '"' | '\\' => {
+                        self.buffer.push('\\');
+                        self.buffer.push(c);
+                    }
+                    '\r' | '\n' => self.buffer.push(' '),
+                    c => self.buffer.push(c),
+                }
+            }
+            self.buffer.push('"');
+        }
+
+        self
+    }
+
+    /// Append free-form `text` to the command.
+    ///
+    /// Line breaks cannot be represented in a SAMv3 command and are replaced with spaces.
+    pub fn text(&mut self, text: &str) -> &mut Self {
+        self.buffer.push(' ');
+        self.buffer.extend(text.chars().map(|c| match c {
+            '\r' | '\n' => ' ',
+            c => c,
+        }));
+        self
+    }
+
+    /// Finish the command and return its serialized form.
+    ///
+    /// The returned command is terminated by exactly one newline.
+    pub fn finish(&mut self) -> &[u8] {
+        self.finished_at = Some(Instant::now());
+        self.buffer.push('\n');
+        self.buffer.as_bytes()
+    }
+
+    /// Time elapsed since the most recent command was finished.
+    ///
+    /// Returns `None` if no command has been finished yet.
+    pub fn elapsed(&self) -> Option<Duration> {
+        self.finished_at.map(|finished_at| finished_at.elapsed())
+    }
+}
+
+/// SAMv3 command.
+pub trait Command {
+    /// Serialize the command into `writer` and return the serialized bytes.
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8];
+}
+
+/// `HELLO VERSION`.
+#[derive(Default)]
+pub struct Hello<'a> {
+    /// Minimum SAMv3 version accepted, if any.
+    pub min_version: Option<&'a str>,
+
+    /// Maximum SAMv3 version accepted, if any.
+    pub max_version: Option<&'a str>,
+}
+
+impl Command for Hello<'_> {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        let command = writer.start("HELLO VERSION");
+
+        if let Some(version) = self.min_version {
+            command.option("MIN", version);
+        }
+
+        if let Some(version) = self.max_version {
+            command.option("MAX", version);
+        }
+
+        command.finish()
+    }
+}
+
+/// `SESSION CREATE`.
+pub struct SessionCreate<'a> {
+    /// Session style.
+    pub style: &'a str,
+
+    /// Session ID.
+    pub id: &'a str,
+
+    /// Destination, either `TRANSIENT` or private key of a persistent destination.
+    pub destination: &'a str,
+
+    /// Style-specific options, SAM options and I2CP options.
+    pub options: &'a [(String, String)],
+}
+
+impl Command for SessionCreate<'_> {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        let command = writer
+            .start("SESSION CREATE")
+            .option("STYLE", self.style)
+            .option("ID", self.id)
+            .option("DESTINATION", self.destination);
+
+        for (key, value) in self.options {
+            command.option(key, value);
+        }
+
+        command.finish()
+    }
+}
+
+/// `STREAM CONNECT`.
+pub struct StreamConnect<'a> {
+    /// Session ID.
+    pub id: &'a str,
+
+    /// Remote destination.
+    pub destination: &'a str,
+
+    /// Local port the stream is opened from, if any.
+    pub from_port: Option<u16>,
+
+    /// Port of the remote destination, if any.
+    pub to_port: Option<u16>,
+
+    /// Should the stream be silent.
+    pub silent: bool,
+
+    /// I2CP options, such as lookup credentials of the remote destination.
+    pub options: &'a [(String, String)],
+}
+
+impl Command for StreamConnect<'_> {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        let command = writer
+            .start("STREAM CONNECT")
+            .option("ID", self.id)
+            .option("DESTINATION", self.destination)
+            .option("SILENT", self.silent);
+
+        if let Some(port) = self.from_port {
+            command.option("FROM_PORT", port);
+        }
+
+        if let Some(port) = self.to_port {
+            command.option("TO_PORT", port);
+        }
+
+        for (key, value) in self.options {
+            command.option(key, value);
+        }
+
+        command.finish()
+    }
+}
+
+/// `STREAM ACCEPT`.
+pub struct StreamAccept<'a> {
+    /// Session ID.
+    pub id: &'a str,
+
+    /// Should the stream be silent.
+    pub silent: bool,
+}
+
+impl Command for StreamAccept<'_> {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        writer
+            .start("STREAM ACCEPT")
+            .option("ID", self.id)
+            .option("SILENT", self.silent)
+            .finish()
+    }
+}
+
+/// `STREAM FORWARD`.
+pub struct StreamForward<'a> {
+    /// Session ID.
+    pub id: &'a str,
+
+    /// Port of the TCP listener where streams are forwarded to.
+    pub port: u16,
+
+    /// Should the forwarded streams be silent.
+    pub silent: bool,
+}
+
+impl Command for StreamForward<'_> {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        writer
+            .start("STREAM FORWARD")
+            .option("ID", self.id)
+            .option("PORT", self.port)
+            .option("SILENT", self.silent)
+            .finish()
+    }
+}
+
+/// `NAMING LOOKUP`.
+pub struct NamingLookup<'a> {
+    /// Name to look up.
+    pub name: &'a str,
+
+    /// I2CP options, such as lookup credentials of the destination.
+    pub options: &'a [(String, String)],
+}
+
+impl Command for NamingLookup<'_> {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        let command = writer.start("NAMING LOOKUP").option("NAME", self.name);
+
+        for (key, value) in self.options {
+            command.option(key, value);
+        }
+
+        command.finish()
+    }
+}
+
+/// `DEST GENERATE`.
+pub struct DestGenerate {
+    /// Signature type of the destination.
+    pub signature_type: SignatureType,
+
+    /// Encryption type of the destination, if the router supports selecting it.
+    pub crypto_type: Option<u16>,
+}
+
+impl Command for DestGenerate {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        let command = writer.start("DEST GENERATE").option("SIGNATURE_TYPE", self.signature_type);
+
+        if let Some(crypto_type) = self.crypto_type {
+            command.option("CRYPTO_TYPE", crypto_type);
+        }
+
+        command.finish()
+    }
+}
+
+/// `QUIT`.
+pub struct Quit;
+
+impl Command for Quit {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        writer.start("QUIT").finish()
+    }
+}
+
+/// `PONG`, sent as a reply to `PING` received from the router.
+pub struct Pong<'a> {
+    /// Data received in `PING`, if any.
+    pub data: Option<&'a str>,
+}
+
+impl Command for Pong<'_> {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        let command = writer.start("PONG");
+
+        if let Some(data) = self.data {
+            command.text(data);
+        }
+
+        command.finish()
+    }
+}
+
+/// Header of a datagram sent to the SAMv3 UDP port of the router.
+pub struct DatagramHeader<'a> {
+    /// Session ID.
+    pub id: &'a str,
+
+    /// Remote destination.
+    pub destination: &'a str,
+
+    /// Local port the datagram is sent from, if any.
+    pub from_port: Option<u16>,
+}
+
+impl Command for DatagramHeader<'_> {
+    fn write<'a>(&self, writer: &'a mut CommandWriter) -> &'a [u8] {
+        let command = writer.start("3.0").text(self.id).text(self.destination);
+
+        if let Some(port) = self.from_port {
+            command.option("FROM_PORT", port);
+        }
+
+        command.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_commands() {
+        let mut writer = CommandWriter::new();
+
+        assert_eq!(writer.start("HELLO VERSION").finish(), b"HELLO VERSION\n");
+        assert_eq!(
+            writer
+                .start("STREAM CONNECT")
+                .option("ID", "nickname")
+                .option("DESTINATION", "host.i2p")
+                .option("SILENT", false)
+                .finish(),
+            b"STREAM CONNECT ID=nickname DESTINATION=host.i2p SILENT=false\n"
+        );
+    }
+
+    #[test]
+    fn pipeline_commands() {
+        let mut writer = CommandWriter::new();
+
+        writer.start("HELLO VERSION").finish();
+        assert_eq!(
+            writer.pipeline().start("STREAM ACCEPT").option("ID", "nickname").finish(),
+            b"HELLO VERSION\nSTREAM ACCEPT ID=nickname\n"
+        );
+
+        // pipelining only applies to the next command
+        assert_eq!(writer.start("HELLO VERSION").finish(), b"HELLO VERSION\n");
+    }
+
+    #[test]
+    fn quote_values() {
+        let mut writer = CommandWriter::new();
+
+        assert_eq!(
+            writer
+                .start("SESSION CREATE")
+                .option("inbound.nickname", "my tunnel")
+                .option("empty", "")
+                .option("quoted", "say \"hi\"")
+                .option("escaped", "a\\b c")
+                .option("multiline", "a\nb")
+                .finish(),
+            b"SESSION CREATE inbound.nickname=\"my tunnel\" empty=\"\" quoted=\"say \\\"hi\\\"\" \
+            escaped=\"a\\\\b c\" multiline=\"a b\"\n"
+        );
+    }
+
+    #[test]
+    fn padded_values_are_not_quoted() {
+        let mut writer = CommandWriter::new();
+        let private_key = "AAAABBBBCCCC".repeat(40) + "==";
+
+        assert_eq!(
+            writer.start("SESSION CREATE").option("DESTINATION", &private_key).finish(),
+            format!("SESSION CREATE DESTINATION={private_key}\n").as_bytes()
+        );
+    }
+
+    #[test]
+    fn typed_commands() {
+        let mut writer = CommandWriter::new();
+
+        assert_eq!(Hello::default().write(&mut writer), b"HELLO VERSION\n");
+        assert_eq!(
+            Hello {
+                min_version: Some("3.1"),
+                max_version: Some("3.3"),
+            }
+            .write(&mut writer),
+            b"HELLO VERSION MIN=3.1 MAX=3.3\n"
+        );
+        assert_eq!(Pong { data: None }.write(&mut writer), b"PONG\n");
+        assert_eq!(Quit.write(&mut writer), b"QUIT\n");
+        assert_eq!(
+            Pong {
+                data: Some("1234 abcd")
+            }
+            .write(&mut writer),
+            b"PONG 1234 abcd\n"
+        );
+        assert_eq!(
+            SessionCreate {
+                style: "STREAM",
+                id: "nickname",
+                destination: "TRANSIENT",
+                options: &[("inbound.length".to_string(), "1".to_string())],
+            }
+            .write(&mut writer),
+            b"SESSION CREATE STYLE=STREAM ID=nickname DESTINATION=TRANSIENT inbound.length=1\n"
+        );
+        assert_eq!(
+            StreamConnect {
+                id: "nickname",
+                destination: "host.i2p",
+                from_port: None,
+                to_port: Some(80),
+                silent: false,
+                options: &[],
+            }
+            .write(&mut writer),
+            b"STREAM CONNECT ID=nickname DESTINATION=host.i2p SILENT=false TO_PORT=80\n"
+        );
+        assert_eq!(
+            StreamConnect {
+                id: "nickname",
+                destination: "host.i2p",
+                from_port: Some(50000),
+                to_port: Some(80),
+                silent: false,
+                options: &[],
+            }
+            .write(&mut writer),
+            b"STREAM CONNECT ID=nickname DESTINATION=host.i2p SILENT=false FROM_PORT=50000 \
+            TO_PORT=80\n"
+        );
+        assert_eq!(
+            StreamConnect {
+                id: "nickname",
+                destination: "host.i2p",
+                from_port: None,
+                to_port: None,
+                silent: false,
+                options: &[("i2cp.leaseSetSecret".to_string(), "c2VjcmV0".to_string())],
+            }
+            .write(&mut writer),
+            b"STREAM CONNECT ID=nickname DESTINATION=host.i2p SILENT=false \
+            i2cp.leaseSetSecret=c2VjcmV0\n"
+        );
+        assert_eq!(
+            StreamAccept {
+                id: "nickname",
+                silent: false,
+            }
+            .write(&mut writer),
+            b"STREAM ACCEPT ID=nickname SILENT=false\n"
+        );
+        assert_eq!(
+            StreamForward {
+                id: "nickname",
+                port: 8888,
+                silent: true,
+            }
+            .write(&mut writer),
+            b"STREAM FORWARD ID=nickname PORT=8888 SILENT=true\n"
+        );
+        assert_eq!(
+            NamingLookup {
+                name: "host.i2p",
+                options: &[],
+            }
+            .write(&mut writer),
+            b"NAMING LOOKUP NAME=host.i2p\n"
+        );
+        assert_eq!(
+            DestGenerate {
+                signature_type: SignatureType::EdDsaSha512Ed25519,
+                crypto_type: None,
+            }
+            .write(&mut writer),
+            b"DEST GENERATE SIGNATURE_TYPE=7\n"
+        );
+        assert_eq!(
+            DestGenerate {
+                signature_type: SignatureType::EdDsaSha512Ed25519,
+                crypto_type: Some(4),
+            }
+            .write(&mut writer),
+            b"DEST GENERATE SIGNATURE_TYPE=7 CRYPTO_TYPE=4\n"
+        );
+        assert_eq!(
+            DatagramHeader {
+                id: "nickname",
+                destination: "host.i2p",
+                from_port: Some(50000),
+            }
+            .write(&mut writer),
+            b"3.0 nickname host.i2p FROM_PORT=50000\n"
+        );
+    }
+    #[test]
+    fn elapsed_since_last_command() {
+        let mut writer = CommandWriter::new();
+        assert!(writer.elapsed().is_none());
+
+        Hello::default().write(&mut writer);
+        assert!(writer.elapsed().is_some());
+    }
+}
@@ -0,0 +1,158 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::error::Error;
+
+/// Line buffer.
+///
+/// Accumulates bytes received from the router until a full line, terminated by either `\n` or
+/// `\r\n`, is available. Bytes that follow the line are not consumed, so the line can be read
+/// from a buffered reader without losing data the router sent after it.
+#[derive(Debug)]
+pub struct LineBuffer {
+    /// Bytes of the current line, including the terminator once the line is complete.
+    buffer: Vec<u8>,
+
+    /// Maximum length of a line, including the terminator.
+    max_len: usize,
+
+    /// Has the current line been terminated.
+    complete: bool,
+}
+
+impl LineBuffer {
+    /// Create new [`LineBuffer`].
+    pub fn new(capacity: usize, max_len: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(capacity.min(max_len)),
+            max_len,
+            complete: false,
+        }
+    }
+
+    /// Clear the buffer for the next line.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.complete = false;
+    }
+
+    /// Is the current line complete.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Push `input` into the buffer.
+    ///
+    /// Consumes bytes up to and including the line terminator and returns the number of bytes
+    /// consumed. Fails if the line is longer than the maximum length.
+    pub fn push(&mut self, input: &[u8]) -> crate::Result<usize> {
+        if self.complete {
+            return Ok(0);
+        }
+
+        let consumed = match input.iter().position(|byte| byte == &b'\n') {
+            Some(position) => {
+                self.complete = true;
+                position + 1
+            }
+            None => input.len(),
+        };
+
+        if self.buffer.len() + consumed > self.max_len {
+            return Err(Error::ResponseTooLong(self.max_len));
+        }
+
+        self.buffer.extend_from_slice(&input[..consumed]);
+        Ok(consumed)
+    }
+
+    /// Get the complete line without its terminator.
+    ///
+    /// Returns `None` if the line is not complete or if it's not valid UTF-8.
+    pub fn line(&self) -> Option<&str> {
+        if !self.complete {
+            return None;
+        }
+
+        let line = self.buffer.strip_suffix(b"\n")?;
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+        std::str::from_utf8(line).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_lines() {
+        let mut buffer = LineBuffer::new(16, 1024);
+
+        assert_eq!(buffer.push(b"HELLO REPLY ").unwrap(), 12);
+        assert!(!buffer.is_complete());
+        assert_eq!(buffer.line(), None);
+
+        assert_eq!(buffer.push(b"RESULT=OK VERSION=3.3").unwrap(), 21);
+        assert_eq!(buffer.push(b"\nSTREAM STATUS").unwrap(), 1);
+        assert!(buffer.is_complete());
+        assert_eq!(buffer.line(), Some("HELLO REPLY RESULT=OK VERSION=3.3"));
+
+        // nothing is consumed after the line is complete
+        assert_eq!(buffer.push(b"STREAM STATUS RESULT=OK\n").unwrap(), 0);
+
+        buffer.clear();
+        assert_eq!(buffer.push(b"STREAM STATUS RESULT=OK\n").unwrap(), 24);
+        assert_eq!(buffer.line(), Some("STREAM STATUS RESULT=OK"));
+    }
+
+    #[test]
+    fn crlf_terminator() {
+        let mut buffer = LineBuffer::new(16, 1024);
+
+        assert_eq!(buffer.push(b"STREAM STATUS RESULT=OK\r").unwrap(), 24);
+        assert!(!buffer.is_complete());
+        assert_eq!(buffer.push(b"\n").unwrap(), 1);
+        assert_eq!(buffer.line(), Some("STREAM STATUS RESULT=OK"));
+    }
+
+    #[test]
+    fn line_too_long() {
+        let mut buffer = LineBuffer::new(16, 16);
+
+        assert_eq!(buffer.push(b"0123456789").unwrap(), 10);
+        assert!(matches!(
+            buffer.push(b"0123456789"),
+            Err(Error::ResponseTooLong(16))
+        ));
+
+        // line of exactly maximum length is accepted
+        buffer.clear();
+        assert_eq!(buffer.push(b"012345678901234\n").unwrap(), 16);
+        assert_eq!(buffer.line(), Some("012345678901234"));
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let mut buffer = LineBuffer::new(16, 1024);
+
+        assert_eq!(buffer.push(b"\xff\xfe\n").unwrap(), 3);
+        assert!(buffer.is_complete());
+        assert_eq!(buffer.line(), None);
+    }
+}
@@ -16,6 +16,11 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
+pub mod command;
+pub mod line;
 pub mod parser;
 pub mod router;
 pub mod session;
+
+#[cfg(feature = "wire-trace")]
+pub(crate) mod trace;
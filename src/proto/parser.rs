@@ -18,17 +18,6 @@
 
 use crate::error::I2pError;
 
-use nom::{
-    branch::alt,
-    bytes::complete::{escaped, is_not, tag, take_while1},
-    character::complete::{alpha1, alphanumeric1, char, multispace0},
-    combinator::{map, opt, recognize},
-    error::{make_error, ErrorKind},
-    multi::{many0, many0_count},
-    sequence::{delimited, pair, preceded, separated_pair, tuple},
-    Err, IResult, Parser,
-};
-
 use std::collections::HashMap;
 
 /// Parsed command.
@@ -47,192 +36,398 @@ struct ParsedCommand<'a> {
     subcommand: Option<&'a str>,
 
     /// Parsed key-value pairs.
-    key_value_pairs: &'a HashMap<&'a str, &'a str>,
+    key_value_pairs: HashMap<&'a str, &'a str>,
 }
 
 /// Response received from SAMv3 server.
+///
+/// Values borrow from the parsed response line so parsing doesn't allocate, except for the
+/// message of an [`I2pError`] and the options of a naming lookup.
+///
+/// Each response carries the `MESSAGE` sent by the router, if any, regardless of whether the
+/// command succeeded or not.
 #[derive(Debug)]
-pub enum Response {
+pub enum Response<'a> {
     /// Response to `HELLO` message.
     Hello {
         /// Supported version or an error.
-        version: Result<String, I2pError>,
+        version: Result<&'a str, I2pError>,
+
+        /// Implementation of the router, if reported.
+        implementation: Option<&'a str>,
+
+        /// Message from the router.
+        message: Option<&'a str>,
     },
 
     /// Session message.
     Session {
         // Destination.
-        destination: Result<String, I2pError>,
+        destination: Result<&'a str, I2pError>,
+
+        /// Message from the router.
+        message: Option<&'a str>,
     },
 
     /// Stream message.
     Stream {
         /// Stream status.
         result: Result<(), I2pError>,
+
+        /// Message from the router.
+        message: Option<&'a str>,
     },
 
     /// Naming lookup.
     NamingLookup {
         /// Lookup result.
-        result: Result<String, I2pError>,
+        result: Result<&'a str, I2pError>,
+
+        /// Options of the destination, returned as `OPTION:<key>=<value>` if the lookup was made
+        /// with `OPTIONS=true`.
+        ///
+        /// The `OPTION:` prefix is stripped from the keys.
+        options: Vec<(&'a str, &'a str)>,
+
+        /// Message from the router.
+        message: Option<&'a str>,
     },
 
     /// Destination generation.
     DestinationGeneration {
         /// Base64-encoded destination.
-        destination: String,
+        destination: &'a str,
 
         /// Base64 of the concatenation of the destination followed by the private key followed by
         /// the signing private key.
-        private_key: String,
+        private_key: &'a str,
+
+        /// Message from the router.
+        message: Option<&'a str>,
+    },
+
+    /// Acknowledgement of `QUIT`.
+    ///
+    /// The router closes the connection after sending the acknowledgement.
+    Quit {
+        /// Result of the command.
+        result: Result<(), I2pError>,
+
+        /// Message from the router.
+        message: Option<&'a str>,
+    },
+
+    /// `PING` sent by the router.
+    ///
+    /// Not a reply to any command. The router expects a `PONG` with the same data back.
+    Ping {
+        /// Arbitrary text, if any.
+        data: Option<&'a str>,
+    },
+
+    /// `PONG` sent by the router.
+    ///
+    /// Not a reply to any command `yosemite` sends.
+    Pong {
+        /// Arbitrary text, if any.
+        data: Option<&'a str>,
     },
 }
 
-impl<'a> TryFrom<ParsedCommand<'a>> for Response {
+impl<'a> TryFrom<ParsedCommand<'a>> for Response<'a> {
     type Error = ();
 
     fn try_from(value: ParsedCommand<'a>) -> Result<Self, Self::Error> {
+        let message = value.key_value_pairs.get("MESSAGE").copied();
+
         match (value.command, value.subcommand) {
             ("HELLO", Some("REPLY")) => match value.key_value_pairs.get("VERSION") {
                 Some(version) => Ok(Response::Hello {
-                    version: Ok(version.to_string()),
+                    version: Ok(version),
+                    implementation: value.key_value_pairs.get("IMPLEMENTATION").copied(),
+                    message,
                 }),
                 None => {
-                    // if `VERSION` doesn't exist, `RESULT` is expected to exist as `NOVERSION`
-                    // an unexpected error since reporting version is optional as of v3.1 and
-                    // `yosemite` doesn't send a version string to the router
+                    // if `VERSION` doesn't exist, `RESULT` is expected to be an error, e.g.,
+                    // `NOVERSION` if the router supports no version between `MIN` and `MAX` of
+                    // `HELLO VERSION`, and a successful reply without a version is malformed
                     let result = value.key_value_pairs.get("RESULT").ok_or(())?;
-                    let message = value.key_value_pairs.get("MESSAGE");
+
+                    if *result == "OK" {
+                        return Err(());
+                    }
 
                     Ok(Response::Hello {
-                        version: Err(I2pError::try_from((*result, message.map(|value| *value)))?),
+                        version: Err(I2pError::from_hello(result, message)),
+                        implementation: None,
+                        message,
                     })
                 }
             },
             ("SESSION", Some("STATUS")) => match value.key_value_pairs.get("DESTINATION") {
                 Some(destination) => Ok(Response::Session {
-                    destination: Ok(destination.to_string()),
+                    destination: Ok(destination),
+                    message,
                 }),
                 None => {
                     let result = value.key_value_pairs.get("RESULT").ok_or(())?;
-                    let message = value.key_value_pairs.get("MESSAGE");
+
+                    if *result == "OK" {
+                        return Err(());
+                    }
 
                     Ok(Response::Session {
-                        destination: Err(I2pError::try_from((
-                            *result,
-                            message.map(|value| *value),
-                        ))?),
+                        destination: Err(I2pError::from((*result, message))),
+                        message,
                     })
                 }
             },
             ("STREAM", Some("STATUS")) => match value.key_value_pairs.get("RESULT") {
-                Some(value) if *value == "OK" => Ok(Response::Stream { result: Ok(()) }),
-                Some(error) => {
-                    let message = value.key_value_pairs.get("MESSAGE");
-
-                    Ok(Response::Stream {
-                        result: Err(I2pError::try_from((*error, message.map(|value| *value)))?),
-                    })
-                }
-                None => return Err(()),
+                Some(result) if *result == "OK" => Ok(Response::Stream {
+                    result: Ok(()),
+                    message,
+                }),
+                Some(error) => Ok(Response::Stream {
+                    result: Err(I2pError::from((*error, message))),
+                    message,
+                }),
+                None => Err(()),
             },
             ("NAMING", Some("REPLY")) => match value.key_value_pairs.get("RESULT") {
                 Some(result) if *result == "OK" => {
-                    let destination = value.key_value_pairs.get("VALUE").ok_or(())?.to_string();
+                    let destination = value.key_value_pairs.get("VALUE").ok_or(())?;
 
                     Ok(Response::NamingLookup {
                         result: Ok(destination),
+                        options: value
+                            .key_value_pairs
+                            .iter()
+                            .filter_map(|(key, value)| Some((key.strip_prefix("OPTION:")?, *value)))
+                            .collect(),
+                        message,
                     })
                 }
-                Some(error) => {
-                    let message = value.key_value_pairs.get("MESSAGE");
-
-                    Ok(Response::NamingLookup {
-                        result: Err(I2pError::try_from((*error, message.map(|value| *value)))?),
-                    })
-                }
-                None => return Err(()),
+                Some(error) => Ok(Response::NamingLookup {
+                    result: Err(I2pError::from((*error, message))),
+                    options: Vec::new(),
+                    message,
+                }),
+                None => Err(()),
+            },
+            ("QUIT", Some("STATUS")) => match value.key_value_pairs.get("RESULT") {
+                Some(result) if *result == "OK" => Ok(Response::Quit {
+                    result: Ok(()),
+                    message,
+                }),
+                Some(error) => Ok(Response::Quit {
+                    result: Err(I2pError::from((*error, message))),
+                    message,
+                }),
+                None => Err(()),
             },
             ("DEST", Some("REPLY")) => {
-                let destination = value.key_value_pairs.get("PUB").ok_or(())?.to_string();
-                let private_key = value.key_value_pairs.get("PRIV").ok_or(())?.to_string();
+                let destination = value.key_value_pairs.get("PUB").ok_or(())?;
+                let private_key = value.key_value_pairs.get("PRIV").ok_or(())?;
 
                 Ok(Response::DestinationGeneration {
                     destination,
                     private_key,
+                    message,
                 })
             }
-            _ => todo!(),
+            _ => Err(()),
         }
     }
 }
 
-impl Response {
+impl<'a> Response<'a> {
     /// Attempt to parse `input` into `Response`.
-    //
-    // Non-public method returning `IResult` for cleaner error handling.
-    fn parse_inner<'a>(input: &'a str) -> IResult<&'a str, Self> {
-        let (rest, (command, _, subcommand, _, key_value_pairs)) = tuple((
-            alt((
-                tag("HELLO"),
-                tag("SESSION"),
-                tag("STREAM"),
-                tag("NAMING"),
-                tag("DEST"),
-            )),
-            opt(char(' ')),
-            opt(alt((tag("REPLY"), tag("STATUS"), tag("REPLY")))),
-            opt(char(' ')),
-            opt(parse_key_value_pairs),
-        ))(input)?;
-
-        Ok((
-            rest,
-            Response::try_from(ParsedCommand {
-                command,
-                subcommand,
-                key_value_pairs: &key_value_pairs.unwrap_or(HashMap::new()),
-            })
-            .map_err(|_| Err::Error(make_error(input, ErrorKind::Fail)))?,
-        ))
+    ///
+    /// `input` is a single response line, with or without the terminating newline.
+    ///
+    /// Returns `None` if the response is malformed or if it's not recognized.
+    pub fn parse(input: &'a str) -> Option<Self> {
+        Self::parse_with(input, false)
     }
 
-    /// Attempt to parse `input` into `Response`.
-    pub fn parse(input: &str) -> Option<Self> {
-        Some(Self::parse_inner(input).ok()?.1)
+    /// Attempt to parse `input` into `Response`, accepting the reply formatting of i2pd.
+    ///
+    /// i2pd doesn't quote the `MESSAGE` of an error so an unquoted `MESSAGE` is read until the
+    /// end of the line, including any spaces.
+    ///
+    /// Returns `None` if the response is malformed or if it's not recognized.
+    pub fn parse_lenient(input: &'a str) -> Option<Self> {
+        Self::parse_with(input, true)
+    }
+
+    /// Parse `input` with [`Response::parse_lenient()`] if `lenient` is set and with
+    /// [`Response::parse()`] otherwise.
+    pub(crate) fn parse_with(input: &'a str, lenient: bool) -> Option<Self> {
+        match Self::parse_unsolicited(input) {
+            Some(response) => Some(response),
+            None => Response::try_from(parse_command(input, lenient)?).ok(),
+        }
+    }
+
+    /// Attempt to parse `input` into a message which the router may send at any time.
+    ///
+    /// Returns `None` if `input` is not `PING` or `PONG`.
+    pub(crate) fn parse_unsolicited(input: &'a str) -> Option<Self> {
+        let input = input.trim_end();
+        let (command, data) = input.split_once(' ').unwrap_or((input, ""));
+        let data = (!data.trim().is_empty()).then(|| data.trim());
+
+        match command {
+            "PING" => Some(Response::Ping { data }),
+            "PONG" => Some(Response::Pong { data }),
+            _ => None,
+        }
+    }
+
+    /// Is the response a message the router may send at any time rather than a reply.
+    pub fn is_unsolicited(&self) -> bool {
+        matches!(self, Response::Ping { .. } | Response::Pong { .. })
     }
 }
 
-fn parse_key_value_pairs(input: &str) -> IResult<&str, HashMap<&str, &str>> {
-    let (input, key_value_pairs) = many0(preceded(multispace0, parse_key_value))(input)?;
-    Ok((input, key_value_pairs.into_iter().collect()))
+/// Parse header of a repliable datagram.
+///
+/// The header starts with the destination of the sender, optionally followed by options such as
+/// `FROM_PORT` and `TO_PORT`, and it's terminated by a newline.
+///
+/// Returns the destination and the offset where the payload starts.
+pub fn parse_datagram_header(datagram: &[u8]) -> Option<(&str, usize)> {
+    let header_end = datagram.iter().position(|byte| byte == &b'\n')?;
+    let header = std::str::from_utf8(&datagram[..header_end]).ok()?;
+    let destination = header.split(' ').next()?;
+
+    (!destination.is_empty()).then_some((destination, header_end + 1))
 }
 
-fn parse_key_value(input: &str) -> IResult<&str, (&str, &str)> {
-    separated_pair(parse_key, char('='), parse_value)(input)
+/// Parse the port a repliable datagram was sent to from its header.
+///
+/// Returns `0` if the header has no `TO_PORT`, like routers do for datagrams sent without a port.
+#[cfg(any(feature = "async", feature = "sans-io"))]
+pub fn parse_datagram_to_port(datagram: &[u8]) -> Option<u16> {
+    let header_end = datagram.iter().position(|byte| byte == &b'\n')?;
+    let header = std::str::from_utf8(&datagram[..header_end]).ok()?;
+
+    match header.split(' ').skip(1).find_map(|option| option.strip_prefix("TO_PORT=")) {
+        Some(port) => port.parse().ok(),
+        None => Some(0),
+    }
 }
 
-fn parse_key(input: &str) -> IResult<&str, &str> {
-    recognize(pair(
-        alt((alpha1, tag("_"))),
-        many0_count(alt((alphanumeric1, tag("_")))),
-    ))
-    .parse(input)
+/// Parse command, subcommand and key-value pairs of a response line.
+///
+/// Parsing of key-value pairs stops at the first token which is not a valid key-value pair. If
+/// `lenient` is set, an unquoted `MESSAGE` extends to the end of the line.
+fn parse_command(input: &str, lenient: bool) -> Option<ParsedCommand<'_>> {
+    let (command, rest) = ["HELLO", "SESSION", "STREAM", "NAMING", "DEST", "QUIT"]
+        .into_iter()
+        .find_map(|command| strip_keyword(input, command).map(|rest| (command, rest)))?;
+
+    let (subcommand, rest) = ["REPLY", "STATUS"]
+        .into_iter()
+        .find_map(|subcommand| strip_keyword(rest, subcommand).map(|rest| (Some(subcommand), rest)))
+        .unwrap_or((None, rest));
+
+    Some(ParsedCommand {
+        command,
+        subcommand,
+        key_value_pairs: parse_key_value_pairs(rest, lenient),
+    })
 }
 
-fn parse_value(input: &str) -> IResult<&str, &str> {
-    alt((
-        parse_quoted_value,
-        map(take_while1(|c: char| !c.is_whitespace()), |s: &str| s),
-    ))(input)
+/// Strip `keyword` and the space following it from `input`.
+///
+/// Returns `None` if `input` doesn't start with `keyword` or if `keyword` is only a prefix of the
+/// first word of `input`, e.g., `HELLO` of `HELLOX`.
+fn strip_keyword<'a>(input: &'a str, keyword: &str) -> Option<&'a str> {
+    let rest = input.strip_prefix(keyword)?;
+
+    match rest.chars().next() {
+        None => Some(rest),
+        Some(' ') => Some(&rest[1..]),
+        Some('\t' | '\r' | '\n') => Some(rest),
+        Some(_) => None,
+    }
 }
 
-fn parse_quoted_value(input: &str) -> IResult<&str, &str> {
-    delimited(
-        char('"'),
-        escaped(is_not("\\\""), '\\', alt((tag("\""), tag("\\")))),
-        char('"'),
-    )(input)
+fn parse_key_value_pairs(mut input: &str, lenient: bool) -> HashMap<&str, &str> {
+    let mut key_value_pairs = HashMap::new();
+
+    loop {
+        input = input.trim_start_matches([' ', '\t', '\r', '\n']);
+
+        if let Some(message) = lenient.then(|| parse_unquoted_message(input)).flatten() {
+            key_value_pairs.insert("MESSAGE", message);
+            break;
+        }
+
+        let Some((key, value, rest)) = parse_key_value(input) else {
+            break;
+        };
+        key_value_pairs.insert(key, value);
+        input = rest;
+    }
+
+    key_value_pairs
+}
+
+/// Parse unquoted `MESSAGE` which extends to the end of the line.
+fn parse_unquoted_message(input: &str) -> Option<&str> {
+    let message = input.strip_prefix("MESSAGE=")?.trim_end();
+
+    (!message.is_empty() && !message.starts_with('"')).then_some(message)
+}
+
+fn parse_key_value(input: &str) -> Option<(&str, &str, &str)> {
+    let (key, rest) = parse_key(input)?;
+    let (value, rest) = parse_value(rest.strip_prefix('=')?)?;
+
+    Some((key, value, rest))
+}
+
+fn parse_key(input: &str) -> Option<(&str, &str)> {
+    let end = input
+        .find(|c: char| !c.is_ascii_alphanumeric() && !matches!(c, '_' | '.' | '-' | ':'))
+        .unwrap_or(input.len());
+
+    input[..end]
+        .starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        .then(|| input.split_at(end))
+}
+
+fn parse_value(input: &str) -> Option<(&str, &str)> {
+    if let Some(value) = parse_quoted_value(input) {
+        return Some(value);
+    }
+
+    let end = input.find(char::is_whitespace).unwrap_or(input.len());
+    (end > 0).then(|| input.split_at(end))
+}
+
+/// Parse quoted value.
+///
+/// The returned value doesn't include the quotes and escape sequences (`\"` and `\\`) are
+/// returned as-is.
+fn parse_quoted_value(input: &str) -> Option<(&str, &str)> {
+    let quoted = input.strip_prefix('"')?;
+    let mut chars = quoted.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Some((&quoted[..index], &quoted[index + 1..])),
+            '\\' => match chars.next() {
+                Some((_, '"' | '\\')) => {}
+                _ => return None,
+            },
+            _ => {}
+        }
+    }
+
+    None
 }
 
 #[cfg(test)]
@@ -244,8 +439,20 @@ mod tests {
         // success
         match Response::parse("HELLO REPLY RESULT=OK VERSION=3.3") {
             Some(Response::Hello {
-                version: Ok(response),
-            }) if response == "3.3".to_string() => {}
+                version: Ok("3.3"),
+                implementation: None,
+                message: None,
+            }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        // implementation reported
+        match Response::parse("HELLO REPLY RESULT=OK VERSION=3.1 IMPLEMENTATION=\"i2pd 2.50\"") {
+            Some(Response::Hello {
+                version: Ok("3.1"),
+                implementation: Some("i2pd 2.50"),
+                ..
+            }) => {}
             response => panic!("invalid response: {response:?}"),
         }
 
@@ -253,6 +460,8 @@ mod tests {
         match Response::parse("HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"router error\"") {
             Some(Response::Hello {
                 version: Err(error),
+                message: Some("router error"),
+                ..
             }) if error == I2pError::I2pError(Some("router error".to_string())) => {}
             response => panic!("invalid response: {response:?}"),
         }
@@ -262,26 +471,127 @@ mod tests {
     fn invalid_hello() {
         assert!(Response::parse("HELLO REPLY").is_none());
         assert!(Response::parse("HELLO REPLY KEY=VALUE").is_none());
-        assert!(Response::parse("HELLO REPLY RESULT=NOVERSION").is_none());
-        assert!(Response::parse("HELLO REPLY RESULT=UKNOWN_ERROR").is_none());
         assert!(Response::parse("HELLO REPLY RESULT=OK").is_none());
         assert!(Response::parse("HELLO REPLY MESSAGE=\"hello, world\"").is_none());
     }
 
+    #[test]
+    fn hello_authentication() {
+        for (response, expected) in [
+            (
+                "HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"USER and PASSWORD required\"",
+                I2pError::AuthRequired,
+            ),
+            (
+                "HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"PASSWORD required\"",
+                I2pError::AuthRequired,
+            ),
+            (
+                "HELLO REPLY RESULT=I2P_ERROR MESSAGE=\"Authorization failed\"",
+                I2pError::AuthFailed,
+            ),
+        ] {
+            match Response::parse(response) {
+                Some(Response::Hello {
+                    version: Err(error),
+                    ..
+                }) => assert_eq!(error, expected),
+                response => panic!("invalid response: {response:?}"),
+            }
+        }
+
+        // other commands keep the generic error
+        match Response::parse("STREAM STATUS RESULT=I2P_ERROR MESSAGE=\"Authorization failed\"") {
+            Some(Response::Stream {
+                result: Err(I2pError::I2pError(Some(_))),
+                ..
+            }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+    }
+
+    #[test]
+    fn unrecognized_result() {
+        match Response::parse("HELLO REPLY RESULT=NOVERSION") {
+            Some(Response::Hello {
+                version:
+                    Err(I2pError::Other {
+                        result,
+                        message: None,
+                    }),
+                ..
+            }) if result == "NOVERSION" => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        match Response::parse("STREAM STATUS RESULT=ALREADY_ACCEPTING MESSAGE=\"in use\"") {
+            Some(Response::Stream {
+                result: Err(I2pError::Other { result, message }),
+                ..
+            }) if result == "ALREADY_ACCEPTING" && message.as_deref() == Some("in use") => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        assert!(Response::parse("SESSION STATUS RESULT=OK").is_none());
+    }
+
+    #[test]
+    fn quit_status() {
+        match Response::parse("QUIT STATUS RESULT=OK MESSAGE=bye") {
+            Some(Response::Quit {
+                result: Ok(()),
+                message: Some("bye"),
+            }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        assert!(Response::parse("QUIT STATUS").is_none());
+    }
+
+    #[test]
+    fn naming_reply_options() {
+        match Response::parse(
+            "NAMING REPLY RESULT=OK NAME=host.i2p VALUE=abcd OPTION:service=http \
+            OPTION:i2p.port=\"80\"",
+        ) {
+            Some(Response::NamingLookup {
+                result: Ok("abcd"),
+                mut options,
+                ..
+            }) => {
+                options.sort();
+                assert_eq!(options, vec![("i2p.port", "80"), ("service", "http")]);
+            }
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        match Response::parse("NAMING REPLY RESULT=OK NAME=host.i2p VALUE=abcd") {
+            Some(Response::NamingLookup {
+                result: Ok("abcd"),
+                options,
+                ..
+            }) => assert!(options.is_empty()),
+            response => panic!("invalid response: {response:?}"),
+        }
+    }
+
     #[test]
     fn unrecognized_command() {
         assert!(Response::parse("TEST COMMAND KEY=VALUE").is_none());
+        assert!(Response::parse("STREAM REPLY RESULT=OK").is_none());
+        assert!(Response::parse("SESSION KEY=VALUE").is_none());
     }
 
     #[test]
     fn session_status() {
         let response =  "SESSION STATUS RESULT=OK DESTINATION=TIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJkyG6cCLidWPZ3iUHuCcrTeb8MfiOghIzI~n1yyDQV4mTIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJkyG6cCLidWPZ3iUHuCcrTeb8MfiOghIzI~n1yyDQV4mTIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJmRZ8D0ewvPmy2QKbhZTS3Y9B~nR2m~2vf3yPdVWR7pokR0PeHn-vQ8Av0VNEKUete3L7pEvwrm8CxrIY2aUkV~CpNliKwvhfsJe7tSDSL32Ia42O45KTZbGkI9jvKDdFblwoOYpcd1ToDFZ5qWQ0bxACistfpu609-1Tw1y26neAAAA08XrilOIapGsMhNO1WihrFDLOycxcJlTlqbhV1NKKgekUa-RjUuL1n2hx7VjQK2iSK4FNUprfsr1GEIrOvaNKUD4B0fc7Xshbr43oZZ-LE0FxhNdOhz5KOEzW-eqE7V84PTWIfpY9to6Mm1JObl6ARHhVxPvSVQzkNMuuoFQoB2STMOw2osPXxr7tk~qVYnBrrHpZYrfGIyO1tN1MDCJPqTbFaCNb3Jtnxz3h7B~aJFAHzzEl~sHpMJx7IWAaVr-e2mIRin7fywJq3IhuPy8DdAJiIa-8qrjDDrNNg02a3BgSN4If6sTFooGRX-cXnuCjbbqjzg3dq8parcTekauEFtlTl6d17wFQ3o~JtFQ4ObzpGuW";
-        let destination = "TIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJkyG6cCLidWPZ3iUHuCcrTeb8MfiOghIzI~n1yyDQV4mTIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJkyG6cCLidWPZ3iUHuCcrTeb8MfiOghIzI~n1yyDQV4mTIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJmRZ8D0ewvPmy2QKbhZTS3Y9B~nR2m~2vf3yPdVWR7pokR0PeHn-vQ8Av0VNEKUete3L7pEvwrm8CxrIY2aUkV~CpNliKwvhfsJe7tSDSL32Ia42O45KTZbGkI9jvKDdFblwoOYpcd1ToDFZ5qWQ0bxACistfpu609-1Tw1y26neAAAA08XrilOIapGsMhNO1WihrFDLOycxcJlTlqbhV1NKKgekUa-RjUuL1n2hx7VjQK2iSK4FNUprfsr1GEIrOvaNKUD4B0fc7Xshbr43oZZ-LE0FxhNdOhz5KOEzW-eqE7V84PTWIfpY9to6Mm1JObl6ARHhVxPvSVQzkNMuuoFQoB2STMOw2osPXxr7tk~qVYnBrrHpZYrfGIyO1tN1MDCJPqTbFaCNb3Jtnxz3h7B~aJFAHzzEl~sHpMJx7IWAaVr-e2mIRin7fywJq3IhuPy8DdAJiIa-8qrjDDrNNg02a3BgSN4If6sTFooGRX-cXnuCjbbqjzg3dq8parcTekauEFtlTl6d17wFQ3o~JtFQ4ObzpGuW".to_string();
+        let destination = "TIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJkyG6cCLidWPZ3iUHuCcrTeb8MfiOghIzI~n1yyDQV4mTIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJkyG6cCLidWPZ3iUHuCcrTeb8MfiOghIzI~n1yyDQV4mTIbpwIuJ1Y9neJQe4JytN5vwx-I6CEjMj-fXLINBXiZMhunAi4nVj2d4lB7gnK03m~DH4joISMyP59csg0FeJmRZ8D0ewvPmy2QKbhZTS3Y9B~nR2m~2vf3yPdVWR7pokR0PeHn-vQ8Av0VNEKUete3L7pEvwrm8CxrIY2aUkV~CpNliKwvhfsJe7tSDSL32Ia42O45KTZbGkI9jvKDdFblwoOYpcd1ToDFZ5qWQ0bxACistfpu609-1Tw1y26neAAAA08XrilOIapGsMhNO1WihrFDLOycxcJlTlqbhV1NKKgekUa-RjUuL1n2hx7VjQK2iSK4FNUprfsr1GEIrOvaNKUD4B0fc7Xshbr43oZZ-LE0FxhNdOhz5KOEzW-eqE7V84PTWIfpY9to6Mm1JObl6ARHhVxPvSVQzkNMuuoFQoB2STMOw2osPXxr7tk~qVYnBrrHpZYrfGIyO1tN1MDCJPqTbFaCNb3Jtnxz3h7B~aJFAHzzEl~sHpMJx7IWAaVr-e2mIRin7fywJq3IhuPy8DdAJiIa-8qrjDDrNNg02a3BgSN4If6sTFooGRX-cXnuCjbbqjzg3dq8parcTekauEFtlTl6d17wFQ3o~JtFQ4ObzpGuW";
 
         // success
-        match Response::parse(&response) {
+        match Response::parse(response) {
             Some(Response::Session {
                 destination: parsed_destination,
+                ..
             }) if Ok(destination) == parsed_destination => {}
             response => panic!("invalid response: {response:?}"),
         }
@@ -290,6 +600,7 @@ mod tests {
         match Response::parse("SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"router error\"") {
             Some(Response::Session {
                 destination: Err(error),
+                ..
             }) if error == I2pError::I2pError(Some("router error".to_string())) => {}
             response => panic!("invalid response: {response:?}"),
         }
@@ -299,16 +610,140 @@ mod tests {
     fn stream_status() {
         // success
         match Response::parse("STREAM STATUS RESULT=OK") {
-            Some(Response::Stream { result: Ok(()) }) => {}
+            Some(Response::Stream {
+                result: Ok(()),
+                message: None,
+            }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        // success with message
+        match Response::parse("STREAM STATUS RESULT=OK MESSAGE=\"stream connected\"") {
+            Some(Response::Stream {
+                result: Ok(()),
+                message: Some("stream connected"),
+            }) => {}
             response => panic!("invalid response: {response:?}"),
         }
 
         // failure
         match Response::parse("STREAM STATUS RESULT=CANT_REACH_PEER MESSAGE=\"Connection failed\"")
         {
-            Some(Response::Stream { result: Err(error) }) if error == I2pError::CantReachPeer => {}
+            Some(Response::Stream {
+                result: Err(I2pError::CantReachPeer),
+                message: Some("Connection failed"),
+            }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+    }
+
+    #[test]
+    fn quoted_values() {
+        let pairs = parse_key_value_pairs(
+            "MESSAGE=\"say \\\"hi\\\" \\\\ bye\" EMPTY=\"\" _KEY=value\n",
+            false,
+        );
+
+        assert_eq!(pairs.get("MESSAGE"), Some(&"say \\\"hi\\\" \\\\ bye"));
+        assert_eq!(pairs.get("EMPTY"), Some(&""));
+        assert_eq!(pairs.get("_KEY"), Some(&"value"));
+
+        // unterminated quote is parsed as an unquoted value
+        let pairs = parse_key_value_pairs("MESSAGE=\"hello world", false);
+        assert_eq!(pairs.get("MESSAGE"), Some(&"\"hello"));
+
+        // parsing stops at the first invalid pair
+        let pairs = parse_key_value_pairs("RESULT=OK 1KEY=value VERSION=3.3", false);
+        assert_eq!(pairs.get("RESULT"), Some(&"OK"));
+        assert_eq!(pairs.get("VERSION"), None);
+    }
+
+    #[test]
+    fn i2pd_replies() {
+        let response = "SESSION STATUS RESULT=I2P_ERROR MESSAGE=Session already exists\n";
+
+        match Response::parse_lenient(response) {
+            Some(Response::Session {
+                destination: Err(I2pError::I2pError(Some(message))),
+                ..
+            }) if message == "Session already exists" => {}
             response => panic!("invalid response: {response:?}"),
         }
+
+        match Response::parse(response) {
+            Some(Response::Session {
+                message: Some("Session"),
+                ..
+            }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        // quoted messages are parsed as usual
+        match Response::parse_lenient("STREAM STATUS RESULT=I2P_ERROR MESSAGE=\"timeout\" X=1") {
+            Some(Response::Stream {
+                message: Some("timeout"),
+                ..
+            }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+    }
+
+    #[test]
+    fn datagram_header() {
+        assert_eq!(
+            parse_datagram_header(b"destination FROM_PORT=0 TO_PORT=0\nhello, world"),
+            Some(("destination", 34))
+        );
+        assert_eq!(
+            parse_datagram_header(b"destination\nhello world"),
+            Some(("destination", 12))
+        );
+        assert_eq!(
+            parse_datagram_header(b"destination\n"),
+            Some(("destination", 12))
+        );
+
+        // header is not terminated
+        assert_eq!(parse_datagram_header(b"destination hello world"), None);
+
+        // destination is missing
+        assert_eq!(parse_datagram_header(b"\nhello world"), None);
+        assert_eq!(parse_datagram_header(b" FROM_PORT=0\nhello world"), None);
+    }
+
+    #[cfg(any(feature = "async", feature = "sans-io"))]
+    #[test]
+    fn datagram_to_port() {
+        assert_eq!(
+            parse_datagram_to_port(b"destination FROM_PORT=1 TO_PORT=53\nTO_PORT=80"),
+            Some(53)
+        );
+        assert_eq!(parse_datagram_to_port(b"destination\nTO_PORT=80"), Some(0));
+        assert_eq!(parse_datagram_to_port(b"destination TO_PORT=x\n"), None);
+        assert_eq!(parse_datagram_to_port(b"destination TO_PORT=80"), None);
+    }
+
+    #[test]
+    fn ping_pong() {
+        match Response::parse("PING\n") {
+            Some(response @ Response::Ping { data: None }) => assert!(response.is_unsolicited()),
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        match Response::parse("PING 1234 abcd\n") {
+            Some(Response::Ping {
+                data: Some("1234 abcd"),
+            }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        match Response::parse("PONG 1234") {
+            Some(Response::Pong { data: Some("1234") }) => {}
+            response => panic!("invalid response: {response:?}"),
+        }
+
+        assert!(Response::parse("PINGS").is_none());
+        assert!(!Response::parse("STREAM STATUS RESULT=OK").unwrap().is_unsolicited());
     }
 
     #[test]
@@ -325,6 +760,7 @@ mod tests {
                 Some(Response::DestinationGeneration {
                     destination: parsed_destination,
                     private_key: parsed_private_key,
+                    ..
                 }) => {
                     assert_eq!(destination, parsed_destination);
                     assert_eq!(private_key, parsed_private_key);
@@ -347,4 +783,22 @@ mod tests {
             assert!(Response::parse(&response).is_none());
         }
     }
+
+    #[test]
+    fn keyword_prefixes_rejected() {
+        for response in [
+            "HELLOX REPLY RESULT=OK VERSION=3.3\n",
+            "HELLO REPLYX RESULT=OK VERSION=3.3\n",
+            "SESSIONSTATUS RESULT=OK DESTINATION=dest\n",
+            "SESSION STATUSRESULT=OK DESTINATION=dest\n",
+            "STREAMS STATUS RESULT=OK\n",
+        ] {
+            assert!(Response::parse(response).is_none(), "{response}");
+        }
+
+        assert!(matches!(
+            Response::parse("STREAM STATUS RESULT=OK\n"),
+            Some(Response::Stream { .. })
+        ));
+    }
 }
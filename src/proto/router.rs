@@ -16,7 +16,19 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-use crate::{error::ProtocolError, proto::parser::Response};
+use crate::{
+    error::ProtocolError,
+    options::{LookupCredentials, RouterCompatibility, SignatureType},
+    proto::{
+        command::{Command, CommandWriter, DestGenerate, Hello, NamingLookup, Quit},
+        parser::Response,
+    },
+    redaction::RedactedLine,
+    router_info::RouterInfo,
+    tracing,
+};
+
+use std::{collections::HashMap, time::Duration};
 
 /// Logging target for the file.
 const LOG_TARGET: &str = "yosemite::proto::router-api";
@@ -45,6 +57,9 @@ enum RouterApiControllerState {
     LookupSucceeded {
         /// Base64-encoded destination.
         destination: String,
+
+        /// Options of the destination, if requested.
+        options: HashMap<String, String>,
     },
 
     /// Destination generation.
@@ -59,6 +74,12 @@ enum RouterApiControllerState {
         private_key: String,
     },
 
+    /// `QUIT` has been sent.
+    Quitting,
+
+    /// Router has acknowledged `QUIT` and closed the connection.
+    Closed,
+
     /// State has been poisoned.
     Poisoned,
 }
@@ -67,6 +88,24 @@ enum RouterApiControllerState {
 pub struct RouterApiController {
     /// State of the router API controller.
     state: RouterApiControllerState,
+
+    /// Command writer.
+    writer: CommandWriter,
+
+    /// Threshold after which a slow router response is reported.
+    slow_operation_threshold: Option<Duration>,
+
+    /// Router implementation whose deviations are accommodated.
+    compatibility: RouterCompatibility,
+
+    /// Router details reported during the handshake.
+    router_info: Option<RouterInfo>,
+}
+
+impl Default for RouterApiController {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl RouterApiController {
@@ -74,11 +113,50 @@ impl RouterApiController {
     pub fn new() -> Self {
         Self {
             state: RouterApiControllerState::Uninitialized,
+            writer: CommandWriter::new(),
+            slow_operation_threshold: None,
+            compatibility: RouterCompatibility::Auto,
+            router_info: None,
+        }
+    }
+
+    /// Report router responses that take longer than `threshold` to arrive.
+    pub fn with_slow_operation_threshold(mut self, threshold: Option<Duration>) -> Self {
+        self.slow_operation_threshold = threshold;
+        self
+    }
+
+    /// Accommodate the deviations of the router implementation selected by `compatibility`.
+    pub fn with_compatibility(mut self, compatibility: RouterCompatibility) -> Self {
+        self.compatibility = compatibility;
+        self
+    }
+
+    /// Should i2pd deviations be accommodated.
+    fn i2pd(&self) -> bool {
+        self.compatibility.i2pd(self.router_info.as_ref())
+    }
+
+    /// Describe the state of the controller for diagnostics.
+    ///
+    /// Destinations and keys are left out.
+    fn describe_state(&self) -> &'static str {
+        match &self.state {
+            RouterApiControllerState::Uninitialized => "Uninitialized",
+            RouterApiControllerState::Handshaking => "Handshaking",
+            RouterApiControllerState::Handshaked => "Handshaked",
+            RouterApiControllerState::AwaitingLookupResponse => "AwaitingLookupResponse",
+            RouterApiControllerState::AwaitingDestinationResponse => "AwaitingDestinationResponse",
+            RouterApiControllerState::LookupSucceeded { .. } => "LookupSucceeded",
+            RouterApiControllerState::DestinationGenerated { .. } => "DestinationGenerated",
+            RouterApiControllerState::Quitting => "Quitting",
+            RouterApiControllerState::Closed => "Closed",
+            RouterApiControllerState::Poisoned => "Poisoned",
         }
     }
 
     /// Initialize router API by handshaking with the router.
-    pub fn handshake_router_api(&mut self) -> Result<Vec<u8>, ProtocolError> {
+    pub fn handshake_router_api(&mut self) -> Result<&[u8], ProtocolError> {
         match std::mem::replace(&mut self.state, RouterApiControllerState::Poisoned) {
             RouterApiControllerState::Uninitialized => {
                 tracing::trace!(
@@ -87,7 +165,7 @@ impl RouterApiController {
                 );
                 self.state = RouterApiControllerState::Handshaking;
 
-                Ok(String::from("HELLO VERSION\n").into_bytes())
+                Ok(Hello::default().write(&mut self.writer))
             }
             state => {
                 tracing::warn!(
@@ -103,7 +181,41 @@ impl RouterApiController {
     }
 
     /// Lookup destination associated with `name`.
-    pub fn lookup_name(&mut self, name: &str) -> Result<Vec<u8>, ProtocolError> {
+    pub fn lookup_name(&mut self, name: &str) -> Result<&[u8], ProtocolError> {
+        self.naming_lookup(name, &[])
+    }
+
+    /// Lookup encrypted destination associated with `name`.
+    ///
+    /// `credentials` are used by the router to look up the lease set of the destination.
+    pub fn lookup_name_with_credentials(
+        &mut self,
+        name: &str,
+        credentials: &LookupCredentials,
+    ) -> Result<&[u8], ProtocolError> {
+        self.naming_lookup(name, &credentials.i2cp_options())
+    }
+
+    /// Lookup destination associated with `name` and the options published for it.
+    ///
+    /// The options, such as service metadata, are returned by
+    /// [`RouterApiController::destination_with_options()`]. `OPTIONS=true` was introduced in
+    /// SAMv3.2 and the lookup fails with [`ProtocolError::UnsupportedByRouter`] if the router
+    /// reported an older version.
+    pub fn lookup_name_with_options(&mut self, name: &str) -> Result<&[u8], ProtocolError> {
+        if let Some(router_info) = &self.router_info {
+            router_info.require("NAMING LOOKUP OPTIONS", "3.2")?;
+        }
+
+        self.naming_lookup(name, &[("OPTIONS".to_string(), "true".to_string())])
+    }
+
+    /// Lookup destination associated with `name`, passing `options` to the router.
+    fn naming_lookup(
+        &mut self,
+        name: &str,
+        options: &[(String, String)],
+    ) -> Result<&[u8], ProtocolError> {
         match std::mem::replace(&mut self.state, RouterApiControllerState::Poisoned) {
             RouterApiControllerState::Handshaked => {
                 tracing::info!(
@@ -113,7 +225,7 @@ impl RouterApiController {
                 );
                 self.state = RouterApiControllerState::AwaitingLookupResponse;
 
-                Ok(format!("NAMING LOOKUP NAME={name}\n").into_bytes())
+                Ok(NamingLookup { name, options }.write(&mut self.writer))
             }
             state => {
                 tracing::warn!(
@@ -128,17 +240,31 @@ impl RouterApiController {
         }
     }
 
-    /// Lookup destination associated with `name`.
-    pub fn generate_destination(&mut self) -> Result<Vec<u8>, ProtocolError> {
+    /// Generate new Ed25519 destination.
+    pub fn generate_destination(&mut self) -> Result<&[u8], ProtocolError> {
+        self.generate_destination_with_type(SignatureType::default())
+    }
+
+    /// Generate new destination with `signature_type`.
+    pub fn generate_destination_with_type(
+        &mut self,
+        signature_type: SignatureType,
+    ) -> Result<&[u8], ProtocolError> {
         match std::mem::replace(&mut self.state, RouterApiControllerState::Poisoned) {
             RouterApiControllerState::Handshaked => {
                 tracing::info!(
                     target: LOG_TARGET,
+                    ?signature_type,
                     "generate destination",
                 );
                 self.state = RouterApiControllerState::AwaitingDestinationResponse;
 
-                Ok(format!("DEST GENERATE SIGNATURE_TYPE=7\n").into_bytes())
+                // i2pd generates destinations with ElGamal keys unless told otherwise
+                Ok(DestGenerate {
+                    signature_type,
+                    crypto_type: self.i2pd().then_some(4),
+                }
+                .write(&mut self.writer))
             }
             state => {
                 tracing::warn!(
@@ -153,78 +279,185 @@ impl RouterApiController {
         }
     }
 
+    /// Close the connection to the router.
+    ///
+    /// `QUIT` was introduced in SAMv3.2 and it fails with [`ProtocolError::UnsupportedByRouter`]
+    /// if the router reported an older version. The router acknowledges `QUIT` and closes the
+    /// connection, the connection may also be closed without an acknowledgement.
+    pub fn quit(&mut self) -> Result<&[u8], ProtocolError> {
+        match std::mem::replace(&mut self.state, RouterApiControllerState::Poisoned) {
+            RouterApiControllerState::Handshaked => {
+                let supported =
+                    self.router_info.as_ref().map_or(Ok(()), |info| info.require("QUIT", "3.2"));
+
+                if let Err(error) = supported {
+                    self.state = RouterApiControllerState::Handshaked;
+                    return Err(error);
+                }
+
+                tracing::trace!(
+                    target: LOG_TARGET,
+                    "close router api connection",
+                );
+                self.state = RouterApiControllerState::Quitting;
+
+                Ok(Quit.write(&mut self.writer))
+            }
+            state => {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    ?state,
+                    "cannot quit, invalid state",
+                );
+
+                debug_assert!(false);
+                Err(ProtocolError::InvalidState)
+            }
+        }
+    }
+
     /// Handle response from router.
+    ///
+    /// Messages which the router may send at any time, such as `PING`, are ignored and they don't
+    /// affect the state of the controller.
     pub fn handle_response(&mut self, response: &str) -> Result<(), ProtocolError> {
+        if let Some(response) = Response::parse_unsolicited(response) {
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?response,
+                "ignoring unsolicited message from router",
+            );
+            return Ok(());
+        }
+
+        if let (Some(threshold), Some(elapsed)) =
+            (self.slow_operation_threshold, self.writer.elapsed())
+        {
+            if elapsed > threshold {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    state = self.describe_state(),
+                    ?elapsed,
+                    ?threshold,
+                    "slow response from router",
+                );
+            }
+        }
+
+        let lenient = self.i2pd();
+
         match std::mem::replace(&mut self.state, RouterApiControllerState::Poisoned) {
-            RouterApiControllerState::Handshaking => match Response::parse(response) {
-                Some(Response::Hello {
-                    version: Ok(version),
-                }) => {
-                    tracing::trace!(
-                        target: LOG_TARGET,
-                        %version,
-                        "router api handshake done",
-                    );
-                    self.state = RouterApiControllerState::Handshaked;
+            RouterApiControllerState::Handshaking =>
+                match Response::parse_with(response, lenient) {
+                    Some(Response::Hello {
+                        version: Ok(version),
+                        implementation,
+                        message,
+                    }) => {
+                        tracing::trace!(
+                            target: LOG_TARGET,
+                            %version,
+                            ?implementation,
+                            ?message,
+                            "router api handshake done",
+                        );
+                        self.router_info = Some(RouterInfo {
+                            version: version.to_string(),
+                            implementation: implementation.map(ToString::to_string),
+                        });
+                        self.state = RouterApiControllerState::Handshaked;
 
-                    Ok(())
-                }
-                Some(Response::Hello {
-                    version: Err(error),
-                }) => return Err(ProtocolError::Router(error)),
-                None => {
-                    tracing::warn!(
-                        target: LOG_TARGET,
-                        ?response,
-                        "invalid response from router for `HELLO`",
-                    );
-                    return Err(ProtocolError::InvalidMessage);
-                }
-                Some(response) => {
-                    tracing::warn!(
-                        target: LOG_TARGET,
-                        ?response,
-                        "unexpected response from router for `HELLO`",
-                    );
-                    return Err(ProtocolError::InvalidState);
-                }
-            },
-            RouterApiControllerState::AwaitingLookupResponse => match Response::parse(response) {
-                Some(Response::NamingLookup {
-                    result: Ok(destination),
-                }) => {
-                    tracing::trace!(
-                        target: LOG_TARGET,
-                        "destination found",
-                    );
+                        Ok(())
+                    }
+                    Some(Response::Hello {
+                        version: Err(error),
+                        message,
+                        ..
+                    }) => {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            ?error,
+                            ?message,
+                            "router returned an error",
+                        );
+                        Err(ProtocolError::Router(error))
+                    }
+                    None => {
+                        tracing::warn!(
+                            target: LOG_TARGET,
+                            response = %RedactedLine(response),
+                            "invalid response from router for `HELLO`",
+                        );
+                        Err(ProtocolError::InvalidMessage)
+                    }
+                    Some(_) => {
+                        tracing::warn!(
+                            target: LOG_TARGET,
+                            response = %RedactedLine(response),
+                            "unexpected response from router for `HELLO`",
+                        );
+                        Err(ProtocolError::InvalidState)
+                    }
+                },
+            RouterApiControllerState::AwaitingLookupResponse =>
+                match Response::parse_with(response, lenient) {
+                    Some(Response::NamingLookup {
+                        result: Ok(destination),
+                        options,
+                        message,
+                    }) => {
+                        tracing::trace!(
+                            target: LOG_TARGET,
+                            num_options = options.len(),
+                            ?message,
+                            "destination found",
+                        );
 
-                    self.state = RouterApiControllerState::LookupSucceeded { destination };
-                    Ok(())
-                }
-                Some(Response::NamingLookup { result: Err(error) }) =>
-                    return Err(ProtocolError::Router(error)),
-                None => {
-                    tracing::warn!(
-                        target: LOG_TARGET,
-                        ?response,
-                        "invalid response from router for `NAMING LOOKUP`",
-                    );
-                    return Err(ProtocolError::InvalidMessage);
-                }
-                Some(response) => {
-                    tracing::warn!(
-                        target: LOG_TARGET,
-                        ?response,
-                        "unexpected response from router for `NAMING LOOKUP`",
-                    );
-                    return Err(ProtocolError::InvalidState);
-                }
-            },
-            RouterApiControllerState::AwaitingDestinationResponse =>
-                match Response::parse(response) {
+                        self.state = RouterApiControllerState::LookupSucceeded {
+                            destination: destination.to_string(),
+                            options: options
+                                .into_iter()
+                                .map(|(key, value)| (key.to_string(), value.to_string()))
+                                .collect(),
+                        };
+                        Ok(())
+                    }
+                    Some(Response::NamingLookup {
+                        result: Err(error),
+                        message,
+                        ..
+                    }) => {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            ?error,
+                            ?message,
+                            "router returned an error",
+                        );
+                        Err(ProtocolError::Router(error))
+                    }
+                    None => {
+                        tracing::warn!(
+                            target: LOG_TARGET,
+                            response = %RedactedLine(response),
+                            "invalid response from router for `NAMING LOOKUP`",
+                        );
+                        Err(ProtocolError::InvalidMessage)
+                    }
+                    Some(_) => {
+                        tracing::warn!(
+                            target: LOG_TARGET,
+                            response = %RedactedLine(response),
+                            "unexpected response from router for `NAMING LOOKUP`",
+                        );
+                        Err(ProtocolError::InvalidState)
+                    }
+                },
+            RouterApiControllerState::AwaitingDestinationResponse => {
+                match Response::parse_with(response, lenient) {
                     Some(Response::DestinationGeneration {
                         destination,
                         private_key,
+                        ..
                     }) => {
                         tracing::trace!(
                             target: LOG_TARGET,
@@ -232,28 +465,72 @@ impl RouterApiController {
                         );
 
                         self.state = RouterApiControllerState::DestinationGenerated {
-                            destination,
-                            private_key,
+                            destination: destination.to_string(),
+                            private_key: private_key.to_string(),
                         };
                         Ok(())
                     }
                     None => {
                         tracing::warn!(
                             target: LOG_TARGET,
-                            ?response,
+                            response = %RedactedLine(response),
                             "invalid response from router for `DEST GENERATE`",
                         );
-                        return Err(ProtocolError::InvalidMessage);
+                        Err(ProtocolError::InvalidMessage)
                     }
-                    Some(response) => {
+                    Some(_) => {
                         tracing::warn!(
                             target: LOG_TARGET,
-                            ?response,
+                            response = %RedactedLine(response),
                             "unexpected response from router for `DEST GENERATE`",
                         );
-                        return Err(ProtocolError::InvalidState);
+                        Err(ProtocolError::InvalidState)
                     }
-                },
+                }
+            }
+            RouterApiControllerState::Quitting => match Response::parse_with(response, lenient) {
+                Some(Response::Quit {
+                    result: Ok(()),
+                    message,
+                }) => {
+                    tracing::trace!(
+                        target: LOG_TARGET,
+                        ?message,
+                        "router api connection closed",
+                    );
+                    self.state = RouterApiControllerState::Closed;
+
+                    Ok(())
+                }
+                Some(Response::Quit {
+                    result: Err(error),
+                    message,
+                }) => {
+                    tracing::debug!(
+                        target: LOG_TARGET,
+                        ?error,
+                        ?message,
+                        "router returned an error",
+                    );
+                    Err(ProtocolError::Router(error))
+                }
+                None => {
+                    tracing::warn!(
+                        target: LOG_TARGET,
+                        response = %RedactedLine(response),
+                        "invalid response from router for `QUIT`",
+                    );
+                    Err(ProtocolError::InvalidMessage)
+                }
+                Some(_) => {
+                    tracing::warn!(
+                        target: LOG_TARGET,
+                        response = %RedactedLine(response),
+                        "unexpected response from router for `QUIT`",
+                    );
+                    Err(ProtocolError::InvalidState)
+                }
+            },
             state => {
                 tracing::warn!(
                     target: LOG_TARGET,
@@ -268,16 +545,33 @@ impl RouterApiController {
     }
 
     /// Get destination of the hostname.
+    ///
+    /// The connection stays handshaked and can be closed with [`RouterApiController::quit()`].
     pub fn destination(&mut self) -> String {
-        match std::mem::replace(&mut self.state, RouterApiControllerState::Uninitialized) {
-            RouterApiControllerState::LookupSucceeded { destination } => destination,
+        self.destination_with_options().0
+    }
+
+    /// Get destination of the hostname and the options returned for it.
+    ///
+    /// The options are empty unless the lookup was made with
+    /// [`RouterApiController::lookup_name_with_options()`].
+    ///
+    /// The connection stays handshaked and can be closed with [`RouterApiController::quit()`].
+    pub fn destination_with_options(&mut self) -> (String, HashMap<String, String>) {
+        match std::mem::replace(&mut self.state, RouterApiControllerState::Handshaked) {
+            RouterApiControllerState::LookupSucceeded {
+                destination,
+                options,
+            } => (destination, options),
             _ => panic!("invalid state"),
         }
     }
 
     /// Get generated destination and private key.
+    ///
+    /// The connection stays handshaked and can be closed with [`RouterApiController::quit()`].
     pub fn generated_destination(&mut self) -> (String, String) {
-        match std::mem::replace(&mut self.state, RouterApiControllerState::Uninitialized) {
+        match std::mem::replace(&mut self.state, RouterApiControllerState::Handshaked) {
             RouterApiControllerState::DestinationGenerated {
                 destination,
                 private_key,
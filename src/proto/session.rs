@@ -17,8 +17,19 @@
 // DEALINGS IN THE SOFTWARE.
 
 use crate::{
-    error::ProtocolError, options::SessionOptions, proto::parser::Response,
-    style::private::SessionParameters, DestinationKind,
+    address::public_destination,
+    error::ProtocolError,
+    options::{LookupCredentials, SessionOptions, StreamOptions},
+    proto::{
+        command::{
+            Command, CommandWriter, Hello, Quit, SessionCreate, StreamAccept, StreamConnect,
+            StreamForward,
+        },
+        parser::Response,
+    },
+    redaction::{RedactedLine, RedactedValue},
+    router_info::RouterInfo,
+    tracing, DestinationKind,
 };
 
 /// Logging target for the file.
@@ -35,6 +46,11 @@ enum StreamKind {
 
     /// `STREAM FORWARD` has been sent.
     Forward,
+
+    /// `STREAM CONNECT`/`STREAM ACCEPT` with `SILENT=true` has been sent.
+    ///
+    /// The router doesn't reply to silent commands.
+    Silent,
 }
 
 /// Virtual stream state.
@@ -46,11 +62,17 @@ enum StreamState {
     /// Stream is being handshaked.
     Handshaking,
 
+    /// Stream handshake and `STREAM CONNECT`/`STREAM ACCEPT` have been pipelined.
+    HandshakingPipelined(StreamKind),
+
     /// Stream has been handshaked.
     Handshaked,
 
     /// `STREAM CONNECT`/`STREAM ACCEPT` is pending.
     Pending(StreamKind),
+
+    /// Stream state has been poisoned.
+    Poisoned,
 }
 
 /// Session state.
@@ -62,6 +84,9 @@ enum SessionState {
     /// Handshake has been sent to router.
     Handshaking,
 
+    /// Handshake and `SESSION CREATE` have been pipelined.
+    HandshakingPipelined,
+
     /// Session has been handshaked.
     Handshaked,
 
@@ -72,15 +97,46 @@ enum SessionState {
     Active {
         /// Created destination.
         destination: String,
+    },
+
+    /// `QUIT` has been sent.
+    Quitting {
+        /// Created destination.
+        destination: String,
+    },
 
-        /// Stream state
-        stream_state: StreamState,
+    /// Router has replied to `QUIT` and the session is closed.
+    Closed {
+        /// Created destination.
+        destination: String,
     },
 
     /// Session state has been poisoned.
     Poisoned,
 }
 
+/// Style-specific parameters of `SESSION CREATE`.
+pub struct SessionParameters {
+    /// Session style.
+    pub(crate) style: String,
+
+    /// Session options.
+    pub(crate) options: Vec<(String, String)>,
+}
+
+impl SessionParameters {
+    /// Create new [`SessionParameters`] for session `style`, such as `STREAM` or `DATAGRAM`.
+    ///
+    /// `options` are included in `SESSION CREATE` in addition to the options derived from
+    /// [`SessionOptions`].
+    pub fn new(style: impl Into<String>, options: Vec<(String, String)>) -> Self {
+        Self {
+            style: style.into(),
+            options,
+        }
+    }
+}
+
 /// State machine for SAMv3 virtual streams.
 #[derive(Clone)]
 pub struct SessionController {
@@ -89,6 +145,19 @@ pub struct SessionController {
 
     /// Session state.
     state: SessionState,
+
+    /// Command writer.
+    writer: CommandWriter,
+
+    /// Router details reported during the session handshake.
+    router_info: Option<RouterInfo>,
+
+    /// Controller for the virtual streams opened with the stream methods of the session
+    /// controller, created when the session becomes active.
+    stream: Option<StreamController>,
+
+    /// Destination of the session without its private keys, set when the session becomes active.
+    public_destination: Option<String>,
 }
 
 impl SessionController {
@@ -97,11 +166,105 @@ impl SessionController {
         Ok(Self {
             options,
             state: SessionState::Uninitialized,
+            writer: CommandWriter::new(),
+            router_info: None,
+            stream: None,
+            public_destination: None,
         })
     }
 
+    /// Should i2pd deviations be accommodated.
+    fn i2pd(&self) -> bool {
+        self.options.compatibility.i2pd(self.router_info.as_ref())
+    }
+
+    /// Get SAM and I2CP options included in `SESSION CREATE`.
+    fn session_options(&self) -> Vec<(String, String)> {
+        let mut options = Vec::from_iter([
+            (
+                "inbound.length".to_string(),
+                self.options.inbound_len.to_string(),
+            ),
+            (
+                "inbound.quantity".to_string(),
+                self.options.inbound_quantity.to_string(),
+            ),
+            (
+                "outbound.length".to_string(),
+                self.options.outbound_len.to_string(),
+            ),
+            (
+                "outbound.quantity".to_string(),
+                self.options.outbound_quantity.to_string(),
+            ),
+        ]);
+
+        // zero variance is the default of the routers so it is not sent
+        for (key, variance) in [
+            ("inbound.lengthVariance", self.options.inbound_len_variance),
+            (
+                "outbound.lengthVariance",
+                self.options.outbound_len_variance,
+            ),
+        ] {
+            if variance != 0 {
+                options.push((key.to_string(), variance.to_string()));
+            }
+        }
+
+        if !self.options.publish {
+            options.push(("i2cp.dontPublishLeaseSet".to_string(), "true".to_string()));
+        }
+
+        if let Some(reduce) = &self.options.reduce_on_idle {
+            options.extend([
+                ("i2cp.reduceOnIdle".to_string(), "true".to_string()),
+                (
+                    "i2cp.reduceIdleTime".to_string(),
+                    reduce.idle_time.as_millis().to_string(),
+                ),
+                (
+                    "i2cp.reduceQuantity".to_string(),
+                    reduce.quantity.to_string(),
+                ),
+            ]);
+        }
+
+        if let Some(idle_time) = &self.options.close_on_idle {
+            options.extend([
+                ("i2cp.closeOnIdle".to_string(), "true".to_string()),
+                (
+                    "i2cp.closeIdleTime".to_string(),
+                    idle_time.as_millis().to_string(),
+                ),
+            ]);
+        }
+
+        // `SIGNATURE_TYPE` was introduced in SAMv3.1, older routers use their default type
+        if self.router_info.as_ref().is_none_or(|info| info.supports(3, 1)) {
+            options.push((
+                "SIGNATURE_TYPE".to_string(),
+                self.options.signature_type.to_string(),
+            ));
+        }
+
+        // i2pd generates transient destinations with ElGamal keys unless told otherwise
+        if self.i2pd() && self.options.destination == DestinationKind::Transient {
+            options.push(("CRYPTO_TYPE".to_string(), "4".to_string()));
+        }
+        options.push(("i2cp.leaseSetEncType".to_string(), "4".to_string()));
+
+        options.extend(self.options.lease_set_type.i2cp_options());
+
+        if let Some(auth) = &self.options.lease_set_auth {
+            options.extend(auth.i2cp_options());
+        }
+
+        options
+    }
+
     /// Initialize new session by handshaking with the router.
-    pub fn handshake_session(&mut self) -> Result<Vec<u8>, ProtocolError> {
+    pub fn handshake_session(&mut self) -> Result<&[u8], ProtocolError> {
         match std::mem::replace(&mut self.state, SessionState::Poisoned) {
             SessionState::Uninitialized => {
                 tracing::trace!(
@@ -111,7 +274,11 @@ impl SessionController {
                 );
                 self.state = SessionState::Handshaking;
 
-                Ok(String::from("HELLO VERSION\n").into_bytes())
+                Ok(Hello {
+                    min_version: self.options.min_version.as_deref(),
+                    max_version: self.options.max_version.as_deref(),
+                }
+                .write(&mut self.writer))
             }
             state => {
                 tracing::warn!(
@@ -127,45 +294,59 @@ impl SessionController {
     }
 
     /// Create new session new session with either transient or persistent destination.
+    ///
+    /// The destination, tunnel and lease set options of [`SessionOptions`] are included for all
+    /// session styles, including `PRIMARY`. `PRIMARY` sessions were introduced in SAMv3.3 and
+    /// creating one fails with [`ProtocolError::UnsupportedByRouter`] if the router reported an
+    /// older version. The same applies to sessions with
+    /// [`SessionOptions::ephemeral_ports`], as `FROM_PORT` was introduced in SAMv3.2.
     pub fn create_session(
         &mut self,
         parameters: SessionParameters,
-    ) -> Result<Vec<u8>, ProtocolError> {
+    ) -> Result<&[u8], ProtocolError> {
+        if let (Some(info), "PRIMARY" | "MASTER") = (&self.router_info, parameters.style.as_str()) {
+            info.require("PRIMARY", "3.3")?;
+        }
+
+        if let (Some(info), Some(_)) = (&self.router_info, &self.options.ephemeral_ports) {
+            info.require("FROM_PORT", "3.2")?;
+        }
+
         match std::mem::replace(&mut self.state, SessionState::Poisoned) {
-            SessionState::Handshaked => {
+            state @ (SessionState::Handshaked | SessionState::Handshaking)
+                if state == SessionState::Handshaked || self.options.pipeline_handshake =>
+            {
                 tracing::trace!(
                     target: LOG_TARGET,
                     nickname = %self.options.nickname,
                     destination = ?self.options.destination,
+                    pipelined = ?(state == SessionState::Handshaking),
                     "create new session",
                 );
-                self.state = SessionState::SessionCreatePending;
-
-                let mut command = format!(
-                    "SESSION CREATE STYLE={} ID={} ",
-                    parameters.style, self.options.nickname
-                );
 
-                for (key, value) in parameters.options {
-                    command += format!("{key}={value} ").as_str();
-                }
-
-                match &self.options.destination {
-                    DestinationKind::Transient => {
-                        command += "DESTINATION=TRANSIENT ";
+                self.state = match state {
+                    SessionState::Handshaking => {
+                        self.writer.pipeline();
+                        SessionState::HandshakingPipelined
                     }
-                    DestinationKind::Persistent { private_key } => {
-                        command += format!("DESTINATION={private_key} ").as_str();
-                    }
-                }
+                    _ => SessionState::SessionCreatePending,
+                };
 
-                if !self.options.publish {
-                    command += "i2cp.dontPublishLeaseSet=true ";
-                }
+                let destination = match &self.options.destination {
+                    DestinationKind::Transient => "TRANSIENT",
+                    DestinationKind::Persistent { private_key } => private_key.as_str(),
+                };
 
-                command += "SIGNATURE_TYPE=7 i2cp.leaseSetEncType=4\n";
+                let mut options = parameters.options;
+                options.extend(self.session_options());
 
-                Ok(command.into_bytes())
+                Ok(SessionCreate {
+                    style: &parameters.style,
+                    id: &self.options.nickname,
+                    destination,
+                    options: &options,
+                }
+                .write(&mut self.writer))
             }
             state => {
                 tracing::warn!(
@@ -180,67 +361,65 @@ impl SessionController {
         }
     }
 
-    /// Handshake stream, either inbound or outbound.
-    pub fn handshake_stream(&mut self) -> Result<Vec<u8>, ProtocolError> {
-        match std::mem::replace(&mut self.state, SessionState::Poisoned) {
-            SessionState::Active {
-                destination,
-                stream_state: StreamState::Uninitialized,
-            } => {
-                tracing::trace!(
-                    target: LOG_TARGET,
-                    nickname = %self.options.nickname,
-                    "send handshake for stream",
-                );
-                self.state = SessionState::Active {
-                    destination,
-                    stream_state: StreamState::Handshaking,
-                };
-
-                Ok(String::from("HELLO VERSION\n").into_bytes())
-            }
+    /// Get a new [`StreamController`] for a control connection of the session.
+    ///
+    /// Each control connection of a virtual stream needs a controller of its own. Stream
+    /// controllers are independent of each other and of the session controller, so, e.g.,
+    /// `STREAM ACCEPT` can be pending on one connection while `STREAM CONNECT`s are issued on
+    /// others.
+    ///
+    /// Fails if the session is not active.
+    pub fn stream_controller(&self) -> Result<StreamController, ProtocolError> {
+        match &self.state {
+            SessionState::Active { .. } => Ok(StreamController::new(
+                self.options.clone(),
+                self.router_info.clone(),
+            )),
             state => {
                 tracing::warn!(
                     target: LOG_TARGET,
                     ?state,
-                    "cannot create session, invalid state",
+                    "cannot create stream controller, invalid state",
                 );
 
-                debug_assert!(false);
                 Err(ProtocolError::InvalidState)
             }
         }
     }
 
-    /// Open virtual stream to `destination`.
-    pub fn create_stream(&mut self, remote_destination: &str) -> Result<Vec<u8>, ProtocolError> {
+    /// Close the session.
+    ///
+    /// `QUIT` is sent over the control connection of the session and the router closes the
+    /// session once it has acknowledged the command, the connection may also be closed without
+    /// an acknowledgement. `QUIT` was introduced in SAMv3.2 and it fails with
+    /// [`ProtocolError::UnsupportedByRouter`] if the router reported an older version.
+    ///
+    /// Fails if the session is not active.
+    pub fn quit(&mut self) -> Result<&[u8], ProtocolError> {
         match std::mem::replace(&mut self.state, SessionState::Poisoned) {
-            SessionState::Active {
-                destination,
-                stream_state: StreamState::Handshaked,
-            } => {
-                tracing::info!(
+            SessionState::Active { destination } => {
+                let supported =
+                    self.router_info.as_ref().map_or(Ok(()), |info| info.require("QUIT", "3.2"));
+
+                if let Err(error) = supported {
+                    self.state = SessionState::Active { destination };
+                    return Err(error);
+                }
+
+                tracing::debug!(
                     target: LOG_TARGET,
                     nickname = %self.options.nickname,
-                    remote_destination = %format!("{}...", &destination[..10]),
-                    "open stream to remote destination",
+                    "close session",
                 );
-                self.state = SessionState::Active {
-                    destination,
-                    stream_state: StreamState::Pending(StreamKind::Connect),
-                };
+                self.state = SessionState::Quitting { destination };
 
-                Ok(format!(
-                    "STREAM CONNECT ID={} DESTINATION={} SILENT=false\n",
-                    self.options.nickname, remote_destination
-                )
-                .into_bytes())
+                Ok(Quit.write(&mut self.writer))
             }
             state => {
                 tracing::warn!(
                     target: LOG_TARGET,
                     ?state,
-                    "cannot create session, invalid state",
+                    "cannot close session, invalid state",
                 );
 
                 debug_assert!(false);
@@ -249,33 +428,15 @@ impl SessionController {
         }
     }
 
-    /// Start accepting a new virtual stream.
-    pub fn accept_stream(&mut self) -> Result<Vec<u8>, ProtocolError> {
-        match std::mem::replace(&mut self.state, SessionState::Poisoned) {
-            SessionState::Active {
-                destination,
-                stream_state: StreamState::Handshaked,
-            } => {
-                tracing::trace!(
-                    target: LOG_TARGET,
-                    nickname = %self.options.nickname,
-                    "start listening for virtual stream",
-                );
-                self.state = SessionState::Active {
-                    destination,
-                    stream_state: StreamState::Pending(StreamKind::Accept),
-                };
-
-                Ok(
-                    format!("STREAM ACCEPT ID={} SILENT=false\n", self.options.nickname)
-                        .into_bytes(),
-                )
-            }
-            state => {
+    /// Get the controller used by the stream methods of the session controller.
+    fn stream(&mut self) -> Result<&mut StreamController, ProtocolError> {
+        match &mut self.stream {
+            Some(stream) => Ok(stream),
+            None => {
                 tracing::warn!(
                     target: LOG_TARGET,
-                    ?state,
-                    "cannot create session, invalid state",
+                    state = ?self.state,
+                    "cannot use stream, invalid state",
                 );
 
                 debug_assert!(false);
@@ -284,205 +445,306 @@ impl SessionController {
         }
     }
 
+    /// Handshake stream, either inbound or outbound.
+    ///
+    /// The stream methods of [`SessionController`] drive one virtual stream at a time, see
+    /// [`SessionController::stream_controller()`] for driving several streams concurrently.
+    pub fn handshake_stream(&mut self) -> Result<&[u8], ProtocolError> {
+        self.stream()?.handshake_stream()
+    }
+
+    /// Use a control connection that has already been handshaked for the next stream.
+    ///
+    /// See [`StreamController::resume_stream()`].
+    pub fn resume_stream(&mut self) -> Result<(), ProtocolError> {
+        self.stream()?.resume_stream()
+    }
+
+    /// Reset stream state so a new stream can be handshaked.
+    ///
+    /// See [`StreamController::reset_stream()`].
+    pub fn reset_stream(&mut self) -> Result<(), ProtocolError> {
+        self.stream()?.reset_stream()
+    }
+
+    /// Open virtual stream to `destination`.
+    pub fn create_stream(&mut self, remote_destination: &str) -> Result<&[u8], ProtocolError> {
+        self.stream()?.create_stream(remote_destination)
+    }
+
+    /// Open virtual stream to `port` of `destination`.
+    pub fn create_stream_to_port(
+        &mut self,
+        remote_destination: &str,
+        port: u16,
+    ) -> Result<&[u8], ProtocolError> {
+        self.stream()?.create_stream_to_port(remote_destination, port)
+    }
+
+    /// Open virtual stream to encrypted `destination`, optionally to a specific port.
+    ///
+    /// See [`StreamController::create_stream_with_credentials()`].
+    pub fn create_stream_with_credentials(
+        &mut self,
+        remote_destination: &str,
+        to_port: Option<u16>,
+        credentials: &LookupCredentials,
+    ) -> Result<&[u8], ProtocolError> {
+        self.stream()?
+            .create_stream_with_credentials(remote_destination, to_port, credentials)
+    }
+
+    /// Open virtual stream to `destination` with stream `options`, optionally to a specific port.
+    ///
+    /// See [`StreamController::create_stream_with_options()`].
+    pub fn create_stream_with_options(
+        &mut self,
+        remote_destination: &str,
+        to_port: Option<u16>,
+        options: &StreamOptions,
+    ) -> Result<&[u8], ProtocolError> {
+        self.stream()?.create_stream_with_options(remote_destination, to_port, options)
+    }
+
+    /// Start accepting a new virtual stream.
+    pub fn accept_stream(&mut self) -> Result<&[u8], ProtocolError> {
+        self.stream()?.accept_stream()
+    }
+
+    /// Start accepting a new virtual stream with stream `options`.
+    ///
+    /// See [`StreamController::accept_stream_with_options()`].
+    pub fn accept_stream_with_options(
+        &mut self,
+        options: &StreamOptions,
+    ) -> Result<&[u8], ProtocolError> {
+        self.stream()?.accept_stream_with_options(options)
+    }
+
     /// Forward inbound virtual streams to a TCP listener listening to `port`.
-    pub fn forward_stream(&mut self, port: u16) -> Result<Vec<u8>, ProtocolError> {
-        match std::mem::replace(&mut self.state, SessionState::Poisoned) {
-            SessionState::Active {
-                destination,
-                stream_state: StreamState::Handshaked,
-            } => {
-                tracing::trace!(
-                    target: LOG_TARGET,
-                    nickname = %self.options.nickname,
-                    ?port,
-                    "forward incoming connections",
-                );
-                self.state = SessionState::Active {
-                    destination,
-                    stream_state: StreamState::Pending(StreamKind::Forward),
-                };
+    pub fn forward_stream(&mut self, port: u16) -> Result<&[u8], ProtocolError> {
+        self.stream()?.forward_stream(port)
+    }
 
-                Ok(format!(
-                    "STREAM FORWARD ID={} PORT={port} SILENT={}\n",
-                    self.options.nickname,
-                    self.options.silent_forward.to_string(),
-                )
-                .into_bytes())
-            }
-            state => {
+    /// Describe the state of the controller for diagnostics.
+    ///
+    /// The destination of an active session is left out.
+    fn describe_state(&self) -> String {
+        match &self.state {
+            SessionState::Active { .. } => "Active".to_string(),
+            SessionState::Quitting { .. } => "Quitting".to_string(),
+            SessionState::Closed { .. } => "Closed".to_string(),
+            state => format!("{state:?}"),
+        }
+    }
+
+    /// Handle response from router.
+    ///
+    /// Messages which the router may send at any time, such as `PING`, are ignored and they don't
+    /// affect the state of the controller. Once the session is active, responses are passed to the
+    /// controller of its streams, except for the reply to `QUIT`.
+    pub fn handle_response(&mut self, response: &str) -> Result<(), ProtocolError> {
+        let quitting = matches!(self.state, SessionState::Quitting { .. });
+
+        if let (false, Some(stream)) = (quitting, &mut self.stream) {
+            return stream.handle_response(response);
+        }
+
+        if let Some(response) = Response::parse_unsolicited(response) {
+            tracing::debug!(
+                target: LOG_TARGET,
+                nickname = %self.options.nickname,
+                ?response,
+                "ignoring unsolicited message from router",
+            );
+            return Ok(());
+        }
+
+        if let (Some(threshold), Some(elapsed)) =
+            (self.options.slow_operation_threshold, self.writer.elapsed())
+        {
+            if elapsed > threshold {
                 tracing::warn!(
                     target: LOG_TARGET,
-                    ?state,
-                    "cannot create session, invalid state",
+                    nickname = %self.options.nickname,
+                    state = %self.describe_state(),
+                    ?elapsed,
+                    ?threshold,
+                    "slow response from router",
                 );
-
-                debug_assert!(false);
-                Err(ProtocolError::InvalidState)
             }
         }
-    }
 
-    /// Handle response from router.
-    pub fn handle_response(&mut self, response: &str) -> Result<(), ProtocolError> {
-        match std::mem::replace(&mut self.state, SessionState::Poisoned) {
-            SessionState::Handshaking => match Response::parse(response) {
-                Some(Response::Hello {
-                    version: Ok(version),
-                }) => {
-                    tracing::trace!(
-                        target: LOG_TARGET,
-                        nickname = %self.options.nickname,
-                        %version,
-                        "session handshake done",
-                    );
-                    self.state = SessionState::Handshaked;
+        let lenient = self.i2pd();
 
-                    Ok(())
-                }
-                Some(Response::Hello {
-                    version: Err(error),
-                }) => return Err(ProtocolError::Router(error)),
-                None => {
-                    tracing::warn!(
-                        target: LOG_TARGET,
-                        nickname = %self.options.nickname,
-                        ?response,
-                        "invalid response from router session `HELLO`",
-                    );
-                    return Err(ProtocolError::InvalidMessage);
-                }
-                Some(response) => {
-                    tracing::warn!(
-                        nickname = %self.options.nickname,
-                        ?response,
-                        "unexpected response from router session `HELLO`",
-                    );
-                    return Err(ProtocolError::InvalidState);
-                }
-            },
-            SessionState::SessionCreatePending => match Response::parse(response) {
+        match std::mem::replace(&mut self.state, SessionState::Poisoned) {
+            state @ (SessionState::Handshaking | SessionState::HandshakingPipelined) =>
+                match Response::parse_with(response, lenient) {
+                    Some(Response::Hello {
+                        version: Ok(version),
+                        implementation,
+                        message,
+                    }) => {
+                        tracing::trace!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            %version,
+                            ?implementation,
+                            ?message,
+                            "session handshake done",
+                        );
+                        self.router_info = Some(RouterInfo {
+                            version: version.to_string(),
+                            implementation: implementation.map(ToString::to_string),
+                        });
+                        self.state = match state {
+                            SessionState::HandshakingPipelined =>
+                                SessionState::SessionCreatePending,
+                            _ => SessionState::Handshaked,
+                        };
+
+                        Ok(())
+                    }
+                    Some(Response::Hello {
+                        version: Err(error),
+                        message,
+                        ..
+                    }) => {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            ?error,
+                            ?message,
+                            "router returned an error",
+                        );
+                        Err(ProtocolError::Router(error))
+                    }
+                    None => {
+                        tracing::warn!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            response = %RedactedLine(response),
+                            "invalid response from router session `HELLO`",
+                        );
+                        Err(ProtocolError::InvalidMessage)
+                    }
+                    Some(_) => {
+                        tracing::warn!(
+                            nickname = %self.options.nickname,
+                            response = %RedactedLine(response),
+                            "unexpected response from router session `HELLO`",
+                        );
+                        Err(ProtocolError::InvalidState)
+                    }
+                },
+            SessionState::SessionCreatePending => match Response::parse_with(response, lenient) {
                 Some(Response::Session {
                     destination: Ok(destination),
+                    message,
                 }) => {
                     tracing::info!(
                         target: LOG_TARGET,
                         nickname = %self.options.nickname,
+                        ?message,
                         "session created",
                     );
 
+                    // the router is expected to return a valid private key but if it doesn't,
+                    // it's not a usable key either and can be reported as-is
+                    self.public_destination = Some(
+                        public_destination(destination).unwrap_or_else(|| destination.to_string()),
+                    );
                     self.state = SessionState::Active {
-                        destination,
-                        stream_state: StreamState::Uninitialized,
+                        destination: destination.to_string(),
                     };
+                    self.stream = self.stream_controller().ok();
 
                     Ok(())
                 }
                 Some(Response::Session {
                     destination: Err(error),
-                }) => return Err(ProtocolError::Router(error)),
-                None => {
-                    tracing::warn!(
-                        target: LOG_TARGET,
-                        nickname = %self.options.nickname,
-                        ?response,
-                        "invalid response from router `SESSION CREATE`",
-                    );
-                    return Err(ProtocolError::InvalidMessage);
-                }
-                Some(response) => {
-                    tracing::warn!(
-                        nickname = %self.options.nickname,
-                        ?response,
-                        "unexpected response from router to `SESSION CREATE`",
-                    );
-                    return Err(ProtocolError::InvalidState);
-                }
-            },
-            SessionState::Active {
-                destination,
-                stream_state: StreamState::Handshaking,
-            } => match Response::parse(response) {
-                Some(Response::Hello {
-                    version: Ok(version),
+                    message,
                 }) => {
-                    tracing::trace!(
+                    tracing::debug!(
                         target: LOG_TARGET,
                         nickname = %self.options.nickname,
-                        %version,
-                        "stream handshake done",
+                        ?error,
+                        ?message,
+                        "router returned an error",
                     );
-
-                    self.state = SessionState::Active {
-                        destination,
-                        stream_state: StreamState::Handshaked,
-                    };
-
-                    Ok(())
+                    Err(ProtocolError::Router(error))
                 }
-                Some(Response::Hello {
-                    version: Err(error),
-                }) => return Err(ProtocolError::Router(error)),
                 None => {
                     tracing::warn!(
                         target: LOG_TARGET,
                         nickname = %self.options.nickname,
-                        ?response,
-                        "invalid response from router stream `HELLO`",
+                        response = %RedactedLine(response),
+                        "invalid response from router `SESSION CREATE`",
                     );
-                    return Err(ProtocolError::InvalidMessage);
+                    Err(ProtocolError::InvalidMessage)
                 }
-                Some(response) => {
+                Some(_) => {
                     tracing::warn!(
                         nickname = %self.options.nickname,
-                        ?response,
-                        "unexpected response from router stream `HELLO`",
+                        response = %RedactedLine(response),
+                        "unexpected response from router to `SESSION CREATE`",
                     );
-                    return Err(ProtocolError::InvalidState);
+                    Err(ProtocolError::InvalidState)
                 }
             },
-            SessionState::Active {
-                destination,
-                stream_state: StreamState::Pending(direction),
-            } => match Response::parse(response) {
-                Some(Response::Stream { result: Ok(()) }) => {
-                    tracing::info!(
-                        target: LOG_TARGET,
-                        nickname = %self.options.nickname,
-                        ?direction,
-                        "stream status ok",
-                    );
+            SessionState::Quitting { destination } => {
+                let result = match Response::parse_with(response, lenient) {
+                    Some(Response::Quit {
+                        result: Ok(()),
+                        message,
+                    }) => {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            ?message,
+                            "session closed",
+                        );
+                        Ok(())
+                    }
+                    Some(Response::Quit {
+                        result: Err(error),
+                        message,
+                    }) => {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            ?error,
+                            ?message,
+                            "router returned an error",
+                        );
+                        Err(ProtocolError::Router(error))
+                    }
+                    None => {
+                        tracing::warn!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            response = %RedactedLine(response),
+                            "invalid response from router for `QUIT`",
+                        );
+                        Err(ProtocolError::InvalidMessage)
+                    }
+                    Some(_) => {
+                        tracing::warn!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            response = %RedactedLine(response),
+                            "unexpected response from router for `QUIT`",
+                        );
+                        Err(ProtocolError::InvalidState)
+                    }
+                };
 
-                    // after the stream is opened/accepted, the stream is handed off
-                    // to user and the stream state can be reset
-                    self.state = SessionState::Active {
-                        destination,
-                        stream_state: StreamState::Uninitialized,
-                    };
+                // the connection is closed after `QUIT` so the session is closed even if the
+                // router didn't acknowledge the command
+                self.state = SessionState::Closed { destination };
 
-                    Ok(())
-                }
-                Some(Response::Stream { result: Err(error) }) =>
-                    return Err(ProtocolError::Router(error)),
-                None => {
-                    tracing::warn!(
-                        target: LOG_TARGET,
-                        nickname = %self.options.nickname,
-                        ?response,
-                        ?direction,
-                        "invalid response from router to `STREAM CREATE`",
-                    );
-                    return Err(ProtocolError::InvalidMessage);
-                }
-                Some(response) => {
-                    tracing::warn!(
-                        nickname = %self.options.nickname,
-                        ?response,
-                        ?direction,
-                        "unexpected response from router to `STREAM CREATE`",
-                    );
-                    return Err(ProtocolError::InvalidState);
-                }
-            },
+                result
+            }
             state => {
                 tracing::warn!(
                     target: LOG_TARGET,
@@ -496,21 +758,521 @@ impl SessionController {
         }
     }
 
+    /// Get router details reported during the session handshake.
+    ///
+    /// Returns `None` if the session hasn't been handshaked.
+    pub fn router_info(&self) -> Option<&RouterInfo> {
+        self.router_info.as_ref()
+    }
+
     /// Get reference to [`SessionController`]'s destination.
     ///
     /// Panics if called before the session is active.
     pub fn destination(&self) -> &str {
-        let SessionState::Active { destination, .. } = &self.state else {
+        let (SessionState::Active { destination }
+        | SessionState::Quitting { destination }
+        | SessionState::Closed { destination }) = &self.state
+        else {
             panic!("invalid state");
         };
 
-        &destination
+        destination
+    }
+
+    /// Get reference to [`SessionController`]'s destination without its private keys.
+    ///
+    /// Unlike [`SessionController::destination()`], the public destination can be shared with
+    /// peers.
+    ///
+    /// Panics if called before the session is active.
+    pub fn public_destination(&self) -> &str {
+        self.public_destination.as_deref().expect("session to be active")
     }
 }
 
-#[cfg(test)]
+/// State machine for the control connection of a SAMv3 virtual stream.
+///
+/// Created with [`SessionController::stream_controller()`] once the session is active. The
+/// controller drives one control connection at a time: `HELLO`, followed by `STREAM CONNECT`,
+/// `STREAM ACCEPT` or `STREAM FORWARD`, after which the connection is handed off as a virtual
+/// stream and the controller can be used for the next control connection.
+#[derive(Clone)]
+pub struct StreamController {
+    /// Session options.
+    options: SessionOptions,
+
+    /// Stream state.
+    state: StreamState,
+
+    /// Command writer.
+    writer: CommandWriter,
+
+    /// Router details reported during the session handshake.
+    router_info: Option<RouterInfo>,
+}
+
+impl StreamController {
+    /// Create new [`StreamController`] for an active session.
+    fn new(options: SessionOptions, router_info: Option<RouterInfo>) -> Self {
+        Self {
+            options,
+            state: StreamState::Uninitialized,
+            writer: CommandWriter::new(),
+            router_info,
+        }
+    }
+
+    /// Should i2pd deviations be accommodated.
+    fn i2pd(&self) -> bool {
+        self.options.compatibility.i2pd(self.router_info.as_ref())
+    }
+
+    /// Get the state of a stream after `kind` has been sent.
+    ///
+    /// If the stream is still handshaking, the handshake is pipelined with the command. Silent
+    /// commands have no reply so the stream is done once the command has been sent.
+    fn pending_stream_state(&mut self, stream_state: StreamState, kind: StreamKind) -> StreamState {
+        match stream_state {
+            StreamState::Handshaking => {
+                self.writer.pipeline();
+                StreamState::HandshakingPipelined(kind)
+            }
+            _ if kind == StreamKind::Silent => StreamState::Uninitialized,
+            _ => StreamState::Pending(kind),
+        }
+    }
+
+    /// Handshake stream, either inbound or outbound.
+    pub fn handshake_stream(&mut self) -> Result<&[u8], ProtocolError> {
+        match std::mem::replace(&mut self.state, StreamState::Poisoned) {
+            StreamState::Uninitialized => {
+                tracing::trace!(
+                    target: LOG_TARGET,
+                    nickname = %self.options.nickname,
+                    "send handshake for stream",
+                );
+                self.state = StreamState::Handshaking;
+
+                Ok(Hello {
+                    min_version: self.options.min_version.as_deref(),
+                    max_version: self.options.max_version.as_deref(),
+                }
+                .write(&mut self.writer))
+            }
+            state => {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    ?state,
+                    "cannot create session, invalid state",
+                );
+
+                debug_assert!(false);
+                Err(ProtocolError::InvalidState)
+            }
+        }
+    }
+
+    /// Use a control connection that has already been handshaked for the next stream.
+    ///
+    /// The handshake was done earlier with [`StreamController::handshake_stream()`], after which
+    /// the stream state was reset with [`StreamController::reset_stream()`].
+    pub fn resume_stream(&mut self) -> Result<(), ProtocolError> {
+        match std::mem::replace(&mut self.state, StreamState::Poisoned) {
+            StreamState::Uninitialized => {
+                self.state = StreamState::Handshaked;
+
+                Ok(())
+            }
+            state => {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    ?state,
+                    "cannot resume stream, invalid state",
+                );
+
+                debug_assert!(false);
+                Err(ProtocolError::InvalidState)
+            }
+        }
+    }
+
+    /// Reset stream state so a new stream can be handshaked.
+    ///
+    /// Called when the control connection of a stream has been set aside or it has failed.
+    pub fn reset_stream(&mut self) -> Result<(), ProtocolError> {
+        self.state = StreamState::Uninitialized;
+
+        Ok(())
+    }
+
+    /// Open virtual stream to `destination`.
+    pub fn create_stream(&mut self, remote_destination: &str) -> Result<&[u8], ProtocolError> {
+        self.connect_stream(remote_destination, None, None, &[], false)
+    }
+
+    /// Open virtual stream to `port` of `destination`.
+    pub fn create_stream_to_port(
+        &mut self,
+        remote_destination: &str,
+        port: u16,
+    ) -> Result<&[u8], ProtocolError> {
+        self.connect_stream(remote_destination, None, Some(port), &[], false)
+    }
+
+    /// Open virtual stream to encrypted `destination`, optionally to a specific port.
+    ///
+    /// `credentials` are used by the router to look up the lease set of `destination`.
+    pub fn create_stream_with_credentials(
+        &mut self,
+        remote_destination: &str,
+        to_port: Option<u16>,
+        credentials: &LookupCredentials,
+    ) -> Result<&[u8], ProtocolError> {
+        self.connect_stream(
+            remote_destination,
+            None,
+            to_port,
+            &credentials.i2cp_options(),
+            false,
+        )
+    }
+
+    /// Open virtual stream to `destination` with stream `options`, optionally to a specific port.
+    ///
+    /// If [`StreamOptions::silent`] is set, the router doesn't reply to `STREAM CONNECT` and the
+    /// stream is ready once the command and, if pipelined, the stream handshake reply have been
+    /// processed. If [`StreamOptions::from_port`] is set, the stream is opened from that port.
+    pub fn create_stream_with_options(
+        &mut self,
+        remote_destination: &str,
+        to_port: Option<u16>,
+        options: &StreamOptions,
+    ) -> Result<&[u8], ProtocolError> {
+        let i2cp_options = options
+            .credentials
+            .as_ref()
+            .map(LookupCredentials::i2cp_options)
+            .unwrap_or_default();
+
+        self.connect_stream(
+            remote_destination,
+            options.from_port,
+            to_port,
+            &i2cp_options,
+            options.silent,
+        )
+    }
+
+    /// Open virtual stream to `destination`, optionally from and to specific ports.
+    fn connect_stream(
+        &mut self,
+        remote_destination: &str,
+        from_port: Option<u16>,
+        to_port: Option<u16>,
+        options: &[(String, String)],
+        silent: bool,
+    ) -> Result<&[u8], ProtocolError> {
+        if let Some(info) = &self.router_info {
+            if from_port.is_some() {
+                info.require("FROM_PORT", "3.2")?;
+            }
+
+            if to_port.is_some() {
+                info.require("TO_PORT", "3.2")?;
+            }
+        }
+
+        match std::mem::replace(&mut self.state, StreamState::Poisoned) {
+            stream_state @ (StreamState::Handshaked | StreamState::Handshaking)
+                if stream_state == StreamState::Handshaked || self.options.pipeline_handshake =>
+            {
+                tracing::info!(
+                    target: LOG_TARGET,
+                    nickname = %self.options.nickname,
+                    remote_destination = %RedactedValue(remote_destination),
+                    ?from_port,
+                    ?to_port,
+                    ?silent,
+                    "open stream to remote destination",
+                );
+                let kind = if silent {
+                    StreamKind::Silent
+                } else {
+                    StreamKind::Connect
+                };
+                self.state = self.pending_stream_state(stream_state, kind);
+
+                Ok(StreamConnect {
+                    id: &self.options.nickname,
+                    destination: remote_destination,
+                    from_port,
+                    to_port,
+                    silent,
+                    options,
+                }
+                .write(&mut self.writer))
+            }
+            state => {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    ?state,
+                    "cannot create session, invalid state",
+                );
+
+                debug_assert!(false);
+                Err(ProtocolError::InvalidState)
+            }
+        }
+    }
+
+    /// Start accepting a new virtual stream.
+    pub fn accept_stream(&mut self) -> Result<&[u8], ProtocolError> {
+        self.accept_stream_with_options(&StreamOptions::default())
+    }
+
+    /// Start accepting a new virtual stream with stream `options`.
+    ///
+    /// If [`StreamOptions::silent`] is set, the router doesn't reply to `STREAM ACCEPT` and the
+    /// remote destination is not sent when a stream is accepted.
+    pub fn accept_stream_with_options(
+        &mut self,
+        options: &StreamOptions,
+    ) -> Result<&[u8], ProtocolError> {
+        match std::mem::replace(&mut self.state, StreamState::Poisoned) {
+            stream_state @ (StreamState::Handshaked | StreamState::Handshaking)
+                if stream_state == StreamState::Handshaked || self.options.pipeline_handshake =>
+            {
+                tracing::trace!(
+                    target: LOG_TARGET,
+                    nickname = %self.options.nickname,
+                    silent = options.silent,
+                    "start listening for virtual stream",
+                );
+                let kind = if options.silent {
+                    StreamKind::Silent
+                } else {
+                    StreamKind::Accept
+                };
+                self.state = self.pending_stream_state(stream_state, kind);
+
+                Ok(StreamAccept {
+                    id: &self.options.nickname,
+                    silent: options.silent,
+                }
+                .write(&mut self.writer))
+            }
+            state => {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    ?state,
+                    "cannot create session, invalid state",
+                );
+
+                debug_assert!(false);
+                Err(ProtocolError::InvalidState)
+            }
+        }
+    }
+
+    /// Forward inbound virtual streams to a TCP listener listening to `port`.
+    pub fn forward_stream(&mut self, port: u16) -> Result<&[u8], ProtocolError> {
+        match std::mem::replace(&mut self.state, StreamState::Poisoned) {
+            stream_state @ (StreamState::Handshaked | StreamState::Handshaking)
+                if stream_state == StreamState::Handshaked || self.options.pipeline_handshake =>
+            {
+                tracing::trace!(
+                    target: LOG_TARGET,
+                    nickname = %self.options.nickname,
+                    ?port,
+                    "forward incoming connections",
+                );
+                self.state = self.pending_stream_state(stream_state, StreamKind::Forward);
+
+                Ok(StreamForward {
+                    id: &self.options.nickname,
+                    port,
+                    silent: self.options.silent_forward,
+                }
+                .write(&mut self.writer))
+            }
+            state => {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    ?state,
+                    "cannot create session, invalid state",
+                );
+
+                debug_assert!(false);
+                Err(ProtocolError::InvalidState)
+            }
+        }
+    }
+
+    /// Handle response from router.
+    ///
+    /// Messages which the router may send at any time, such as `PING`, are ignored and they don't
+    /// affect the state of the controller.
+    pub fn handle_response(&mut self, response: &str) -> Result<(), ProtocolError> {
+        if let Some(response) = Response::parse_unsolicited(response) {
+            tracing::debug!(
+                target: LOG_TARGET,
+                nickname = %self.options.nickname,
+                ?response,
+                "ignoring unsolicited message from router",
+            );
+            return Ok(());
+        }
+
+        if let (Some(threshold), Some(elapsed)) =
+            (self.options.slow_operation_threshold, self.writer.elapsed())
+        {
+            if elapsed > threshold {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    nickname = %self.options.nickname,
+                    state = ?self.state,
+                    ?elapsed,
+                    ?threshold,
+                    "slow response from router",
+                );
+            }
+        }
+
+        let lenient = self.i2pd();
+
+        match std::mem::replace(&mut self.state, StreamState::Poisoned) {
+            stream_state @ (StreamState::Handshaking | StreamState::HandshakingPipelined(_)) =>
+                match Response::parse_with(response, lenient) {
+                    Some(Response::Hello {
+                        version: Ok(version),
+                        message,
+                        ..
+                    }) => {
+                        tracing::trace!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            %version,
+                            ?message,
+                            "stream handshake done",
+                        );
+
+                        self.state = match stream_state {
+                            StreamState::HandshakingPipelined(StreamKind::Silent) =>
+                                StreamState::Uninitialized,
+                            StreamState::HandshakingPipelined(kind) => StreamState::Pending(kind),
+                            _ => StreamState::Handshaked,
+                        };
+
+                        Ok(())
+                    }
+                    Some(Response::Hello {
+                        version: Err(error),
+                        message,
+                        ..
+                    }) => {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            ?error,
+                            ?message,
+                            "router returned an error",
+                        );
+                        Err(ProtocolError::Router(error))
+                    }
+                    None => {
+                        tracing::warn!(
+                            target: LOG_TARGET,
+                            nickname = %self.options.nickname,
+                            response = %RedactedLine(response),
+                            "invalid response from router stream `HELLO`",
+                        );
+                        Err(ProtocolError::InvalidMessage)
+                    }
+                    Some(_) => {
+                        tracing::warn!(
+                            nickname = %self.options.nickname,
+                            response = %RedactedLine(response),
+                            "unexpected response from router stream `HELLO`",
+                        );
+                        Err(ProtocolError::InvalidState)
+                    }
+                },
+            StreamState::Pending(direction) => match Response::parse_with(response, lenient) {
+                Some(Response::Stream {
+                    result: Ok(()),
+                    message,
+                }) => {
+                    tracing::info!(
+                        target: LOG_TARGET,
+                        nickname = %self.options.nickname,
+                        ?direction,
+                        ?message,
+                        "stream status ok",
+                    );
+
+                    // after the stream is opened/accepted, the stream is handed off
+                    // to user and the stream state can be reset
+                    self.state = StreamState::Uninitialized;
+
+                    Ok(())
+                }
+                Some(Response::Stream {
+                    result: Err(error),
+                    message,
+                }) => {
+                    tracing::debug!(
+                        target: LOG_TARGET,
+                        nickname = %self.options.nickname,
+                        ?error,
+                        ?message,
+                        ?direction,
+                        "router returned an error",
+                    );
+                    Err(ProtocolError::Router(error))
+                }
+                None => {
+                    tracing::warn!(
+                        target: LOG_TARGET,
+                        nickname = %self.options.nickname,
+                        response = %RedactedLine(response),
+                        ?direction,
+                        "invalid response from router to `STREAM CREATE`",
+                    );
+                    Err(ProtocolError::InvalidMessage)
+                }
+                Some(_) => {
+                    tracing::warn!(
+                        nickname = %self.options.nickname,
+                        response = %RedactedLine(response),
+                        ?direction,
+                        "unexpected response from router to `STREAM CREATE`",
+                    );
+                    Err(ProtocolError::InvalidState)
+                }
+            },
+            state => {
+                tracing::warn!(
+                    target: LOG_TARGET,
+                    ?state,
+                    "cannot handle response, invalid state",
+                );
+
+                debug_assert!(false);
+                Err(ProtocolError::InvalidState)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{
+        error::I2pError,
+        options::{ReduceOnIdle, RouterCompatibility, SignatureType},
+    };
+
+    use std::time::Duration;
 
     #[test]
     fn open_virtual_stream() {
@@ -520,13 +1282,14 @@ mod tests {
         assert_eq!(controller.state, SessionState::Uninitialized);
         assert_eq!(
             controller.handshake_session(),
-            Ok(String::from("HELLO VERSION\n").into_bytes())
+            Ok("HELLO VERSION\n".as_bytes())
         );
         assert_eq!(controller.state, SessionState::Handshaking);
 
         // handle response
         assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
         assert_eq!(controller.state, SessionState::Handshaked);
+        assert_eq!(controller.router_info().unwrap().version, "3.3");
 
         // create session
         let parameters = SessionParameters {
@@ -534,8 +1297,11 @@ mod tests {
             options: Vec::new(),
         };
         let command = controller.create_session(parameters).unwrap();
-        let command = std::str::from_utf8(&command).unwrap();
+        let command = std::str::from_utf8(command).unwrap();
         assert!(!command.contains("i2cp.dontPublishLeaseSet=true"));
+        assert!(command.contains("inbound.length=3 inbound.quantity=2"));
+        assert!(command.contains("outbound.length=3 outbound.quantity=2"));
+        assert!(!command.contains("i2cp.leaseSetAuthType"));
         assert_eq!(controller.state, SessionState::SessionCreatePending);
 
         // handle response and create virtual stream
@@ -552,47 +1318,35 @@ mod tests {
         // handshake virtual stream
         assert!(controller.handshake_stream().is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Handshaking,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Handshaking
+        );
 
         // handle handshake response
         assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Handshaked,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Handshaked
+        );
 
         // create virtual stream
         assert!(controller.create_stream("destination").is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Pending(StreamKind::Connect),
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Pending(StreamKind::Connect)
+        );
 
         // handle connect response
         assert!(controller.handle_response("STREAM STATUS RESULT=OK\n").is_ok());
 
         // stream state is reset after it has been opened/accepted
-        let SessionState::Active {
-            stream_state: StreamState::Uninitialized,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Uninitialized
+        );
     }
 
     #[test]
@@ -603,7 +1357,7 @@ mod tests {
         assert_eq!(controller.state, SessionState::Uninitialized);
         assert_eq!(
             controller.handshake_session(),
-            Ok(String::from("HELLO VERSION\n").into_bytes())
+            Ok("HELLO VERSION\n".as_bytes())
         );
         assert_eq!(controller.state, SessionState::Handshaking);
 
@@ -617,7 +1371,7 @@ mod tests {
             options: Vec::new(),
         };
         let command = controller.create_session(parameters).unwrap();
-        let command = std::str::from_utf8(&command).unwrap();
+        let command = std::str::from_utf8(command).unwrap();
         assert!(!command.contains("i2cp.dontPublishLeaseSet=true"));
         assert_eq!(controller.state, SessionState::SessionCreatePending);
 
@@ -635,47 +1389,245 @@ mod tests {
         // handshake virtual stream
         assert!(controller.handshake_stream().is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Handshaking,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Handshaking
+        );
 
         // handle handshake response
         assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Handshaked,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Handshaked
+        );
 
         // create virtual stream
         assert!(controller.accept_stream().is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Pending(StreamKind::Accept),
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Pending(StreamKind::Accept)
+        );
 
         // handle connect response
         assert!(controller.handle_response("STREAM STATUS RESULT=OK\n").is_ok());
 
         // stream state is reset after it has been opened/accepted
-        let SessionState::Active {
-            stream_state: StreamState::Uninitialized,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Uninitialized
+        );
+    }
+
+    #[test]
+    fn pipelined_handshake() {
+        let mut controller = SessionController::new(SessionOptions {
+            nickname: "nickname".to_string(),
+            pipeline_handshake: true,
+            ..Default::default()
+        })
+        .unwrap();
+
+        // handshake is sent together with `SESSION CREATE`
+        assert!(controller.handshake_session().is_ok());
+
+        let parameters = SessionParameters {
+            style: "STREAM".to_string(),
+            options: Vec::new(),
         };
+        let command = controller.create_session(parameters).unwrap();
+        let command = std::str::from_utf8(command).unwrap();
+        assert!(command.starts_with("HELLO VERSION\nSESSION CREATE STYLE=STREAM"));
+        assert_eq!(command.matches('\n').count(), 2);
+        assert_eq!(controller.state, SessionState::HandshakingPipelined);
+
+        // handle both responses
+        assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+        assert_eq!(controller.state, SessionState::SessionCreatePending);
+        assert!(controller
+            .handle_response("SESSION STATUS RESULT=OK DESTINATION=I2P_DESTINATION\n")
+            .is_ok());
+        assert_eq!(controller.destination(), "I2P_DESTINATION");
+
+        // handshake is sent together with `STREAM CONNECT`
+        assert!(controller.handshake_stream().is_ok());
+        assert_eq!(
+            controller.create_stream("host.i2p"),
+            Ok(
+                "HELLO VERSION\nSTREAM CONNECT ID=nickname DESTINATION=host.i2p SILENT=false\n"
+                    .as_bytes()
+            )
+        );
+
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::HandshakingPipelined(StreamKind::Connect)
+        );
+
+        // handle both responses
+        assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+        assert!(controller.handle_response("STREAM STATUS RESULT=OK\n").is_ok());
+
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Uninitialized
+        );
+    }
+
+    #[test]
+    fn silent_streams() {
+        for pipeline_handshake in [false, true] {
+            let mut controller = SessionController::new(SessionOptions {
+                nickname: "nickname".to_string(),
+                pipeline_handshake,
+                ..Default::default()
+            })
+            .unwrap();
+            let options = StreamOptions {
+                silent: true,
+                ..Default::default()
+            };
+
+            assert!(controller.handshake_session().is_ok());
+            assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+            let parameters = SessionParameters {
+                style: "STREAM".to_string(),
+                options: Vec::new(),
+            };
+            assert!(controller.create_session(parameters).is_ok());
+            assert!(controller
+                .handle_response("SESSION STATUS RESULT=OK DESTINATION=I2P_DESTINATION\n")
+                .is_ok());
+
+            // silent connect is done once the stream handshake has been processed
+            assert!(controller.handshake_stream().is_ok());
+            if !pipeline_handshake {
+                assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+            }
+            let command = controller.create_stream_with_options("host.i2p", None, &options);
+            assert!(std::str::from_utf8(command.unwrap())
+                .unwrap()
+                .ends_with("STREAM CONNECT ID=nickname DESTINATION=host.i2p SILENT=true\n"));
+            if pipeline_handshake {
+                assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+            }
+
+            assert_eq!(
+                controller.stream.as_ref().unwrap().state,
+                StreamState::Uninitialized
+            );
+
+            // same for silent accept
+            assert!(controller.handshake_stream().is_ok());
+            if !pipeline_handshake {
+                assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+            }
+            let command = controller.accept_stream_with_options(&options);
+            assert!(std::str::from_utf8(command.unwrap())
+                .unwrap()
+                .ends_with("STREAM ACCEPT ID=nickname SILENT=true\n"));
+            if pipeline_handshake {
+                assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+            }
+
+            assert_eq!(
+                controller.stream.as_ref().unwrap().state,
+                StreamState::Uninitialized
+            );
+        }
+    }
+
+    #[test]
+    fn concurrent_streams() {
+        let mut controller = SessionController::new(Default::default()).unwrap();
+        assert_eq!(
+            controller.stream_controller().err(),
+            Some(ProtocolError::InvalidState)
+        );
+
+        assert!(controller.handshake_session().is_ok());
+        assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+        assert!(controller
+            .create_session(SessionParameters {
+                style: "STREAM".to_string(),
+                options: Vec::new(),
+            })
+            .is_ok());
+        assert!(controller
+            .handle_response("SESSION STATUS RESULT=OK DESTINATION=I2P_DESTINATION\n")
+            .is_ok());
+
+        let mut acceptor = controller.stream_controller().unwrap();
+        let mut connector = controller.stream_controller().unwrap();
+
+        // accept is pending while a stream is opened over another connection
+        assert!(acceptor.handshake_stream().is_ok());
+        assert!(acceptor.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+        assert!(acceptor.accept_stream().is_ok());
+
+        for _ in 0..2 {
+            assert!(connector.handshake_stream().is_ok());
+            assert!(connector.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+            assert!(connector.create_stream("host.i2p").is_ok());
+            assert!(connector.handle_response("STREAM STATUS RESULT=OK\n").is_ok());
+            assert_eq!(connector.state, StreamState::Uninitialized);
+        }
+
+        assert_eq!(acceptor.state, StreamState::Pending(StreamKind::Accept));
+        assert!(acceptor.handle_response("STREAM STATUS RESULT=OK\n").is_ok());
+        assert_eq!(acceptor.state, StreamState::Uninitialized);
+
+        // the stream methods of the session controller are not affected
+        assert!(controller.handshake_stream().is_ok());
+    }
+
+    #[test]
+    fn resume_handshaked_stream() {
+        let mut controller = SessionController::new(Default::default()).unwrap();
+
+        assert!(controller.handshake_session().is_ok());
+        assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+        assert!(controller
+            .create_session(SessionParameters {
+                style: "STREAM".to_string(),
+                options: Vec::new(),
+            })
+            .is_ok());
+        assert!(controller
+            .handle_response("SESSION STATUS RESULT=OK DESTINATION=I2P_DESTINATION\n")
+            .is_ok());
+
+        // handshake stream and set it aside
+        assert!(controller.handshake_stream().is_ok());
+        assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+        assert!(controller.reset_stream().is_ok());
+
+        // a new stream can be handshaked while the other one is set aside
+        assert!(controller.handshake_stream().is_ok());
+        assert!(controller.reset_stream().is_ok());
+
+        // resume the handshaked stream and open virtual stream over it
+        assert!(controller.resume_stream().is_ok());
+        assert!(controller.create_stream("host.i2p").is_ok());
+        assert!(controller.handle_response("STREAM STATUS RESULT=OK\n").is_ok());
+
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Uninitialized
+        );
+    }
+
+    #[test]
+    fn ignore_unsolicited_messages() {
+        let mut controller = SessionController::new(Default::default()).unwrap();
+
+        assert!(controller.handshake_session().is_ok());
+        assert!(controller.handle_response("PING 1234\n").is_ok());
+        assert_eq!(controller.state, SessionState::Handshaking);
+
+        assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
+        assert_eq!(controller.state, SessionState::Handshaked);
     }
 
     #[test]
@@ -690,7 +1642,7 @@ mod tests {
         assert_eq!(controller.state, SessionState::Uninitialized);
         assert_eq!(
             controller.handshake_session(),
-            Ok(String::from("HELLO VERSION\n").into_bytes())
+            Ok("HELLO VERSION\n".as_bytes())
         );
         assert_eq!(controller.state, SessionState::Handshaking);
 
@@ -704,8 +1656,9 @@ mod tests {
             options: Vec::new(),
         };
         let command = controller.create_session(parameters).unwrap();
-        let command = std::str::from_utf8(&command).unwrap();
+        let command = std::str::from_utf8(command).unwrap();
         assert!(command.contains("i2cp.dontPublishLeaseSet=true"));
+        assert!(!command.contains("lengthVariance"));
         assert_eq!(controller.state, SessionState::SessionCreatePending);
 
         // handle response and create virtual stream
@@ -722,46 +1675,339 @@ mod tests {
         // handshake virtual stream
         assert!(controller.handshake_stream().is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Handshaking,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Handshaking
+        );
 
         // handle handshake response
         assert!(controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Handshaked,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Handshaked
+        );
 
         // create virtual stream
         assert!(controller.create_stream("destination").is_ok());
 
-        let SessionState::Active {
-            stream_state: StreamState::Pending(StreamKind::Connect),
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
-        };
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Pending(StreamKind::Connect)
+        );
 
         // handle connect response
         assert!(controller.handle_response("STREAM STATUS RESULT=OK\n").is_ok());
 
         // stream state is reset after it has been opened/accepted
-        let SessionState::Active {
-            stream_state: StreamState::Uninitialized,
-            ..
-        } = controller.state
-        else {
-            panic!("invalid state");
+        assert_eq!(
+            controller.stream.as_ref().unwrap().state,
+            StreamState::Uninitialized
+        );
+    }
+
+    #[test]
+    fn idle_tunnel_options() {
+        let mut controller = SessionController::new(SessionOptions {
+            reduce_on_idle: Some(ReduceOnIdle {
+                idle_time: Duration::from_secs(5 * 60),
+                quantity: 1,
+            }),
+            close_on_idle: Some(Duration::from_secs(30 * 60)),
+            ..Default::default()
+        })
+        .unwrap();
+
+        controller.handshake_session().unwrap();
+        controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").unwrap();
+
+        let parameters = SessionParameters {
+            style: "STREAM".to_string(),
+            options: Vec::new(),
+        };
+        let command = controller.create_session(parameters).unwrap();
+        let command = std::str::from_utf8(command).unwrap();
+        assert!(command
+            .contains("i2cp.reduceOnIdle=true i2cp.reduceIdleTime=300000 i2cp.reduceQuantity=1"));
+        assert!(command.contains("i2cp.closeOnIdle=true i2cp.closeIdleTime=1800000"));
+
+        // idle options are not sent unless set
+        let mut controller = SessionController::new(Default::default()).unwrap();
+
+        controller.handshake_session().unwrap();
+        controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").unwrap();
+
+        let parameters = SessionParameters {
+            style: "STREAM".to_string(),
+            options: Vec::new(),
+        };
+        let command = controller.create_session(parameters).unwrap();
+        let command = std::str::from_utf8(command).unwrap();
+        assert!(!command.contains("OnIdle"));
+    }
+
+    #[test]
+    fn lease_set_client_auth() {
+        let mut controller = SessionController::new(SessionOptions {
+            lease_set_auth: Some(crate::LeaseSetAuth::DhClients(vec![(
+                "alice".to_string(),
+                "AAAA".to_string(),
+            )])),
+            ..Default::default()
+        })
+        .unwrap();
+
+        controller.handshake_session().unwrap();
+        controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").unwrap();
+
+        let parameters = SessionParameters {
+            style: "STREAM".to_string(),
+            options: Vec::new(),
+        };
+        let command = controller.create_session(parameters).unwrap();
+        let command = std::str::from_utf8(command).unwrap();
+        assert!(command.contains("i2cp.leaseSetAuthType=1 i2cp.leaseSetClient.dh.0=alice:AAAA"));
+    }
+
+    #[test]
+    fn encrypted_lease_set() {
+        let mut controller = SessionController::new(SessionOptions {
+            lease_set_type: crate::LeaseSetType::Encrypted {
+                secret: Some("my secret".to_string()),
+                blinded_signature_type: SignatureType::RedDsaSha512Ed25519,
+            },
+            ..Default::default()
+        })
+        .unwrap();
+
+        controller.handshake_session().unwrap();
+        controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").unwrap();
+
+        let parameters = SessionParameters {
+            style: "STREAM".to_string(),
+            options: Vec::new(),
+        };
+        let command = controller.create_session(parameters).unwrap();
+        let command = std::str::from_utf8(command).unwrap();
+        assert!(command.contains(
+            "i2cp.leaseSetType=5 i2cp.leaseSetBlindedType=11 i2cp.leaseSetSecret=bXkgc2VjcmV0"
+        ));
+    }
+
+    #[test]
+    fn tunnel_length_variance() {
+        let mut controller = SessionController::new(SessionOptions::high_anonymity()).unwrap();
+
+        controller.handshake_session().unwrap();
+        controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").unwrap();
+
+        let command = controller.create_session(SessionParameters::new("STREAM", Vec::new()));
+        let command = std::str::from_utf8(command.unwrap()).unwrap();
+        assert!(command.contains("inbound.lengthVariance=1 outbound.lengthVariance=1"));
+    }
+
+    #[test]
+    fn connect_with_credentials() {
+        let mut controller = SessionController::new(SessionOptions::default()).unwrap();
+
+        controller.handshake_session().unwrap();
+        controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").unwrap();
+
+        let parameters = SessionParameters {
+            style: "STREAM".to_string(),
+            options: Vec::new(),
+        };
+        controller.create_session(parameters).unwrap();
+        controller
+            .handle_response("SESSION STATUS RESULT=OK DESTINATION=I2P_DESTINATION\n")
+            .unwrap();
+
+        controller.handshake_stream().unwrap();
+        controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n").unwrap();
+
+        let credentials = LookupCredentials {
+            secret: None,
+            auth: Some(crate::LeaseSetAuth::DhKey("AAAA".to_string())),
         };
+        let command = controller
+            .create_stream_with_credentials("host.i2p", Some(80), &credentials)
+            .unwrap();
+        assert!(std::str::from_utf8(command).unwrap().ends_with(
+            "DESTINATION=host.i2p SILENT=false TO_PORT=80 i2cp.leaseSetAuthType=1 \
+            i2cp.leaseSetPrivKey=AAAA\n"
+        ));
+    }
+
+    #[test]
+    fn older_router() {
+        for (version, signature_type) in [("3.0", false), ("3.1", true)] {
+            let mut controller = SessionController::new(SessionOptions::default()).unwrap();
+
+            controller.handshake_session().unwrap();
+            controller
+                .handle_response(&format!("HELLO REPLY RESULT=OK VERSION={version}\n"))
+                .unwrap();
+
+            let command =
+                controller.create_session(SessionParameters::new("STREAM", Vec::new())).unwrap();
+            assert_eq!(
+                std::str::from_utf8(command).unwrap().contains("SIGNATURE_TYPE=7"),
+                signature_type
+            );
+            controller
+                .handle_response("SESSION STATUS RESULT=OK DESTINATION=I2P_DESTINATION\n")
+                .unwrap();
+
+            controller.handshake_stream().unwrap();
+            controller
+                .handle_response(&format!("HELLO REPLY RESULT=OK VERSION={version}\n"))
+                .unwrap();
+
+            // ports are not supported but the stream can be opened without one
+            assert_eq!(
+                controller.create_stream_to_port("host.i2p", 80),
+                Err(ProtocolError::UnsupportedByRouter {
+                    feature: "TO_PORT",
+                    required: "3.2",
+                    version: version.to_string(),
+                })
+            );
+            assert!(controller.create_stream("host.i2p").is_ok());
+        }
+    }
+
+    #[test]
+    fn i2pd_compatibility() {
+        let hello = "HELLO REPLY RESULT=OK VERSION=3.1 IMPLEMENTATION=\"i2pd 2.50\"\n";
+
+        for (compatibility, i2pd) in [
+            (RouterCompatibility::Auto, true),
+            (RouterCompatibility::Java, false),
+            (RouterCompatibility::I2pd, true),
+        ] {
+            let mut controller = SessionController::new(SessionOptions {
+                compatibility,
+                ..Default::default()
+            })
+            .unwrap();
+
+            controller.handshake_session().unwrap();
+            controller.handle_response(hello).unwrap();
+
+            let command =
+                controller.create_session(SessionParameters::new("STREAM", Vec::new())).unwrap();
+            assert_eq!(
+                std::str::from_utf8(command).unwrap().contains("CRYPTO_TYPE=4"),
+                i2pd
+            );
+
+            // i2pd doesn't quote error messages
+            let response = "SESSION STATUS RESULT=I2P_ERROR MESSAGE=Session already exists\n";
+
+            match controller.handle_response(response) {
+                Err(ProtocolError::Router(I2pError::I2pError(Some(message)))) =>
+                    assert_eq!(message == "Session already exists", i2pd),
+                result => panic!("unexpected result: {result:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn version_range() {
+        let mut controller = SessionController::new(SessionOptions {
+            min_version: Some("3.1".to_string()),
+            max_version: Some("3.1".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        assert_eq!(
+            controller.handshake_session(),
+            Ok("HELLO VERSION MIN=3.1 MAX=3.1\n".as_bytes())
+        );
+        controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.1\n").unwrap();
+        controller.create_session(SessionParameters::new("STREAM", Vec::new())).unwrap();
+        controller
+            .handle_response("SESSION STATUS RESULT=OK DESTINATION=I2P_DESTINATION\n")
+            .unwrap();
+
+        assert_eq!(
+            controller.stream_controller().unwrap().handshake_stream(),
+            Ok("HELLO VERSION MIN=3.1 MAX=3.1\n".as_bytes())
+        );
+    }
+
+    #[test]
+    fn primary_session() {
+        for (version, supported) in [("3.3", true), ("3.2", false)] {
+            let mut controller = SessionController::new(SessionOptions {
+                nickname: "primary".to_string(),
+                inbound_len: 1,
+                publish: false,
+                lease_set_type: crate::LeaseSetType::Standard,
+                ..Default::default()
+            })
+            .unwrap();
+
+            controller.handshake_session().unwrap();
+            controller
+                .handle_response(&format!("HELLO REPLY RESULT=OK VERSION={version}\n"))
+                .unwrap();
+
+            let command = controller.create_session(SessionParameters::new("PRIMARY", Vec::new()));
+            if !supported {
+                assert!(matches!(
+                    command,
+                    Err(ProtocolError::UnsupportedByRouter {
+                        feature: "PRIMARY",
+                        ..
+                    })
+                ));
+                continue;
+            }
+
+            let command = std::str::from_utf8(command.unwrap()).unwrap();
+            assert!(command.starts_with(
+                "SESSION CREATE STYLE=PRIMARY ID=primary DESTINATION=TRANSIENT inbound.length=1"
+            ));
+            assert!(command.contains("i2cp.dontPublishLeaseSet=true"));
+            assert!(command.contains("SIGNATURE_TYPE=7"));
+            assert!(command.contains("i2cp.leaseSetType=3"));
+        }
+    }
+
+    #[test]
+    fn quit_session() {
+        for (version, supported) in [("3.1", false), ("3.2", true)] {
+            let mut controller = SessionController::new(SessionOptions::default()).unwrap();
+
+            controller.handshake_session().unwrap();
+            controller
+                .handle_response(&format!("HELLO REPLY RESULT=OK VERSION={version}\n"))
+                .unwrap();
+            controller.create_session(SessionParameters::new("STREAM", Vec::new())).unwrap();
+            controller
+                .handle_response("SESSION STATUS RESULT=OK DESTINATION=I2P_DESTINATION\n")
+                .unwrap();
+
+            if !supported {
+                assert!(matches!(
+                    controller.quit(),
+                    Err(ProtocolError::UnsupportedByRouter {
+                        feature: "QUIT",
+                        ..
+                    })
+                ));
+                assert_eq!(controller.describe_state(), "Active");
+                continue;
+            }
+
+            assert_eq!(controller.quit(), Ok("QUIT\n".as_bytes()));
+            assert_eq!(controller.describe_state(), "Quitting");
+
+            controller.handle_response("QUIT STATUS RESULT=OK MESSAGE=bye\n").unwrap();
+            assert_eq!(controller.describe_state(), "Closed");
+            assert_eq!(controller.destination(), "I2P_DESTINATION");
+        }
     }
 }
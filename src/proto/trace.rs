@@ -0,0 +1,52 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Wire-level tracing of SAMv3 commands and responses.
+
+use crate::{
+    redaction::{redact_line, LogRedaction},
+    tracing,
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::wire";
+
+/// Get the redaction policy of the wire trace.
+fn policy(redact_values: bool) -> LogRedaction {
+    match redact_values {
+        true => LogRedaction::Full,
+        false => LogRedaction::None,
+    }
+}
+
+/// Trace each line of `command` sent to the router.
+pub(crate) fn trace_command(command: &[u8], redact_values: bool) {
+    for line in command.split(|byte| byte == &b'\n').filter(|line| !line.is_empty()) {
+        let line = String::from_utf8_lossy(line);
+        let line = redact_line(&line, policy(redact_values));
+
+        tracing::trace!(target: LOG_TARGET, "-> {line}");
+    }
+}
+
+/// Trace `response` received from the router.
+pub(crate) fn trace_response(response: &str, redact_values: bool) {
+    let response = redact_line(response, policy(redact_values));
+
+    tracing::trace!(target: LOG_TARGET, "<- {response}");
+}
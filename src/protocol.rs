@@ -0,0 +1,133 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! SAMv3 protocol.
+//!
+//! Parser for the responses of a SAMv3 server, for applications which talk to the router over
+//! their own sockets but don't want to implement the grammar themselves.
+//!
+//! ```
+//! use yosemite::protocol::Response;
+//!
+//! match Response::parse("STREAM STATUS RESULT=CANT_REACH_PEER MESSAGE=\"unreachable\"\n") {
+//!     Some(Response::Stream {
+//!         result: Err(error),
+//!         message,
+//!     }) => println!("stream failed: {error} ({message:?})"),
+//!     response => println!("unexpected response: {response:?}"),
+//! }
+//! ```
+//!
+//! The commands `yosemite` sends for a [`SessionOptions`] can be rendered without opening any
+//! sockets, e.g., for snapshot-testing the configuration of an application:
+//!
+//! ```
+//! use yosemite::{protocol, SessionOptions};
+//!
+//! # fn main() -> yosemite::Result<()> {
+//! let options = SessionOptions {
+//!     nickname: "test".to_string(),
+//!     publish: false,
+//!     ..Default::default()
+//! };
+//!
+//! assert_eq!(
+//!     protocol::session_create(&options, "STREAM")?,
+//!     "SESSION CREATE STYLE=STREAM ID=test DESTINATION=TRANSIENT inbound.length=3 \
+//!      inbound.quantity=2 outbound.length=3 outbound.quantity=2 i2cp.dontPublishLeaseSet=true \
+//!      SIGNATURE_TYPE=7 i2cp.leaseSetEncType=4",
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::{
+    options::SessionOptions,
+    proto::session::{SessionController, SessionParameters},
+};
+
+pub use crate::proto::parser::Response;
+
+/// Destination the rendered commands pretend the router created for the session.
+const RENDER_DESTINATION: &str = "DESTINATION";
+
+/// Create a session controller for `options` which has handshaked with the router.
+fn handshaked_controller(options: &SessionOptions) -> crate::Result<SessionController> {
+    // a pipelined handshake would be included in the rendered command
+    let mut controller = SessionController::new(SessionOptions {
+        pipeline_handshake: false,
+        ..options.clone()
+    })?;
+
+    controller.handshake_session()?;
+    controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n")?;
+
+    Ok(controller)
+}
+
+/// Convert command rendered by a controller into a string without the terminating newline.
+fn render(command: &[u8]) -> String {
+    String::from_utf8_lossy(command).trim_end().to_string()
+}
+
+/// Render the `SESSION CREATE` command sent for a session of `style`, such as `STREAM`, created
+/// with `options`.
+///
+/// Datagram sessions also send the `HOST` and `PORT` of their UDP socket which are not included.
+pub fn session_create(options: &SessionOptions, style: &str) -> crate::Result<String> {
+    let mut controller = handshaked_controller(options)?;
+
+    Ok(render(controller.create_session(
+        SessionParameters::new(style, Vec::new()),
+    )?))
+}
+
+/// Render the `STREAM CONNECT` command sent for opening a stream to `destination` from a session
+/// created with `options`.
+pub fn stream_connect(options: &SessionOptions, destination: &str) -> crate::Result<String> {
+    let mut controller = handshaked_controller(options)?;
+
+    controller.create_session(SessionParameters::new("STREAM", Vec::new()))?;
+    controller.handle_response(&format!(
+        "SESSION STATUS RESULT=OK DESTINATION={RENDER_DESTINATION}\n"
+    ))?;
+    controller.handshake_stream()?;
+    controller.handle_response("HELLO REPLY RESULT=OK VERSION=3.3\n")?;
+
+    Ok(render(controller.create_stream(destination)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_stream_connect() {
+        let options = SessionOptions {
+            nickname: "test".to_string(),
+            pipeline_handshake: true,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            stream_connect(&options, "host.i2p").unwrap(),
+            "STREAM CONNECT ID=test DESTINATION=host.i2p SILENT=false",
+        );
+        assert!(session_create(&options, "STREAM").unwrap().starts_with("SESSION CREATE"));
+    }
+}
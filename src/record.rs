@@ -0,0 +1,441 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Recording and replaying SAMv3 conversations.
+//!
+//! [`Recorder`] is an [`Interceptor`] which writes every command sent to and every response
+//! received from the router into a file, one line per message, prefixed with the number of
+//! milliseconds since the recorder was created and the direction of the message:
+//!
+//! ```text
+//! 0 > HELLO VERSION
+//! 2 < HELLO REPLY RESULT=OK VERSION=3.3
+//! ```
+//!
+//! The recorded [`Conversation`] can then be replayed through the session state machine, which
+//! makes it possible to reproduce a bug against a specific router version without the router.
+//!
+//! ```no_run
+//! use yosemite::{record::Recorder, SessionOptions, SharedInterceptor};
+//!
+//! # fn main() -> yosemite::Result<()> {
+//! let options = SessionOptions {
+//!     interceptor: Some(SharedInterceptor::new(Recorder::create("conversation.log")?)),
+//!     ..Default::default()
+//! };
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The recording contains destinations and private keys as-is.
+
+use crate::{
+    error::Error,
+    interceptor::Interceptor,
+    options::SessionOptions,
+    proto::{
+        parser::Response,
+        session::{SessionController, SessionParameters},
+    },
+    tracing,
+};
+
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::record";
+
+/// Direction of a recorded message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// Command sent to the router.
+    Command,
+
+    /// Response received from the router.
+    Response,
+}
+
+impl Direction {
+    /// Get the marker of the direction used in recordings.
+    fn marker(&self) -> &'static str {
+        match self {
+            Self::Command => ">",
+            Self::Response => "<",
+        }
+    }
+}
+
+/// Recorded message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Time since the recording was started.
+    pub elapsed: Duration,
+
+    /// Direction of the message.
+    pub direction: Direction,
+
+    /// Message, without the line terminator.
+    pub line: String,
+}
+
+/// [`Interceptor`] which records commands and responses.
+pub struct Recorder {
+    /// Time when the recording was started.
+    started: Instant,
+
+    /// Output of the recording.
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Recorder {
+    /// Create new [`Recorder`] which writes the recording into `output`.
+    pub fn new(output: impl Write + Send + 'static) -> Self {
+        Self {
+            started: Instant::now(),
+            output: Mutex::new(Box::new(output)),
+        }
+    }
+
+    /// Create new [`Recorder`] which writes the recording into a file at `path`.
+    ///
+    /// The file is truncated if it exists.
+    pub fn create(path: impl AsRef<Path>) -> crate::Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Record `line`.
+    ///
+    /// The output is flushed after each line so the recording is complete even if the
+    /// application crashes.
+    fn record(&self, direction: Direction, line: &str) {
+        let mut output = self.output.lock().expect("to succeed");
+        let elapsed = self.started.elapsed().as_millis();
+
+        if let Err(error) =
+            writeln!(output, "{elapsed} {} {line}", direction.marker()).and_then(|_| output.flush())
+        {
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?error,
+                "failed to record message",
+            );
+        }
+    }
+}
+
+impl Interceptor for Recorder {
+    fn on_command(&self, command: &str) {
+        self.record(Direction::Command, command);
+    }
+
+    fn on_response(&self, response: &str) {
+        self.record(Direction::Response, response);
+    }
+}
+
+/// Recorded SAMv3 conversation.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Conversation {
+    /// Recorded messages.
+    entries: Vec<Entry>,
+}
+
+impl Conversation {
+    /// Parse conversation from the output of [`Recorder`].
+    ///
+    /// Empty lines are ignored.
+    pub fn parse(input: &str) -> crate::Result<Self> {
+        let entries = input
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                let mut parts = line.splitn(3, ' ');
+
+                let elapsed = parts.next().and_then(|elapsed| elapsed.parse::<u64>().ok());
+                let direction = match parts.next() {
+                    Some(">") => Some(Direction::Command),
+                    Some("<") => Some(Direction::Response),
+                    _ => None,
+                };
+
+                match (elapsed, direction) {
+                    (Some(elapsed), Some(direction)) => Ok(Entry {
+                        elapsed: Duration::from_millis(elapsed),
+                        direction,
+                        line: parts.next().unwrap_or("").to_string(),
+                    }),
+                    _ => Err(Error::Malformed),
+                }
+            })
+            .collect::<crate::Result<_>>()?;
+
+        Ok(Self { entries })
+    }
+
+    /// Load conversation recorded into a file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> crate::Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    /// Get recorded messages.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// Replay the conversation through the session state machine.
+    ///
+    /// Each recorded command is issued to the state machine in the order it was sent and each
+    /// recorded response is handed to it in the order it was received, as a session would have
+    /// done. Returns the first error the state machine reports.
+    ///
+    /// Handshake pipelining and pooled control connections are detected from the recording.
+    /// `PING`/`PONG`, the destination of an accepted stream and commands that the session state
+    /// machine doesn't handle, such as the ones sent with `Session::send_raw_command()`, are
+    /// skipped together with their replies.
+    pub fn replay(&self) -> crate::Result<()> {
+        let pipeline_handshake = self.entries.windows(2).any(|entries| {
+            entries[0].line.starts_with("HELLO VERSION")
+                && entries[1].direction == Direction::Command
+        });
+        let mut controller = SessionController::new(SessionOptions {
+            pipeline_handshake,
+            ..Default::default()
+        })?;
+
+        let mut session_created = false;
+        let mut stream_handshaked = false;
+        let mut accept_pending = false;
+        let mut skipped_responses = 0usize;
+
+        for entry in &self.entries {
+            let line = entry.line.as_str();
+
+            tracing::trace!(
+                target: LOG_TARGET,
+                elapsed = ?entry.elapsed,
+                direction = ?entry.direction,
+                "replay message",
+            );
+
+            match entry.direction {
+                Direction::Command => {
+                    let mut tokens = line.split(' ');
+                    let command = (tokens.next(), tokens.next());
+                    let value = |key: &str| {
+                        line.split(' ').find_map(|token| {
+                            token.strip_prefix(key).and_then(|token| token.strip_prefix('='))
+                        })
+                    };
+
+                    match command {
+                        (Some("HELLO"), Some("VERSION")) if !session_created => {
+                            controller.handshake_session()?;
+                        }
+                        (Some("HELLO"), Some("VERSION")) => {
+                            // the previous connection was handshaked for the pool
+                            if stream_handshaked {
+                                controller.reset_stream()?;
+                            }
+                            controller.handshake_stream()?;
+                            stream_handshaked = true;
+                        }
+                        (Some("SESSION"), Some("CREATE")) => {
+                            let style = value("STYLE").ok_or(Error::Malformed)?;
+
+                            controller.create_session(SessionParameters::new(style, Vec::new()))?;
+                            session_created = true;
+                        }
+                        (Some("STREAM"), Some(kind @ ("CONNECT" | "ACCEPT" | "FORWARD"))) => {
+                            // connection was taken from the pool
+                            if !stream_handshaked {
+                                controller.resume_stream()?;
+                            }
+                            stream_handshaked = false;
+
+                            match kind {
+                                "CONNECT" => {
+                                    let destination =
+                                        value("DESTINATION").ok_or(Error::Malformed)?;
+                                    controller.create_stream(destination)?;
+                                }
+                                "ACCEPT" => {
+                                    controller.accept_stream()?;
+                                    accept_pending = true;
+                                }
+                                _ => {
+                                    let port = value("PORT")
+                                        .and_then(|port| port.parse::<u16>().ok())
+                                        .ok_or(Error::Malformed)?;
+                                    controller.forward_stream(port)?;
+                                }
+                            }
+                        }
+                        (Some("PING" | "PONG"), _) => {}
+                        _ => {
+                            tracing::debug!(
+                                target: LOG_TARGET,
+                                ?command,
+                                "skipping unsupported command",
+                            );
+                            skipped_responses += 1;
+                        }
+                    }
+                }
+                Direction::Response => {
+                    if Response::parse_unsolicited(line).is_some() {
+                        continue;
+                    }
+
+                    if skipped_responses > 0 {
+                        skipped_responses -= 1;
+                        continue;
+                    }
+
+                    controller.handle_response(line)?;
+
+                    // destination of the remote peer follows the status of an accepted stream
+                    if accept_pending && line.starts_with("STREAM STATUS") {
+                        accept_pending = false;
+                        skipped_responses += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    /// Output shared between a [`Recorder`] and the test.
+    #[derive(Clone, Default)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Output {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn record_and_parse() {
+        let output = Output::default();
+        let recorder = Recorder::new(output.clone());
+
+        recorder.on_command("HELLO VERSION");
+        recorder.on_response("HELLO REPLY RESULT=OK VERSION=3.3");
+
+        let recording = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        let conversation = Conversation::parse(&recording).unwrap();
+
+        assert_eq!(conversation.entries().len(), 2);
+        assert_eq!(conversation.entries()[0].direction, Direction::Command);
+        assert_eq!(conversation.entries()[0].line, "HELLO VERSION");
+        assert_eq!(conversation.entries()[1].direction, Direction::Response);
+        assert_eq!(
+            conversation.entries()[1].line,
+            "HELLO REPLY RESULT=OK VERSION=3.3"
+        );
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert!(Conversation::parse("HELLO VERSION\n").is_err());
+        assert!(Conversation::parse("12 ? HELLO VERSION\n").is_err());
+        assert!(Conversation::parse("\n\n").unwrap().entries().is_empty());
+    }
+
+    #[test]
+    fn replay_session() {
+        let conversation = Conversation::parse(
+            "0 > HELLO VERSION\n\
+            1 < HELLO REPLY RESULT=OK VERSION=3.3\n\
+            1 > SESSION CREATE STYLE=STREAM ID=test DESTINATION=TRANSIENT\n\
+            5 < SESSION STATUS RESULT=OK DESTINATION=abcd\n\
+            6 > HELLO VERSION\n\
+            6 < HELLO REPLY RESULT=OK VERSION=3.3\n\
+            6 > STREAM CONNECT ID=test DESTINATION=host.i2p SILENT=false\n\
+            7 < PING 1234\n\
+            7 > PONG 1234\n\
+            9 < STREAM STATUS RESULT=OK\n\
+            10 > HELLO VERSION\n\
+            10 < HELLO REPLY RESULT=OK VERSION=3.3\n\
+            10 > STREAM ACCEPT ID=test SILENT=false\n\
+            11 < STREAM STATUS RESULT=OK\n\
+            20 < efgh FROM_PORT=0 TO_PORT=0\n\
+            21 > NAMING LOOKUP NAME=ME\n\
+            21 < NAMING REPLY RESULT=OK NAME=ME VALUE=abcd\n",
+        )
+        .unwrap();
+
+        assert!(conversation.replay().is_ok());
+    }
+
+    #[test]
+    fn replay_pipelined_and_pooled() {
+        let conversation = Conversation::parse(
+            "0 > HELLO VERSION\n\
+            0 > SESSION CREATE STYLE=STREAM ID=test DESTINATION=TRANSIENT\n\
+            1 < HELLO REPLY RESULT=OK VERSION=3.3\n\
+            5 < SESSION STATUS RESULT=OK DESTINATION=abcd\n\
+            6 > HELLO VERSION\n\
+            6 > STREAM CONNECT ID=test DESTINATION=host.i2p SILENT=false\n\
+            6 < HELLO REPLY RESULT=OK VERSION=3.3\n\
+            9 < STREAM STATUS RESULT=OK\n\
+            10 > HELLO VERSION\n\
+            10 < HELLO REPLY RESULT=OK VERSION=3.3\n\
+            10 > HELLO VERSION\n\
+            10 < HELLO REPLY RESULT=OK VERSION=3.3\n\
+            12 > STREAM CONNECT ID=test DESTINATION=host.i2p SILENT=false\n\
+            13 < STREAM STATUS RESULT=OK\n\
+            14 > STREAM CONNECT ID=test DESTINATION=host.i2p SILENT=false\n\
+            15 < STREAM STATUS RESULT=OK\n",
+        )
+        .unwrap();
+
+        assert!(conversation.replay().is_ok());
+    }
+
+    #[test]
+    fn replay_reproduces_router_error() {
+        let conversation = Conversation::parse(
+            "0 > HELLO VERSION\n\
+            1 < HELLO REPLY RESULT=NOVERSION\n",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            conversation.replay(),
+            Err(Error::Protocol(crate::ProtocolError::Router(_)))
+        ));
+    }
+}
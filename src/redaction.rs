@@ -0,0 +1,243 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use std::{
+    borrow::Cow,
+    fmt,
+    hash::{DefaultHasher, Hash, Hasher},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+/// Keys whose values hold destinations, private keys or credentials.
+const REDACTED_KEYS: &[&str] = &[
+    "DESTINATION",
+    "PASSWORD",
+    "PRIV",
+    "PUB",
+    "VALUE",
+    "i2cp.leaseSetPrivKey",
+    "i2cp.leaseSetSecret",
+];
+
+/// Prefixes of keys whose values hold credentials.
+const REDACTED_KEY_PREFIXES: &[&str] = &["i2cp.leaseSetClient."];
+
+/// Replacement for fully redacted values.
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// Number of characters kept of truncated values.
+const TRUNCATED_LEN: usize = 8;
+
+/// Global log redaction policy.
+static LOG_REDACTION: AtomicU8 = AtomicU8::new(LogRedaction::Truncated as u8);
+
+/// Redaction policy for destinations, private keys and credentials in log events.
+///
+/// The policy is global and applies to all sessions. See [`set_log_redaction()`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogRedaction {
+    /// Values are logged as-is.
+    None,
+
+    /// Values are replaced with `<redacted>`.
+    Full,
+
+    /// Values are truncated to their first eight characters.
+    #[default]
+    Truncated,
+
+    /// Values are replaced with a hash so log events about the same destination can still be
+    /// correlated with each other.
+    Hashed,
+}
+
+impl LogRedaction {
+    /// Convert `value` back into [`LogRedaction`].
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::None,
+            1 => Self::Full,
+            3 => Self::Hashed,
+            _ => Self::Truncated,
+        }
+    }
+}
+
+/// Set the global log redaction policy.
+///
+/// Defaults to [`LogRedaction::Truncated`].
+pub fn set_log_redaction(policy: LogRedaction) {
+    LOG_REDACTION.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Get the global log redaction policy.
+pub fn log_redaction() -> LogRedaction {
+    LogRedaction::from_u8(LOG_REDACTION.load(Ordering::Relaxed))
+}
+
+/// Redact `value` according to `policy`.
+pub(crate) fn redact_value(value: &str, policy: LogRedaction) -> Cow<'_, str> {
+    match policy {
+        LogRedaction::None => Cow::Borrowed(value),
+        LogRedaction::Full => Cow::Borrowed(REDACTED),
+        LogRedaction::Truncated => match value.char_indices().nth(TRUNCATED_LEN) {
+            Some((index, _)) => Cow::Owned(format!("{}...", &value[..index])),
+            None => Cow::Borrowed(value),
+        },
+        LogRedaction::Hashed => {
+            let mut hasher = DefaultHasher::new();
+            value.hash(&mut hasher);
+
+            Cow::Owned(format!("#{:016x}", hasher.finish()))
+        }
+    }
+}
+
+/// Does the value of `key` hold a destination, a private key or a credential.
+fn is_redacted_key(key: &str) -> bool {
+    REDACTED_KEYS.contains(&key)
+        || REDACTED_KEY_PREFIXES.iter().any(|prefix| key.starts_with(prefix))
+}
+
+/// Redact destinations, private keys and credentials from SAMv3 `line` according to `policy`.
+///
+/// Values of [`REDACTED_KEYS`] and of keys starting with [`REDACTED_KEY_PREFIXES`] are redacted
+/// and so is the first token of the line if it's not a command, which is the case for the
+/// destination the router sends after a virtual stream has been accepted.
+pub(crate) fn redact_line(line: &str, policy: LogRedaction) -> Cow<'_, str> {
+    let is_command = |token: &str| token.chars().all(|c| c.is_ascii_uppercase());
+    let is_redacted = |token: &str| match token.split_once('=') {
+        Some((key, _)) => is_redacted_key(key),
+        None => false,
+    };
+    let mut tokens = line.split(' ');
+
+    match tokens.next() {
+        _ if policy == LogRedaction::None => Cow::Borrowed(line),
+        Some(first) if is_command(first) && !line.split(' ').any(is_redacted) =>
+            Cow::Borrowed(line),
+        Some(first) => {
+            let first = match is_command(first) {
+                true => Cow::Borrowed(first),
+                false => redact_value(first, policy),
+            };
+
+            Cow::Owned(
+                std::iter::once(first)
+                    .chain(tokens.map(|token| match token.split_once('=') {
+                        Some((key, value)) if is_redacted_key(key) =>
+                            Cow::Owned(format!("{key}={}", redact_value(value, policy))),
+                        _ => Cow::Borrowed(token),
+                    }))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            )
+        }
+        None => Cow::Borrowed(line),
+    }
+}
+
+/// Destination, private key or credential, redacted according to the global policy when
+/// formatted.
+pub(crate) struct RedactedValue<'a>(pub(crate) &'a str);
+
+impl fmt::Display for RedactedValue<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&redact_value(self.0, log_redaction()))
+    }
+}
+
+/// SAMv3 command or response, redacted according to the global policy when formatted.
+pub(crate) struct RedactedLine<'a>(pub(crate) &'a str);
+
+impl fmt::Display for RedactedLine<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&redact_line(self.0, log_redaction()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_keys() {
+        assert_eq!(
+            redact_line(
+                "SESSION CREATE STYLE=STREAM ID=test DESTINATION=abcd~ SIGNATURE_TYPE=7",
+                LogRedaction::Full,
+            ),
+            "SESSION CREATE STYLE=STREAM ID=test DESTINATION=<redacted> SIGNATURE_TYPE=7",
+        );
+        assert_eq!(
+            redact_line(
+                "DEST REPLY PUB=abcdefghijkl PRIV=efgh",
+                LogRedaction::Truncated
+            ),
+            "DEST REPLY PUB=abcdefgh... PRIV=efgh",
+        );
+        assert_eq!(
+            redact_line(
+                "NAMING REPLY RESULT=OK NAME=host.i2p VALUE=abcd",
+                LogRedaction::Full,
+            ),
+            "NAMING REPLY RESULT=OK NAME=host.i2p VALUE=<redacted>",
+        );
+        assert_eq!(
+            redact_line(
+                "SESSION CREATE i2cp.leaseSetAuthType=1 i2cp.leaseSetClient.dh.0=alice:abcd",
+                LogRedaction::Full,
+            ),
+            "SESSION CREATE i2cp.leaseSetAuthType=1 i2cp.leaseSetClient.dh.0=<redacted>",
+        );
+    }
+
+    #[test]
+    fn redact_accepted_destination() {
+        assert_eq!(redact_line("abcd~efgh", LogRedaction::Full), "<redacted>");
+        assert_eq!(
+            redact_line("abcd~efgh FROM_PORT=0 TO_PORT=0", LogRedaction::Full),
+            "<redacted> FROM_PORT=0 TO_PORT=0",
+        );
+    }
+
+    #[test]
+    fn nothing_to_redact() {
+        let line = "STREAM STATUS RESULT=OK";
+
+        assert!(matches!(
+            redact_line(line, LogRedaction::Full),
+            Cow::Borrowed(redacted) if redacted == line
+        ));
+        assert_eq!(redact_line("PING", LogRedaction::Full), "PING");
+        assert_eq!(redact_line("", LogRedaction::Full), "");
+        assert_eq!(
+            redact_line("DEST REPLY PUB=abcd PRIV=efgh", LogRedaction::None),
+            "DEST REPLY PUB=abcd PRIV=efgh",
+        );
+    }
+
+    #[test]
+    fn hashed_values_can_be_correlated() {
+        let first = redact_value("abcdefghijkl", LogRedaction::Hashed);
+
+        assert_eq!(first, redact_value("abcdefghijkl", LogRedaction::Hashed));
+        assert_ne!(first, redact_value("abcdefghijkm", LogRedaction::Hashed));
+        assert!(!first.contains("abcd"));
+    }
+}
@@ -0,0 +1,109 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::error::ProtocolError;
+
+/// Router details reported during the `HELLO` handshake.
+///
+/// See `Session::router_info()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouterInfo {
+    /// SAMv3 version negotiated with the router, e.g., `3.3`.
+    pub version: String,
+
+    /// Implementation of the router, if the router reported it with the non-standard
+    /// `IMPLEMENTATION` key.
+    pub implementation: Option<String>,
+}
+
+impl RouterInfo {
+    /// Get the negotiated version as a `(major, minor)` pair.
+    ///
+    /// Returns `None` if the version reported by the router is not of the form `major.minor`.
+    pub fn version_number(&self) -> Option<(u8, u8)> {
+        let (major, minor) = self.version.split_once('.')?;
+
+        Some((major.parse().ok()?, minor.parse().ok()?))
+    }
+
+    /// Is the negotiated version at least `major.minor`.
+    ///
+    /// Returns `false` if the version cannot be parsed.
+    pub fn supports(&self, major: u8, minor: u8) -> bool {
+        self.version_number().is_some_and(|version| version >= (major, minor))
+    }
+
+    /// Did the router report itself as i2pd.
+    pub fn is_i2pd(&self) -> bool {
+        self.implementation
+            .as_deref()
+            .is_some_and(|implementation| implementation.to_ascii_lowercase().contains("i2pd"))
+    }
+
+    /// Check that `feature`, introduced in SAMv3 version `required`, can be used with the router.
+    ///
+    /// Features are only refused if the router reported a version older than `required`, an
+    /// unparsable version is given the benefit of the doubt.
+    pub(crate) fn require(
+        &self,
+        feature: &'static str,
+        required: &'static str,
+    ) -> Result<(), ProtocolError> {
+        let supported = match (self.version_number(), required.split_once('.')) {
+            (Some(version), Some((major, minor))) => match (major.parse(), minor.parse()) {
+                (Ok(major), Ok(minor)) => version >= (major, minor),
+                _ => true,
+            },
+            _ => true,
+        };
+
+        match supported {
+            true => Ok(()),
+            false => Err(ProtocolError::UnsupportedByRouter {
+                feature,
+                required,
+                version: self.version.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_numbers() {
+        let info = RouterInfo {
+            version: "3.1".to_string(),
+            implementation: None,
+        };
+
+        assert_eq!(info.version_number(), Some((3, 1)));
+        assert!(info.supports(3, 0));
+        assert!(info.supports(3, 1));
+        assert!(!info.supports(3, 2));
+
+        let info = RouterInfo {
+            version: "three".to_string(),
+            implementation: None,
+        };
+        assert_eq!(info.version_number(), None);
+        assert!(!info.supports(3, 0));
+    }
+}
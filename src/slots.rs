@@ -0,0 +1,253 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Concurrency limits of sessions.
+
+use crate::{
+    error::Error,
+    options::{SessionOptions, StreamLimitPolicy},
+};
+
+#[cfg(feature = "async")]
+use tokio::sync::Notify;
+
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+
+/// Slots of a session.
+struct Slots {
+    /// Number of streams open or being opened.
+    open: Mutex<usize>,
+
+    /// Maximum number of streams.
+    max: usize,
+
+    /// Policy for streams opened while all slots are taken.
+    policy: StreamLimitPolicy,
+
+    /// Notifies blocking callers when a slot is released.
+    released: Condvar,
+
+    /// Notifies asynchronous callers when a slot is released.
+    #[cfg(feature = "async")]
+    notify: Notify,
+}
+
+impl Slots {
+    /// Lock the number of open streams.
+    fn open(&self) -> MutexGuard<'_, usize> {
+        self.open.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Stream slots of a session.
+///
+/// Limits the number of virtual streams of the session open at the same time to
+/// [`SessionOptions::max_streams`]. Each stream, including a stream which is still being opened
+/// or accepted, holds a [`StreamSlot`] until it's dropped.
+#[derive(Clone)]
+pub(crate) struct StreamSlots {
+    /// Slots.
+    slots: Arc<Slots>,
+}
+
+impl StreamSlots {
+    /// Create new [`StreamSlots`] for the stream limit of `options`.
+    ///
+    /// Returns `None` if the session has no stream limit and fails with [`Error::InvalidOption`]
+    /// if the limit is zero.
+    pub(crate) fn new(options: &SessionOptions) -> crate::Result<Option<Self>> {
+        match options.max_streams {
+            None => Ok(None),
+            Some(0) => Err(Error::InvalidOption {
+                key: "max_streams".to_string(),
+                value: "0".to_string(),
+            }),
+            Some(max) => Ok(Some(Self {
+                slots: Arc::new(Slots {
+                    open: Mutex::new(0usize),
+                    max,
+                    policy: options.stream_limit_policy,
+                    released: Condvar::new(),
+                    #[cfg(feature = "async")]
+                    notify: Notify::new(),
+                }),
+            })),
+        }
+    }
+
+    /// Take a slot if one is free.
+    #[cfg(any(feature = "async", test))]
+    fn try_acquire(&self) -> Option<StreamSlot> {
+        let mut open = self.slots.open();
+
+        (*open < self.slots.max).then(|| {
+            *open += 1;
+            StreamSlot {
+                slots: Arc::clone(&self.slots),
+            }
+        })
+    }
+
+    /// Take a slot, waiting for one to be released if the policy is
+    /// [`StreamLimitPolicy::Queue`].
+    ///
+    /// Fails with [`Error::TooManyStreams`] if all slots are taken and the policy is
+    /// [`StreamLimitPolicy::Reject`].
+    #[cfg(feature = "async")]
+    pub(crate) async fn acquire(&self) -> crate::Result<StreamSlot> {
+        loop {
+            // registered before the slots are checked so a release in between isn't missed
+            let released = self.slots.notify.notified();
+
+            if let Some(slot) = self.try_acquire() {
+                return Ok(slot);
+            }
+
+            if self.slots.policy == StreamLimitPolicy::Reject {
+                return Err(Error::TooManyStreams);
+            }
+
+            released.await;
+        }
+    }
+
+    /// Take a slot, blocking until one is released if the policy is [`StreamLimitPolicy::Queue`].
+    ///
+    /// Fails with [`Error::TooManyStreams`] if all slots are taken and the policy is
+    /// [`StreamLimitPolicy::Reject`].
+    #[cfg(feature = "sync")]
+    pub(crate) fn acquire_blocking(&self) -> crate::Result<StreamSlot> {
+        let mut open = self.slots.open();
+
+        while *open >= self.slots.max {
+            if self.slots.policy == StreamLimitPolicy::Reject {
+                return Err(Error::TooManyStreams);
+            }
+
+            open = self.slots.released.wait(open).unwrap_or_else(PoisonError::into_inner);
+        }
+        *open += 1;
+
+        Ok(StreamSlot {
+            slots: Arc::clone(&self.slots),
+        })
+    }
+}
+
+/// Take a stream slot from `slots`, if the session limits the number of streams.
+#[cfg(feature = "async")]
+pub(crate) async fn acquire_slot(slots: Option<&StreamSlots>) -> crate::Result<Option<StreamSlot>> {
+    match slots {
+        Some(slots) => slots.acquire().await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Take a stream slot from `slots`, if the session limits the number of streams.
+#[cfg(feature = "sync")]
+pub(crate) fn acquire_slot_blocking(
+    slots: Option<&StreamSlots>,
+) -> crate::Result<Option<StreamSlot>> {
+    slots.map(StreamSlots::acquire_blocking).transpose()
+}
+
+/// Slot held by a stream.
+///
+/// The slot is released when it's dropped.
+pub(crate) struct StreamSlot {
+    /// Slots of the session.
+    slots: Arc<Slots>,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        *self.slots.open() -= 1;
+        self.slots.released.notify_one();
+
+        #[cfg(feature = "async")]
+        self.slots.notify.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn slots(max_streams: usize, policy: StreamLimitPolicy) -> StreamSlots {
+        StreamSlots::new(&SessionOptions {
+            max_streams: Some(max_streams),
+            stream_limit_policy: policy,
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap()
+    }
+
+    #[test]
+    fn slots_released_on_drop() {
+        let slots = slots(2, StreamLimitPolicy::Reject);
+
+        let first = slots.try_acquire().unwrap();
+        let _second = slots.try_acquire().unwrap();
+        assert!(slots.try_acquire().is_none());
+
+        drop(first);
+        assert!(slots.try_acquire().is_some());
+    }
+
+    #[test]
+    fn invalid_limit() {
+        assert!(StreamSlots::new(&Default::default()).unwrap().is_none());
+        assert!(matches!(
+            StreamSlots::new(&SessionOptions {
+                max_streams: Some(0),
+                ..Default::default()
+            }),
+            Err(Error::InvalidOption { .. })
+        ));
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn queued_until_released() {
+        let slots = slots(1, StreamLimitPolicy::Queue);
+        let slot = slots.acquire().await.unwrap();
+
+        let waiter = tokio::spawn({
+            let slots = slots.clone();
+            async move { slots.acquire().await.map(|_| ()) }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        drop(slot);
+        assert!(waiter.await.unwrap().is_ok());
+    }
+
+    #[cfg(feature = "sync")]
+    #[test]
+    fn rejected_when_full() {
+        let slots = slots(1, StreamLimitPolicy::Reject);
+        let _slot = slots.acquire_blocking().unwrap();
+
+        assert!(matches!(
+            slots.acquire_blocking(),
+            Err(Error::TooManyStreams)
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Session status.
+
+use std::net::SocketAddr;
+
+/// State of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// Session has been created and its control connection is open.
+    Active,
+
+    /// Router closed the control connection of the session.
+    ///
+    /// The control connection is read only when a command is sent over it so the state changes
+    /// when the next such command fails, see
+    /// [`SessionEvent::Disconnected`](crate::SessionEvent::Disconnected).
+    Disconnected,
+
+    /// Session has been closed with `Session::close()`.
+    Closed,
+}
+
+/// State of stream forwarding of a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardingState {
+    /// Inbound virtual streams are not forwarded.
+    Inactive,
+
+    /// Inbound virtual streams are forwarded to `port`.
+    Active {
+        /// Port of the TCP listener where streams are forwarded to.
+        port: u16,
+    },
+
+    /// Router closed the control connection used to forward inbound virtual streams to `port`,
+    /// see [`SessionEvent::ForwardingBroken`](crate::SessionEvent::ForwardingBroken).
+    Broken {
+        /// Port of the TCP listener where streams were forwarded to.
+        port: u16,
+    },
+}
+
+/// Snapshot of the status of a session.
+///
+/// See `Session::status()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionStatus {
+    /// State of the session.
+    pub state: SessionState,
+
+    /// Style of the session as sent in `SESSION CREATE`, such as `STREAM` or `DATAGRAM`.
+    pub style: String,
+
+    /// Public destination of the session.
+    ///
+    /// The private keys of the session are never included in the status, so it can be shared
+    /// freely, see `Session::public_destination()`.
+    pub destination: String,
+
+    /// `.b32.i2p` address of the session.
+    ///
+    /// `None` if the router returned a destination which couldn't be decoded.
+    pub b32_address: Option<String>,
+
+    /// Nickname of the session.
+    pub nickname: String,
+
+    /// State of stream forwarding.
+    ///
+    /// Always [`ForwardingState::Inactive`] for datagram sessions.
+    pub forwarding: ForwardingState,
+}
+
+/// Local and peer address of a socket connected to the SAMv3 bridge of the router.
+///
+/// The addresses can be used to correlate the sockets of a session with firewall logs and the
+/// output of tools such as `ss`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SocketAddrs {
+    /// Local address of the socket.
+    pub local: SocketAddr,
+
+    /// Address of the router.
+    pub peer: SocketAddr,
+}
@@ -0,0 +1,53 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+use crate::events::SessionEvent;
+
+use std::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc, Mutex,
+};
+
+/// Subscribers of session events.
+///
+/// Cloned into the control connections of the session so events can be emitted as the router
+/// sends them.
+#[derive(Clone, Default)]
+pub(crate) struct Events {
+    /// Event subscribers.
+    subscribers: Arc<Mutex<Vec<Sender<SessionEvent>>>>,
+}
+
+impl Events {
+    /// Subscribe to session events, starting with `initial`.
+    pub(crate) fn subscribe(&self, initial: SessionEvent) -> Receiver<SessionEvent> {
+        let (tx, rx) = channel();
+        let _ = tx.send(initial);
+        self.subscribers.lock().expect("to succeed").push(tx);
+
+        rx
+    }
+
+    /// Emit `event` to all subscribers, removing the ones that have been dropped.
+    pub(crate) fn emit(&self, event: SessionEvent) {
+        self.subscribers
+            .lock()
+            .expect("to succeed")
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
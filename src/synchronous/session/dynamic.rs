@@ -0,0 +1,171 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Object-safe session traits.
+
+use crate::{
+    status::SessionStatus,
+    synchronous::{
+        session::{style, Session},
+        stream::Stream,
+    },
+};
+
+/// ## Object-safe session of virtual streams.
+///
+/// Implemented by [`Session<Stream>`](Session) so sessions can be stored behind trait objects,
+/// such as `Box<dyn DynStreamSession>`, without naming the session style.
+pub trait DynStreamSession: Send {
+    /// Get destination of the session, see [`Session::destination()`].
+    fn destination(&self) -> &str;
+
+    /// Get public destination of the session, see [`Session::public_destination()`].
+    fn public_destination(&self) -> &str;
+
+    /// Get nickname of the session.
+    fn nickname(&self) -> &str;
+
+    /// Get status of the session, see [`Session::status()`].
+    fn status(&self) -> SessionStatus;
+
+    /// Create new outbound virtual stream to `destination`, see [`Session::connect()`].
+    fn connect(&mut self, destination: &str) -> crate::Result<Stream>;
+
+    /// Accept inbound virtual stream, see [`Session::accept()`].
+    fn accept(&mut self) -> crate::Result<Stream>;
+
+    /// Forward inbound virtual streams to a TCP listener at `port`, see [`Session::forward()`].
+    fn forward(&mut self, port: u16) -> crate::Result<()>;
+}
+
+/// ## Object-safe session of datagrams.
+///
+/// Implemented by [`Session<Repliable>`](Session) and [`Session<Anonymous>`](Session) so sessions
+/// of either datagram style can be stored behind trait objects, such as
+/// `Box<dyn DynDatagramSession>`.
+///
+/// [`Session<Anonymous>`](Session) receives datagrams without the destination of the sender so
+/// [`DynDatagramSession::recv_from()`] returns it only for repliable datagrams.
+pub trait DynDatagramSession: Send {
+    /// Get destination of the session, see [`Session::destination()`].
+    fn destination(&self) -> &str;
+
+    /// Get public destination of the session, see [`Session::public_destination()`].
+    fn public_destination(&self) -> &str;
+
+    /// Get nickname of the session.
+    fn nickname(&self) -> &str;
+
+    /// Get status of the session, see [`Session::status()`].
+    fn status(&self) -> SessionStatus;
+
+    /// Send datagram `buf` to `destination`.
+    fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()>;
+
+    /// Receive datagram into `buf`.
+    ///
+    /// Returns the number of bytes read and, for repliable datagrams, the destination who sent the
+    /// datagram.
+    fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, Option<String>)>;
+}
+
+// inherent methods of `Session` take precedence over the trait methods of the same name
+
+impl DynStreamSession for Session<style::Stream> {
+    fn destination(&self) -> &str {
+        self.destination()
+    }
+
+    fn public_destination(&self) -> &str {
+        self.public_destination()
+    }
+
+    fn nickname(&self) -> &str {
+        self.nickname()
+    }
+
+    fn status(&self) -> SessionStatus {
+        self.status()
+    }
+
+    fn connect(&mut self, destination: &str) -> crate::Result<Stream> {
+        self.connect(destination)
+    }
+
+    fn accept(&mut self) -> crate::Result<Stream> {
+        self.accept()
+    }
+
+    fn forward(&mut self, port: u16) -> crate::Result<()> {
+        self.forward(port)
+    }
+}
+
+impl DynDatagramSession for Session<style::Repliable> {
+    fn destination(&self) -> &str {
+        self.destination()
+    }
+
+    fn public_destination(&self) -> &str {
+        self.public_destination()
+    }
+
+    fn nickname(&self) -> &str {
+        self.nickname()
+    }
+
+    fn status(&self) -> SessionStatus {
+        self.status()
+    }
+
+    fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
+        self.send_to(buf, destination)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, Option<String>)> {
+        let (nread, destination) = self.recv_from(buf)?;
+
+        Ok((nread, Some(destination)))
+    }
+}
+
+impl DynDatagramSession for Session<style::Anonymous> {
+    fn destination(&self) -> &str {
+        self.destination()
+    }
+
+    fn public_destination(&self) -> &str {
+        self.public_destination()
+    }
+
+    fn nickname(&self) -> &str {
+        self.nickname()
+    }
+
+    fn status(&self) -> SessionStatus {
+        self.status()
+    }
+
+    fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
+        self.send_to(buf, destination)
+    }
+
+    fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, Option<String>)> {
+        Ok((self.recv(buf)?, None))
+    }
+}
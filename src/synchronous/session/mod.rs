@@ -19,14 +19,86 @@
 //! Synchronous SAMv3 session.
 
 use crate::{
-    options::SessionOptions, proto::session::SessionController, style::SessionStyle,
-    synchronous::stream::Stream,
+    address::split_port,
+    backoff::{dial_delay, BackoffCache},
+    error::{report_error, Error, I2pError, ProtocolError},
+    events::SessionEvent,
+    idle::Activity,
+    metrics::MetricsSnapshot,
+    options::{DestinationKind, LookupCredentials, SessionOptions, StreamOptions},
+    ports::{assign_port, datagram_port, PortAllocator},
+    proto::session::SessionController,
+    redaction::{RedactedLine, RedactedValue},
+    router_info::RouterInfo,
+    slots::{acquire_slot_blocking, StreamSlots},
+    status::{SessionState, SessionStatus, SocketAddrs},
+    synchronous::{
+        control::ControlSocket, events::Events, session::style::SessionStyle, stream::Stream,
+    },
+    throttle::RateLimiter,
+    tracing::{self, Span},
 };
 
-use std::{io::Write, net::TcpStream};
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Read, Write},
+    ops::Range,
+    sync::{mpsc::Receiver, Arc},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub use dynamic::{DynDatagramSession, DynStreamSession};
 
+mod dynamic;
 pub mod style;
 
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::session";
+
+/// How many times creating the session is retried in [`Session::reconfigure()`] while the router
+/// still has the old session.
+const RECONFIGURE_ATTEMPTS: usize = 10;
+
+/// How long [`Session::reconfigure()`] waits before retrying to create the session.
+const RECONFIGURE_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// How long the self-test of a datagram session waits for the test datagram.
+const SELF_TEST_DATAGRAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Create payload for a self-test of the session.
+///
+/// The payload is made unique with the current time so it can't be confused with data sent by
+/// other destinations or by an earlier self-test.
+fn self_test_payload(nickname: &str) -> Vec<u8> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+
+    format!("yosemite self-test {nickname} {nanos}").into_bytes()
+}
+
+/// Get the read timeout for the next datagram of a self-test which must finish by `deadline`.
+///
+/// Fails the self-test if `deadline` has passed.
+fn self_test_timeout(deadline: Instant) -> crate::Result<Duration> {
+    deadline
+        .checked_duration_since(Instant::now())
+        .filter(|timeout| !timeout.is_zero())
+        .ok_or(Error::SelfTestFailed("test datagram wasn't received"))
+}
+
+/// Fail the self-test if `error` is caused by the read timeout of the datagram socket.
+fn self_test_error(error: Error) -> Error {
+    match error {
+        Error::IoError(error)
+            if matches!(
+                error.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+            Error::SelfTestFailed("test datagram wasn't received"),
+        error => error,
+    }
+}
+
 /// SAMv3 session.
 ///
 /// `SessionStyle` defines the protocol of the session and can be one of three types:
@@ -47,7 +119,7 @@ pub mod style;
 /// **Connecting to remote destination and exchanging data with them**
 ///
 /// ```no_run
-/// use yosemite::{Session, style::Stream};
+/// use yosemite::blocking::{Session, style::Stream};
 /// use std::io::{Read, Write};
 ///
 /// fn main() -> yosemite::Result<()> {
@@ -70,7 +142,7 @@ pub mod style;
 /// **Echo server**
 ///
 /// ```no_run
-/// use yosemite::{Session, style::Repliable};
+/// use yosemite::blocking::{Session, style::Repliable};
 /// use std::io::{Read, Write};
 ///
 /// fn main() -> yosemite::Result<()> {
@@ -92,7 +164,7 @@ pub mod style;
 /// these datagrams.
 ///
 /// ```no_run
-/// use yosemite::{RouterApi, Session, style::Anonymous};
+/// use yosemite::blocking::{RouterApi, Session, style::Anonymous};
 /// use std::io::Write;
 ///
 /// fn main() -> yosemite::Result<()> {
@@ -109,7 +181,7 @@ pub mod style;
 /// ```
 ///
 /// See [examples](https://github.com/altonen/yosemite/tree/master/examples) for more details on how to use `yosemite`.
-pub struct Session<S> {
+pub struct Session<S: SessionStyle> {
     /// Session controller.
     controller: SessionController,
 
@@ -118,6 +190,52 @@ pub struct Session<S> {
 
     /// Session style context.
     context: S,
+
+    /// Session events.
+    events: Events,
+
+    /// Session metrics.
+    metrics: MetricsSnapshot,
+
+    /// Activity of the session.
+    activity: Activity,
+
+    /// Allocator of ephemeral ports, if the session has an ephemeral port range.
+    ports: Option<PortAllocator>,
+
+    /// Rate limiter of the streams, if the session has rate limits.
+    limiter: Option<RateLimiter>,
+
+    /// Stream slots, if the session limits the number of streams.
+    slots: Option<StreamSlots>,
+
+    /// Backoff cache of unreachable destinations, if enabled for the session.
+    backoff: Option<BackoffCache>,
+
+    /// Span that log events of the session are attached to.
+    span: Span,
+
+    /// ID of the most recently opened virtual stream.
+    stream_id: usize,
+
+    /// Aliases of remote destinations.
+    aliases: HashMap<String, String>,
+
+    /// State of the session.
+    state: SessionState,
+}
+
+impl<S: SessionStyle + fmt::Debug> fmt::Debug for Session<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Session")
+            .field("nickname", &self.options.nickname)
+            .field(
+                "destination",
+                &format_args!("{}", RedactedValue(self.controller.destination())),
+            )
+            .field("style", &self.context)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<S: SessionStyle> Session<S> {
@@ -125,114 +243,901 @@ impl<S: SessionStyle> Session<S> {
     ///
     /// See [`SessionOptions`] for more details on how to configure the session.
     pub fn new(options: SessionOptions) -> crate::Result<Self> {
-        let mut controller = SessionController::new(options.clone())?;
+        let span = tracing::info_span!(
+            target: LOG_TARGET,
+            "session",
+            nickname = %options.nickname,
+            destination = tracing::field::Empty,
+        );
+        let ports = options
+            .ephemeral_ports
+            .clone()
+            .map(PortAllocator::new)
+            .transpose()
+            .inspect_err(report_error)?;
+        let limiter = RateLimiter::new(&options).inspect_err(report_error)?;
+        let slots = StreamSlots::new(&options).inspect_err(report_error)?;
+        let backoff = options
+            .dial_backoff
+            .map(BackoffCache::new)
+            .transpose()
+            .inspect_err(report_error)?;
+        let (controller, context) =
+            span.in_scope(|| Self::create_session(&options)).inspect_err(report_error)?;
+        span.record(
+            "destination",
+            tracing::field::display(RedactedValue(controller.destination())),
+        );
+
+        let session = Self {
+            controller,
+            options,
+            context,
+            events: Events::default(),
+            metrics: MetricsSnapshot::default(),
+            activity: Activity::new(),
+            ports,
+            limiter,
+            slots,
+            backoff,
+            span,
+            stream_id: 0usize,
+            aliases: HashMap::new(),
+            state: SessionState::Active,
+        };
+
+        if let Some(threshold) = session.options.idle_threshold {
+            session.watch_idle(threshold);
+        }
+
+        Ok(session)
+    }
+
+    /// Report the session idle after `threshold` without activity.
+    ///
+    /// The watcher thread exits once the session has been dropped.
+    fn watch_idle(&self, threshold: Duration) {
+        let mut watcher = self.activity.watcher(threshold);
+        let events = self.events.clone();
+        let span = self.span.clone();
+
+        std::thread::spawn(move || {
+            let _span = span.enter();
+
+            while let Some(delay) = watcher.poll(|event| events.emit(event)) {
+                std::thread::sleep(delay);
+            }
+        });
+    }
+
+    /// Handshake with the router and create the session.
+    fn create_session(options: &SessionOptions) -> crate::Result<(SessionController, S)> {
+        options.check_signature_type()?;
+
         let mut context = S::new(options.clone())?;
+        let controller = Self::create_session_on(options, &mut context)?;
+
+        Ok((controller, context))
+    }
+
+    /// Handshake with the router over the control socket of `context` and create the session.
+    fn create_session_on(
+        options: &SessionOptions,
+        context: &mut S,
+    ) -> crate::Result<SessionController> {
+        let mut controller = SessionController::new(options.clone())?;
 
         // send handhake to router
+        //
+        // if handshakes are pipelined, the handshake is sent together with `SESSION CREATE`
         let command = controller.handshake_session()?;
-        context.write_command(&command)?;
 
-        // read handshake response and create new session
-        let response = context.read_command()?;
-        controller.handle_response(&response)?;
+        if !options.pipeline_handshake {
+            context.write_command(command)?;
+
+            let response = context.read_command()?;
+            controller.handle_response(response)?;
+        }
 
         // create new session
         let command = controller.create_session(context.create_session())?;
-        context.write_command(&command)?;
+        context.write_command(command)?;
 
-        // read handshake response and create new session
+        if options.pipeline_handshake {
+            let response = context.read_command()?;
+            controller.handle_response(response)?;
+        }
+
+        // read session status
         let response = context.read_command()?;
-        controller.handle_response(&response)?;
+        controller.handle_response(response)?;
 
-        Ok(Self {
-            controller,
-            options,
-            context,
-        })
+        Ok(controller)
     }
 
     /// Get destination of the [`Session`].
+    ///
+    /// The destination is followed by the private keys of the session and it can be used to
+    /// recreate the session with [`DestinationKind::Persistent`]. It must not be shared with peers,
+    /// see [`Session::public_destination()`].
     pub fn destination(&self) -> &str {
         self.controller.destination()
     }
+
+    /// Get public destination of the [`Session`].
+    ///
+    /// This is the destination peers see for streams and datagrams of the session and which they
+    /// connect to. Unlike [`Session::destination()`], it doesn't contain the private keys.
+    pub fn public_destination(&self) -> &str {
+        self.controller.public_destination()
+    }
+
+    /// Get router details reported during the handshake of the [`Session`].
+    ///
+    /// The negotiated version can be used to enable features only supported by newer routers.
+    pub fn router_info(&self) -> &RouterInfo {
+        self.controller.router_info().expect("session to be handshaked")
+    }
+
+    /// Get options the [`Session`] was created with.
+    pub fn options(&self) -> &SessionOptions {
+        &self.options
+    }
+
+    /// Get nickname of the [`Session`].
+    ///
+    /// The nickname is the ID of the session in SAMv3 commands.
+    pub fn nickname(&self) -> &str {
+        &self.options.nickname
+    }
+
+    /// Register `alias` for `destination`.
+    ///
+    /// Aliases are resolved by [`Session::connect()`], [`Session::connect_to_port()`] and
+    /// `send_to()` before the destination is passed to the router, so peers discovered at runtime
+    /// can be referred to with short, stable names. The alias can be suffixed with `:port` in
+    /// [`Session::connect()`].
+    ///
+    /// Returns the previous destination of `alias`, if any.
+    pub fn add_alias(
+        &mut self,
+        alias: impl Into<String>,
+        destination: impl Into<String>,
+    ) -> Option<String> {
+        self.aliases.insert(alias.into(), destination.into())
+    }
+
+    /// Remove `alias`, returning its destination.
+    pub fn remove_alias(&mut self, alias: &str) -> Option<String> {
+        self.aliases.remove(alias)
+    }
+
+    /// Get destination of `alias`.
+    pub fn alias(&self, alias: &str) -> Option<&str> {
+        self.aliases.get(alias).map(String::as_str)
+    }
+
+    /// Get snapshot of the metrics of the [`Session`].
+    pub fn metrics_snapshot(&self) -> MetricsSnapshot {
+        self.metrics
+    }
+
+    /// Get snapshot of the status of the [`Session`].
+    pub fn status(&self) -> SessionStatus {
+        SessionStatus {
+            state: self.state,
+            style: self.context.create_session().style,
+            destination: self.public_destination().to_string(),
+            b32_address: crate::b32_address(self.public_destination()),
+            nickname: self.options.nickname.clone(),
+            forwarding: self.context.forwarding(),
+        }
+    }
+
+    /// Get local and peer address of the control connection of the [`Session`].
+    ///
+    /// Virtual streams and forwarding use connections of their own, see
+    /// [`Stream::socket_addrs()`].
+    pub fn control_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.context.control_socket_addrs()
+    }
+
+    /// Get the time elapsed since the last activity of the [`Session`].
+    ///
+    /// See [`SessionOptions::idle_threshold`] for what counts as activity.
+    pub fn idle_time(&self) -> Duration {
+        self.activity.idle_time()
+    }
+
+    /// Call `hook` with the idle time of the [`Session`] when it becomes idle.
+    ///
+    /// The hook is called from a background thread once [`SessionOptions::idle_threshold`] has
+    /// passed without activity, before [`SessionEvent::Idle`] is emitted. It replaces any
+    /// previously set hook and it's never called if the threshold is not set.
+    pub fn set_idle_hook(&mut self, hook: impl Fn(Duration) + Send + Sync + 'static) {
+        self.activity.set_hook(Arc::new(hook));
+    }
+
+    /// Subscribe to events of the [`Session`].
+    ///
+    /// Each call returns a new receiver which receives the events emitted after the call.
+    pub fn events(&self) -> Receiver<SessionEvent> {
+        self.events.subscribe(SessionEvent::Created {
+            destination: self.public_destination().to_string(),
+        })
+    }
+
+    /// Send raw `command` on the control connection of the session and return the reply of the
+    /// router, without the line terminator.
+    ///
+    /// This is meant for experimenting with SAMv3 commands that `yosemite` doesn't support. Line
+    /// breaks cannot be represented in a SAMv3 command and are replaced with spaces.
+    ///
+    /// ### Warning
+    ///
+    /// The command bypasses the state machine of the session. A command that changes the state of
+    /// the session on the router, such as `SESSION ADD` or `QUIT`, or a command for which the
+    /// router doesn't send exactly one reply leaves the session out of sync with the router and
+    /// subsequent calls may fail or hang.
+    pub fn send_raw_command(&mut self, command: &str) -> crate::Result<String> {
+        let _span = self.span.enter();
+        let command = command.trim_end().replace(['\r', '\n'], " ");
+
+        tracing::debug!(
+            target: LOG_TARGET,
+            command = %RedactedLine(&command),
+            "send raw command",
+        );
+
+        let result = self
+            .context
+            .write_command(format!("{command}\n").as_bytes())
+            .and_then(|_| Ok(self.context.read_command()?.to_string()));
+
+        if let Err(error) = &result {
+            report_error(error);
+
+            if let Error::IoError(_) = error {
+                self.state = SessionState::Disconnected;
+                self.events.emit(SessionEvent::Disconnected);
+            }
+        }
+
+        result
+    }
+
+    /// Recreate the session with the tunnel parameters of `options`.
+    ///
+    /// The session is recreated with the same nickname and destination keys, so the destination
+    /// stays the same. Only the tunnel parameters of `options` are applied:
+    /// [`SessionOptions::inbound_len`], [`SessionOptions::inbound_len_variance`],
+    /// [`SessionOptions::inbound_quantity`], their outbound counterparts and
+    /// [`SessionOptions::publish`]. Other options keep the values the session was created with.
+    ///
+    /// Virtual streams of the old session are closed by the router and stream forwarding must be
+    /// requested again with [`Session::forward()`]. If recreating the session fails, the old
+    /// session may already have been closed and the session should be dropped.
+    pub fn reconfigure(&mut self, options: SessionOptions) -> crate::Result<()> {
+        let span = self.span.clone();
+
+        span.in_scope(|| self.reconfigure_inner(options)).inspect_err(report_error)
+    }
+
+    fn reconfigure_inner(&mut self, options: SessionOptions) -> crate::Result<()> {
+        let options = self.options.with_tunnel_parameters(&options);
+        let create_options = SessionOptions {
+            destination: DestinationKind::Persistent {
+                private_key: match &options.destination {
+                    DestinationKind::Persistent { private_key } => private_key.clone(),
+                    DestinationKind::Transient => self.controller.destination().to_string(),
+                },
+            },
+            ..options.clone()
+        };
+        let mut attempts = 0;
+
+        tracing::info!(
+            target: LOG_TARGET,
+            inbound_len = %options.inbound_len,
+            inbound_quantity = %options.inbound_quantity,
+            outbound_len = %options.outbound_len,
+            outbound_quantity = %options.outbound_quantity,
+            publish = %options.publish,
+            "reconfigure session",
+        );
+
+        // the router destroys the old session once its control connection has been closed but
+        // possibly not before the new session with the same nickname and keys is created
+        let controller = loop {
+            self.context.reconnect(create_options.clone())?;
+
+            match Self::create_session_on(&create_options, &mut self.context) {
+                Err(Error::Protocol(ProtocolError::Router(
+                    I2pError::DuplicateId | I2pError::DuplicateDest,
+                ))) if attempts < RECONFIGURE_ATTEMPTS => {
+                    attempts += 1;
+                    std::thread::sleep(RECONFIGURE_RETRY_DELAY);
+                }
+                result => break result?,
+            }
+        };
+
+        self.controller = controller;
+        self.options = options;
+        self.state = SessionState::Active;
+
+        Ok(())
+    }
+
+    /// Close the session.
+    ///
+    /// `QUIT` is sent to the router, which destroys the session and releases its nickname, and the
+    /// connections of the session to the router are closed. Inbound virtual streams are no longer
+    /// forwarded. The call returns once the router has closed the control connection of the
+    /// session.
+    ///
+    /// `QUIT` was introduced in SAMv3.2, older routers destroy the session when its control
+    /// connection is closed. Closing a session that is already closed is a no-op.
+    ///
+    /// Virtual streams that have already been opened are not closed and operations on a closed
+    /// session fail since the router no longer knows the session. If the session is dropped
+    /// without closing it, `QUIT` is sent on a best-effort basis.
+    pub fn close(&mut self) -> crate::Result<()> {
+        let span = self.span.clone();
+
+        span.in_scope(|| self.close_inner()).inspect_err(report_error)
+    }
+
+    fn close_inner(&mut self) -> crate::Result<()> {
+        if self.state == SessionState::Closed {
+            return Ok(());
+        }
+
+        tracing::info!(
+            target: LOG_TARGET,
+            "close session",
+        );
+
+        let result = match self.state {
+            SessionState::Active => self.quit(),
+            _ => Ok(()),
+        };
+
+        self.context.close();
+        self.state = SessionState::Closed;
+
+        result
+    }
+
+    /// Send `QUIT` over the control connection, if the router supports it.
+    ///
+    /// I/O errors are ignored since they mean that the router has already closed the connection.
+    fn quit(&mut self) -> crate::Result<()> {
+        let command = match self.controller.quit() {
+            Ok(command) => command,
+            Err(ProtocolError::UnsupportedByRouter { .. }) => return Ok(()),
+            Err(error) => return Err(error.into()),
+        };
+
+        if self.context.write_command(command).is_err() {
+            return Ok(());
+        }
+
+        // the router may close the connection without acknowledging `QUIT`
+        match self.context.read_command() {
+            Ok(response) => self.controller.handle_response(response).map_err(From::from),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+impl<S: SessionStyle> Drop for Session<S> {
+    fn drop(&mut self) {
+        if self.state != SessionState::Active {
+            return;
+        }
+
+        // the connection is closed regardless, so failures to send `QUIT` are ignored
+        if let Ok(command) = self.controller.quit() {
+            let _ = self.context.write_command(command);
+        }
+    }
 }
 
 impl Session<style::Stream> {
+    /// Create span for the next virtual stream of the session.
+    fn stream_span(&mut self, destination: Option<&str>) -> Span {
+        self.stream_id += 1;
+
+        tracing::debug_span!(
+            target: LOG_TARGET,
+            parent: &self.span,
+            "stream",
+            id = self.stream_id,
+            destination = destination.map(|destination| tracing::field::display(RedactedValue(destination))),
+        )
+    }
+
+    /// Open control connection for a stream and handshake it.
+    ///
+    /// If handshakes are pipelined, the handshake is sent with the next command instead.
+    fn handshake_stream(&mut self) -> crate::Result<ControlSocket> {
+        let mut stream =
+            ControlSocket::from_options(&self.options)?.with_events(self.events.clone());
+        let command = self.controller.handshake_stream()?;
+
+        if !self.options.pipeline_handshake {
+            stream.write_command(command)?;
+
+            let response = stream.read_response()?;
+            self.controller.handle_response(response)?;
+        }
+
+        Ok(stream)
+    }
+
+    /// Open virtual stream to `destination` over a pooled control connection, if there is one.
+    ///
+    /// Returns `Ok(None)` if the pool is empty or if the pooled connection has been closed, in
+    /// which case the connection is discarded and the stream must be opened over a new one.
+    fn connect_pooled(
+        &mut self,
+        destination: &str,
+        port: Option<u16>,
+        options: &StreamOptions,
+    ) -> crate::Result<Option<ControlSocket>> {
+        let Some(mut stream) = self.context.take_pooled() else {
+            return Ok(None);
+        };
+
+        self.controller.resume_stream()?;
+        let command = self.connect_command(destination, port, options)?;
+
+        let result = match stream.write_command(command) {
+            Ok(()) => stream.read_response(),
+            Err(error) => Err(error),
+        };
+
+        match result {
+            Ok(response) => {
+                self.controller.handle_response(response)?;
+                Ok(Some(stream))
+            }
+            Err(error) => {
+                tracing::debug!(
+                    target: LOG_TARGET,
+                    ?error,
+                    "pooled control connection failed, discarding",
+                );
+                report_error(&error);
+                self.controller.reset_stream()?;
+
+                Ok(None)
+            }
+        }
+    }
+
+    /// Fill the pool of handshaked control connections up to `connect_pool_size`.
+    fn fill_pool(&mut self) -> crate::Result<()> {
+        while self.context.pooled() < self.options.connect_pool_size {
+            let mut stream =
+                ControlSocket::from_options(&self.options)?.with_events(self.events.clone());
+            let command = self.controller.handshake_stream()?;
+            stream.write_command(command)?;
+
+            let response = stream.read_response()?;
+            self.controller.handle_response(response)?;
+            self.controller.reset_stream()?;
+
+            self.context.store_pooled(stream);
+        }
+
+        Ok(())
+    }
+
+    /// Read reply to `STREAM CONNECT`/`STREAM ACCEPT`/`STREAM FORWARD`.
+    ///
+    /// If handshakes are pipelined, the handshake reply is read first. Silent commands have no
+    /// reply so only the handshake reply, if any, is read.
+    fn read_stream_status(
+        &mut self,
+        stream: &mut ControlSocket,
+        silent: bool,
+    ) -> crate::Result<()> {
+        if self.options.pipeline_handshake {
+            let response = stream.read_response()?;
+            self.controller.handle_response(response)?;
+        }
+
+        if silent {
+            return Ok(());
+        }
+
+        let response = stream.read_response()?;
+        self.controller.handle_response(response)?;
+
+        Ok(())
+    }
+
     /// Create new outbound virtual stream to `destination`.
     ///
     /// Destination can
     ///  * hostname such as `host.i2p`
     ///  * base32-encoded session received from
-    ///    [`RouterApi::lookup_name()`](crate::RouterApi::lookup_name)
+    ///    [`RouterApi::lookup_name()`](crate::blocking::RouterApi::lookup_name)
     ///  * base64-encoded string received from, e.g., [`Session::new()`]
+    ///
+    /// Host names and base32 addresses can be suffixed with `:port`, e.g., `service.i2p:8080`, to
+    /// connect to a specific port of the destination like [`Session::connect_to_port()`].
     pub fn connect(&mut self, destination: &str) -> crate::Result<Stream> {
-        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", self.options.samv3_tcp_port))?;
-        let command = self.controller.handshake_stream()?;
-        stream.write_all(&command)?;
+        let (destination, port) = split_port(destination);
+        let alias = self.aliases.get(destination).cloned();
+
+        let options = StreamOptions::default();
+
+        self.connect_inner(alias.as_deref().unwrap_or(destination), port, &options)
+    }
+
+    /// Create new outbound virtual stream to `port` of `destination`.
+    ///
+    /// See [`Session::connect()`] for supported destination formats.
+    pub fn connect_to_port(&mut self, destination: &str, port: u16) -> crate::Result<Stream> {
+        let alias = self.aliases.get(destination).cloned();
 
-        let (mut stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+        let options = StreamOptions::default();
 
-        let command = self.controller.create_stream(&destination)?;
-        stream.write_all(&command)?;
+        self.connect_inner(
+            alias.as_deref().unwrap_or(destination),
+            Some(port),
+            &options,
+        )
+    }
+
+    /// Create new outbound virtual stream to encrypted `destination`.
+    ///
+    /// `credentials` are used by the router to look up the lease set of `destination` if it's
+    /// hosted with [`LeaseSetType::Encrypted`](crate::LeaseSetType::Encrypted) and requires a
+    /// lookup secret or client authorization. If the router rejects the credentials,
+    /// [`Error::CredentialsRejected`] is returned.
+    ///
+    /// See [`Session::connect()`] for supported destination formats.
+    pub fn connect_with_credentials(
+        &mut self,
+        destination: &str,
+        credentials: &LookupCredentials,
+    ) -> crate::Result<Stream> {
+        let (destination, port) = split_port(destination);
+        let alias = self.aliases.get(destination).cloned();
+
+        let options = StreamOptions {
+            credentials: Some(credentials.clone()),
+            ..Default::default()
+        };
 
-        let (stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+        self.connect_inner(alias.as_deref().unwrap_or(destination), port, &options)
+    }
+
+    /// Create new outbound virtual stream to `destination` with stream `options`.
+    ///
+    /// If [`StreamOptions::silent`] is set, the stream is returned as soon as `STREAM CONNECT` has
+    /// been sent and connection errors are only seen when using the stream.
+    ///
+    /// See [`Session::connect()`] for supported destination formats.
+    pub fn connect_with_options(
+        &mut self,
+        destination: &str,
+        options: &StreamOptions,
+    ) -> crate::Result<Stream> {
+        let (destination, port) = split_port(destination);
+        let alias = self.aliases.get(destination).cloned();
 
-        Ok(Stream::from_stream(stream, destination.to_string()))
+        self.connect_inner(alias.as_deref().unwrap_or(destination), port, options)
+    }
+
+    /// Serialize `STREAM CONNECT` to `destination`.
+    fn connect_command(
+        &mut self,
+        destination: &str,
+        port: Option<u16>,
+        options: &StreamOptions,
+    ) -> Result<&[u8], ProtocolError> {
+        let with_options = options.silent || options.from_port.is_some();
+
+        match (port, with_options, &options.credentials) {
+            (port, false, Some(credentials)) =>
+                self.controller.create_stream_with_credentials(destination, port, credentials),
+            (Some(port), false, None) => self.controller.create_stream_to_port(destination, port),
+            (None, false, None) => self.controller.create_stream(destination),
+            (port, true, _) =>
+                self.controller.create_stream_with_options(destination, port, options),
+        }
+    }
+
+    /// Create new outbound virtual stream and record the result.
+    fn connect_inner(
+        &mut self,
+        destination: &str,
+        port: Option<u16>,
+        options: &StreamOptions,
+    ) -> crate::Result<Stream> {
+        let span = self.stream_span(Some(destination));
+        let mut result = span.in_scope(|| self.open_stream(destination, port, options));
+        if let Some(backoff) = &self.backoff {
+            backoff.record(destination, &result);
+        }
+
+        if options.credentials.is_some() {
+            result = result.map_err(Error::into_credentials_error);
+        }
+        self.metrics.record_connect(&result);
+        self.activity.record();
+
+        if let Err(error) = &result {
+            report_error(error);
+        }
+
+        result
     }
 
     /// Accept inbound virtual stream.
     ///
     /// The function call will fail if [`Session::forward()`] has been called before.
     pub fn accept(&mut self) -> crate::Result<Stream> {
-        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", self.options.samv3_tcp_port))?;
-        let command = self.controller.handshake_stream()?;
-        stream.write_all(&command)?;
+        self.accept_with_options(&StreamOptions::default())
+    }
 
-        let (mut stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+    /// Accept inbound virtual stream with stream `options`.
+    ///
+    /// If [`StreamOptions::silent`] is set, the router doesn't send the destination of the remote
+    /// peer and [`Stream::remote_destination()`] of the accepted stream is empty.
+    ///
+    /// The function call will fail if [`Session::forward()`] has been called before.
+    pub fn accept_with_options(&mut self, options: &StreamOptions) -> crate::Result<Stream> {
+        let span = self.stream_span(None);
+        let result = span.in_scope(|| self.accept_stream(options));
+        self.metrics.record_accept(&result);
+        self.activity.record();
 
-        let command = self.controller.accept_stream()?;
-        stream.write_all(&command)?;
+        match &result {
+            Ok(stream) => self.events.emit(SessionEvent::StreamAccepted {
+                destination: stream.remote_destination().to_string(),
+            }),
+            Err(error) => report_error(error),
+        }
+
+        result
+    }
+
+    /// Open outbound virtual stream to `destination`, optionally to a specific `port`.
+    ///
+    /// Silent streams are not opened over pooled connections as a closed pooled connection
+    /// cannot be detected without a reply from the router.
+    ///
+    /// If the session has an ephemeral port range, the stream is opened from a port leased from it
+    /// unless `options` specify the port.
+    ///
+    /// If the session limits the number of streams, a slot is taken before the stream is opened.
+    /// If `destination` is in backoff, the call fails or waits for the backoff to end.
+    fn open_stream(
+        &mut self,
+        destination: &str,
+        port: Option<u16>,
+        options: &StreamOptions,
+    ) -> crate::Result<Stream> {
+        if let Some(delay) = dial_delay(self.backoff.as_ref(), destination)? {
+            std::thread::sleep(delay);
+        }
+
+        let slot = acquire_slot_blocking(self.slots.as_ref())?;
+        let (options, local_port) = assign_port(self.ports.as_ref(), options)?;
+        let options = options.as_ref();
+
+        let pooled = match options.silent {
+            true => None,
+            false => self.connect_pooled(destination, port, options)?,
+        };
+
+        let stream = match pooled {
+            Some(stream) => stream,
+            None => {
+                let mut stream = self.handshake_stream()?;
+
+                let command = self.connect_command(destination, port, options)?;
+                stream.write_command(command)?;
+                self.read_stream_status(&mut stream, options.silent)?;
+
+                stream
+            }
+        };
+
+        if let Err(error) = self.fill_pool() {
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?error,
+                "failed to fill control connection pool",
+            );
+            report_error(&error);
+            self.controller.reset_stream()?;
+        }
+
+        Ok(Stream::from_stream(
+            stream.into_inner(),
+            destination.to_string(),
+            self.options.stream_write_buffer_size,
+        )
+        .with_port(local_port)
+        .with_slot(slot)
+        .with_throttles(self.limiter.as_ref()))
+    }
+
+    /// Wait for inbound virtual stream.
+    ///
+    /// Streams rejected by [`SessionOptions::accept_filter`] are closed and the next stream is
+    /// accepted in their place.
+    fn accept_stream(&mut self, options: &StreamOptions) -> crate::Result<Stream> {
+        let slot = acquire_slot_blocking(self.slots.as_ref())?;
+
+        loop {
+            let mut stream = self.handshake_stream()?;
+
+            let command = self.controller.accept_stream_with_options(options)?;
+            stream.write_command(command)?;
+            self.read_stream_status(&mut stream, options.silent)?;
 
-        let (stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+            if options.silent {
+                return Ok(Stream::from_stream(
+                    stream.into_inner(),
+                    String::new(),
+                    self.options.stream_write_buffer_size,
+                )
+                .with_slot(slot)
+                .with_throttles(self.limiter.as_ref()));
+            }
 
-        // read remote's destination which signals that the connection is open
-        let (stream, response) = read_response!(stream);
+            // read remote's destination which signals that the connection is open
+            //
+            // the server may have bundled data after the newline but it stays in the reader's
+            // buffer and is returned to the client on the first read from the stream
+            let line = stream.read_response()?.trim_end();
+            let remote_destination =
+                line.split_once(' ').map_or(line, |(destination, _)| destination).to_string();
 
-        Ok(Stream::from_stream(stream, response.to_string()))
+            if self
+                .options
+                .accept_filter
+                .as_ref()
+                .is_some_and(|filter| !filter.accepts(&remote_destination))
+            {
+                tracing::debug!(
+                    target: LOG_TARGET,
+                    remote_destination = %RedactedValue(&remote_destination),
+                    "inbound stream rejected by accept filter",
+                );
+                self.metrics.record_rejected();
+                continue;
+            }
+
+            return Ok(Stream::from_stream(
+                stream.into_inner(),
+                remote_destination,
+                self.options.stream_write_buffer_size,
+            )
+            .with_slot(slot)
+            .with_throttles(self.limiter.as_ref()));
+        }
     }
 
     /// Forward inbound virtual streams to a TCP listener at `port`.
     ///
     /// The function call will fail if [`Session::accept()`] has been called before.
     pub fn forward(&mut self, port: u16) -> crate::Result<()> {
-        let mut stream = TcpStream::connect(format!("127.0.0.1:{}", self.options.samv3_tcp_port))?;
-        let command = self.controller.handshake_stream()?;
-        stream.write_all(&command)?;
+        let span = self.stream_span(None);
 
-        let (mut stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+        span.in_scope(|| self.forward_stream(port)).inspect_err(report_error)
+    }
 
-        let command = self.controller.forward_stream(port)?;
-        stream.write_all(&command)?;
+    /// Forward inbound virtual streams to `port`.
+    fn forward_stream(&mut self, port: u16) -> crate::Result<()> {
+        let mut stream = self.handshake_stream()?;
 
-        let (stream, response) = read_response!(stream);
-        self.controller.handle_response(&response)?;
+        let command = self.controller.forward_stream(port)?;
+        stream.write_command(command)?;
+        self.read_stream_status(&mut stream, false)?;
 
         // store the command stream into the session context so the router keeps forwarding streams
-        style::Stream::store_forwarded(&mut self.context, stream);
+        style::Stream::store_forwarded(&mut self.context, stream, port, self.events.clone())?;
 
         Ok(())
     }
+
+    /// Test the session by opening a virtual stream to its own destination.
+    ///
+    /// Data written to the stream is echoed back over the inbound stream and compared to what was
+    /// sent, which checks that the tunnels of the session are built, its lease set has been
+    /// published and the SAM bridge relays data.
+    ///
+    /// The test accepts one inbound stream, so it should be run before the session starts
+    /// accepting streams. If another destination connects to the session while the test is
+    /// running, its stream is dropped and the test fails with [`Error::SelfTestFailed`]. The test
+    /// has no timeout of its own and blocks until the router has opened the stream.
+    ///
+    /// Returns the round-trip time of the test data.
+    pub fn self_test(&mut self) -> crate::Result<Duration> {
+        self.self_test_stream().inspect_err(report_error)
+    }
+
+    /// Run self-test over a virtual stream.
+    ///
+    /// `STREAM ACCEPT` is sent before the stream is opened so that both ends of the stream can be
+    /// handled by the calling thread.
+    fn self_test_stream(&mut self) -> crate::Result<Duration> {
+        let destination = self.public_destination().to_string();
+        let payload = self_test_payload(&self.options.nickname);
+
+        let mut stream = self.handshake_stream()?;
+        let command = self.controller.accept_stream()?;
+        stream.write_command(command)?;
+        self.read_stream_status(&mut stream, false)?;
+
+        let mut outbound = self.connect(&destination)?;
+
+        let line = stream.read_response()?.trim_end();
+        let remote_destination = line.split_once(' ').map_or(line, |(destination, _)| destination);
+        if remote_destination != destination {
+            return Err(Error::SelfTestFailed(
+                "stream accepted from another destination",
+            ));
+        }
+
+        let inbound = Ok(Stream::from_stream(
+            stream.into_inner(),
+            destination,
+            self.options.stream_write_buffer_size,
+        ));
+        self.metrics.record_accept(&inbound);
+        let mut inbound = inbound?;
+
+        let started = Instant::now();
+        let mut buffer = vec![0u8; payload.len()];
+
+        outbound.write_all(&payload)?;
+        outbound.flush()?;
+        inbound.read_exact(&mut buffer)?;
+        inbound.write_all(&buffer)?;
+        inbound.flush()?;
+
+        buffer.fill(0);
+        outbound.read_exact(&mut buffer)?;
+
+        if buffer != payload {
+            return Err(Error::SelfTestFailed("echoed data doesn't match"));
+        }
+
+        Ok(started.elapsed())
+    }
 }
 
 impl Session<style::Repliable> {
+    /// Get local address of the datagram socket of the [`Session`] and the address of the
+    /// SAMv3 UDP port datagrams are sent to.
+    pub fn datagram_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.context.datagram_socket_addrs()
+    }
+
     /// Send data on the socket to given `destination`.
+    ///
+    /// `destination` can be an alias registered with [`Session::add_alias()`]. If the session has
+    /// [`SessionOptions::ephemeral_ports`], the datagram is sent from the next port of the range.
     pub fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
-        style::Repliable::send_to(&mut self.context, buf, destination)
+        let destination = self.aliases.get(destination).map_or(destination, String::as_str);
+        let from_port = datagram_port(self.ports.as_ref()).inspect_err(report_error)?;
+
+        style::Repliable::send_to(&mut self.context, buf, destination, from_port)
+            .inspect_err(report_error)?;
+        self.metrics.record_datagram_sent(buf.len());
+        self.activity.record();
+
+        Ok(())
     }
 
     /// Receive a single datagram on the socket.
@@ -241,14 +1146,89 @@ impl Session<style::Repliable> {
     ///
     /// Returns the number of bytes read and the destination who sent the datagram.
     pub fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, String)> {
-        style::Repliable::recv_from(&mut self.context, buf)
+        let (nread, destination) =
+            style::Repliable::recv_from(&mut self.context, buf).inspect_err(report_error)?;
+        self.metrics.record_datagram_received(nread);
+        self.activity.record();
+
+        Ok((nread, destination))
+    }
+
+    /// Receive a single datagram on the socket without copying it.
+    ///
+    /// The datagram is received directly into `buf` which must be of sufficient size to hold
+    /// both the datagram header and the entire datagram.
+    ///
+    /// Returns the range of `buf` holding the datagram and the destination who sent the datagram.
+    pub fn recv_from_in_place(&mut self, buf: &mut [u8]) -> crate::Result<(Range<usize>, String)> {
+        let (range, destination) = style::Repliable::recv_from_in_place(&mut self.context, buf)
+            .inspect_err(report_error)?;
+        self.metrics.record_datagram_received(range.len());
+        self.activity.record();
+
+        Ok((range, destination))
+    }
+
+    /// Test the session by sending a datagram to its own destination.
+    ///
+    /// The datagram is received back and compared to what was sent, which checks that the tunnels
+    /// of the session are built, its lease set has been published and the SAM bridge relays
+    /// datagrams.
+    ///
+    /// Datagrams received from other destinations while the test is running are discarded, so the
+    /// test should be run before the session starts receiving datagrams. Datagrams are not
+    /// reliable and if the test datagram isn't received within 60 seconds, the test fails with
+    /// [`Error::SelfTestFailed`].
+    ///
+    /// Returns the round-trip time of the datagram.
+    pub fn self_test(&mut self) -> crate::Result<Duration> {
+        let started = Instant::now();
+        let result = self.self_test_datagram(started + SELF_TEST_DATAGRAM_TIMEOUT);
+        self.context.set_read_timeout(None)?;
+
+        result.map(|_| started.elapsed()).inspect_err(report_error)
+    }
+
+    /// Send the self-test datagram and wait until it's received or `deadline` has passed.
+    fn self_test_datagram(&mut self, deadline: Instant) -> crate::Result<()> {
+        let destination = self.public_destination().to_string();
+        let payload = self_test_payload(&self.options.nickname);
+        let mut buffer = vec![0u8; payload.len() + 1];
+
+        self.send_to(&payload, &destination)?;
+
+        loop {
+            self.context.set_read_timeout(Some(self_test_timeout(deadline)?))?;
+            let (nread, sender) = self.recv_from(&mut buffer).map_err(self_test_error)?;
+
+            if sender == destination && buffer[..nread] == payload[..] {
+                return Ok(());
+            }
+        }
     }
 }
 
 impl Session<style::Anonymous> {
+    /// Get local address of the datagram socket of the [`Session`] and the address of the
+    /// SAMv3 UDP port datagrams are sent to.
+    pub fn datagram_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.context.datagram_socket_addrs()
+    }
+
     /// Send data on the socket to given `destination`.
+    ///
+    /// `destination` can be an alias registered with [`Session::add_alias()`]. If the session has
+    /// [`SessionOptions::ephemeral_ports`], the datagram is sent from the next port of the range.
     pub fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
-        style::Anonymous::send_to(&mut self.context, buf, destination)
+        let destination = self.aliases.get(destination).map_or(destination, String::as_str);
+        let from_port = datagram_port(self.ports.as_ref()).inspect_err(report_error)?;
+
+        style::Anonymous::send_to(&mut self.context, buf, destination, from_port)
+            .inspect_err(report_error)?;
+        self.metrics.record_datagram_sent(buf.len());
+        self.activity.record();
+
+        Ok(())
     }
 
     /// Receive a single datagram on the socket.
@@ -257,6 +1237,100 @@ impl Session<style::Anonymous> {
     ///
     /// Returns the number of bytes read.
     pub fn recv(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
-        style::Anonymous::recv(&mut self.context, buf)
+        let nread = style::Anonymous::recv(&mut self.context, buf).inspect_err(report_error)?;
+        self.metrics.record_datagram_received(nread);
+        self.activity.record();
+
+        Ok(nread)
+    }
+
+    /// Test the session by sending a datagram to its own destination.
+    ///
+    /// The datagram is received back and compared to what was sent, which checks that the tunnels
+    /// of the session are built, its lease set has been published and the SAM bridge relays
+    /// datagrams.
+    ///
+    /// Datagrams received while the test is running are discarded until the test datagram
+    /// arrives, so the test should be run before the session starts receiving datagrams.
+    /// Datagrams are not reliable and if the test datagram isn't received within 60 seconds, the
+    /// test fails with [`Error::SelfTestFailed`].
+    ///
+    /// Returns the round-trip time of the datagram.
+    pub fn self_test(&mut self) -> crate::Result<Duration> {
+        let started = Instant::now();
+        let result = self.self_test_datagram(started + SELF_TEST_DATAGRAM_TIMEOUT);
+        self.context.set_read_timeout(None)?;
+
+        result.map(|_| started.elapsed()).inspect_err(report_error)
+    }
+
+    /// Send the self-test datagram and wait until it's received or `deadline` has passed.
+    fn self_test_datagram(&mut self, deadline: Instant) -> crate::Result<()> {
+        let destination = self.public_destination().to_string();
+        let payload = self_test_payload(&self.options.nickname);
+        let mut buffer = vec![0u8; payload.len() + 1];
+
+        self.send_to(&payload, &destination)?;
+
+        loop {
+            self.context.set_read_timeout(Some(self_test_timeout(deadline)?))?;
+            let nread = self.recv(&mut buffer).map_err(self_test_error)?;
+
+            if buffer[..nread] == payload[..] {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::MockSam;
+
+    #[test]
+    fn reconfigure() {
+        let sam = MockSam::new().unwrap();
+        let mut session = Session::<style::Stream>::new(sam.session_options()).unwrap();
+        let destination = session.destination().to_string();
+
+        session.reconfigure(SessionOptions::high_anonymity()).unwrap();
+        assert_eq!(session.destination(), destination);
+        assert_eq!(session.options().inbound_len_variance, 1);
+        assert!(sam.commands().iter().any(|command| {
+            command.contains(&format!("DESTINATION={destination}"))
+                && command.contains("inbound.lengthVariance=1")
+        }));
+    }
+
+    #[test]
+    fn close_session() {
+        let sam = MockSam::new().unwrap();
+        let options = SessionOptions {
+            nickname: "closed".to_string(),
+            ..sam.session_options()
+        };
+        let mut session = Session::<style::Repliable>::new(options.clone()).unwrap();
+
+        session.close().unwrap();
+        assert_eq!(session.status().state, SessionState::Closed);
+        assert!(sam.commands().contains(&"QUIT".to_string()));
+
+        // the nickname has been released
+        Session::<style::Repliable>::new(options).unwrap();
+    }
+    #[test]
+    fn self_test() {
+        let sam = MockSam::new().unwrap();
+
+        let mut session = Session::<style::Stream>::new(sam.session_options()).unwrap();
+        session.self_test().unwrap();
+        assert_eq!(session.metrics_snapshot().streams_accepted, 1);
+
+        let mut session = Session::<style::Repliable>::new(sam.session_options()).unwrap();
+        session.self_test().unwrap();
+
+        let mut session = Session::<style::Anonymous>::new(sam.session_options()).unwrap();
+        session.self_test().unwrap();
     }
 }
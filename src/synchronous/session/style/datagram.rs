@@ -16,19 +16,51 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-#![cfg(all(feature = "sync", not(feature = "async")))]
+#![cfg(feature = "sync")]
 
 use crate::{
     options::SessionOptions,
-    style::{private, SessionStyle},
+    proto::{
+        command::{Command, CommandWriter, DatagramHeader},
+        parser::parse_datagram_header,
+    },
+    status::SocketAddrs,
+    synchronous::{
+        control::ControlSocket,
+        session::style::{private, SessionStyle},
+    },
     Error,
 };
 
 use std::{
-    io::{BufRead, BufReader, Write},
-    net::{SocketAddr, TcpStream, UdpSocket},
+    fmt,
+    net::{SocketAddr, UdpSocket},
+    ops::Range,
+    time::Duration,
 };
 
+/// Send `buf` to `destination` over the SAMv3 UDP port of the router at `server_address`,
+/// optionally from a specific port.
+fn send_datagram(
+    socket: &UdpSocket,
+    server_address: SocketAddr,
+    nickname: &str,
+    buf: &[u8],
+    destination: &str,
+    from_port: Option<u16>,
+) -> crate::Result<()> {
+    let mut writer = CommandWriter::new();
+    let header = DatagramHeader {
+        id: nickname,
+        destination,
+        from_port,
+    };
+    let mut datagram = header.write(&mut writer).to_vec();
+    datagram.extend_from_slice(buf);
+
+    socket.send_to(&datagram, server_address).map(|_| ()).map_err(From::from)
+}
+
 /// Repliable datagrams.
 pub struct Repliable {
     /// Read buffer
@@ -43,43 +75,59 @@ pub struct Repliable {
     /// Datagram socket.
     socket: UdpSocket,
 
-    /// TCP stream used to communicate with the router.
-    stream: BufReader<TcpStream>,
+    /// Control socket used to communicate with the router.
+    stream: ControlSocket,
 }
 
 impl Repliable {
-    pub(crate) fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
-        let mut datagram =
-            format!("3.0 {} {}\n", self.options.nickname, destination).as_bytes().to_vec();
-        datagram.extend_from_slice(buf);
+    /// Get local address of the datagram socket and the address of the SAMv3 UDP port.
+    pub(crate) fn datagram_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        Ok(SocketAddrs {
+            local: self.socket.local_addr()?,
+            peer: self.server_address,
+        })
+    }
+
+    pub(crate) fn send_to(
+        &mut self,
+        buf: &[u8],
+        destination: &str,
+        from_port: Option<u16>,
+    ) -> crate::Result<()> {
+        send_datagram(
+            &self.socket,
+            self.server_address,
+            &self.options.nickname,
+            buf,
+            destination,
+            from_port,
+        )
+    }
 
-        self.socket
-            .send_to(&datagram, &self.server_address)
-            .map(|_| ())
-            .map_err(From::from)
+    /// Set read timeout of the datagram socket.
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.socket.set_read_timeout(timeout).map_err(From::from)
     }
 
     pub(crate) fn recv_from(&mut self, buf: &mut [u8]) -> crate::Result<(usize, String)> {
         let nread = self.socket.recv(&mut self.buffer)?;
+        let (destination, offset) =
+            parse_datagram_header(&self.buffer[..nread]).ok_or(Error::Malformed)?;
 
-        let destination = {
-            let destination_end =
-                self.buffer[..nread].iter().position(|byte| byte == &b' ').unwrap();
-
-            std::str::from_utf8(&self.buffer[..destination_end])
-                .map_err(|_| Error::Malformed)?
-                .to_owned()
-        };
+        let datagram_len = (nread - offset).min(buf.len());
+        buf[..datagram_len].copy_from_slice(&self.buffer[offset..offset + datagram_len]);
 
-        let nread = {
-            let header_end = self.buffer[..nread].iter().position(|byte| byte == &b'\n').unwrap();
-            let datagram_len = nread - header_end - 1;
-            buf[..datagram_len].copy_from_slice(&self.buffer[header_end + 1..nread]);
+        Ok((datagram_len, destination.to_owned()))
+    }
 
-            datagram_len
-        };
+    pub(crate) fn recv_from_in_place(
+        &mut self,
+        buf: &mut [u8],
+    ) -> crate::Result<(Range<usize>, String)> {
+        let nread = self.socket.recv(buf)?;
+        let (destination, offset) = parse_datagram_header(&buf[..nread]).ok_or(Error::Malformed)?;
 
-        Ok((nread, destination))
+        Ok((offset..nread, destination.to_owned()))
     }
 }
 
@@ -88,16 +136,13 @@ impl private::SessionStyle for Repliable {
     where
         Self: Sized,
     {
-        let socket = UdpSocket::bind(format!("127.0.0.1:{}", options.datagram_port))?;
-        let stream = BufReader::new(TcpStream::connect(format!(
-            "127.0.0.1:{}",
-            options.samv3_tcp_port
-        ))?);
+        let socket = UdpSocket::bind(options.datagram_address()?)?;
+        let stream = ControlSocket::from_options(&options)?;
         let server_address =
             format!("127.0.0.1:{}", options.samv3_udp_port).parse().expect("to succeed");
 
         Ok(Self {
-            buffer: vec![0u8; 0xfff],
+            buffer: vec![0u8; options.datagram_buffer_size],
             options,
             server_address,
             socket,
@@ -105,31 +150,54 @@ impl private::SessionStyle for Repliable {
         })
     }
 
+    fn reconnect(&mut self, options: SessionOptions) -> crate::Result<()> {
+        self.stream = ControlSocket::from_options(&options)?;
+        self.options = options;
+
+        Ok(())
+    }
+
     fn write_command(&mut self, command: &[u8]) -> crate::Result<()> {
-        self.stream.get_mut().write_all(command).map_err(From::from)
+        self.stream.write_command(command)
     }
 
-    fn read_command(&mut self) -> crate::Result<String> {
-        let mut response = String::new();
+    fn read_command(&mut self) -> crate::Result<&str> {
+        self.stream.read_response()
+    }
 
-        self.stream.read_line(&mut response).map(|_| response).map_err(From::from)
+    fn close(&mut self) {
+        self.stream.close();
     }
 
     fn create_session(&self) -> private::SessionParameters {
-        let port = self.socket.local_addr().expect("to succeed").port();
+        let address = self.socket.local_addr().expect("to succeed");
 
         private::SessionParameters {
             style: "DATAGRAM".to_string(),
             options: Vec::from_iter([
-                ("PORT".to_string(), port.to_string()),
-                ("HOST".to_string(), "127.0.0.1".to_string()),
+                ("PORT".to_string(), address.port().to_string()),
+                ("HOST".to_string(), address.ip().to_string()),
             ]),
         }
     }
+
+    fn control_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.stream.socket_addrs()
+    }
 }
 
 impl SessionStyle for Repliable {}
 
+impl fmt::Debug for Repliable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Repliable")
+            .field("local_addr", &self.socket.local_addr().ok())
+            .field("server_address", &self.server_address)
+            .field("control", &self.stream)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Anonymous datagrams.
 pub struct Anonymous {
     /// Session options.
@@ -141,20 +209,38 @@ pub struct Anonymous {
     /// Datagram socket.
     socket: UdpSocket,
 
-    /// TCP stream used to communicate with the router.
-    stream: BufReader<TcpStream>,
+    /// Control socket used to communicate with the router.
+    stream: ControlSocket,
 }
 
 impl Anonymous {
-    pub(crate) fn send_to(&mut self, buf: &[u8], destination: &str) -> crate::Result<()> {
-        let mut datagram =
-            format!("3.0 {} {}\n", self.options.nickname, destination).as_bytes().to_vec();
-        datagram.extend_from_slice(buf);
+    /// Get local address of the datagram socket and the address of the SAMv3 UDP port.
+    pub(crate) fn datagram_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        Ok(SocketAddrs {
+            local: self.socket.local_addr()?,
+            peer: self.server_address,
+        })
+    }
 
-        self.socket
-            .send_to(&datagram, &self.server_address)
-            .map(|_| ())
-            .map_err(From::from)
+    pub(crate) fn send_to(
+        &mut self,
+        buf: &[u8],
+        destination: &str,
+        from_port: Option<u16>,
+    ) -> crate::Result<()> {
+        send_datagram(
+            &self.socket,
+            self.server_address,
+            &self.options.nickname,
+            buf,
+            destination,
+            from_port,
+        )
+    }
+
+    /// Set read timeout of the datagram socket.
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> crate::Result<()> {
+        self.socket.set_read_timeout(timeout).map_err(From::from)
     }
 
     pub(crate) fn recv(&mut self, buf: &mut [u8]) -> crate::Result<usize> {
@@ -167,11 +253,8 @@ impl private::SessionStyle for Anonymous {
     where
         Self: Sized,
     {
-        let socket = UdpSocket::bind(format!("127.0.0.1:{}", options.datagram_port))?;
-        let stream = BufReader::new(TcpStream::connect(format!(
-            "127.0.0.1:{}",
-            options.samv3_tcp_port
-        ))?);
+        let socket = UdpSocket::bind(options.datagram_address()?)?;
+        let stream = ControlSocket::from_options(&options)?;
         let server_address =
             format!("127.0.0.1:{}", options.samv3_udp_port).parse().expect("to succeed");
 
@@ -183,27 +266,50 @@ impl private::SessionStyle for Anonymous {
         })
     }
 
+    fn reconnect(&mut self, options: SessionOptions) -> crate::Result<()> {
+        self.stream = ControlSocket::from_options(&options)?;
+        self.options = options;
+
+        Ok(())
+    }
+
     fn write_command(&mut self, command: &[u8]) -> crate::Result<()> {
-        self.stream.get_mut().write_all(command).map_err(From::from)
+        self.stream.write_command(command)
     }
 
-    fn read_command(&mut self) -> crate::Result<String> {
-        let mut response = String::new();
+    fn read_command(&mut self) -> crate::Result<&str> {
+        self.stream.read_response()
+    }
 
-        self.stream.read_line(&mut response).map(|_| response).map_err(From::from)
+    fn close(&mut self) {
+        self.stream.close();
     }
 
     fn create_session(&self) -> private::SessionParameters {
-        let port = self.socket.local_addr().expect("to succeed").port();
+        let address = self.socket.local_addr().expect("to succeed");
 
         private::SessionParameters {
             style: "RAW".to_string(),
             options: Vec::from_iter([
-                ("PORT".to_string(), port.to_string()),
-                ("HOST".to_string(), "127.0.0.1".to_string()),
+                ("PORT".to_string(), address.port().to_string()),
+                ("HOST".to_string(), address.ip().to_string()),
             ]),
         }
     }
+
+    fn control_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.stream.socket_addrs()
+    }
 }
 
 impl SessionStyle for Anonymous {}
+
+impl fmt::Debug for Anonymous {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Anonymous")
+            .field("local_addr", &self.socket.local_addr().ok())
+            .field("server_address", &self.server_address)
+            .field("control", &self.stream)
+            .finish_non_exhaustive()
+    }
+}
@@ -18,7 +18,7 @@
 
 //! Session style.
 
-#![cfg(all(feature = "sync", not(feature = "async")))]
+#![cfg(feature = "sync")]
 
 pub use datagram::{Anonymous, Repliable};
 pub use stream::Stream;
@@ -27,14 +27,7 @@ mod datagram;
 mod stream;
 
 pub(crate) mod private {
-    /// Session parameters.
-    pub struct SessionParameters {
-        /// Session style.
-        pub(crate) style: String,
-
-        /// Session options.
-        pub(crate) options: Vec<(String, String)>,
-    }
+    pub use crate::proto::session::SessionParameters;
 
     pub trait SessionStyle {
         /// Create new `SessionStyle` object.
@@ -42,14 +35,32 @@ pub(crate) mod private {
         where
             Self: Sized;
 
+        /// Replace the control socket with a new connection to the router, used to recreate the
+        /// session with `options`.
+        ///
+        /// Datagram sockets are kept so the recreated session delivers datagrams to the same
+        /// socket.
+        fn reconnect(&mut self, options: crate::options::SessionOptions) -> crate::Result<()>;
+
         /// Send command to router.
         fn write_command(&mut self, command: &[u8]) -> crate::Result<()>;
 
         /// Read command from router.
-        fn read_command(&mut self) -> crate::Result<String>;
+        fn read_command(&mut self) -> crate::Result<&str>;
+
+        /// Close the connections of the session to the router and stop the threads of the session.
+        fn close(&mut self);
 
         /// Get `SESSION CREATE` command for this session style.
         fn create_session(&self) -> SessionParameters;
+
+        /// Get local and peer address of the control socket.
+        fn control_socket_addrs(&self) -> crate::Result<crate::SocketAddrs>;
+
+        /// Get the state of stream forwarding of the session.
+        fn forwarding(&self) -> crate::ForwardingState {
+            crate::ForwardingState::Inactive
+        }
     }
 }
 
@@ -16,34 +16,147 @@
 // FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
 // DEALINGS IN THE SOFTWARE.
 
-#![cfg(all(feature = "sync", not(feature = "async")))]
+#![cfg(feature = "sync")]
 
 use crate::{
+    error::report_error,
+    events::SessionEvent,
     options::SessionOptions,
-    style::{private, SessionStyle},
+    redaction::RedactedLine,
+    status::{ForwardingState, SocketAddrs},
+    synchronous::{
+        control::ControlSocket,
+        events::Events,
+        session::style::{private, SessionStyle},
+    },
+    tracing::{self, Span},
 };
 
 use std::{
-    io::{BufRead, BufReader, Write},
-    net::TcpStream,
+    collections::VecDeque,
+    fmt,
+    net::{Shutdown, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
 };
 
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::session::stream";
+
+/// Forwarding of inbound virtual streams.
+struct Forwarding {
+    /// Port of the TCP listener where streams are forwarded to.
+    port: u16,
+
+    /// Socket that was sent the forwarding request.
+    socket: TcpStream,
+
+    /// Flag telling the watcher thread that the session is being closed.
+    closing: Arc<AtomicBool>,
+
+    /// Thread watching the socket.
+    watcher: JoinHandle<()>,
+}
+
 /// Stream.
 pub struct Stream {
-    /// TCP stream used to communicate with router.
-    stream: BufReader<TcpStream>,
+    /// Control socket used to communicate with the router.
+    stream: ControlSocket,
 
     /// Session options.
     _options: SessionOptions,
 
-    /// Socket that was sent the forwarding request, if any.
-    _forwarding_stream: Option<TcpStream>,
+    /// Forwarding of inbound virtual streams, if any.
+    forwarding: Option<Forwarding>,
+
+    /// Handshaked control connections ready to be used for outbound streams.
+    pool: VecDeque<ControlSocket>,
 }
 
 impl Stream {
-    /// Store the TCP used to send the forwarding command into [`Stream`]'s context.
-    pub(crate) fn store_forwarded(&mut self, stream: TcpStream) {
-        self._forwarding_stream = Some(stream);
+    /// Keep the socket used to send the forwarding command to `port` open.
+    ///
+    /// The socket is watched by a background thread which answers `PING`s from the router and
+    /// emits [`SessionEvent::ForwardingBroken`] if the router closes the socket. The socket is shut
+    /// down when [`Stream`] is dropped, which also stops the thread.
+    pub(crate) fn store_forwarded(
+        &mut self,
+        mut stream: ControlSocket,
+        port: u16,
+        events: Events,
+    ) -> crate::Result<()> {
+        let socket = stream.try_clone_socket()?;
+        let closing = Arc::new(AtomicBool::new(false));
+        let span = Span::current();
+
+        let watcher = std::thread::spawn({
+            let closing = Arc::clone(&closing);
+
+            move || {
+                let _span = span.enter();
+
+                // the router doesn't send anything else after the reply to `STREAM FORWARD`
+                let error = loop {
+                    match stream.read_response() {
+                        Ok(response) => tracing::debug!(
+                            target: LOG_TARGET,
+                            response = %RedactedLine(response),
+                            "ignoring unexpected message on forwarding connection",
+                        ),
+                        Err(error) => break error,
+                    }
+                };
+
+                tracing::debug!(
+                    target: LOG_TARGET,
+                    ?error,
+                    "forwarding connection closed",
+                );
+
+                // the socket is shut down when the session is dropped which isn't an error
+                if !closing.load(Ordering::Acquire) {
+                    report_error(&error);
+                    events.emit(SessionEvent::ForwardingBroken { port });
+                }
+            }
+        });
+
+        self.forwarding = Some(Forwarding {
+            port,
+            socket,
+            closing,
+            watcher,
+        });
+        Ok(())
+    }
+
+    /// Take a handshaked control connection from the pool, if there is one.
+    pub(crate) fn take_pooled(&mut self) -> Option<ControlSocket> {
+        self.pool.pop_front()
+    }
+
+    /// Store a handshaked control connection into the pool.
+    pub(crate) fn store_pooled(&mut self, stream: ControlSocket) {
+        self.pool.push_back(stream);
+    }
+
+    /// Get the number of pooled control connections.
+    pub(crate) fn pooled(&self) -> usize {
+        self.pool.len()
+    }
+
+    /// Stop forwarding inbound virtual streams, if they are forwarded.
+    fn stop_forwarding(&mut self) {
+        if let Some(Forwarding {
+            socket, closing, ..
+        }) = self.forwarding.take()
+        {
+            closing.store(true, Ordering::Release);
+            let _ = socket.shutdown(Shutdown::Both);
+        }
     }
 }
 
@@ -53,23 +166,36 @@ impl private::SessionStyle for Stream {
         Self: Sized,
     {
         Ok(Self {
-            stream: BufReader::new(TcpStream::connect(format!(
-                "127.0.0.1:{}",
-                _options.samv3_tcp_port
-            ))?),
+            stream: ControlSocket::from_options(&_options)?,
             _options,
-            _forwarding_stream: None,
+            forwarding: None,
+            pool: VecDeque::new(),
         })
     }
 
+    fn reconnect(&mut self, options: SessionOptions) -> crate::Result<()> {
+        self.stream = ControlSocket::from_options(&options)?;
+        self._options = options;
+        self.pool.clear();
+
+        // forwarding was requested for the old session which the router closes with it
+        self.stop_forwarding();
+
+        Ok(())
+    }
+
     fn write_command(&mut self, command: &[u8]) -> crate::Result<()> {
-        self.stream.get_mut().write_all(command).map_err(From::from)
+        self.stream.write_command(command)
     }
 
-    fn read_command(&mut self) -> crate::Result<String> {
-        let mut response = String::new();
+    fn read_command(&mut self) -> crate::Result<&str> {
+        self.stream.read_response()
+    }
 
-        self.stream.read_line(&mut response).map(|_| response).map_err(From::from)
+    fn close(&mut self) {
+        self.pool.clear();
+        self.stop_forwarding();
+        self.stream.close();
     }
 
     fn create_session(&self) -> private::SessionParameters {
@@ -78,6 +204,35 @@ impl private::SessionStyle for Stream {
             options: Vec::new(),
         }
     }
+
+    fn control_socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        self.stream.socket_addrs()
+    }
+
+    fn forwarding(&self) -> ForwardingState {
+        match &self.forwarding {
+            None => ForwardingState::Inactive,
+            Some(Forwarding { port, watcher, .. }) if watcher.is_finished() =>
+                ForwardingState::Broken { port: *port },
+            Some(Forwarding { port, .. }) => ForwardingState::Active { port: *port },
+        }
+    }
 }
 
 impl SessionStyle for Stream {}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field("control", &self.stream)
+            .field("forwarding", &self.forwarding.is_some())
+            .field("pooled", &self.pool.len())
+            .finish()
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        self.stop_forwarding();
+    }
+}
@@ -18,59 +18,222 @@
 
 #![cfg(feature = "sync")]
 
+use crate::{
+    ports::PortLease,
+    redaction::RedactedValue,
+    slots::StreamSlot,
+    status::SocketAddrs,
+    throttle::{RateLimiter, Throttle},
+};
+
 use std::{
-    io::{Read, Write},
+    fmt,
+    io::{BufReader, Read, Write},
     net::TcpStream,
 };
 
 /// Synchronous virtual stream.
+///
+/// If writes are buffered, see
+/// [`SessionOptions::stream_write_buffer_size`](crate::SessionOptions::stream_write_buffer_size),
+/// the buffered data is sent when the stream is flushed or dropped.
+///
+/// If the session has rate limits, see
+/// [`SessionOptions::stream_rate_limit`](crate::SessionOptions::stream_rate_limit), reads and
+/// writes block once the stream exceeds them.
 pub struct Stream {
     /// Data stream.
-    stream: TcpStream,
+    stream: BufReader<TcpStream>,
 
     /// Remote destination.
     remote_destination: String,
+
+    /// Local port of the stream, if it was opened from a specific port.
+    port: Option<PortLease>,
+
+    /// Slot of the stream, if the session limits the number of streams.
+    slot: Option<StreamSlot>,
+
+    /// Write buffer.
+    write_buffer: Vec<u8>,
+
+    /// Size of the write buffer, zero if writes are not buffered.
+    write_buffer_size: usize,
+
+    /// Read throttle, if the session has rate limits.
+    read_throttle: Option<Throttle>,
+
+    /// Write throttle, if the session has rate limits.
+    write_throttle: Option<Throttle>,
+}
+
+/// Block until `throttle`, if any, grants bytes and get how many of `wanted` can be transferred.
+fn wait_grant(throttle: Option<&mut Throttle>, wanted: usize) -> usize {
+    let Some(throttle) = throttle.filter(|_| wanted > 0) else {
+        return wanted;
+    };
+
+    loop {
+        match throttle.grant(wanted) {
+            Ok(granted) => return granted,
+            Err(delay) => std::thread::sleep(delay),
+        }
+    }
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Stream")
+            .field(
+                "remote_destination",
+                &format_args!("{}", RedactedValue(&self.remote_destination)),
+            )
+            .field("local_addr", &self.stream.get_ref().local_addr().ok())
+            .field("local_port", &self.local_port())
+            .field("peer_addr", &self.stream.get_ref().peer_addr().ok())
+            .finish_non_exhaustive()
+    }
 }
 
 impl Stream {
     /// Create new [`Stream`] from an inbound connection.
-    pub(crate) fn from_stream(stream: TcpStream, remote_destination: String) -> Self {
+    ///
+    /// Writes smaller than `write_buffer_size` are buffered.
+    pub(crate) fn from_stream(
+        stream: BufReader<TcpStream>,
+        remote_destination: String,
+        write_buffer_size: usize,
+    ) -> Self {
         Self {
             stream,
             remote_destination,
+            write_buffer: Vec::with_capacity(write_buffer_size),
+            write_buffer_size,
+            port: None,
+            slot: None,
+            read_throttle: None,
+            write_throttle: None,
+        }
+    }
+
+    /// Hold `slot` until the stream is dropped.
+    pub(crate) fn with_slot(mut self, slot: Option<StreamSlot>) -> Self {
+        self.slot = slot;
+        self
+    }
+
+    /// Throttle reads and writes with the rate limits of `limiter`, if any.
+    pub(crate) fn with_throttles(mut self, limiter: Option<&RateLimiter>) -> Self {
+        if let Some((read, write)) = limiter.map(RateLimiter::throttles) {
+            self.read_throttle = Some(read);
+            self.write_throttle = Some(write);
+        }
+        self
+    }
+
+    /// Send buffered data to the router.
+    fn flush_buffer(&mut self) -> std::io::Result<()> {
+        if !self.write_buffer.is_empty() {
+            self.stream.get_mut().write_all(&self.write_buffer)?;
+            self.write_buffer.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Write `buf` to the write buffer or to the router, bypassing the write throttle.
+    fn write_unthrottled(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.write_buffer.len() + buf.len() > self.write_buffer_size {
+            self.flush_buffer()?;
         }
+
+        // writes that don't fit into the buffer are sent directly
+        if buf.len() >= self.write_buffer_size {
+            return self.stream.get_mut().write(buf);
+        }
+
+        self.write_buffer.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    /// Hold `port` as the local port of the stream.
+    ///
+    /// Ports leased from the ephemeral port range of the session are released when the stream is
+    /// dropped.
+    pub(crate) fn with_port(mut self, port: Option<PortLease>) -> Self {
+        self.port = port;
+        self
     }
 
     /// Get reference to remote destination.
     pub fn remote_destination(&self) -> &str {
         &self.remote_destination
     }
+
+    /// Get the local port the stream was opened from.
+    ///
+    /// Returns `None` for inbound streams and for outbound streams opened without
+    /// [`StreamOptions::from_port`](crate::StreamOptions::from_port) if the session has no
+    /// [`SessionOptions::ephemeral_ports`](crate::SessionOptions::ephemeral_ports).
+    pub fn local_port(&self) -> Option<u16> {
+        self.port.as_ref().map(PortLease::port)
+    }
+
+    /// Get local and peer address of the connection of the stream to the router.
+    pub fn socket_addrs(&self) -> crate::Result<SocketAddrs> {
+        let socket = self.stream.get_ref();
+
+        Ok(SocketAddrs {
+            local: socket.local_addr()?,
+            peer: socket.peer_addr()?,
+        })
+    }
 }
 
 impl Read for Stream {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        self.stream.read(buf)
+        let len = wait_grant(self.read_throttle.as_mut(), buf.len());
+        let nread = self.stream.read(&mut buf[..len])?;
+
+        if let Some(throttle) = &mut self.read_throttle {
+            throttle.consume(nread);
+        }
+
+        Ok(nread)
     }
 }
 
 impl Write for Stream {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        self.stream.write(buf)
+        let len = wait_grant(self.write_throttle.as_mut(), buf.len());
+        let nwritten = self.write_unthrottled(&buf[..len])?;
+
+        if let Some(throttle) = &mut self.write_throttle {
+            throttle.consume(nwritten);
+        }
+
+        Ok(nwritten)
     }
 
     fn write_vectored(&mut self, bufs: &[std::io::IoSlice<'_>]) -> std::io::Result<usize> {
-        self.stream.write_vectored(bufs)
-    }
+        if self.write_buffer_size == 0 && self.write_throttle.is_none() {
+            return self.stream.get_mut().write_vectored(bufs);
+        }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        self.stream.flush()
+        match bufs.iter().find(|buf| !buf.is_empty()) {
+            Some(buf) => self.write(buf),
+            None => Ok(0),
+        }
     }
 
-    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
-        self.stream.write_all(buf)
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.flush_buffer()?;
+        self.stream.get_mut().flush()
     }
+}
 
-    fn write_fmt(&mut self, fmt: std::fmt::Arguments<'_>) -> std::io::Result<()> {
-        self.stream.write_fmt(fmt)
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let _ = self.flush_buffer();
     }
 }
@@ -0,0 +1,953 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Mock SAMv3 bridge for tests.
+//!
+//! [`MockSam`] listens on local TCP and UDP ports and speaks just enough SAMv3 to create sessions,
+//! open virtual streams between them, forward streams to TCP listeners, look up names and deliver
+//! datagrams, which makes it possible to test code built on `yosemite` without a live router.
+//!
+//! Router errors, slow responses and dropped connections can be injected with
+//! [`MockSam::inject()`] to exercise the error paths of an application.
+//!
+//! Sessions created on the same [`MockSam`] can reach each other using
+//! [`Session::public_destination()`]. Like a router, the bridge returns the private keys of a
+//! session in `SESSION STATUS` and only reports public destinations to peers. Streams are spliced
+//! together locally and datagrams are delivered to the UDP port of the receiving session.
+//!
+//! ```
+//! use yosemite::testing::MockSam;
+//!
+//! # fn main() -> yosemite::Result<()> {
+//! let sam = MockSam::new()?;
+//!
+//! // make the next `STREAM CONNECT` fail
+//! sam.respond_to("STREAM CONNECT", "STREAM STATUS RESULT=PEER_NOT_FOUND");
+//!
+//! let options = sam.session_options();
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! [`stream_pair()`] and [`datagram_pair()`] create two sessions against the same bridge or
+//! router, which is what most tests start with.
+//!
+//! [`Session::public_destination()`]: crate::Session::public_destination
+
+use crate::{
+    address::{encode_base64, public_destination},
+    options::SessionOptions,
+    tracing,
+};
+
+#[cfg(any(feature = "async", feature = "sync"))]
+use crate::{style, Session, Stream};
+
+use rand::{
+    distributions::{Alphanumeric, DistString},
+    thread_rng, Rng,
+};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, BufRead, BufReader, Write},
+    net::{Shutdown, TcpListener, TcpStream, UdpSocket},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex, MutexGuard, PoisonError,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Logging target for the file.
+const LOG_TARGET: &str = "yosemite::testing";
+
+/// Length of generated destinations.
+const DESTINATION_LEN: usize = 516;
+
+/// Length of the private keys of generated destinations.
+///
+/// 256-byte ElGamal private key and 20-byte DSA signing private key.
+const PRIVATE_KEYS_LEN: usize = 276;
+
+/// How long `STREAM CONNECT` waits for the remote session to start listening.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum size of a UDP datagram.
+const MAX_DATAGRAM_SIZE: usize = 65535;
+
+/// Fault injected into the handling of a command.
+///
+/// See [`MockSam::inject()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Respond with the given response, such as `STREAM STATUS RESULT=CANT_REACH_PEER`, instead
+    /// of handling the command.
+    Respond(String),
+
+    /// Wait before handling the command.
+    Delay(Duration),
+
+    /// Close the connection without responding.
+    Disconnect,
+}
+
+/// Listener for inbound streams of a session.
+#[derive(Clone)]
+enum Listener {
+    /// `STREAM ACCEPT` is pending.
+    ///
+    /// The connecting stream and its destination are handed over to the accepting connection.
+    Accept(mpsc::Sender<(BufReader<TcpStream>, String)>),
+
+    /// Streams are forwarded to a TCP listener.
+    Forward {
+        /// Port of the TCP listener.
+        port: u16,
+
+        /// Don't send the destination of the connecting peer.
+        silent: bool,
+    },
+}
+
+/// Session created on the mock bridge.
+struct MockSession {
+    /// Public destination of the session.
+    destination: String,
+
+    /// Session style.
+    style: String,
+
+    /// UDP port where datagrams of the session are delivered to.
+    datagram_port: Option<u16>,
+
+    /// Listeners for inbound streams.
+    listeners: VecDeque<Listener>,
+}
+
+/// State of the mock bridge.
+#[derive(Default)]
+struct State {
+    /// Active sessions, keyed by nickname.
+    sessions: HashMap<String, MockSession>,
+
+    /// Names known to `NAMING LOOKUP`.
+    names: HashMap<String, String>,
+
+    /// Injected faults, keyed by command prefix.
+    faults: VecDeque<(String, Fault)>,
+
+    /// Commands received from clients.
+    commands: Vec<String>,
+}
+
+impl State {
+    /// Resolve `destination`, which may be a name added with [`MockSam::add_name()`].
+    fn resolve(&self, destination: &str) -> String {
+        self.names.get(destination).cloned().unwrap_or_else(|| destination.to_string())
+    }
+
+    /// Get the session of `destination`.
+    fn session_mut(&mut self, destination: &str) -> Option<&mut MockSession> {
+        let destination = self.resolve(destination);
+
+        self.sessions.values_mut().find(|session| session.destination == destination)
+    }
+}
+
+/// State shared between [`MockSam`] and its connections.
+struct Shared {
+    /// State of the mock bridge.
+    state: Mutex<State>,
+
+    /// Notified when a session starts listening for inbound streams.
+    listener_added: Condvar,
+
+    /// Has [`MockSam`] been dropped.
+    shutdown: AtomicBool,
+}
+
+impl Shared {
+    /// Lock the state.
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Mock SAMv3 bridge.
+///
+/// The listeners are closed when [`MockSam`] is dropped. See the [module
+/// documentation](crate::testing) for more details.
+pub struct MockSam {
+    /// TCP port of the bridge.
+    tcp_port: u16,
+
+    /// UDP port of the bridge.
+    udp_port: u16,
+
+    /// State shared with the connections.
+    shared: Arc<Shared>,
+}
+
+impl MockSam {
+    /// Create new [`MockSam`] listening on random local ports.
+    pub fn new() -> crate::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let socket = UdpSocket::bind("127.0.0.1:0")?;
+        let tcp_port = listener.local_addr()?.port();
+        let udp_port = socket.local_addr()?.port();
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            listener_added: Condvar::new(),
+            shutdown: AtomicBool::new(false),
+        });
+
+        thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || accept_connections(listener, shared)
+        });
+        thread::spawn({
+            let shared = Arc::clone(&shared);
+            move || deliver_datagrams(socket, shared)
+        });
+
+        Ok(Self {
+            tcp_port,
+            udp_port,
+            shared,
+        })
+    }
+
+    /// Get the TCP port of the bridge.
+    ///
+    /// Pass it to [`RouterApi::new()`](crate::RouterApi::new) to use the bridge for router API
+    /// calls.
+    pub fn tcp_port(&self) -> u16 {
+        self.tcp_port
+    }
+
+    /// Get the UDP port of the bridge.
+    pub fn udp_port(&self) -> u16 {
+        self.udp_port
+    }
+
+    /// Get default [`SessionOptions`] configured to use the bridge.
+    pub fn session_options(&self) -> SessionOptions {
+        SessionOptions {
+            samv3_tcp_port: self.tcp_port,
+            samv3_udp_port: self.udp_port,
+            ..Default::default()
+        }
+    }
+
+    /// Create new session of style `S` with [`MockSam::session_options()`].
+    #[cfg(feature = "async")]
+    pub async fn session<S: style::SessionStyle>(&self) -> crate::Result<Session<S>> {
+        Session::new(self.session_options()).await
+    }
+
+    /// Create new session of style `S` with [`MockSam::session_options()`].
+    #[cfg(all(feature = "sync", not(feature = "async")))]
+    pub fn session<S: style::SessionStyle>(&self) -> crate::Result<Session<S>> {
+        Session::new(self.session_options())
+    }
+
+    /// Make `name` resolve to `destination`.
+    ///
+    /// The name can be used with `NAMING LOOKUP` and as the destination of streams and
+    /// datagrams.
+    pub fn add_name(&self, name: impl Into<String>, destination: impl Into<String>) {
+        self.shared.state().names.insert(name.into(), destination.into());
+    }
+
+    /// Respond to the next command starting with `command` with `response`.
+    ///
+    /// The command is otherwise ignored. Shorthand for injecting [`Fault::Respond`].
+    pub fn respond_to(&self, command: impl Into<String>, response: impl Into<String>) {
+        self.inject(command, Fault::Respond(response.into()));
+    }
+
+    /// Inject `fault` into the handling of the next command starting with `command`.
+    ///
+    /// Faults are used in the order they were added and each is used once, so, e.g., the first
+    /// `STREAM CONNECT` can be made to fail while the retry succeeds.
+    pub fn inject(&self, command: impl Into<String>, fault: Fault) {
+        self.shared.state().faults.push_back((command.into(), fault));
+    }
+
+    /// Get the commands received by the bridge, in the order they were received.
+    pub fn commands(&self) -> Vec<String> {
+        self.shared.state().commands.clone()
+    }
+}
+
+impl Drop for MockSam {
+    fn drop(&mut self) {
+        self.shared.shutdown.store(true, Ordering::Release);
+
+        // wake up the threads so they notice the shutdown
+        let _ = TcpStream::connect(("127.0.0.1", self.tcp_port));
+        let _ = UdpSocket::bind("127.0.0.1:0")
+            .and_then(|socket| socket.send_to(&[], ("127.0.0.1", self.udp_port)));
+    }
+}
+
+/// Two sessions connected to each other with a virtual stream.
+///
+/// See [`stream_pair()`].
+#[cfg(any(feature = "async", feature = "sync"))]
+pub struct StreamPair {
+    /// Session which opened the stream.
+    pub client: Session<style::Stream>,
+
+    /// Stream opened by `client`.
+    pub client_stream: Stream,
+
+    /// Session which accepted the stream.
+    pub server: Session<style::Stream>,
+
+    /// Stream accepted by `server`.
+    pub server_stream: Stream,
+}
+
+/// Create two sessions connected to each other with a virtual stream.
+///
+/// Both sessions are created from `options`, such as [`MockSam::session_options()`], with a
+/// nickname of their own.
+#[cfg(feature = "async")]
+pub async fn stream_pair(options: SessionOptions) -> crate::Result<StreamPair> {
+    let mut server = Session::<style::Stream>::new(with_new_nickname(&options)).await?;
+    let mut client = Session::<style::Stream>::new(with_new_nickname(&options)).await?;
+    let destination = server.public_destination().to_string();
+
+    let (server_stream, client_stream) =
+        futures::future::try_join(server.accept(), client.connect(&destination)).await?;
+
+    Ok(StreamPair {
+        client,
+        client_stream,
+        server,
+        server_stream,
+    })
+}
+
+/// Create two sessions connected to each other with a virtual stream.
+///
+/// Both sessions are created from `options`, such as [`MockSam::session_options()`], with a
+/// nickname of their own.
+#[cfg(all(feature = "sync", not(feature = "async")))]
+pub fn stream_pair(options: SessionOptions) -> crate::Result<StreamPair> {
+    let mut server = Session::<style::Stream>::new(with_new_nickname(&options))?;
+    let mut client = Session::<style::Stream>::new(with_new_nickname(&options))?;
+    let destination = server.public_destination().to_string();
+
+    let (server_stream, client_stream) = thread::scope(|scope| {
+        let server_stream = scope.spawn(|| server.accept());
+        let client_stream = client.connect(&destination);
+
+        (server_stream.join().expect("to succeed"), client_stream)
+    });
+
+    Ok(StreamPair {
+        client_stream: client_stream?,
+        server_stream: server_stream?,
+        client,
+        server,
+    })
+}
+
+/// Create two repliable datagram sessions which can send datagrams to each other.
+///
+/// Both sessions are created from `options`, such as [`MockSam::session_options()`], with a
+/// nickname of their own.
+#[cfg(feature = "async")]
+pub async fn datagram_pair(
+    options: SessionOptions,
+) -> crate::Result<(Session<style::Repliable>, Session<style::Repliable>)> {
+    Ok((
+        Session::new(with_new_nickname(&options)).await?,
+        Session::new(with_new_nickname(&options)).await?,
+    ))
+}
+
+/// Create two repliable datagram sessions which can send datagrams to each other.
+///
+/// Both sessions are created from `options`, such as [`MockSam::session_options()`], with a
+/// nickname of their own.
+#[cfg(all(feature = "sync", not(feature = "async")))]
+pub fn datagram_pair(
+    options: SessionOptions,
+) -> crate::Result<(Session<style::Repliable>, Session<style::Repliable>)> {
+    Ok((
+        Session::new(with_new_nickname(&options))?,
+        Session::new(with_new_nickname(&options))?,
+    ))
+}
+
+/// Clone `options` with a new random nickname.
+#[cfg(any(feature = "async", feature = "sync"))]
+pub(crate) fn with_new_nickname(options: &SessionOptions) -> SessionOptions {
+    SessionOptions {
+        nickname: SessionOptions::default().nickname,
+        ..options.clone()
+    }
+}
+
+/// Get the value of `key` in `command`.
+fn value<'a>(command: &'a str, key: &str) -> Option<&'a str> {
+    command
+        .split(' ')
+        .find_map(|token| token.strip_prefix(key).and_then(|token| token.strip_prefix('=')))
+}
+
+/// Generate random destination.
+///
+/// The destination ends with a null certificate so it has the structure of a real destination and
+/// its `.b32.i2p` address can be computed.
+pub(crate) fn random_destination() -> String {
+    let mut destination = Alphanumeric.sample_string(&mut thread_rng(), DESTINATION_LEN - 4);
+    destination.push_str("AAAA");
+    destination
+}
+
+/// Generate random private key for `destination`.
+///
+/// Like the private keys returned by a router, the private key is `destination` followed by its
+/// private keys, so `destination` can be recovered from it.
+fn random_private_key(destination: &str) -> String {
+    let mut private_keys = [0u8; PRIVATE_KEYS_LEN];
+    thread_rng().fill(&mut private_keys[..]);
+
+    format!("{destination}{}", encode_base64(&private_keys))
+}
+
+/// Accept connections until [`MockSam`] is dropped.
+fn accept_connections(listener: TcpListener, shared: Arc<Shared>) {
+    for stream in listener.incoming() {
+        if shared.shutdown.load(Ordering::Acquire) {
+            break;
+        }
+
+        match stream {
+            Ok(stream) => {
+                let shared = Arc::clone(&shared);
+
+                thread::spawn(move || {
+                    if let Err(error) = Connection::new(stream, shared).and_then(Connection::run) {
+                        tracing::debug!(
+                            target: LOG_TARGET,
+                            ?error,
+                            "connection failed",
+                        );
+                    }
+                });
+            }
+            Err(error) => tracing::debug!(
+                target: LOG_TARGET,
+                ?error,
+                "failed to accept connection",
+            ),
+        }
+    }
+}
+
+/// Deliver datagrams sent by sessions until [`MockSam`] is dropped.
+fn deliver_datagrams(socket: UdpSocket, shared: Arc<Shared>) {
+    let mut buffer = vec![0u8; MAX_DATAGRAM_SIZE];
+
+    loop {
+        let result = socket.recv(&mut buffer);
+
+        if shared.shutdown.load(Ordering::Acquire) {
+            break;
+        }
+
+        let Ok(nread) = result else {
+            continue;
+        };
+        let Some(header_end) = buffer[..nread].iter().position(|byte| byte == &b'\n') else {
+            continue;
+        };
+        let Ok(header) = std::str::from_utf8(&buffer[..header_end]) else {
+            continue;
+        };
+
+        // `3.0 <nickname> <destination> [options]`
+        let mut tokens = header.split(' ').skip(1);
+        let (Some(nickname), Some(destination)) = (tokens.next(), tokens.next()) else {
+            continue;
+        };
+        let from_port = value(header, "FROM_PORT").unwrap_or("0");
+        let to_port = value(header, "TO_PORT").unwrap_or("0");
+
+        let (source, style, port) = {
+            let mut state = shared.state();
+            let Some(source) = state.sessions.get(nickname).map(|s| s.destination.clone()) else {
+                continue;
+            };
+            let Some((style, port)) = state.session_mut(destination).and_then(|session| {
+                session.datagram_port.map(|port| (session.style.clone(), port))
+            }) else {
+                tracing::debug!(
+                    target: LOG_TARGET,
+                    %nickname,
+                    "dropping datagram to unknown destination",
+                );
+                continue;
+            };
+
+            (source, style, port)
+        };
+
+        let payload = &buffer[header_end + 1..nread];
+        let datagram = match style.as_str() {
+            "DATAGRAM" => {
+                let mut datagram =
+                    format!("{source} FROM_PORT={from_port} TO_PORT={to_port}\n").into_bytes();
+                datagram.extend_from_slice(payload);
+                datagram
+            }
+            _ => payload.to_vec(),
+        };
+
+        if let Err(error) = socket.send_to(&datagram, ("127.0.0.1", port)) {
+            tracing::debug!(
+                target: LOG_TARGET,
+                ?error,
+                "failed to deliver datagram",
+            );
+        }
+    }
+}
+
+/// Copy data between two connections until either of them is closed.
+fn splice(mut first: BufReader<TcpStream>, mut second: BufReader<TcpStream>) -> io::Result<()> {
+    let mut first_writer = first.get_ref().try_clone()?;
+    let mut second_writer = second.get_ref().try_clone()?;
+
+    thread::spawn(move || {
+        let _ = io::copy(&mut second, &mut first_writer);
+        let _ = first_writer.shutdown(Shutdown::Write);
+    });
+
+    let _ = io::copy(&mut first, &mut second_writer);
+    let _ = second_writer.shutdown(Shutdown::Write);
+
+    Ok(())
+}
+
+/// Client connection.
+struct Connection {
+    /// Reader of the connection.
+    reader: BufReader<TcpStream>,
+
+    /// Writer of the connection.
+    writer: TcpStream,
+
+    /// State shared with [`MockSam`].
+    shared: Arc<Shared>,
+
+    /// Session created over the connection.
+    session: SessionGuard,
+}
+
+impl Connection {
+    /// Create new [`Connection`].
+    fn new(stream: TcpStream, shared: Arc<Shared>) -> io::Result<Self> {
+        Ok(Self {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+            session: SessionGuard {
+                shared: Arc::clone(&shared),
+                nickname: None,
+            },
+            shared,
+        })
+    }
+
+    /// Write `response` to the client.
+    fn respond(&mut self, response: &str) -> io::Result<()> {
+        self.writer.write_all(format!("{response}\n").as_bytes())
+    }
+
+    /// Send `STREAM STATUS` reply `status` unless the command was silent.
+    ///
+    /// Silent commands have no reply and the connection is closed if the stream cannot be opened.
+    fn respond_status(&mut self, silent: bool, status: &str) -> io::Result<()> {
+        match silent {
+            true => Ok(()),
+            false => self.respond(status),
+        }
+    }
+
+    /// Handle commands until the client closes the connection or the connection becomes a virtual
+    /// stream.
+    fn run(mut self) -> io::Result<()> {
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+
+            let command = line.trim_end();
+            let fault = {
+                let mut state = self.shared.state();
+                state.commands.push(command.to_string());
+                state
+                    .faults
+                    .iter()
+                    .position(|(prefix, _)| command.starts_with(prefix.as_str()))
+                    .and_then(|index| state.faults.remove(index))
+            };
+
+            match fault.map(|(_, fault)| fault) {
+                Some(Fault::Respond(response)) => {
+                    self.respond(&response)?;
+                    continue;
+                }
+                Some(Fault::Delay(delay)) => thread::sleep(delay),
+                Some(Fault::Disconnect) => return self.writer.shutdown(Shutdown::Both),
+                None => {}
+            }
+
+            let mut tokens = command.split(' ');
+
+            match (tokens.next(), tokens.next()) {
+                (Some("HELLO"), Some("VERSION")) =>
+                    self.respond("HELLO REPLY RESULT=OK VERSION=3.3")?,
+                (Some("PING"), data) => self.respond(&format!("PONG {}", data.unwrap_or("")))?,
+                (Some(command @ ("QUIT" | "STOP" | "EXIT")), _) => {
+                    self.session.destroy();
+                    self.respond(&format!("{command} STATUS RESULT=OK MESSAGE=bye"))?;
+                    return self.writer.shutdown(Shutdown::Both);
+                }
+                (Some("SESSION"), Some("CREATE")) => {
+                    let response = self.create_session(command);
+                    self.respond(&response)?;
+                }
+                (Some("STREAM"), Some("CONNECT")) => {
+                    let command = command.to_string();
+                    return self.connect_stream(&command);
+                }
+                (Some("STREAM"), Some("ACCEPT")) => {
+                    let command = command.to_string();
+                    return self.accept_stream(&command);
+                }
+                (Some("STREAM"), Some("FORWARD")) => {
+                    let response = self.forward_stream(command);
+                    self.respond(&response)?;
+                }
+                (Some("NAMING"), Some("LOOKUP")) => {
+                    let response = self.lookup_name(command);
+                    self.respond(&response)?;
+                }
+                (Some("DEST"), Some("GENERATE")) => {
+                    let destination = random_destination();
+                    let private_key = random_private_key(&destination);
+
+                    self.respond(&format!("DEST REPLY PUB={destination} PRIV={private_key}"))?;
+                }
+                _ => self.respond("ERROR RESULT=I2P_ERROR MESSAGE=\"unsupported command\"")?,
+            }
+        }
+    }
+
+    /// Handle `SESSION CREATE`.
+    fn create_session(&mut self, command: &str) -> String {
+        let (Some(nickname), Some(style)) = (value(command, "ID"), value(command, "STYLE")) else {
+            return "SESSION STATUS RESULT=I2P_ERROR MESSAGE=\"missing ID or STYLE\"".to_string();
+        };
+        let mut state = self.shared.state();
+
+        if state.sessions.contains_key(nickname) {
+            return "SESSION STATUS RESULT=DUPLICATE_ID".to_string();
+        }
+
+        let (destination, private_key) = match value(command, "DESTINATION") {
+            Some("TRANSIENT") | None => {
+                let destination = random_destination();
+                let private_key = random_private_key(&destination);
+
+                (destination, private_key)
+            }
+            Some(private_key) => match public_destination(private_key) {
+                Some(destination) => (destination, private_key.to_string()),
+                None => return "SESSION STATUS RESULT=INVALID_KEY".to_string(),
+            },
+        };
+
+        state.sessions.insert(
+            nickname.to_string(),
+            MockSession {
+                destination: destination.clone(),
+                style: style.to_string(),
+                datagram_port: value(command, "PORT").and_then(|port| port.parse().ok()),
+                listeners: VecDeque::new(),
+            },
+        );
+        self.session.nickname = Some(nickname.to_string());
+
+        format!("SESSION STATUS RESULT=OK DESTINATION={private_key}")
+    }
+
+    /// Handle `STREAM CONNECT`.
+    ///
+    /// If the remote session has a listener, the connection is spliced together with it.
+    fn connect_stream(mut self, command: &str) -> io::Result<()> {
+        let silent = value(command, "SILENT") == Some("true");
+        let (source, listener) = {
+            let state = self.shared.state();
+            let Some(source) = value(command, "ID")
+                .and_then(|nickname| state.sessions.get(nickname))
+                .map(|session| session.destination.clone())
+            else {
+                drop(state);
+                return self.respond_status(silent, "STREAM STATUS RESULT=INVALID_ID");
+            };
+            let destination = value(command, "DESTINATION").unwrap_or_default();
+
+            // like a router, give the remote session some time to start listening
+            let (mut state, _) = self
+                .shared
+                .listener_added
+                .wait_timeout_while(state, CONNECT_TIMEOUT, |state| {
+                    state
+                        .session_mut(destination)
+                        .is_some_and(|session| session.listeners.is_empty())
+                })
+                .unwrap_or_else(PoisonError::into_inner);
+            let listener =
+                state.session_mut(destination).map(|session| match session.listeners.front() {
+                    Some(Listener::Forward { .. }) => session.listeners.front().cloned(),
+                    _ => session.listeners.pop_front(),
+                });
+
+            (source, listener)
+        };
+
+        match listener {
+            Some(None) => self.respond_status(silent, "STREAM STATUS RESULT=TIMEOUT"),
+            Some(Some(Listener::Accept(sender))) => {
+                self.respond_status(silent, "STREAM STATUS RESULT=OK")?;
+                sender.send((self.reader, source)).map_err(|_| io::ErrorKind::BrokenPipe.into())
+            }
+            Some(Some(Listener::Forward {
+                port,
+                silent: forward_silent,
+            })) => {
+                let Ok(mut forwarded) = TcpStream::connect(("127.0.0.1", port)) else {
+                    return self.respond_status(silent, "STREAM STATUS RESULT=CANT_REACH_PEER");
+                };
+                self.respond_status(silent, "STREAM STATUS RESULT=OK")?;
+
+                if !forward_silent {
+                    forwarded.write_all(format!("{source}\n").as_bytes())?;
+                }
+
+                splice(self.reader, BufReader::new(forwarded))
+            }
+            None => self.respond_status(silent, "STREAM STATUS RESULT=CANT_REACH_PEER"),
+        }
+    }
+
+    /// Handle `STREAM ACCEPT`.
+    ///
+    /// The connection waits for a stream from another session and is then spliced together with
+    /// it.
+    fn accept_stream(mut self, command: &str) -> io::Result<()> {
+        let silent = value(command, "SILENT") == Some("true");
+        let (sender, receiver) = mpsc::channel();
+
+        {
+            let mut state = self.shared.state();
+            let Some(session) =
+                value(command, "ID").and_then(|nickname| state.sessions.get_mut(nickname))
+            else {
+                drop(state);
+                return self.respond_status(silent, "STREAM STATUS RESULT=INVALID_ID");
+            };
+            session.listeners.push_back(Listener::Accept(sender));
+            self.shared.listener_added.notify_all();
+        }
+        self.respond_status(silent, "STREAM STATUS RESULT=OK")?;
+
+        // the sender is dropped if the session is destroyed
+        let Ok((stream, destination)) = receiver.recv() else {
+            return Ok(());
+        };
+
+        if !silent {
+            self.respond(&destination)?;
+        }
+
+        splice(self.reader, stream)
+    }
+
+    /// Handle `STREAM FORWARD`.
+    fn forward_stream(&mut self, command: &str) -> String {
+        let mut state = self.shared.state();
+        let Some(session) =
+            value(command, "ID").and_then(|nickname| state.sessions.get_mut(nickname))
+        else {
+            return "STREAM STATUS RESULT=INVALID_ID".to_string();
+        };
+        let Some(port) = value(command, "PORT").and_then(|port| port.parse().ok()) else {
+            return "STREAM STATUS RESULT=I2P_ERROR MESSAGE=\"invalid PORT\"".to_string();
+        };
+
+        session.listeners.push_back(Listener::Forward {
+            port,
+            silent: value(command, "SILENT") == Some("true"),
+        });
+        self.shared.listener_added.notify_all();
+
+        "STREAM STATUS RESULT=OK".to_string()
+    }
+
+    /// Handle `NAMING LOOKUP`.
+    fn lookup_name(&self, command: &str) -> String {
+        let name = value(command, "NAME").unwrap_or_default();
+        let state = self.shared.state();
+        let destination = state.names.get(name).cloned().or_else(|| {
+            state
+                .sessions
+                .values()
+                .any(|session| session.destination == name)
+                .then(|| name.to_string())
+        });
+
+        match destination {
+            Some(destination) => format!("NAMING REPLY RESULT=OK NAME={name} VALUE={destination}"),
+            None => format!("NAMING REPLY RESULT=KEY_NOT_FOUND NAME={name}"),
+        }
+    }
+}
+
+/// Session created over a connection.
+///
+/// The session is destroyed when its control connection is closed.
+struct SessionGuard {
+    /// State shared with [`MockSam`].
+    shared: Arc<Shared>,
+
+    /// Nickname of the session, if one has been created.
+    nickname: Option<String>,
+}
+
+impl SessionGuard {
+    /// Destroy the session, if one has been created.
+    fn destroy(&mut self) {
+        if let Some(nickname) = self.nickname.take() {
+            self.shared.state().sessions.remove(&nickname);
+        }
+    }
+}
+
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
+
+#[cfg(all(test, feature = "async"))]
+mod tests {
+    use super::*;
+    use crate::{style, I2pError};
+    use futures::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn scripted_response() {
+        let sam = MockSam::new().unwrap();
+        sam.respond_to("STREAM CONNECT", "STREAM STATUS RESULT=PEER_NOT_FOUND");
+
+        let mut session = sam.session::<style::Stream>().await.unwrap();
+
+        match session.connect("host.i2p").await {
+            Err(crate::Error::Protocol(crate::ProtocolError::Router(I2pError::PeerNotFound))) => {}
+            _ => panic!("invalid result"),
+        }
+        assert!(sam.commands().iter().any(|command| command.starts_with("STREAM CONNECT")));
+    }
+
+    #[tokio::test]
+    async fn injected_faults() {
+        let sam = MockSam::new().unwrap();
+        let mut pair = stream_pair(sam.session_options()).await.unwrap();
+
+        sam.inject("STREAM CONNECT", Fault::Disconnect);
+        assert!(matches!(
+            pair.client.connect(pair.server.public_destination()).await,
+            Err(crate::Error::IoError(_))
+        ));
+
+        let mut session = sam.session::<style::Stream>().await.unwrap();
+        let started = std::time::Instant::now();
+
+        sam.inject("STREAM FORWARD", Fault::Delay(Duration::from_millis(50)));
+        session.forward(8888).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn session_pairs() {
+        let sam = MockSam::new().unwrap();
+
+        let mut pair = stream_pair(sam.session_options()).await.unwrap();
+        assert_eq!(
+            pair.server_stream.remote_destination(),
+            pair.client.public_destination()
+        );
+
+        // peers only see the public destination, not the private keys that follow it
+        assert_ne!(pair.client.destination(), pair.client.public_destination());
+        assert!(pair.client.destination().starts_with(pair.client.public_destination()));
+
+        pair.client_stream.write_all(b"hello").await.unwrap();
+        let mut buffer = [0u8; 5];
+        pair.server_stream.read_exact(&mut buffer).await.unwrap();
+        assert_eq!(&buffer, b"hello");
+
+        let (mut first, mut second) = datagram_pair(sam.session_options()).await.unwrap();
+        first.send_to(b"ping", second.public_destination()).await.unwrap();
+
+        let (nread, destination) = second.recv_from(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..nread], b"ping");
+        assert_eq!(destination, first.public_destination());
+    }
+}
+
+#[cfg(all(test, feature = "sync", not(feature = "async")))]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn stream_pair() {
+        let sam = MockSam::new().unwrap();
+        let mut pair = super::stream_pair(sam.session_options()).unwrap();
+
+        pair.client_stream.write_all(b"hello").unwrap();
+        let mut buffer = [0u8; 5];
+        pair.server_stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"hello");
+    }
+}
@@ -0,0 +1,236 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Bandwidth throttling of virtual streams.
+
+use crate::{
+    error::Error,
+    options::{RateLimit, SessionOptions},
+};
+
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+/// Token bucket.
+struct TokenBucket {
+    /// Tokens added per second.
+    rate: f64,
+
+    /// Maximum number of tokens.
+    burst: f64,
+
+    /// Available tokens, negative if more was consumed than was granted.
+    tokens: f64,
+
+    /// When were tokens last added.
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    /// Create new, full [`TokenBucket`] for `limit`.
+    fn new(limit: &RateLimit) -> Self {
+        Self {
+            rate: limit.bytes_per_second as f64,
+            burst: limit.burst as f64,
+            tokens: limit.burst as f64,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// Get the number of bytes, at most `wanted`, that can be transferred now.
+    ///
+    /// If nothing can be transferred, returns how long to wait until `wanted` bytes, capped to the
+    /// burst, can be transferred.
+    fn grant(&mut self, wanted: usize) -> Result<usize, Duration> {
+        let now = Instant::now();
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.burst);
+        self.refilled = now;
+
+        if self.tokens >= 1f64 {
+            return Ok((self.tokens as usize).min(wanted));
+        }
+
+        let missing = (wanted as f64).min(self.burst) - self.tokens;
+        Err(Duration::from_secs_f64(missing / self.rate))
+    }
+
+    /// Consume `amount` tokens.
+    fn consume(&mut self, amount: usize) {
+        self.tokens -= amount as f64;
+    }
+}
+
+/// Validate `limit` of option `key`.
+fn validate(key: &str, limit: Option<&RateLimit>) -> crate::Result<()> {
+    match limit {
+        Some(limit) if limit.bytes_per_second == 0 || limit.burst == 0 =>
+            Err(Error::InvalidOption {
+                key: key.to_string(),
+                value: format!("{limit:?}"),
+            }),
+        _ => Ok(()),
+    }
+}
+
+/// Bandwidth limiter of a session.
+///
+/// Holds the buckets shared by the streams of the session and creates the [`Throttle`]s of
+/// individual streams.
+#[derive(Clone)]
+pub(crate) struct RateLimiter {
+    /// Limit of each stream, if any.
+    stream_limit: Option<RateLimit>,
+
+    /// Read and write buckets shared by all streams of the session, if any.
+    session: Option<[Arc<Mutex<TokenBucket>>; 2]>,
+}
+
+impl RateLimiter {
+    /// Create new [`RateLimiter`] for the rate limits of `options`.
+    ///
+    /// Returns `None` if the session has no rate limits and fails with [`Error::InvalidOption`] if
+    /// a limit has zero rate or burst.
+    pub(crate) fn new(options: &SessionOptions) -> crate::Result<Option<Self>> {
+        validate("stream_rate_limit", options.stream_rate_limit.as_ref())?;
+        validate("session_rate_limit", options.session_rate_limit.as_ref())?;
+
+        if options.stream_rate_limit.is_none() && options.session_rate_limit.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            stream_limit: options.stream_rate_limit,
+            session: options.session_rate_limit.map(|limit| {
+                [
+                    Arc::new(Mutex::new(TokenBucket::new(&limit))),
+                    Arc::new(Mutex::new(TokenBucket::new(&limit))),
+                ]
+            }),
+        }))
+    }
+
+    /// Create the read and write throttles of a new stream.
+    pub(crate) fn throttles(&self) -> (Throttle, Throttle) {
+        let throttle = |direction: usize| Throttle {
+            stream: self.stream_limit.as_ref().map(TokenBucket::new),
+            session: self.session.as_ref().map(|buckets| Arc::clone(&buckets[direction])),
+        };
+
+        (throttle(0), throttle(1))
+    }
+}
+
+/// Throttle of one direction of a stream.
+pub(crate) struct Throttle {
+    /// Bucket of the stream, if any.
+    stream: Option<TokenBucket>,
+
+    /// Bucket shared with the other streams of the session, if any.
+    session: Option<Arc<Mutex<TokenBucket>>>,
+}
+
+impl Throttle {
+    /// Get the number of bytes, at most `wanted`, that can be transferred now or how long to wait
+    /// before trying again.
+    ///
+    /// The bytes that were transferred must be reported with [`Throttle::consume()`].
+    pub(crate) fn grant(&mut self, wanted: usize) -> Result<usize, Duration> {
+        let stream = match &mut self.stream {
+            Some(bucket) => bucket.grant(wanted),
+            None => Ok(wanted),
+        };
+        let session = match &self.session {
+            Some(bucket) => bucket.lock().unwrap_or_else(PoisonError::into_inner).grant(wanted),
+            None => Ok(wanted),
+        };
+
+        match (stream, session) {
+            (Ok(stream), Ok(session)) => Ok(stream.min(session)),
+            (Err(stream), Err(session)) => Err(stream.max(session)),
+            (Err(delay), _) | (_, Err(delay)) => Err(delay),
+        }
+    }
+
+    /// Consume `amount` bytes from the buckets of the throttle.
+    pub(crate) fn consume(&mut self, amount: usize) {
+        if let Some(bucket) = &mut self.stream {
+            bucket.consume(amount);
+        }
+
+        if let Some(bucket) = &self.session {
+            bucket.lock().unwrap_or_else(PoisonError::into_inner).consume(amount);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn burst_then_delay() {
+        let limiter = RateLimiter::new(&SessionOptions {
+            stream_rate_limit: Some(RateLimit::new(1000).with_burst(100)),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        let (mut read, mut write) = limiter.throttles();
+
+        assert_eq!(write.grant(1024), Ok(100));
+        write.consume(100);
+
+        // remaining bytes are granted at the rate of the limit
+        let delay = write.grant(1024).unwrap_err();
+        assert!(delay > Duration::from_millis(90) && delay <= Duration::from_millis(100));
+
+        // directions are limited separately
+        assert_eq!(read.grant(64), Ok(64));
+    }
+
+    #[test]
+    fn session_limit_shared() {
+        let limiter = RateLimiter::new(&SessionOptions {
+            session_rate_limit: Some(RateLimit::new(100)),
+            ..Default::default()
+        })
+        .unwrap()
+        .unwrap();
+        let (_, mut first) = limiter.throttles();
+        let (_, mut second) = limiter.throttles();
+
+        assert_eq!(first.grant(80), Ok(80));
+        first.consume(80);
+        assert!(matches!(second.grant(80), Ok(20 | 21)));
+    }
+
+    #[test]
+    fn invalid_limits() {
+        assert!(RateLimiter::new(&Default::default()).unwrap().is_none());
+        assert!(matches!(
+            RateLimiter::new(&SessionOptions {
+                stream_rate_limit: Some(RateLimit::new(0)),
+                ..Default::default()
+            }),
+            Err(Error::InvalidOption { .. })
+        ));
+    }
+}
@@ -0,0 +1,154 @@
+// Permission is hereby granted, free of charge, to any person obtaining a
+// copy of this software and associated documentation files (the "Software"),
+// to deal in the Software without restriction, including without limitation
+// the rights to use, copy, modify, merge, publish, distribute, sublicense,
+// and/or sell copies of the Software, and to permit persons to whom the
+// Software is furnished to do so, subject to the following conditions:
+//
+// The above copyright notice and this permission notice shall be included in
+// all copies or substantial portions of the Software.
+//
+// THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS
+// OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+// FITNESS FOR A PARTICULAR PURPOSE AND NONINFRINGEMENT. IN NO EVENT SHALL THE
+// AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+// LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING
+// FROM, OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER
+// DEALINGS IN THE SOFTWARE.
+
+//! Logging facade of the crate.
+//!
+//! Re-exports `tracing` if the `tracing` feature is enabled. Otherwise the macros and types used
+//! by the crate are replaced with no-ops which type-check their arguments but don't evaluate them.
+
+#[cfg(feature = "tracing")]
+#[allow(unused_imports)]
+pub(crate) use ::tracing::{
+    debug, debug_span, field, info, info_span, trace, warn, Instrument, Span,
+};
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) use self::noop::*;
+
+#[cfg(not(feature = "tracing"))]
+#[allow(dead_code, unused_imports, unused_macros)]
+mod noop {
+    /// Type-check the arguments of a `tracing` macro without evaluating them.
+    macro_rules! consume {
+        () => {};
+        (target: $value:expr $(, $($rest:tt)*)?) => {
+            let _ = &$value;
+            $crate::tracing::consume!($($($rest)*)?);
+        };
+        (parent: $value:expr $(, $($rest:tt)*)?) => {
+            let _ = &$value;
+            $crate::tracing::consume!($($($rest)*)?);
+        };
+        ($key:ident = % $value:expr $(, $($rest:tt)*)?) => {
+            let _ = &$value;
+            $crate::tracing::consume!($($($rest)*)?);
+        };
+        ($key:ident = ? $value:expr $(, $($rest:tt)*)?) => {
+            let _ = &$value;
+            $crate::tracing::consume!($($($rest)*)?);
+        };
+        ($key:ident = $value:expr $(, $($rest:tt)*)?) => {
+            let _ = &$value;
+            $crate::tracing::consume!($($($rest)*)?);
+        };
+        (% $value:expr $(, $($rest:tt)*)?) => {
+            let _ = &$value;
+            $crate::tracing::consume!($($($rest)*)?);
+        };
+        (? $value:expr $(, $($rest:tt)*)?) => {
+            let _ = &$value;
+            $crate::tracing::consume!($($($rest)*)?);
+        };
+        ($format:literal $(, $args:expr)* $(,)?) => {
+            let _ = ::std::format!($format $(, $args)*);
+        };
+    }
+
+    /// No-op event.
+    macro_rules! event {
+        ($($args:tt)*) => {
+            if false {
+                $crate::tracing::consume!($($args)*);
+            }
+        };
+    }
+
+    /// No-op span.
+    macro_rules! span {
+        (target: $target:expr, $(parent: $parent:expr,)? $name:literal $(, $($fields:tt)*)?) => {{
+            if false {
+                $crate::tracing::consume!(target: $target, $(parent: $parent,)? $($($fields)*)?);
+            }
+
+            $crate::tracing::Span::none()
+        }};
+    }
+
+    pub(crate) use consume;
+    pub(crate) use event as debug;
+    pub(crate) use event as info;
+    pub(crate) use event as trace;
+    pub(crate) use event as warn;
+    pub(crate) use span as debug_span;
+    pub(crate) use span as info_span;
+
+    /// No-op span.
+    #[derive(Debug, Clone)]
+    pub(crate) struct Span;
+
+    /// Guard of an entered no-op span.
+    pub(crate) struct Entered;
+
+    impl Span {
+        /// Create new no-op span.
+        pub(crate) fn none() -> Self {
+            Self
+        }
+
+        /// Get the current span.
+        pub(crate) fn current() -> Self {
+            Self
+        }
+
+        /// Enter the span.
+        pub(crate) fn enter(&self) -> Entered {
+            Entered
+        }
+
+        /// Call `f` inside the span.
+        pub(crate) fn in_scope<T>(&self, f: impl FnOnce() -> T) -> T {
+            f()
+        }
+
+        /// Record `value` for `field`.
+        pub(crate) fn record<V>(&self, _field: &str, _value: V) -> &Self {
+            self
+        }
+    }
+
+    /// No-op instrumentation of futures.
+    pub(crate) trait Instrument: Sized {
+        /// Return `self` unmodified.
+        fn instrument(self, _span: Span) -> Self {
+            self
+        }
+    }
+
+    impl<T> Instrument for T {}
+
+    /// Field values.
+    pub(crate) mod field {
+        /// Field without a value.
+        pub(crate) struct Empty;
+
+        /// Display `value`.
+        pub(crate) fn display<T>(value: T) -> T {
+            value
+        }
+    }
+}